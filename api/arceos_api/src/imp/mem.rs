@@ -10,6 +10,54 @@ cfg_alloc! {
     pub fn ax_dealloc(ptr: NonNull<u8>, layout: Layout) {
         axalloc::global_allocator().dealloc(ptr, layout)
     }
+
+    pub use axalloc::oom::OomPolicy as AxOomPolicy;
+
+    pub fn ax_set_oom_policy(policy: AxOomPolicy) {
+        axalloc::oom::set_policy(policy)
+    }
+
+    pub fn ax_register_reclaimer(f: fn(usize) -> usize) -> crate::AxResult {
+        axalloc::oom::register_reclaimer(f).map_err(|_| axerrno::AxError::NoMemory)
+    }
+
+    pub fn ax_unregister_reclaimer(f: fn(usize) -> usize) {
+        axalloc::oom::unregister_reclaimer(f)
+    }
+
+    pub fn ax_set_low_watermark(pages: usize) {
+        axalloc::oom::set_low_watermark(pages)
+    }
+
+    pub fn ax_is_low_memory() -> bool {
+        axalloc::oom::is_low_memory()
+    }
+
+    pub fn ax_low_memory_events() -> usize {
+        axalloc::oom::low_memory_events()
+    }
+}
+
+cfg_alloc_stats! {
+    use core::fmt;
+
+    pub use axalloc::stats::Snapshot as AxAllocSnapshot;
+
+    struct ConsoleWriter;
+
+    impl fmt::Write for ConsoleWriter {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            axlog::print_fmt(format_args!("{}", s))
+        }
+    }
+
+    pub fn ax_alloc_stats_snapshot() -> AxAllocSnapshot {
+        axalloc::stats::snapshot()
+    }
+
+    pub fn ax_alloc_stats_dump(since: Option<&AxAllocSnapshot>) {
+        axalloc::stats::dump(&mut ConsoleWriter, since).ok();
+    }
 }
 
 cfg_dma! {
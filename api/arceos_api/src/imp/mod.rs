@@ -16,13 +16,82 @@ cfg_display! {
     pub use display::*;
 }
 
+cfg_perf! {
+    mod perf;
+    pub use perf::*;
+}
+
 mod stdio {
     use core::fmt;
 
+    #[cfg(feature = "tty")]
+    pub fn ax_console_read_byte() -> Option<u8> {
+        let mut c = 0;
+        match axtty::console().read(core::slice::from_mut(&mut c), true) {
+            Ok(1) => Some(c),
+            _ => None,
+        }
+    }
+
+    #[cfg(not(feature = "tty"))]
     pub fn ax_console_read_byte() -> Option<u8> {
         axhal::console::getchar().map(|c| if c == b'\r' { b'\n' } else { c })
     }
 
+    #[cfg(feature = "tty")]
+    pub fn ax_console_read_bytes(buf: &mut [u8]) -> crate::AxResult<usize> {
+        axtty::console().read(buf, false)
+    }
+
+    #[cfg(not(feature = "tty"))]
+    pub fn ax_console_read_bytes(buf: &mut [u8]) -> crate::AxResult<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let mut read_len = 0;
+            while read_len < buf.len() {
+                match ax_console_read_byte() {
+                    Some(c) => buf[read_len] = c,
+                    None => break,
+                }
+                read_len += 1;
+            }
+            if read_len > 0 {
+                return Ok(read_len);
+            }
+            super::ax_yield_now();
+        }
+    }
+
+    /// The console attributes before it is switched to raw mode.
+    #[cfg(feature = "tty")]
+    static SAVED_TERMIOS: axsync::Mutex<Option<axtty::Termios>> = axsync::Mutex::new(None);
+
+    #[cfg(feature = "tty")]
+    pub fn ax_console_set_raw_mode(raw: bool) {
+        let console = axtty::console();
+        let mut saved = SAVED_TERMIOS.lock();
+        if raw {
+            let mut termios = console.termios();
+            saved.get_or_insert(termios);
+            termios.make_raw();
+            console.set_termios(&termios);
+        } else {
+            console.set_termios(&saved.take().unwrap_or_default());
+        }
+    }
+
+    /// The console is always read byte by byte without the terminals.
+    #[cfg(not(feature = "tty"))]
+    pub fn ax_console_set_raw_mode(_raw: bool) {}
+
+    #[cfg(feature = "tty")]
+    pub fn ax_console_write_bytes(buf: &[u8]) -> crate::AxResult<usize> {
+        axtty::console().write(buf)
+    }
+
+    #[cfg(not(feature = "tty"))]
     pub fn ax_console_write_bytes(buf: &[u8]) -> crate::AxResult<usize> {
         axhal::console::write_bytes(buf);
         Ok(buf.len())
@@ -31,6 +100,37 @@ mod stdio {
     pub fn ax_console_write_fmt(args: fmt::Arguments) -> fmt::Result {
         axlog::print_fmt(args)
     }
+
+    /// A handle to a remote session attached to the console.
+    #[cfg(feature = "tty")]
+    pub struct AxConsoleSessionHandle(axtty::ConsoleSession);
+
+    #[cfg(feature = "tty")]
+    pub fn ax_console_attach() -> crate::AxResult<AxConsoleSessionHandle> {
+        axtty::ConsoleSession::attach().map(AxConsoleSessionHandle)
+    }
+
+    #[cfg(feature = "tty")]
+    pub fn ax_console_session_read(
+        session: &AxConsoleSessionHandle,
+        buf: &mut [u8],
+        nonblocking: bool,
+    ) -> crate::AxResult<usize> {
+        session.0.read(buf, nonblocking)
+    }
+
+    #[cfg(feature = "tty")]
+    pub fn ax_console_session_write(
+        session: &AxConsoleSessionHandle,
+        buf: &[u8],
+    ) -> crate::AxResult<usize> {
+        session.0.write(buf)
+    }
+
+    #[cfg(feature = "tty")]
+    pub fn ax_console_session_close(session: &AxConsoleSessionHandle) {
+        session.0.close()
+    }
 }
 
 mod time {
@@ -44,5 +144,5 @@ pub use self::stdio::*;
 pub use self::task::*;
 pub use self::time::*;
 
-pub use axhal::misc::terminate as ax_terminate;
+pub use axhal::misc::{poweroff as ax_poweroff, reboot as ax_reboot, terminate as ax_terminate};
 pub use axio::PollState as AxPollState;
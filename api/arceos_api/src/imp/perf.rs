@@ -0,0 +1,17 @@
+pub use axperf::PerfEvent as AxPerfEvent;
+
+/// Starts sampling `event` every `period` occurrences, on all CPUs.
+pub fn ax_perf_start(event: AxPerfEvent, period: u64) -> crate::AxResult {
+    axperf::start(event, period)
+}
+
+/// Stops sampling on all CPUs.
+pub fn ax_perf_stop() {
+    axperf::stop()
+}
+
+/// Writes the recorded samples to the file at `path` in the folded stack
+/// format, and returns the number of samples.
+pub fn ax_perf_dump(path: &str) -> crate::AxResult<usize> {
+    axperf::dump(path)
+}
@@ -32,6 +32,10 @@ pub mod sys {
     define_api! {
         /// Shutdown the whole system and all CPUs.
         pub fn ax_terminate() -> !;
+        /// Powers off the whole system and all CPUs.
+        pub fn ax_poweroff() -> !;
+        /// Reboots the whole system.
+        pub fn ax_reboot() -> !;
     }
 }
 
@@ -75,6 +79,48 @@ pub mod mem {
         pub unsafe fn ax_dealloc(ptr: NonNull<u8>, layout: Layout);
     }
 
+    define_api_type! {
+        @cfg "alloc";
+        pub type AxOomPolicy;
+    }
+
+    define_api! {
+        @cfg "alloc";
+        /// Sets what to do when memory runs out and nothing can be reclaimed.
+        pub fn ax_set_oom_policy(policy: AxOomPolicy);
+        /// Registers a callback to be invoked when an allocation fails.
+        ///
+        /// The callback is given the number of requested bytes, and returns
+        /// the number of bytes it has freed.
+        pub fn ax_register_reclaimer(f: fn(usize) -> usize) -> crate::AxResult;
+        /// Unregisters a callback registered by [`ax_register_reclaimer`].
+        pub fn ax_unregister_reclaimer(f: fn(usize) -> usize);
+        /// Sets the low watermark of available memory, in pages.
+        pub fn ax_set_low_watermark(pages: usize);
+        /// Whether the available memory is below the low watermark.
+        pub fn ax_is_low_memory() -> bool;
+        /// Returns the number of times the available memory dropped below
+        /// the low watermark.
+        pub fn ax_low_memory_events() -> usize;
+    }
+
+    define_api_type! {
+        @cfg "alloc-stats";
+        pub type AxAllocSnapshot;
+    }
+
+    define_api! {
+        @cfg "alloc-stats";
+        /// Takes a snapshot of the allocation statistics of all call sites
+        /// (caller addresses or subsystem tags).
+        pub fn ax_alloc_stats_snapshot() -> AxAllocSnapshot;
+        /// Prints the allocation statistics of all call sites to the console.
+        ///
+        /// If `since` is given, prints the changes since that snapshot instead,
+        /// which can be used to find memory leaks.
+        pub fn ax_alloc_stats_dump(since: Option<&AxAllocSnapshot>);
+    }
+
     define_api_type! {
         @cfg "dma";
         pub type DMAInfo;
@@ -107,12 +153,56 @@ pub mod stdio {
     use core::fmt;
     define_api! {
         /// Reads a byte from the console, or returns [`None`] if no input is available.
+        ///
+        /// In canonical mode, only the lines completed by the user are available.
         pub fn ax_console_read_byte() -> Option<u8>;
+        /// Reads the console input into `buf`, blocking until some input is
+        /// available. Returns the number of bytes read, and 0 at an end-of-file.
+        ///
+        /// The console is in raw mode by default. In canonical mode (with the
+        /// `tty` feature), the input is echoed and edited by lines, and at most
+        /// one line is read.
+        pub fn ax_console_read_bytes(buf: &mut [u8]) -> crate::AxResult<usize>;
+        /// Switches the console to raw mode (the input is read byte by byte,
+        /// without echo) if `raw` is `true`. Otherwise, restores the mode
+        /// before it was switched, or switches to canonical mode if it was
+        /// not.
+        ///
+        /// Without the `tty` feature, the console is always in raw mode.
+        pub fn ax_console_set_raw_mode(raw: bool);
         /// Writes a slice of bytes to the console, returns the number of bytes written.
         pub fn ax_console_write_bytes(buf: &[u8]) -> crate::AxResult<usize>;
         /// Writes a formatted string to the console.
         pub fn ax_console_write_fmt(args: fmt::Arguments) -> fmt::Result;
     }
+
+    define_api_type! {
+        @cfg "tty";
+        pub type AxConsoleSessionHandle;
+    }
+
+    define_api! {
+        @cfg "tty";
+        /// Attaches a remote session to the console, which receives all the
+        /// console output and passes its input to the console. The session is
+        /// detached when the handle is dropped.
+        pub fn ax_console_attach() -> crate::AxResult<AxConsoleSessionHandle>;
+        /// Reads the console output of the session into `buf`, blocking until
+        /// some output is available unless `nonblocking` is set.
+        pub fn ax_console_session_read(
+            session: &AxConsoleSessionHandle,
+            buf: &mut [u8],
+            nonblocking: bool,
+        ) -> crate::AxResult<usize>;
+        /// Writes the bytes to the console as the input of the session.
+        pub fn ax_console_session_write(
+            session: &AxConsoleSessionHandle,
+            buf: &[u8],
+        ) -> crate::AxResult<usize>;
+        /// Closes the session, so that the blocked readers of its output get
+        /// an end-of-file.
+        pub fn ax_console_session_close(session: &AxConsoleSessionHandle);
+    }
 }
 
 /// Multi-threading management.
@@ -368,6 +458,25 @@ pub mod display {
     }
 }
 
+/// Sampling profiler based on hardware performance counters.
+pub mod perf {
+    define_api_type! {
+        @cfg "perf";
+        pub type AxPerfEvent;
+    }
+
+    define_api! {
+        @cfg "perf";
+        /// Starts sampling `event` every `period` occurrences, on all CPUs.
+        pub fn ax_perf_start(event: AxPerfEvent, period: u64) -> crate::AxResult;
+        /// Stops sampling on all CPUs.
+        pub fn ax_perf_stop();
+        /// Writes the recorded samples to the file at `path` in the folded
+        /// stack format, and returns the number of samples.
+        pub fn ax_perf_dump(path: &str) -> crate::AxResult<usize>;
+    }
+}
+
 /// Input/output operations.
 pub mod io {
     define_api_type! {
@@ -400,6 +509,8 @@ pub mod modules {
     pub use axmm;
     #[cfg(feature = "net")]
     pub use axnet;
+    #[cfg(feature = "perf")]
+    pub use axperf;
     #[cfg(feature = "multitask")]
     pub use axtask;
 }
@@ -89,6 +89,10 @@ macro_rules! cfg_alloc {
     ($($item:item)*) => { _cfg_common!{ "alloc" $($item)* } }
 }
 
+macro_rules! cfg_alloc_stats {
+    ($($item:item)*) => { _cfg_common!{ "alloc-stats" $($item)* } }
+}
+
 macro_rules! cfg_dma {
     ($($item:item)*) => { _cfg_common!{ "dma" $($item)* } }
 }
@@ -105,6 +109,10 @@ macro_rules! cfg_display {
     ($($item:item)*) => { _cfg_common!{ "display" $($item)* } }
 }
 
+macro_rules! cfg_perf {
+    ($($item:item)*) => { _cfg_common!{ "perf" $($item)* } }
+}
+
 macro_rules! cfg_task {
     ($($item:item)*) => { _cfg_common!{ "multitask" $($item)* } }
 }
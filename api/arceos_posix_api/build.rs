@@ -53,6 +53,8 @@ typedef struct {{
             "clockid_t",
             "rlimit",
             "aibuf",
+            "termios",
+            "winsize",
         ];
         let allow_vars = [
             "CLOCK_.*",
@@ -67,7 +69,10 @@ typedef struct {{
             "EPOLL.*",
             "RLIMIT_.*",
             "EAI_.*",
+            "RB_.*",
             "MAXADDRS",
+            "TCSA.*",
+            "TC.*FLUSH",
         ];
 
         #[derive(Debug)]
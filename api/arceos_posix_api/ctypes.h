@@ -3,8 +3,10 @@
 #include <netinet/in.h>
 #include <pthread.h>
 #include <stddef.h>
+#include <termios.h>
 #include <time.h>
 #include <sys/epoll.h>
+#include <sys/reboot.h>
 #include <sys/resource.h>
 #include <sys/select.h>
 #include <sys/socket.h>
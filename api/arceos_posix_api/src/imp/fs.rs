@@ -220,6 +220,13 @@ pub fn sys_open(filename: *const c_char, flags: c_int, mode: ctypes::mode_t) ->
     let filename = char_ptr_to_str(filename);
     debug!("sys_open <= {:?} {:#o} {:#o}", filename, flags, mode);
     syscall_body!(sys_open, {
+        #[cfg(feature = "tty")]
+        if let Some(tty) = super::tty::open_device(filename?)? {
+            if flags as u32 & ctypes::O_NONBLOCK != 0 {
+                tty.set_nonblocking(true)?;
+            }
+            return super::fd_ops::add_file_like(tty);
+        }
         let options = flags_to_options(flags, mode);
         if options.has_directory() {
             return Directory::from_path(filename?.into(), &options)?.add_to_fd_table();
@@ -16,8 +16,14 @@ pub mod io_mpx;
 pub mod net;
 #[cfg(feature = "pipe")]
 pub mod pipe;
+#[cfg(feature = "uspace")]
+pub mod process;
 #[cfg(feature = "multitask")]
 pub mod pthread;
+#[cfg(all(feature = "fd", feature = "tty"))]
+pub mod pty;
+#[cfg(feature = "tty")]
+pub mod tty;
 
 #[ctor_bare::register_ctor]
 #[cfg(feature = "fd")]
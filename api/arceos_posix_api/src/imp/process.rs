@@ -0,0 +1,205 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::ffi::{c_char, c_int, c_long, c_void};
+
+use axerrno::{AxError, LinuxError, LinuxResult};
+use axfs::{CURRENT_DIR, CURRENT_DIR_PATH};
+use axhal::arch::TrapFrame;
+use axns::AxNamespace;
+use axprocess::{CloneFlags, WaitOptions, NS_DROP_HOOKS, NS_INIT_HOOKS};
+use axtask::Tms;
+use linkme::distributed_slice;
+
+use crate::imp::fd_ops::FD_TABLE;
+use crate::utils::char_ptr_to_str;
+
+/// Copies the per-process resources of the current process to the namespace
+/// of a new process, or shares them with [`CloneFlags::CLONE_FILES`] (the file
+/// descriptor table) and [`CloneFlags::CLONE_FS`] (the current directory).
+#[distributed_slice(NS_INIT_HOOKS)]
+fn init_process_resources(ns: &AxNamespace, flags: CloneFlags) {
+    unsafe {
+        FD_TABLE.init_new_from(ns);
+        CURRENT_DIR.init_new_from(ns);
+        CURRENT_DIR_PATH.init_new_from(ns);
+    }
+    if flags.contains(CloneFlags::CLONE_FILES) {
+        FD_TABLE.deref_from(ns).init_shared(FD_TABLE.share());
+    } else {
+        FD_TABLE.deref_from(ns).init_new(FD_TABLE.copy_inner());
+    }
+    if flags.contains(CloneFlags::CLONE_FS) {
+        CURRENT_DIR.deref_from(ns).init_shared(CURRENT_DIR.share());
+        CURRENT_DIR_PATH
+            .deref_from(ns)
+            .init_shared(CURRENT_DIR_PATH.share());
+    } else {
+        CURRENT_DIR
+            .deref_from(ns)
+            .init_new(CURRENT_DIR.copy_inner());
+        CURRENT_DIR_PATH
+            .deref_from(ns)
+            .init_new(CURRENT_DIR_PATH.copy_inner());
+    }
+}
+
+#[distributed_slice(NS_DROP_HOOKS)]
+fn drop_process_resources(ns: &AxNamespace) {
+    unsafe {
+        FD_TABLE.drop_from(ns);
+        CURRENT_DIR.drop_from(ns);
+        CURRENT_DIR_PATH.drop_from(ns);
+    }
+}
+
+/// Converts a null-terminated array of C strings to a vector of strings.
+unsafe fn str_array_to_vec(array: *const *const c_char) -> LinuxResult<Vec<String>> {
+    let mut strs = Vec::new();
+    if array.is_null() {
+        return Ok(strs);
+    }
+    let mut ptr = array;
+    loop {
+        let s = unsafe { *ptr };
+        if s.is_null() {
+            break;
+        }
+        strs.push(char_ptr_to_str(s)?.to_string());
+        ptr = unsafe { ptr.add(1) };
+    }
+    Ok(strs)
+}
+
+/// Create a child process as a copy of the calling process.
+///
+/// `tf` is the trap frame of the calling task. Returns the PID of the child in
+/// the parent, and 0 in the child.
+pub fn sys_fork(tf: &TrapFrame) -> c_int {
+    debug!("sys_fork");
+    syscall_body!(sys_fork, {
+        let pid = axprocess::clone_current(tf, CloneFlags::empty(), 0, 0, 0, 0)?;
+        Ok(pid as c_int)
+    })
+}
+
+/// Create a child process or thread.
+///
+/// The low byte of `flags` (the signal sent to the parent when the child
+/// exits) is ignored. `CLONE_VFORK` is not supported for threads.
+pub fn sys_clone(
+    tf: &TrapFrame,
+    flags: usize,
+    stack: usize,
+    parent_tid: *mut c_int,
+    tls: usize,
+    child_tid: *mut c_int,
+) -> c_int {
+    debug!(
+        "sys_clone <= flags: {:#x}, stack: {:#x}, tls: {:#x}",
+        flags, stack, tls
+    );
+    syscall_body!(sys_clone, {
+        let flags = CloneFlags::from_bits_truncate(flags as u32);
+        if flags.contains(CloneFlags::CLONE_THREAD)
+            && (!flags.contains(CloneFlags::CLONE_VM) || flags.contains(CloneFlags::CLONE_VFORK))
+        {
+            return Err(LinuxError::EINVAL);
+        }
+        let tid = axprocess::clone_current(
+            tf,
+            flags,
+            stack,
+            parent_tid as usize,
+            tls,
+            child_tid as usize,
+        )?;
+        Ok(tid as c_int)
+    })
+}
+
+/// Execute the program at `pathname`, replacing the current program.
+///
+/// It does not return on success.
+pub unsafe fn sys_execve(
+    pathname: *const c_char,
+    argv: *const *const c_char,
+    envp: *const *const c_char,
+) -> c_int {
+    syscall_body!(sys_execve, {
+        // Copy all strings out, as the old address space will be released.
+        let path = char_ptr_to_str(pathname)?.to_string();
+        let args = unsafe { str_array_to_vec(argv)? };
+        let envs = unsafe { str_array_to_vec(envp)? };
+        debug!("sys_execve <= {:?} {:?} {:?}", path, args, envs);
+
+        let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+        let envs = envs.iter().map(String::as_str).collect::<Vec<_>>();
+        axprocess::exec_current(&path, &args, &envs)?;
+        Ok(0)
+    })
+}
+
+/// Wait for a child process to change state.
+///
+/// If `pid` is -1 (or 0, as process groups are not supported), waits for any
+/// child process. The resource usage (`rusage`) is not reported.
+pub unsafe fn sys_wait4(
+    pid: c_int,
+    wstatus: *mut c_int,
+    options: c_int,
+    _rusage: *mut c_void,
+) -> c_int {
+    debug!("sys_wait4 <= pid: {}, options: {:#x}", pid, options);
+    syscall_body!(sys_wait4, {
+        let pid = if pid <= 0 { -1 } else { pid as isize };
+        let options = WaitOptions::from_bits_truncate(options as u32);
+        let proc = axprocess::current_process();
+        match proc.wait_child(pid, options) {
+            Ok(Some((pid, exit_code))) => {
+                if !wstatus.is_null() {
+                    unsafe { wstatus.write((exit_code & 0xff) << 8) };
+                }
+                Ok(pid as c_int)
+            }
+            Ok(None) => Ok(0),
+            Err(AxError::NotFound) => Err(LinuxError::ECHILD),
+            Err(e) => Err(e.into()),
+        }
+    })
+}
+
+/// Exit all threads in the current process.
+pub fn sys_exit_group(exit_code: c_int) -> ! {
+    debug!("sys_exit_group <= {}", exit_code);
+    axprocess::exit_group_current(exit_code)
+}
+
+/// Get the parent process ID.
+pub fn sys_getppid() -> c_int {
+    syscall_body!(sys_getppid, {
+        Ok(axprocess::current_process().ppid() as c_int)
+    })
+}
+
+/// Set the program break (the end of the heap) of the current process.
+///
+/// Returns the new program break on success, or the current one on failure
+/// (as the Linux syscall does, the C library `brk` wrapper checks it).
+pub fn sys_brk(addr: usize) -> usize {
+    let brk = axprocess::current_process().brk(addr);
+    debug!("sys_brk <= {:#x} => {:#x}", addr, brk);
+    brk
+}
+
+/// Get the process times of the current task and its waited-for children.
+///
+/// Returns the current time in clock ticks.
+pub unsafe fn sys_times(tms: *mut Tms) -> c_long {
+    syscall_body!(sys_times, {
+        if !tms.is_null() {
+            let times = axtask::current().sys_times(&[]);
+            unsafe { tms.write(times) };
+        }
+        Ok(axhal::time::current_ticks() as c_long)
+    })
+}
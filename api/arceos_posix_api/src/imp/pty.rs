@@ -0,0 +1,134 @@
+use alloc::sync::Arc;
+use core::ffi::{c_char, c_int};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use axerrno::{LinuxError, LinuxResult};
+use axio::PollState;
+use axtty::{PtyMaster, PtySlave, TtyRef};
+
+use super::fd_ops::{add_file_like, close_file_like, get_file_like, FileLike};
+use super::tty::TtyFile;
+use crate::ctypes;
+
+/// The master of a pseudo-terminal.
+pub struct PtyMasterFile {
+    inner: PtyMaster,
+    nonblocking: AtomicBool,
+}
+
+impl PtyMasterFile {
+    pub fn open() -> LinuxResult<Self> {
+        Ok(Self {
+            inner: PtyMaster::open()?,
+            nonblocking: AtomicBool::new(false),
+        })
+    }
+
+    pub fn slave(&self) -> &Arc<PtySlave> {
+        self.inner.slave()
+    }
+
+    fn from_fd(fd: c_int) -> LinuxResult<Arc<Self>> {
+        get_file_like(fd)?
+            .into_any()
+            .downcast::<Self>()
+            .map_err(|_| LinuxError::ENOTTY)
+    }
+}
+
+impl FileLike for PtyMasterFile {
+    fn read(&self, buf: &mut [u8]) -> LinuxResult<usize> {
+        Ok(self
+            .inner
+            .read(buf, self.nonblocking.load(Ordering::Acquire))?)
+    }
+
+    fn write(&self, buf: &[u8]) -> LinuxResult<usize> {
+        Ok(self.inner.write(buf)?)
+    }
+
+    fn stat(&self) -> LinuxResult<ctypes::stat> {
+        let st_mode = 0o20000 | 0o666u32; // S_IFCHR | rw-rw-rw-
+        Ok(ctypes::stat {
+            st_ino: 1,
+            st_nlink: 1,
+            st_mode,
+            ..Default::default()
+        })
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync> {
+        self
+    }
+
+    fn poll(&self) -> LinuxResult<PollState> {
+        Ok(PollState {
+            readable: self.inner.readable(),
+            writable: true,
+        })
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> LinuxResult {
+        self.nonblocking.store(nonblocking, Ordering::Release);
+        Ok(())
+    }
+}
+
+/// Open the master of a new pseudo-terminal, as opening `/dev/ptmx`.
+///
+/// Return the file descriptor of the master.
+pub fn sys_posix_openpt(flags: c_int) -> c_int {
+    debug!("sys_posix_openpt <= {:#o}", flags);
+    syscall_body!(sys_posix_openpt, {
+        let master = PtyMasterFile::open()?;
+        if flags as u32 & ctypes::O_NONBLOCK != 0 {
+            master.set_nonblocking(true)?;
+        }
+        add_file_like(Arc::new(master))
+    })
+}
+
+/// Write the path of the slave of the pseudo-terminal, whose master is `fd`,
+/// to `buf`.
+///
+/// Return `ERANGE` if `buf` is too small.
+pub unsafe fn sys_ptsname_r(fd: c_int, buf: *mut c_char, buflen: usize) -> c_int {
+    debug!("sys_ptsname_r <= {} {:#x} {}", fd, buf as usize, buflen);
+    syscall_body!(sys_ptsname_r, {
+        if buf.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        let index = PtyMasterFile::from_fd(fd)?.inner.index();
+        let name = alloc::format!("/dev/pts/{}\0", index);
+        if name.len() > buflen {
+            return Err(LinuxError::ERANGE);
+        }
+        unsafe { core::ptr::copy_nonoverlapping(name.as_ptr(), buf as *mut u8, name.len()) };
+        Ok(0)
+    })
+}
+
+/// Open a new pseudo-terminal, and write the file descriptors of its master
+/// and slave to `fds`.
+///
+/// Return 0 if succeed.
+pub fn sys_openpty(fds: &mut [c_int]) -> c_int {
+    debug!("sys_openpty <= {:#x}", fds.as_ptr() as usize);
+    syscall_body!(sys_openpty, {
+        if fds.len() != 2 {
+            return Err(LinuxError::EFAULT);
+        }
+
+        let master = PtyMasterFile::open()?;
+        let slave = TtyFile::new(TtyRef::Shared(master.slave().clone()));
+        let master_fd = add_file_like(Arc::new(master))?;
+        let slave_fd = add_file_like(Arc::new(slave)).inspect_err(|_| {
+            close_file_like(master_fd).ok();
+        })?;
+
+        fds[0] = master_fd;
+        fds[1] = slave_fd;
+
+        Ok(0)
+    })
+}
@@ -1,38 +1,55 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
 use axerrno::AxResult;
-use axio::{prelude::*, BufReader};
+use axio::prelude::*;
 use axsync::Mutex;
 
 #[cfg(feature = "fd")]
 use {alloc::sync::Arc, axerrno::LinuxError, axerrno::LinuxResult, axio::PollState};
 
-fn console_read_bytes() -> Option<u8> {
-    axhal::console::getchar().map(|c| if c == b'\r' { b'\n' } else { c })
-}
-
-fn console_write_bytes(buf: &[u8]) -> AxResult<usize> {
-    axhal::console::write_bytes(buf);
-    Ok(buf.len())
+#[cfg(feature = "tty")]
+fn console_read_bytes(buf: &mut [u8], nonblocking: bool) -> AxResult<usize> {
+    axtty::console().read(buf, nonblocking)
 }
 
-struct StdinRaw;
-struct StdoutRaw;
-
-impl Read for StdinRaw {
-    // Non-blocking read, returns number of bytes read.
-    fn read(&mut self, buf: &mut [u8]) -> AxResult<usize> {
+// Polls the console UART, with CR translated to NL.
+#[cfg(not(feature = "tty"))]
+fn console_read_bytes(buf: &mut [u8], nonblocking: bool) -> AxResult<usize> {
+    if buf.is_empty() {
+        return Ok(0);
+    }
+    loop {
         let mut read_len = 0;
         while read_len < buf.len() {
-            if let Some(c) = console_read_bytes() {
-                buf[read_len] = c;
-                read_len += 1;
-            } else {
-                break;
+            match axhal::console::getchar() {
+                Some(c) => buf[read_len] = if c == b'\r' { b'\n' } else { c },
+                None => break,
             }
+            read_len += 1;
+        }
+        if read_len > 0 {
+            return Ok(read_len);
         }
-        Ok(read_len)
+        if nonblocking {
+            return Err(axerrno::AxError::WouldBlock);
+        }
+        crate::sys_sched_yield();
     }
 }
 
+#[cfg(feature = "tty")]
+fn console_write_bytes(buf: &[u8]) -> AxResult<usize> {
+    axtty::console().write(buf)
+}
+
+#[cfg(not(feature = "tty"))]
+fn console_write_bytes(buf: &[u8]) -> AxResult<usize> {
+    axhal::console::write_bytes(buf);
+    Ok(buf.len())
+}
+
+struct StdoutRaw;
+
 impl Write for StdoutRaw {
     fn write(&mut self, buf: &[u8]) -> AxResult<usize> {
         console_write_bytes(buf)
@@ -43,31 +60,23 @@ impl Write for StdoutRaw {
     }
 }
 
+/// The standard input, which reads the console terminal (or the console UART
+/// without the `tty` feature).
 pub struct Stdin {
-    inner: &'static Mutex<BufReader<StdinRaw>>,
+    nonblocking: AtomicBool,
 }
 
 impl Stdin {
-    // Block until at least one byte is read.
-    fn read_blocked(&self, buf: &mut [u8]) -> AxResult<usize> {
-        let read_len = self.inner.lock().read(buf)?;
-        if buf.is_empty() || read_len > 0 {
-            return Ok(read_len);
-        }
-        // try again until we get something
-        loop {
-            let read_len = self.inner.lock().read(buf)?;
-            if read_len > 0 {
-                return Ok(read_len);
-            }
-            crate::sys_sched_yield();
-        }
+    // Block until at least one byte is read (or the end of input), unless
+    // in non-blocking mode.
+    fn read_tty(&self, buf: &mut [u8]) -> AxResult<usize> {
+        console_read_bytes(buf, self.nonblocking.load(Ordering::Acquire))
     }
 }
 
 impl Read for Stdin {
     fn read(&mut self, buf: &mut [u8]) -> AxResult<usize> {
-        self.read_blocked(buf)
+        self.read_tty(buf)
     }
 }
 
@@ -87,8 +96,9 @@ impl Write for Stdout {
 
 /// Constructs a new handle to the standard input of the current process.
 pub fn stdin() -> Stdin {
-    static INSTANCE: Mutex<BufReader<StdinRaw>> = Mutex::new(BufReader::new(StdinRaw));
-    Stdin { inner: &INSTANCE }
+    Stdin {
+        nonblocking: AtomicBool::new(false),
+    }
 }
 
 /// Constructs a new handle to the standard output of the current process.
@@ -100,7 +110,7 @@ pub fn stdout() -> Stdout {
 #[cfg(feature = "fd")]
 impl super::fd_ops::FileLike for Stdin {
     fn read(&self, buf: &mut [u8]) -> LinuxResult<usize> {
-        Ok(self.read_blocked(buf)?)
+        Ok(self.read_tty(buf)?)
     }
 
     fn write(&self, _buf: &[u8]) -> LinuxResult<usize> {
@@ -122,13 +132,19 @@ impl super::fd_ops::FileLike for Stdin {
     }
 
     fn poll(&self) -> LinuxResult<PollState> {
+        #[cfg(feature = "tty")]
+        axtty::console().poll_input();
         Ok(PollState {
+            #[cfg(feature = "tty")]
+            readable: axtty::console().readable(),
+            #[cfg(not(feature = "tty"))]
             readable: true,
             writable: true,
         })
     }
 
-    fn set_nonblocking(&self, _nonblocking: bool) -> LinuxResult {
+    fn set_nonblocking(&self, nonblocking: bool) -> LinuxResult {
+        self.nonblocking.store(nonblocking, Ordering::Release);
         Ok(())
     }
 }
@@ -1,9 +1,14 @@
-use core::ffi::{c_int, c_long};
+use core::ffi::{c_int, c_long, c_void};
+
+use axerrno::LinuxError;
 
 use crate::ctypes;
 
 const PAGE_SIZE_4K: usize = 4096;
 
+const LINUX_REBOOT_MAGIC1: u32 = 0xfee1_dead;
+const LINUX_REBOOT_MAGIC2: [u32; 4] = [672274793, 85072278, 369367448, 537993216];
+
 /// Return system configuration infomation
 ///
 /// Notice: currently only support what unikraft covers
@@ -14,9 +19,9 @@ pub fn sys_sysconf(name: c_int) -> c_long {
             // Page size
             ctypes::_SC_PAGE_SIZE => Ok(PAGE_SIZE_4K),
             // Total physical pages
-            ctypes::_SC_PHYS_PAGES => Ok(axconfig::PHYS_MEMORY_SIZE / PAGE_SIZE_4K),
+            ctypes::_SC_PHYS_PAGES => Ok(axhal::mem::total_ram_size() / PAGE_SIZE_4K),
             // Number of processors in use
-            ctypes::_SC_NPROCESSORS_ONLN => Ok(axconfig::SMP),
+            ctypes::_SC_NPROCESSORS_ONLN => Ok(axhal::cpu::cpu_count()),
             // Avaliable physical pages
             #[cfg(feature = "alloc")]
             ctypes::_SC_AVPHYS_PAGES => Ok(axalloc::global_allocator().available_pages()),
@@ -27,3 +32,24 @@ pub fn sys_sysconf(name: c_int) -> c_long {
         }
     })
 }
+
+/// Reboot or power off the system, or enable/disable the Ctrl-Alt-Del
+/// reboot (which is ignored).
+///
+/// The arguments are the same as the Linux `reboot` syscall. `arg` is unused,
+/// as the commands taking it are not supported.
+pub fn sys_reboot(magic1: c_int, magic2: c_int, cmd: c_int, _arg: *mut c_void) -> c_int {
+    debug!("sys_reboot <= {:#x}", cmd);
+    syscall_body!(sys_reboot, {
+        if magic1 as u32 != LINUX_REBOOT_MAGIC1 || !LINUX_REBOOT_MAGIC2.contains(&(magic2 as u32)) {
+            return Err(LinuxError::EINVAL);
+        }
+        match cmd as u32 {
+            ctypes::RB_AUTOBOOT => axhal::misc::reboot(),
+            ctypes::RB_POWER_OFF => axhal::misc::poweroff(),
+            ctypes::RB_HALT_SYSTEM => axhal::misc::terminate(),
+            ctypes::RB_ENABLE_CAD | ctypes::RB_DISABLE_CAD => Ok(0),
+            _ => Err(LinuxError::EINVAL),
+        }
+    })
+}
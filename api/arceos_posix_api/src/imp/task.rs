@@ -19,7 +19,11 @@ pub fn sys_sched_yield() -> c_int {
 /// Get current thread ID.
 pub fn sys_getpid() -> c_int {
     syscall_body!(sys_getpid,
-        #[cfg(feature = "multitask")]
+        #[cfg(feature = "uspace")]
+        {
+            Ok(axprocess::current_process().pid() as c_int)
+        }
+        #[cfg(all(feature = "multitask", not(feature = "uspace")))]
         {
             Ok(axtask::current().id().as_u64() as c_int)
         }
@@ -33,7 +37,9 @@ pub fn sys_getpid() -> c_int {
 /// Exit current task
 pub fn sys_exit(exit_code: c_int) -> ! {
     debug!("sys_exit <= {}", exit_code);
-    #[cfg(feature = "multitask")]
+    #[cfg(feature = "uspace")]
+    axprocess::exit_current(exit_code);
+    #[cfg(all(feature = "multitask", not(feature = "uspace")))]
     axtask::exit(exit_code);
     #[cfg(not(feature = "multitask"))]
     axhal::misc::terminate();
@@ -0,0 +1,214 @@
+use core::ffi::c_int;
+
+use axerrno::{LinuxError, LinuxResult};
+use axtty::termios::{InputFlags, LocalFlags, OutputFlags};
+use axtty::{Termios, Tty, TtyDriver};
+
+use crate::ctypes;
+
+#[cfg(feature = "fd")]
+use {
+    alloc::sync::Arc,
+    axio::PollState,
+    axtty::TtyRef,
+    core::sync::atomic::{AtomicBool, Ordering},
+};
+
+/// The baud rate bits of `c_cflag`.
+const CBAUD: u32 = 0o010017;
+
+/// A terminal opened by its device file, e.g., `/dev/console` or
+/// `/dev/pts/N`.
+#[cfg(feature = "fd")]
+pub struct TtyFile {
+    tty: TtyRef,
+    nonblocking: AtomicBool,
+}
+
+#[cfg(feature = "fd")]
+impl TtyFile {
+    pub fn new(tty: TtyRef) -> Self {
+        Self {
+            tty,
+            nonblocking: AtomicBool::new(false),
+        }
+    }
+}
+
+#[cfg(feature = "fd")]
+impl super::fd_ops::FileLike for TtyFile {
+    fn read(&self, buf: &mut [u8]) -> LinuxResult<usize> {
+        Ok(self
+            .tty
+            .read(buf, self.nonblocking.load(Ordering::Acquire))?)
+    }
+
+    fn write(&self, buf: &[u8]) -> LinuxResult<usize> {
+        Ok(self.tty.write(buf)?)
+    }
+
+    fn stat(&self) -> LinuxResult<ctypes::stat> {
+        let st_mode = 0o20000 | 0o620u32; // S_IFCHR | rw--w----
+        Ok(ctypes::stat {
+            st_ino: 1,
+            st_nlink: 1,
+            st_mode,
+            ..Default::default()
+        })
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync> {
+        self
+    }
+
+    fn poll(&self) -> LinuxResult<PollState> {
+        self.tty.poll_input();
+        Ok(PollState {
+            readable: self.tty.readable() || self.tty.is_hung_up(),
+            writable: true,
+        })
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> LinuxResult {
+        self.nonblocking.store(nonblocking, Ordering::Release);
+        Ok(())
+    }
+}
+
+/// Opens the terminal device file at `path`, or returns [`None`] if it is not
+/// a terminal device.
+///
+/// The terminal devices in devfs are opened here instead of in [`axfs`], as
+/// opening `/dev/ptmx` allocates a new pseudo-terminal each time.
+#[cfg(feature = "fs")]
+pub fn open_device(path: &str) -> LinuxResult<Option<Arc<dyn super::fd_ops::FileLike>>> {
+    let path = axfs::api::canonicalize(path)?;
+    let tty = match path.as_str() {
+        "/dev/console" | "/dev/ttyS0" => TtyRef::Static(axtty::console()),
+        "/dev/ptmx" => return Ok(Some(Arc::new(super::pty::PtyMasterFile::open()?))),
+        _ => match path
+            .strip_prefix("/dev/pts/")
+            .and_then(|n| n.parse().ok())
+            .and_then(axtty::pty_slave)
+        {
+            Some(slave) => TtyRef::Shared(slave),
+            None => return Ok(None),
+        },
+    };
+    Ok(Some(Arc::new(TtyFile::new(tty))))
+}
+
+/// Calls `f` with the terminal referred to by `fd`.
+///
+/// For the master of a pseudo-terminal, it is the slave terminal, as on Linux.
+fn with_tty<R>(fd: c_int, f: impl FnOnce(&Tty<dyn TtyDriver>) -> R) -> LinuxResult<R> {
+    #[cfg(feature = "fd")]
+    {
+        use super::stdio::{Stdin, Stdout};
+        let any = super::fd_ops::get_file_like(fd)?.into_any();
+        if any.is::<Stdin>() || any.is::<Stdout>() {
+            return Ok(f(axtty::console()));
+        }
+        if let Some(file) = any.downcast_ref::<TtyFile>() {
+            return Ok(f(&file.tty));
+        }
+        if let Some(master) = any.downcast_ref::<super::pty::PtyMasterFile>() {
+            return Ok(f(&**master.slave()));
+        }
+        Err(LinuxError::ENOTTY)
+    }
+    #[cfg(not(feature = "fd"))]
+    match fd {
+        0..=2 => Ok(f(axtty::console())),
+        _ => Err(LinuxError::EBADF),
+    }
+}
+
+fn to_ctermios(termios: &Termios) -> ctypes::termios {
+    let speed = termios.cflag & CBAUD;
+    ctypes::termios {
+        c_iflag: termios.iflag.bits(),
+        c_oflag: termios.oflag.bits(),
+        c_cflag: termios.cflag,
+        c_lflag: termios.lflag.bits(),
+        c_line: 0,
+        c_cc: termios.cc,
+        __c_ispeed: speed,
+        __c_ospeed: speed,
+    }
+}
+
+fn from_ctermios(termios: &ctypes::termios) -> Termios {
+    Termios {
+        iflag: InputFlags::from_bits_truncate(termios.c_iflag),
+        oflag: OutputFlags::from_bits_truncate(termios.c_oflag),
+        cflag: termios.c_cflag,
+        lflag: LocalFlags::from_bits_truncate(termios.c_lflag),
+        cc: termios.c_cc,
+    }
+}
+
+/// Get the attributes of the terminal referred to by `fd`.
+pub unsafe fn sys_tcgetattr(fd: c_int, termios: *mut ctypes::termios) -> c_int {
+    debug!("sys_tcgetattr <= {} {:#x}", fd, termios as usize);
+    syscall_body!(sys_tcgetattr, {
+        if termios.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        let attr = with_tty(fd, |tty| tty.termios())?;
+        unsafe { *termios = to_ctermios(&attr) };
+        Ok(0)
+    })
+}
+
+/// Set the attributes of the terminal referred to by `fd`.
+///
+/// With `TCSAFLUSH`, the input not read yet is discarded. The output is
+/// never buffered, so `TCSADRAIN` is the same as `TCSANOW`.
+pub unsafe fn sys_tcsetattr(
+    fd: c_int,
+    optional_actions: c_int,
+    termios: *const ctypes::termios,
+) -> c_int {
+    debug!(
+        "sys_tcsetattr <= {} {} {:#x}",
+        fd, optional_actions, termios as usize
+    );
+    syscall_body!(sys_tcsetattr, {
+        if termios.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        let flush = match optional_actions as u32 {
+            ctypes::TCSANOW | ctypes::TCSADRAIN => false,
+            ctypes::TCSAFLUSH => true,
+            _ => return Err(LinuxError::EINVAL),
+        };
+        let attr = from_ctermios(unsafe { &*termios });
+        with_tty(fd, |tty| {
+            if flush {
+                tty.flush_input();
+            }
+            tty.set_termios(&attr);
+        })?;
+        Ok(0)
+    })
+}
+
+/// Discard the data not read (`TCIFLUSH`) or not transmitted (`TCOFLUSH`) of
+/// the terminal referred to by `fd`.
+pub fn sys_tcflush(fd: c_int, queue_selector: c_int) -> c_int {
+    debug!("sys_tcflush <= {} {}", fd, queue_selector);
+    syscall_body!(sys_tcflush, {
+        let flush_input = match queue_selector as u32 {
+            ctypes::TCIFLUSH | ctypes::TCIOFLUSH => true,
+            ctypes::TCOFLUSH => false,
+            _ => return Err(LinuxError::EINVAL),
+        };
+        with_tty(fd, |tty| {
+            if flush_input {
+                tty.flush_input();
+            }
+        })?;
+        Ok(0)
+    })
+}
@@ -33,7 +33,7 @@ mod ctypes_ext;
 
 pub use imp::io::{sys_read, sys_write, sys_writev};
 pub use imp::resources::{sys_getrlimit, sys_setrlimit};
-pub use imp::sys::sys_sysconf;
+pub use imp::sys::{sys_reboot, sys_sysconf};
 pub use imp::task::{sys_exit, sys_getpid, sys_sched_yield};
 pub use imp::time::{sys_clock_gettime, sys_get_time_of_day, sys_nanosleep};
 pub use utils::char_ptr_to_str;
@@ -57,9 +57,17 @@ pub use imp::net::{
 };
 #[cfg(feature = "pipe")]
 pub use imp::pipe::sys_pipe;
+#[cfg(feature = "uspace")]
+pub use imp::process::{
+    sys_brk, sys_clone, sys_execve, sys_exit_group, sys_fork, sys_getppid, sys_times, sys_wait4,
+};
 #[cfg(feature = "multitask")]
 pub use imp::pthread::mutex::{
     sys_pthread_mutex_init, sys_pthread_mutex_lock, sys_pthread_mutex_unlock,
 };
 #[cfg(feature = "multitask")]
 pub use imp::pthread::{sys_pthread_create, sys_pthread_exit, sys_pthread_join, sys_pthread_self};
+#[cfg(all(feature = "fd", feature = "tty"))]
+pub use imp::pty::{sys_openpty, sys_posix_openpt, sys_ptsname_r};
+#[cfg(feature = "tty")]
+pub use imp::tty::{sys_tcflush, sys_tcgetattr, sys_tcsetattr};
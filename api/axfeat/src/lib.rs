@@ -12,6 +12,12 @@
 //!     - `alloc-tlsf`: Use the TLSF allocator.
 //!     - `alloc-slab`: Use the slab allocator.
 //!     - `alloc-buddy`: Use the buddy system allocator.
+//!     - `alloc-page-buddy`: Use the buddy system page allocator for contiguous pages.
+//!     - `alloc-zones`: Manage pages in separate pools by memory zone (DMA32/normal) and NUMA node.
+//!     - `alloc-percpu-cache`: Use per-CPU caches for small allocations.
+//!     - `alloc-stats`: Enable allocation statistics and leak tracking.
+//!     - `alloc-debug`: Check heap corruptions with redzones, poisoning and a quarantine.
+//!     - `alloc-debug-guard`: Place each allocation before a guard page to trap out-of-bounds accesses.
 //!     - `paging`: Enable page table manipulation.
 //!     - `tls`: Enable thread-local storage.
 //! - Task management
@@ -24,12 +30,17 @@
 //!     - `myfs`: Allow users to define their custom filesystems to override the default.
 //!     - `net`: Enable networking support.
 //!     - `display`: Enable graphics support.
+//!     - `tty`: Enable the terminals: the console line discipline, pseudo-terminals and console sessions.
 //! - Device drivers
 //!     - `bus-mmio`: Use device tree to probe all MMIO devices.
 //!     - `bus-pci`: Use PCI bus to probe all PCI devices.
 //!     - `driver-ramdisk`: Use the RAM disk to emulate the block device.
 //!     - `driver-ixgbe`: Enable the Intel 82599 10Gbit NIC driver.
 //!     - `driver-bcm2835-sdhci`: Enable the BCM2835 SDHCI driver (Raspberry Pi SD card).
+//! - Debugging
+//!     - `backtrace`: Print stack backtraces with symbol names on panic.
+//!     - `gdbstub`: Debug the kernel with GDB over the console UART.
+//!     - `perf`: Enable the sampling profiler based on hardware performance counters.
 //! - Logging
 //!     - `log-level-off`: Disable all logging.
 //!     - `log-level-error`, `log-level-warn`, `log-level-info`, `log-level-debug`,
@@ -23,6 +23,8 @@ const CMD_TABLE: &[(&str, CmdHandler)] = &[
     ("exit", do_exit),
     ("help", do_help),
     ("ls", do_ls),
+    #[cfg(feature = "alloc-stats")]
+    ("memstat", do_memstat),
     ("mkdir", do_mkdir),
     ("pwd", do_pwd),
     ("rm", do_rm),
@@ -267,6 +269,29 @@ fn do_help(_args: &str) {
     }
 }
 
+#[cfg(feature = "alloc-stats")]
+fn do_memstat(args: &str) {
+    use std::os::arceos::api::mem::{
+        ax_alloc_stats_dump, ax_alloc_stats_snapshot, AxAllocSnapshot,
+    };
+    use std::sync::Mutex;
+
+    static MARK: Mutex<Option<AxAllocSnapshot>> = Mutex::new(None);
+
+    match args.trim() {
+        "" => ax_alloc_stats_dump(None),
+        "mark" => {
+            *MARK.lock() = Some(ax_alloc_stats_snapshot());
+            println!("memstat: snapshot saved");
+        }
+        "diff" => match MARK.lock().as_ref() {
+            Some(mark) => ax_alloc_stats_dump(Some(mark)),
+            None => print_err!("memstat", "no snapshot, run `memstat mark` first"),
+        },
+        _ => print_err!("memstat", "usage: memstat [mark|diff]"),
+    }
+}
+
 fn do_exit(_args: &str) {
     println!("Bye~");
     std::process::exit(0);
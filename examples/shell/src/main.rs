@@ -23,6 +23,9 @@ mod cmd;
 #[cfg(feature = "use-ramfs")]
 mod ramfs;
 
+#[cfg(all(feature = "axstd", feature = "remote"))]
+mod remote;
+
 use std::io::prelude::*;
 
 const LF: u8 = b'\n';
@@ -46,6 +49,13 @@ fn main() {
     let mut stdin = std::io::stdin();
     let mut stdout = std::io::stdout();
 
+    // The shell echoes and edits the input itself.
+    #[cfg(feature = "axstd")]
+    std::os::arceos::api::stdio::ax_console_set_raw_mode(true);
+
+    #[cfg(all(feature = "axstd", feature = "remote"))]
+    remote::spawn_server();
+
     let mut buf = [0; MAX_CMD_LEN];
     let mut cursor = 0;
     cmd::run_cmd("help".as_bytes());
@@ -0,0 +1,148 @@
+//! A telnet-like remote shell.
+//!
+//! Each TCP client is attached to the system console as a console session, so
+//! it shares the shell with the local console: it sees all the console output,
+//! and its input is passed to the shell as if typed on the console.
+//!
+//! Run with `make A=examples/shell APP_FEATURES=remote NET=y run`, and
+//! connect with `telnet X.X.X.X 5555` or `nc X.X.X.X 5555`.
+
+use std::io::{self, prelude::*};
+use std::net::{TcpListener, TcpStream};
+use std::os::arceos::api::stdio::{self as api, AxConsoleSessionHandle};
+use std::sync::Arc;
+use std::thread;
+
+const LOCAL_IP: &str = "0.0.0.0";
+const LOCAL_PORT: u16 = 5555;
+
+const IAC: u8 = 255;
+const WILL: u8 = 251;
+const DONT: u8 = 254;
+const SB: u8 = 250;
+const SE: u8 = 240;
+const OPT_ECHO: u8 = 1;
+const OPT_SGA: u8 = 3;
+
+/// The state of the parser of telnet commands in the input.
+#[derive(Clone, Copy)]
+enum InputState {
+    Data,
+    /// After a CR, which may be followed by a NUL or LF to be dropped.
+    Cr,
+    Iac,
+    /// After `WILL`, `WONT`, `DO` or `DONT`, which is followed by an option.
+    Option,
+    Subnegotiation,
+    SubnegotiationIac,
+}
+
+/// Removes the telnet commands from the input, and translates CR-NUL and
+/// CR-LF to CR as the console receives.
+fn filter_input(state: &mut InputState, input: &[u8], output: &mut [u8]) -> usize {
+    let mut n = 0;
+    for &c in input {
+        let (next, data) = match (*state, c) {
+            (InputState::Cr, 0 | b'\n') => (InputState::Data, None),
+            (InputState::Data | InputState::Cr, IAC) => (InputState::Iac, None),
+            (InputState::Data | InputState::Cr, b'\r') => (InputState::Cr, Some(c)),
+            (InputState::Data | InputState::Cr, _) => (InputState::Data, Some(c)),
+            (InputState::Iac, IAC) => (InputState::Data, Some(IAC)),
+            (InputState::Iac, SB) => (InputState::Subnegotiation, None),
+            (InputState::Iac, WILL..=DONT) => (InputState::Option, None),
+            (InputState::Iac | InputState::Option, _) => (InputState::Data, None),
+            (InputState::Subnegotiation, IAC) => (InputState::SubnegotiationIac, None),
+            (InputState::Subnegotiation, _) => (InputState::Subnegotiation, None),
+            (InputState::SubnegotiationIac, SE) => (InputState::Data, None),
+            (InputState::SubnegotiationIac, _) => (InputState::Subnegotiation, None),
+        };
+        *state = next;
+        if let Some(c) = data {
+            output[n] = c;
+            n += 1;
+        }
+    }
+    n
+}
+
+/// Sends the console output to the client, until the session is closed.
+fn output_loop(session: &AxConsoleSessionHandle, mut stream: &TcpStream) -> io::Result<()> {
+    let mut buf = [0; 1024];
+    loop {
+        let n = api::ax_console_session_read(session, &mut buf, false)?;
+        if n == 0 {
+            return Ok(());
+        }
+        // Escape the IAC bytes in the output.
+        for (i, chunk) in buf[..n].split(|&c| c == IAC).enumerate() {
+            if i > 0 {
+                stream.write_all(&[IAC, IAC])?;
+            }
+            stream.write_all(chunk)?;
+        }
+    }
+}
+
+/// Passes the input of the client to the console, until it is disconnected.
+fn input_loop(session: &AxConsoleSessionHandle, mut stream: &TcpStream) -> io::Result<()> {
+    // The shell echoes the input itself, and works byte by byte.
+    stream.write_all(&[IAC, WILL, OPT_ECHO, IAC, WILL, OPT_SGA])?;
+
+    let mut state = InputState::Data;
+    let mut buf = [0; 256];
+    let mut input = [0; 256];
+    loop {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        let n = filter_input(&mut state, &buf[..n], &mut input);
+        if n > 0 {
+            api::ax_console_session_write(session, &input[..n])?;
+        }
+    }
+}
+
+fn remote_shell(stream: TcpStream) -> io::Result<()> {
+    let session = Arc::new(api::ax_console_attach()?);
+    let stream = Arc::new(stream);
+
+    let output = {
+        let session = session.clone();
+        let stream = stream.clone();
+        thread::spawn(move || {
+            let res = output_loop(&session, &stream);
+            // Also stop the input loop if the client cannot be written.
+            api::ax_console_session_close(&session);
+            stream.shutdown().ok();
+            res
+        })
+    };
+    let res = input_loop(&session, &stream);
+    api::ax_console_session_close(&session);
+    output.join().ok();
+    res
+}
+
+fn accept_loop() -> io::Result<()> {
+    let listener = TcpListener::bind((LOCAL_IP, LOCAL_PORT))?;
+    println!("remote shell on: {}", listener.local_addr().unwrap());
+
+    loop {
+        let (stream, addr) = listener.accept()?;
+        thread::spawn(move || {
+            if let Err(e) = remote_shell(stream) {
+                println!("remote shell {}: {:?}", addr, e);
+            }
+        });
+    }
+}
+
+/// Starts the remote shell server in the background.
+pub fn spawn_server() {
+    thread::spawn(|| {
+        if let Err(e) = accept_loop() {
+            println!("remote shell: {:?}", e);
+        }
+    });
+}
@@ -0,0 +1,508 @@
+//! Buddy system page allocator.
+//!
+//! The managed region is split into blocks of `2^order` pages, whose
+//! addresses are aligned to their sizes. Free blocks of each order are kept in
+//! intrusive doubly linked lists stored in the free pages themselves. When a
+//! block is freed, it is merged with its buddy if the buddy is also free, so
+//! large contiguous runs can be rebuilt after being split.
+//!
+//! A bitmap at the beginning of the region records which pages are the heads
+//! of free blocks, so the allocator does not need any memory beyond the
+//! region it manages.
+
+use allocator::{AllocError, AllocResult, BaseAllocator, PageAllocator};
+use memory_addr::{align_down, align_up, is_aligned};
+
+/// The maximum order of blocks. Blocks have at most `2^MAX_ORDER` pages.
+pub const MAX_ORDER: usize = 12;
+
+/// Number of different block orders.
+pub const NUM_ORDERS: usize = MAX_ORDER + 1;
+
+/// The header stored at the beginning of each free block.
+struct FreeNode {
+    prev: usize,
+    next: usize,
+    order: usize,
+}
+
+/// Fragmentation statistics of a [`BuddyPageAllocator`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FragmentationStats {
+    /// Number of free blocks of each order.
+    pub free_blocks: [usize; NUM_ORDERS],
+    /// Total number of free pages.
+    pub free_pages: usize,
+}
+
+impl FragmentationStats {
+    /// Returns the order of the largest free block, or `None` if there is no
+    /// free page.
+    pub fn largest_free_order(&self) -> Option<usize> {
+        self.free_blocks.iter().rposition(|&n| n > 0)
+    }
+
+    /// Returns the number of free pages that can satisfy an allocation of
+    /// the given order, i.e., the pages in free blocks of `order` or larger.
+    pub fn usable_pages(&self, order: usize) -> usize {
+        self.free_blocks
+            .iter()
+            .enumerate()
+            .skip(order)
+            .map(|(o, &n)| n << o)
+            .sum()
+    }
+
+    /// Returns the per mille of free pages that can not be used for an
+    /// allocation of the given order (the "unusable free space index").
+    ///
+    /// 0 means no fragmentation for this order, 1000 means that no free block
+    /// is large enough.
+    pub fn unusable_index(&self, order: usize) -> usize {
+        if self.free_pages == 0 {
+            return 0;
+        }
+        (self.free_pages - self.usable_pages(order)) * 1000 / self.free_pages
+    }
+}
+
+/// A page allocator using the buddy system.
+///
+/// In addition to the [`PageAllocator`] interface, it provides order-based
+/// allocation ([`alloc_order`]) and [fragmentation statistics].
+///
+/// Allocations of a number of pages that is not a power of 2 take the
+/// smallest block that fits, and give back the unused tail immediately.
+///
+/// [`alloc_order`]: BuddyPageAllocator::alloc_order
+/// [fragmentation statistics]: BuddyPageAllocator::fragmentation
+pub struct BuddyPageAllocator<const PAGE_SIZE: usize> {
+    /// Address of page 0, aligned to the size of the largest block.
+    base: usize,
+    /// Number of pages from `base` to the end of the region.
+    num_pages: usize,
+    /// Address of the bitmap of free block heads.
+    free_map: usize,
+    free_lists: [usize; NUM_ORDERS],
+    free_blocks: [usize; NUM_ORDERS],
+    total_pages: usize,
+    used_pages: usize,
+}
+
+impl<const PAGE_SIZE: usize> BuddyPageAllocator<PAGE_SIZE> {
+    /// Creates a new empty [`BuddyPageAllocator`].
+    pub const fn new() -> Self {
+        Self {
+            base: 0,
+            num_pages: 0,
+            free_map: 0,
+            free_lists: [0; NUM_ORDERS],
+            free_blocks: [0; NUM_ORDERS],
+            total_pages: 0,
+            used_pages: 0,
+        }
+    }
+
+    /// Returns the smallest order whose blocks have at least `num_pages`
+    /// pages.
+    pub const fn order_of(num_pages: usize) -> usize {
+        num_pages.next_power_of_two().trailing_zeros() as usize
+    }
+
+    /// Allocates a block of `2^order` pages, aligned to its size.
+    pub fn alloc_order(&mut self, order: usize) -> AllocResult<usize> {
+        if order > MAX_ORDER {
+            return Err(AllocError::InvalidParam);
+        }
+        let idx = self.alloc_block(order)?;
+        self.used_pages += 1 << order;
+        Ok(self.page_addr(idx))
+    }
+
+    /// Gives back a block allocated by [`alloc_order`] with the same
+    /// `order`.
+    ///
+    /// [`alloc_order`]: BuddyPageAllocator::alloc_order
+    pub fn dealloc_order(&mut self, pos: usize, order: usize) {
+        assert!(order <= MAX_ORDER);
+        assert!(
+            is_aligned(pos, PAGE_SIZE << order),
+            "dealloc_order: unaligned block {:#x} of order {}",
+            pos,
+            order
+        );
+        self.free_block(self.page_index(pos), order);
+        self.used_pages -= 1 << order;
+    }
+
+    /// Returns the fragmentation statistics.
+    pub fn fragmentation(&self) -> FragmentationStats {
+        FragmentationStats {
+            free_blocks: self.free_blocks,
+            free_pages: self.total_pages - self.used_pages,
+        }
+    }
+
+    fn page_addr(&self, idx: usize) -> usize {
+        self.base + idx * PAGE_SIZE
+    }
+
+    fn page_index(&self, pos: usize) -> usize {
+        assert!(
+            pos >= self.base && is_aligned(pos, PAGE_SIZE),
+            "invalid page address {:#x}",
+            pos
+        );
+        let idx = (pos - self.base) / PAGE_SIZE;
+        assert!(idx < self.num_pages, "invalid page address {:#x}", pos);
+        idx
+    }
+
+    fn node(&self, idx: usize) -> &'static mut FreeNode {
+        // Safety: only the head pages of free blocks are accessed, which are
+        // owned by the allocator.
+        unsafe { &mut *(self.page_addr(idx) as *mut FreeNode) }
+    }
+
+    fn is_free_head(&self, idx: usize) -> bool {
+        let byte = unsafe { *((self.free_map + idx / 8) as *const u8) };
+        byte & (1 << (idx % 8)) != 0
+    }
+
+    fn set_free_head(&mut self, idx: usize, free: bool) {
+        let byte = unsafe { &mut *((self.free_map + idx / 8) as *mut u8) };
+        if free {
+            *byte |= 1 << (idx % 8);
+        } else {
+            *byte &= !(1 << (idx % 8));
+        }
+    }
+
+    /// Pushes a free block to the list of its order. The list entries are
+    /// page indices plus one, so that 0 means the end of the list.
+    fn push_free(&mut self, idx: usize, order: usize) {
+        let head = self.free_lists[order];
+        *self.node(idx) = FreeNode {
+            prev: 0,
+            next: head,
+            order,
+        };
+        if head != 0 {
+            self.node(head - 1).prev = idx + 1;
+        }
+        self.free_lists[order] = idx + 1;
+        self.free_blocks[order] += 1;
+        self.set_free_head(idx, true);
+    }
+
+    /// Removes a free block from the list of its order.
+    fn remove_free(&mut self, idx: usize, order: usize) {
+        let (prev, next) = {
+            let node = self.node(idx);
+            (node.prev, node.next)
+        };
+        if prev != 0 {
+            self.node(prev - 1).next = next;
+        } else {
+            self.free_lists[order] = next;
+        }
+        if next != 0 {
+            self.node(next - 1).prev = prev;
+        }
+        self.free_blocks[order] -= 1;
+        self.set_free_head(idx, false);
+    }
+
+    fn alloc_block(&mut self, order: usize) -> AllocResult<usize> {
+        let mut cur = (order..NUM_ORDERS)
+            .find(|&o| self.free_lists[o] != 0)
+            .ok_or(AllocError::NoMemory)?;
+        let idx = self.free_lists[cur] - 1;
+        self.remove_free(idx, cur);
+        // Split the block, keeping the lower half.
+        while cur > order {
+            cur -= 1;
+            self.push_free(idx + (1 << cur), cur);
+        }
+        Ok(idx)
+    }
+
+    fn free_block(&mut self, mut idx: usize, mut order: usize) {
+        while order < MAX_ORDER {
+            let buddy = idx ^ (1 << order);
+            if buddy >= self.num_pages
+                || !self.is_free_head(buddy)
+                || self.node(buddy).order != order
+            {
+                break;
+            }
+            self.remove_free(buddy, order);
+            idx = idx.min(buddy);
+            order += 1;
+        }
+        self.push_free(idx, order);
+    }
+
+    /// Frees the pages `[idx, idx + num_pages)`, split into the largest
+    /// aligned blocks.
+    fn free_range(&mut self, mut idx: usize, mut num_pages: usize) {
+        while num_pages > 0 {
+            let order = (idx.trailing_zeros() as usize)
+                .min(usize::BITS as usize - 1 - num_pages.leading_zeros() as usize)
+                .min(MAX_ORDER);
+            self.free_block(idx, order);
+            idx += 1 << order;
+            num_pages -= 1 << order;
+        }
+    }
+}
+
+impl<const PAGE_SIZE: usize> BaseAllocator for BuddyPageAllocator<PAGE_SIZE> {
+    fn init(&mut self, start: usize, size: usize) {
+        let start = align_up(start, PAGE_SIZE);
+        let end = align_down(start + size, PAGE_SIZE);
+        self.base = align_down(start, PAGE_SIZE << MAX_ORDER);
+        self.num_pages = (end - self.base) / PAGE_SIZE;
+
+        // Place the bitmap of free block heads at the beginning of the region.
+        let map_size = align_up(self.num_pages.div_ceil(8), PAGE_SIZE);
+        assert!(start + map_size < end, "region too small for the bitmap");
+        self.free_map = start;
+        unsafe { core::ptr::write_bytes(start as *mut u8, 0, map_size) };
+
+        let first = (start + map_size - self.base) / PAGE_SIZE;
+        self.total_pages = self.num_pages - first;
+        self.free_range(first, self.total_pages);
+    }
+
+    fn add_memory(&mut self, _start: usize, _size: usize) -> AllocResult {
+        Err(AllocError::NoMemory) // unsupported
+    }
+}
+
+impl<const PAGE_SIZE: usize> PageAllocator for BuddyPageAllocator<PAGE_SIZE> {
+    const PAGE_SIZE: usize = PAGE_SIZE;
+
+    fn alloc_pages(&mut self, num_pages: usize, align_pow2: usize) -> AllocResult<usize> {
+        if num_pages == 0 || !align_pow2.is_power_of_two() || !is_aligned(align_pow2, PAGE_SIZE) {
+            return Err(AllocError::InvalidParam);
+        }
+        let align_order = (align_pow2 / PAGE_SIZE).trailing_zeros() as usize;
+        let order = Self::order_of(num_pages).max(align_order);
+        if order > MAX_ORDER {
+            return Err(AllocError::NoMemory);
+        }
+        let idx = self.alloc_block(order)?;
+        // Give back the unused tail.
+        self.free_range(idx + num_pages, (1 << order) - num_pages);
+        self.used_pages += num_pages;
+        Ok(self.page_addr(idx))
+    }
+
+    fn dealloc_pages(&mut self, pos: usize, num_pages: usize) {
+        self.free_range(self.page_index(pos), num_pages);
+        self.used_pages -= num_pages;
+    }
+
+    fn total_pages(&self) -> usize {
+        self.total_pages
+    }
+
+    fn used_pages(&self) -> usize {
+        self.used_pages
+    }
+
+    fn available_pages(&self) -> usize {
+        self.total_pages - self.used_pages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::alloc::{alloc, dealloc, Layout};
+
+    const PAGE_SIZE: usize = 0x100;
+    /// Size of the largest block.
+    const MAX_BLOCK_SIZE: usize = PAGE_SIZE << MAX_ORDER;
+
+    type Buddy = BuddyPageAllocator<PAGE_SIZE>;
+
+    /// A region of two maximum blocks, aligned to the largest block so that
+    /// its page 0 is the start of the region.
+    struct Region {
+        start: usize,
+        layout: Layout,
+    }
+
+    impl Region {
+        fn new() -> Self {
+            let layout = Layout::from_size_align(2 * MAX_BLOCK_SIZE, MAX_BLOCK_SIZE).unwrap();
+            let start = unsafe { alloc(layout) } as usize;
+            assert_ne!(start, 0);
+            Self { start, layout }
+        }
+
+        fn allocator(&self) -> Buddy {
+            let mut buddy = Buddy::new();
+            buddy.init(self.start, self.layout.size());
+            buddy
+        }
+    }
+
+    impl Drop for Region {
+        fn drop(&mut self) {
+            unsafe { dealloc(self.start as *mut u8, self.layout) };
+        }
+    }
+
+    /// Number of pages taken by the bitmap of the test region.
+    const MAP_PAGES: usize = (2 << MAX_ORDER) / 8 / PAGE_SIZE;
+
+    #[test]
+    fn test_init() {
+        let region = Region::new();
+        let buddy = region.allocator();
+        let total = (2 << MAX_ORDER) - MAP_PAGES;
+        assert_eq!(buddy.total_pages(), total);
+        assert_eq!(buddy.available_pages(), total);
+
+        let stats = buddy.fragmentation();
+        assert_eq!(stats.free_pages, total);
+        // The pages after the bitmap are split into the largest aligned
+        // blocks: one block of each order from log2(MAP_PAGES) to MAX_ORDER.
+        for order in 0..NUM_ORDERS {
+            let expected = (order >= MAP_PAGES.trailing_zeros() as usize) as usize;
+            assert_eq!(stats.free_blocks[order], expected, "order {}", order);
+        }
+        assert_eq!(stats.largest_free_order(), Some(MAX_ORDER));
+    }
+
+    #[test]
+    fn test_alloc_order_alignment() {
+        let region = Region::new();
+        let mut buddy = region.allocator();
+        for order in [0, 1, 3, 5, 8] {
+            let pos = buddy.alloc_order(order).unwrap();
+            assert_eq!(pos % (PAGE_SIZE << order), 0, "order {}", order);
+            assert!(pos >= region.start + MAP_PAGES * PAGE_SIZE);
+            assert!(pos + (PAGE_SIZE << order) <= region.start + region.layout.size());
+        }
+        assert_eq!(
+            buddy.alloc_order(MAX_ORDER + 1),
+            Err(AllocError::InvalidParam)
+        );
+    }
+
+    #[test]
+    fn test_split_and_merge() {
+        let region = Region::new();
+        let mut buddy = region.allocator();
+        let initial = buddy.fragmentation();
+        let small = MAP_PAGES.trailing_zeros() as usize;
+
+        // The smallest free block is split, keeping the lower half.
+        let a = buddy.alloc_order(0).unwrap();
+        assert_eq!(a, region.start + MAP_PAGES * PAGE_SIZE);
+        let stats = buddy.fragmentation();
+        assert_eq!(stats.free_blocks[small], 0);
+        for order in 0..small {
+            assert_eq!(stats.free_blocks[order], 1, "order {}", order);
+        }
+
+        // The buddy of the first block is allocated next.
+        let b = buddy.alloc_order(0).unwrap();
+        assert_eq!(b, a + PAGE_SIZE);
+        assert_eq!(buddy.used_pages(), 2);
+
+        // Freeing both merges them back into the original block.
+        buddy.dealloc_order(a, 0);
+        assert_eq!(buddy.fragmentation().free_blocks[0], 1);
+        buddy.dealloc_order(b, 0);
+        assert_eq!(buddy.fragmentation().free_blocks, initial.free_blocks);
+        assert_eq!(buddy.used_pages(), 0);
+    }
+
+    #[test]
+    fn test_exhaustion() {
+        let region = Region::new();
+        let mut buddy = region.allocator();
+        let pos = buddy.alloc_order(MAX_ORDER).unwrap();
+        assert_eq!(pos, region.start + MAX_BLOCK_SIZE);
+        // The first maximum block is partly used by the bitmap.
+        assert_eq!(buddy.alloc_order(MAX_ORDER), Err(AllocError::NoMemory));
+        assert_eq!(
+            buddy.alloc_pages(1 << MAX_ORDER, PAGE_SIZE),
+            Err(AllocError::NoMemory)
+        );
+        buddy.dealloc_order(pos, MAX_ORDER);
+        assert_eq!(buddy.alloc_order(MAX_ORDER), Ok(pos));
+    }
+
+    #[test]
+    fn test_alloc_pages_tail() {
+        let region = Region::new();
+        let mut buddy = region.allocator();
+        let initial = buddy.fragmentation();
+
+        // 3 pages take a block of 4 pages, the last page is given back.
+        let pos = buddy.alloc_pages(3, PAGE_SIZE).unwrap();
+        assert_eq!(buddy.used_pages(), 3);
+        assert_eq!(buddy.fragmentation().free_pages, initial.free_pages - 3);
+        let tail = buddy.alloc_order(0).unwrap();
+        assert_eq!(tail, pos + 3 * PAGE_SIZE);
+        buddy.dealloc_order(tail, 0);
+
+        buddy.dealloc_pages(pos, 3);
+        assert_eq!(buddy.used_pages(), 0);
+        assert_eq!(buddy.fragmentation().free_blocks, initial.free_blocks);
+    }
+
+    #[test]
+    fn test_alloc_pages_alignment() {
+        let region = Region::new();
+        let mut buddy = region.allocator();
+        let initial = buddy.fragmentation();
+        let align = PAGE_SIZE << 6;
+        let pos = buddy.alloc_pages(1, align).unwrap();
+        assert_eq!(pos % align, 0);
+        assert_eq!(buddy.used_pages(), 1);
+        assert_eq!(
+            buddy.alloc_pages(1, PAGE_SIZE / 2),
+            Err(AllocError::InvalidParam)
+        );
+        assert_eq!(
+            buddy.alloc_pages(0, PAGE_SIZE),
+            Err(AllocError::InvalidParam)
+        );
+        buddy.dealloc_pages(pos, 1);
+        assert_eq!(buddy.fragmentation().free_blocks, initial.free_blocks);
+    }
+
+    #[test]
+    fn test_fragmentation_stats() {
+        let mut stats = FragmentationStats::default();
+        assert_eq!(stats.largest_free_order(), None);
+        assert_eq!(stats.unusable_index(0), 0);
+
+        stats.free_blocks[0] = 4;
+        stats.free_blocks[2] = 1;
+        stats.free_pages = 8;
+        assert_eq!(stats.largest_free_order(), Some(2));
+        assert_eq!(stats.usable_pages(0), 8);
+        assert_eq!(stats.usable_pages(1), 4);
+        assert_eq!(stats.usable_pages(3), 0);
+        assert_eq!(stats.unusable_index(0), 0);
+        assert_eq!(stats.unusable_index(1), 500);
+        assert_eq!(stats.unusable_index(3), 1000);
+    }
+
+    #[test]
+    fn test_order_of() {
+        assert_eq!(Buddy::order_of(1), 0);
+        assert_eq!(Buddy::order_of(2), 1);
+        assert_eq!(Buddy::order_of(3), 2);
+        assert_eq!(Buddy::order_of(4), 2);
+        assert_eq!(Buddy::order_of(1 << MAX_ORDER), MAX_ORDER);
+    }
+}
@@ -0,0 +1,313 @@
+//! Debug allocator for finding heap corruption bugs.
+//!
+//! [`DebugByteAllocator`] wraps the byte allocator selected by features:
+//!
+//! - Each block is surrounded by canary redzones, which are checked when the
+//!   block is freed to detect buffer overflows and underflows.
+//! - Newly allocated memory is filled with [`ALLOC_POISON`], to make reads of
+//!   uninitialized memory visible.
+//! - Freed memory is filled with [`FREE_POISON`] and kept in a quarantine for
+//!   a while before being reused. The poison is checked when the block leaves
+//!   the quarantine, to detect writes after free.
+//!
+//! With the `debug-guard` feature, every allocation is placed in its own pages
+//! instead, right before an inaccessible guard page, so that out-of-bounds
+//! accesses trap immediately. Freed pages are made inaccessible while they are
+//! in the quarantine. Changing the page permissions requires a hook from the
+//! memory management module (see [`set_guard_hook`]); without it, blocks are
+//! still laid out this way but not protected.
+
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+use allocator::{AllocResult, BaseAllocator, ByteAllocator};
+
+use crate::BackendByteAllocator;
+
+/// The byte that fills redzones.
+pub const REDZONE_BYTE: u8 = 0xfd;
+/// The byte that fills newly allocated memory.
+pub const ALLOC_POISON: u8 = 0xcd;
+/// The byte that fills freed memory.
+pub const FREE_POISON: u8 = 0xdd;
+
+/// Minimum size of the redzones before and after each block.
+const REDZONE_SIZE: usize = 16;
+
+/// Maximum number of blocks in the quarantine.
+const QUARANTINE_LEN: usize = 512;
+/// Maximum number of bytes in the quarantine.
+const QUARANTINE_BYTES: usize = 0x10_0000; // 1M
+
+/// A FIFO of freed blocks whose reuse is delayed.
+struct Quarantine<const N: usize> {
+    blocks: [Option<(usize, Layout)>; N],
+    head: usize,
+    len: usize,
+    bytes: usize,
+}
+
+impl<const N: usize> Quarantine<N> {
+    const fn new() -> Self {
+        Self {
+            blocks: [None; N],
+            head: 0,
+            len: 0,
+            bytes: 0,
+        }
+    }
+
+    fn push(&mut self, pos: usize, layout: Layout) {
+        let tail = (self.head + self.len) % N;
+        self.blocks[tail] = Some((pos, layout));
+        self.len += 1;
+        self.bytes += layout.size();
+    }
+
+    fn pop(&mut self) -> Option<(usize, Layout)> {
+        if self.len == 0 {
+            return None;
+        }
+        let block = self.blocks[self.head].take();
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        self.bytes -= block.map_or(0, |(_, l)| l.size());
+        block
+    }
+
+    fn is_full(&self, max_bytes: usize) -> bool {
+        self.len == N || self.bytes > max_bytes
+    }
+}
+
+fn fill(start: usize, len: usize, byte: u8) {
+    unsafe { core::ptr::write_bytes(start as *mut u8, byte, len) };
+}
+
+/// Returns the offset of the first byte in `[start, start + len)` that is
+/// not `byte`.
+fn check(start: usize, len: usize, byte: u8) -> Option<usize> {
+    let bytes = unsafe { core::slice::from_raw_parts(start as *const u8, len) };
+    bytes.iter().position(|&b| b != byte)
+}
+
+/// A byte allocator with redzones, poisoning and a quarantine, for debugging.
+///
+/// See the [module-level documentation](self) for details. The redzones and
+/// the blocks in the quarantine are counted as used bytes.
+pub struct DebugByteAllocator {
+    inner: BackendByteAllocator,
+    quarantine: Quarantine<QUARANTINE_LEN>,
+}
+
+impl DebugByteAllocator {
+    /// Creates an empty [`DebugByteAllocator`].
+    pub const fn new() -> Self {
+        Self {
+            inner: BackendByteAllocator::new(),
+            quarantine: Quarantine::new(),
+        }
+    }
+
+    fn front_size(layout: Layout) -> usize {
+        REDZONE_SIZE.max(layout.align())
+    }
+
+    fn outer_layout(layout: Layout) -> Layout {
+        let size = Self::front_size(layout) + layout.size() + REDZONE_SIZE;
+        Layout::from_size_align(size, layout.align()).unwrap()
+    }
+
+    /// Gives back the oldest block in the quarantine to the inner allocator,
+    /// after checking that it has not been written.
+    fn evict_one(&mut self) -> bool {
+        let Some((base, outer)) = self.quarantine.pop() else {
+            return false;
+        };
+        if let Some(off) = check(base, outer.size(), FREE_POISON) {
+            panic!(
+                "heap corruption: write after free at {:#x} (block [{:#x}, {:#x}))",
+                base + off,
+                base,
+                base + outer.size()
+            );
+        }
+        self.inner
+            .dealloc(unsafe { NonNull::new_unchecked(base as *mut u8) }, outer);
+        true
+    }
+}
+
+impl BaseAllocator for DebugByteAllocator {
+    fn init(&mut self, start: usize, size: usize) {
+        self.inner.init(start, size);
+    }
+
+    fn add_memory(&mut self, start: usize, size: usize) -> AllocResult {
+        self.inner.add_memory(start, size)
+    }
+}
+
+impl ByteAllocator for DebugByteAllocator {
+    fn alloc(&mut self, layout: Layout) -> AllocResult<NonNull<u8>> {
+        let outer = Self::outer_layout(layout);
+        let base = loop {
+            match self.inner.alloc(outer) {
+                Ok(ptr) => break ptr.as_ptr() as usize,
+                // Release the quarantined blocks before reporting OOM.
+                Err(e) => {
+                    if !self.evict_one() {
+                        return Err(e);
+                    }
+                }
+            }
+        };
+        let front = Self::front_size(layout);
+        fill(base, front, REDZONE_BYTE);
+        fill(base + front, layout.size(), ALLOC_POISON);
+        fill(base + front + layout.size(), REDZONE_SIZE, REDZONE_BYTE);
+        Ok(unsafe { NonNull::new_unchecked((base + front) as *mut u8) })
+    }
+
+    fn dealloc(&mut self, pos: NonNull<u8>, layout: Layout) {
+        let outer = Self::outer_layout(layout);
+        let front = Self::front_size(layout);
+        let ptr = pos.as_ptr() as usize;
+        let base = ptr - front;
+        if let Some(off) = check(base, front, REDZONE_BYTE) {
+            panic!(
+                "heap corruption: buffer underflow at {:#x} (block {:#x}, {:?})",
+                base + off,
+                ptr,
+                layout
+            );
+        }
+        if let Some(off) = check(ptr + layout.size(), REDZONE_SIZE, REDZONE_BYTE) {
+            panic!(
+                "heap corruption: buffer overflow at {:#x} (block {:#x}, {:?})",
+                ptr + layout.size() + off,
+                ptr,
+                layout
+            );
+        }
+        fill(base, outer.size(), FREE_POISON);
+        while self.quarantine.is_full(QUARANTINE_BYTES) {
+            self.evict_one();
+        }
+        self.quarantine.push(base, outer);
+    }
+
+    fn total_bytes(&self) -> usize {
+        self.inner.total_bytes()
+    }
+
+    fn used_bytes(&self) -> usize {
+        self.inner.used_bytes()
+    }
+
+    fn available_bytes(&self) -> usize {
+        self.inner.available_bytes()
+    }
+}
+
+#[cfg(feature = "debug-guard")]
+mod guard {
+    use core::alloc::Layout;
+    use core::ptr::NonNull;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use allocator::{AllocError, AllocResult};
+    use kspin::SpinNoIrq;
+    use memory_addr::{align_down, align_up};
+
+    use super::Quarantine;
+    use crate::{GlobalAllocator, PAGE_SIZE};
+
+    /// Maximum number of freed blocks whose pages are kept inaccessible.
+    const GUARD_QUARANTINE_LEN: usize = 256;
+
+    /// The hook to change page permissions, see [`set_guard_hook`].
+    static GUARD_HOOK: AtomicUsize = AtomicUsize::new(0);
+
+    static GUARD_QUARANTINE: SpinNoIrq<Quarantine<GUARD_QUARANTINE_LEN>> =
+        SpinNoIrq::new(Quarantine::new());
+
+    /// Sets the hook to change the access permission of kernel pages.
+    ///
+    /// The hook is called as `hook(vaddr, num_pages, accessible)`, and should
+    /// return `false` if the permission can not be changed, in which case the
+    /// allocation fails. It must not allocate memory or sleep.
+    pub fn set_guard_hook(hook: fn(usize, usize, bool) -> bool) {
+        GUARD_HOOK.store(hook as usize, Ordering::Release);
+    }
+
+    fn set_accessible(vaddr: usize, num_pages: usize, accessible: bool) -> bool {
+        let hook = GUARD_HOOK.load(Ordering::Acquire);
+        if hook == 0 {
+            return false;
+        }
+        let hook: fn(usize, usize, bool) -> bool = unsafe { core::mem::transmute(hook) };
+        hook(vaddr, num_pages, accessible)
+    }
+
+    fn data_pages(layout: Layout) -> usize {
+        align_up(layout.size().max(1), PAGE_SIZE) / PAGE_SIZE
+    }
+
+    /// Allocates a block in its own pages, right before a guard page.
+    ///
+    /// Fails if the guard page can not be made inaccessible.
+    pub(crate) fn alloc(ga: &GlobalAllocator, layout: Layout) -> AllocResult<NonNull<u8>> {
+        let num_pages = data_pages(layout);
+        let start = ga.alloc_pages_raw(num_pages + 1, layout.align().max(PAGE_SIZE))?;
+        let guard = start + num_pages * PAGE_SIZE;
+        if !set_accessible(guard, 1, false) && GUARD_HOOK.load(Ordering::Acquire) != 0 {
+            // Fail rather than handing out a block without its guard page.
+            ga.dealloc_pages(start, num_pages + 1);
+            return Err(AllocError::NoMemory);
+        }
+        let pos = align_down(guard - layout.size().max(1), layout.align());
+        super::fill(pos, layout.size(), super::ALLOC_POISON);
+        Ok(unsafe { NonNull::new_unchecked(pos as *mut u8) })
+    }
+
+    /// Frees a block allocated by [`alloc`]. Its pages are made inaccessible
+    /// and put into the quarantine.
+    pub(crate) fn dealloc(ga: &GlobalAllocator, pos: NonNull<u8>, layout: Layout) {
+        let num_pages = data_pages(layout);
+        let guard = align_up(pos.as_ptr() as usize + layout.size().max(1), PAGE_SIZE);
+        let start = guard - num_pages * PAGE_SIZE;
+        set_accessible(start, num_pages, false);
+
+        // The layout is only used to remember the number of pages.
+        let pages_layout = Layout::from_size_align(num_pages + 1, 1).unwrap();
+        let evicted = {
+            let mut quarantine = GUARD_QUARANTINE.lock();
+            let evicted = if quarantine.is_full(usize::MAX) {
+                quarantine.pop()
+            } else {
+                None
+            };
+            quarantine.push(start, pages_layout);
+            evicted
+        };
+        if let Some((start, pages)) = evicted {
+            let num_pages = pages.size();
+            if set_accessible(start, num_pages, true) || GUARD_HOOK.load(Ordering::Acquire) == 0 {
+                ga.dealloc_pages(start, num_pages);
+            } else {
+                // Some of the pages may be still inaccessible, leak them
+                // rather than handing them out.
+                warn!(
+                    "debug allocator: failed to unprotect [{:#x}, {} pages), leaked",
+                    start, num_pages
+                );
+            }
+        }
+    }
+}
+
+#[cfg(feature = "debug-guard")]
+pub use self::guard::set_guard_hook;
+#[cfg(feature = "debug-guard")]
+pub(crate) use self::guard::{alloc as alloc_guarded, dealloc as dealloc_guarded};
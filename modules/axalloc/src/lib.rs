@@ -5,7 +5,7 @@
 //! [`GlobalAllocator`] is defined with the `#[global_allocator]` attribute, to
 //! be registered as the standard library’s default allocator.
 
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 #[macro_use]
 extern crate log;
@@ -13,26 +13,70 @@ extern crate alloc;
 
 mod page;
 
-use allocator::{AllocResult, BaseAllocator, BitmapPageAllocator, ByteAllocator, PageAllocator};
+pub mod oom;
+
+mod zone;
+
+#[cfg(feature = "page-buddy")]
+mod buddy;
+
+#[cfg(feature = "percpu-cache")]
+mod magazine;
+
+#[cfg(feature = "stats")]
+pub mod stats;
+
+#[cfg(feature = "debug")]
+pub mod debug;
+
+use allocator::{AllocResult, BaseAllocator, ByteAllocator, PageAllocator};
 use core::alloc::{GlobalAlloc, Layout};
 use core::ptr::NonNull;
 use kspin::SpinNoIrq;
+use zone::PagePools;
 
 const PAGE_SIZE: usize = 0x1000;
 const MIN_HEAP_SIZE: usize = 0x8000; // 32 K
 
+/// The default low watermark is 1/32 of all pages.
+const DEFAULT_LOW_WATERMARK_RATIO: usize = 32;
+
 pub use page::GlobalPage;
+pub use zone::{MemZone, PageHint, PoolStats};
+
+#[cfg(feature = "page-buddy")]
+pub use buddy::{BuddyPageAllocator, FragmentationStats, MAX_ORDER as MAX_PAGE_ORDER};
+
+#[cfg(feature = "percpu-cache")]
+pub use magazine::{SizeClassStats, NUM_SIZE_CLASSES};
 
 cfg_if::cfg_if! {
     if #[cfg(feature = "slab")] {
-        /// The default byte allocator.
-        pub type DefaultByteAllocator = allocator::SlabByteAllocator;
+        type BackendByteAllocator = allocator::SlabByteAllocator;
     } else if #[cfg(feature = "buddy")] {
-        /// The default byte allocator.
-        pub type DefaultByteAllocator = allocator::BuddyByteAllocator;
+        type BackendByteAllocator = allocator::BuddyByteAllocator;
     } else if #[cfg(feature = "tlsf")] {
+        type BackendByteAllocator = allocator::TlsfByteAllocator;
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "page-buddy")] {
+        /// The default page allocator.
+        pub type DefaultPageAllocator = BuddyPageAllocator<PAGE_SIZE>;
+    } else {
+        /// The default page allocator.
+        pub type DefaultPageAllocator = allocator::BitmapPageAllocator<PAGE_SIZE>;
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "debug")] {
+        /// The default byte allocator.
+        pub type DefaultByteAllocator = debug::DebugByteAllocator;
+    } else {
         /// The default byte allocator.
-        pub type DefaultByteAllocator = allocator::TlsfByteAllocator;
+        pub type DefaultByteAllocator = BackendByteAllocator;
     }
 }
 
@@ -44,12 +88,32 @@ cfg_if::cfg_if! {
 /// the byte allocator.
 ///
 /// Currently, [`TlsfByteAllocator`] is used as the byte allocator, while
-/// [`BitmapPageAllocator`] is used as the page allocator.
+/// [`BitmapPageAllocator`] is used as the page allocator. With the
+/// `page-buddy` feature, the page allocator is replaced by a buddy system
+/// allocator, which is better at allocating large or highly aligned runs of
+/// pages.
+///
+/// With the `percpu-cache` feature, small allocations are served by per-CPU
+/// magazine caches in front of the byte allocator, to reduce the contention
+/// on the byte allocator lock.
+///
+/// With the `zones` feature, pages are managed in multiple pools by memory
+/// zone and NUMA node, and can be allocated with a [`PageHint`].
+///
+/// When an allocation fails, the reclaim callbacks registered in [`oom`] are
+/// invoked before giving up, and the OOM policy decides what to do.
+///
+/// With the `debug` feature, the byte allocator is wrapped by a
+/// [`DebugByteAllocator`] to detect heap corruptions, and the per-CPU caches
+/// are bypassed.
+///
+/// [`DebugByteAllocator`]: debug::DebugByteAllocator
 ///
 /// [`TlsfByteAllocator`]: allocator::TlsfByteAllocator
+/// [`BitmapPageAllocator`]: allocator::BitmapPageAllocator
 pub struct GlobalAllocator {
     balloc: SpinNoIrq<DefaultByteAllocator>,
-    palloc: SpinNoIrq<BitmapPageAllocator<PAGE_SIZE>>,
+    pools: PagePools,
 }
 
 impl GlobalAllocator {
@@ -57,7 +121,7 @@ impl GlobalAllocator {
     pub const fn new() -> Self {
         Self {
             balloc: SpinNoIrq::new(DefaultByteAllocator::new()),
-            palloc: SpinNoIrq::new(BitmapPageAllocator::new()),
+            pools: PagePools::new(),
         }
     }
 
@@ -79,10 +143,23 @@ impl GlobalAllocator {
     /// It firstly adds the whole region to the page allocator, then allocates
     /// a small region (32 KB) to initialize the byte allocator. Therefore,
     /// the given region must be larger than 32 KB.
+    ///
+    /// The region is assumed to be normal memory on NUMA node 0.
     pub fn init(&self, start_vaddr: usize, size: usize) {
+        self.init_zone(start_vaddr, size, MemZone::Normal, 0)
+    }
+
+    /// Initializes the allocator with the given region, which is in the
+    /// given zone and NUMA node.
+    ///
+    /// See [`init`](GlobalAllocator::init) for details.
+    pub fn init_zone(&self, start_vaddr: usize, size: usize, zone: MemZone, node: usize) {
         assert!(size > MIN_HEAP_SIZE);
         let init_heap_size = MIN_HEAP_SIZE;
-        self.palloc.lock().init(start_vaddr, size);
+        self.pools.init(start_vaddr, size, zone, node);
+        if oom::low_watermark() == 0 {
+            oom::set_low_watermark(self.total_pages() / DEFAULT_LOW_WATERMARK_RATIO);
+        }
         let heap_ptr = self
             .alloc_pages(init_heap_size / PAGE_SIZE, PAGE_SIZE)
             .unwrap();
@@ -96,15 +173,68 @@ impl GlobalAllocator {
         self.balloc.lock().add_memory(start_vaddr, size)
     }
 
+    /// Adds the given region to the page allocator, as a new page pool in the
+    /// given zone and NUMA node.
+    ///
+    /// Returns [`AllocError::NoMemory`] if the `zones` feature is disabled or
+    /// there are too many pools, in which case the region can still be added
+    /// to the byte allocator by [`add_memory`].
+    ///
+    /// [`AllocError::NoMemory`]: allocator::AllocError::NoMemory
+    /// [`add_memory`]: GlobalAllocator::add_memory
+    pub fn add_pages(
+        &self,
+        start_vaddr: usize,
+        size: usize,
+        zone: MemZone,
+        node: usize,
+    ) -> AllocResult {
+        self.pools.add(start_vaddr, size, zone, node)
+    }
+
     /// Allocate arbitrary number of bytes. Returns the left bound of the
     /// allocated region.
     ///
     /// It firstly tries to allocate from the byte allocator. If there is no
     /// memory, it asks the page allocator for more memory and adds it to the
-    /// byte allocator.
+    /// byte allocator. If it still fails, the reclaim callbacks are invoked
+    /// and the allocation is retried (see [`oom`]).
     pub fn alloc(&self, layout: Layout) -> AllocResult<NonNull<u8>> {
+        oom::alloc_with_reclaim(layout.size(), || self.try_alloc(layout))
+    }
+
+    fn try_alloc(&self, layout: Layout) -> AllocResult<NonNull<u8>> {
+        #[cfg(feature = "stats")]
+        {
+            let ptr = self.alloc_inner(stats::outer_layout(layout))?;
+            Ok(stats::on_alloc(ptr, layout))
+        }
+        #[cfg(not(feature = "stats"))]
+        self.alloc_inner(layout)
+    }
+
+    fn alloc_inner(&self, layout: Layout) -> AllocResult<NonNull<u8>> {
+        #[cfg(feature = "debug-guard")]
+        {
+            debug::alloc_guarded(self, layout)
+        }
+        #[cfg(not(feature = "debug-guard"))]
+        {
+            // Bypass the caches in debug mode, so that freed objects are checked.
+            #[cfg(all(feature = "percpu-cache", not(feature = "debug")))]
+            if let Some(class) = magazine::size_class(layout) {
+                return magazine::alloc(self, class);
+            }
+            self.alloc_locked(&mut self.balloc.lock(), layout)
+        }
+    }
+
+    fn alloc_locked(
+        &self,
+        balloc: &mut DefaultByteAllocator,
+        layout: Layout,
+    ) -> AllocResult<NonNull<u8>> {
         // simple two-level allocator: if no heap memory, allocate from the page allocator.
-        let mut balloc = self.balloc.lock();
         loop {
             if let Ok(ptr) = balloc.alloc(layout) {
                 return Ok(ptr);
@@ -114,7 +244,7 @@ impl GlobalAllocator {
                     .max(layout.size())
                     .next_power_of_two()
                     .max(PAGE_SIZE);
-                let heap_ptr = self.alloc_pages(expand_size / PAGE_SIZE, PAGE_SIZE)?;
+                let heap_ptr = self.alloc_pages_raw(expand_size / PAGE_SIZE, PAGE_SIZE)?;
                 debug!(
                     "expand heap memory: [{:#x}, {:#x})",
                     heap_ptr,
@@ -133,7 +263,66 @@ impl GlobalAllocator {
     ///
     /// [`alloc`]: GlobalAllocator::alloc
     pub fn dealloc(&self, pos: NonNull<u8>, layout: Layout) {
-        self.balloc.lock().dealloc(pos, layout)
+        #[cfg(feature = "stats")]
+        let (pos, layout) = (stats::on_dealloc(pos, layout), stats::outer_layout(layout));
+        self.dealloc_inner(pos, layout)
+    }
+
+    fn dealloc_inner(&self, pos: NonNull<u8>, layout: Layout) {
+        #[cfg(feature = "debug-guard")]
+        {
+            debug::dealloc_guarded(self, pos, layout)
+        }
+        #[cfg(not(feature = "debug-guard"))]
+        {
+            #[cfg(all(feature = "percpu-cache", not(feature = "debug")))]
+            if let Some(class) = magazine::size_class(layout) {
+                return magazine::dealloc(self, pos, class);
+            }
+            self.balloc.lock().dealloc(pos, layout)
+        }
+    }
+
+    /// Allocates objects with the same layout to fill `objs`, taking the lock
+    /// only once.
+    ///
+    /// Returns the number of allocated objects, which may be less than the
+    /// length of `objs` if memory runs out. Returns an error if no object can
+    /// be allocated.
+    #[cfg(feature = "percpu-cache")]
+    fn alloc_batch(&self, layout: Layout, objs: &mut [usize]) -> AllocResult<usize> {
+        let mut balloc = self.balloc.lock();
+        for (i, obj) in objs.iter_mut().enumerate() {
+            match self.alloc_locked(&mut balloc, layout) {
+                Ok(ptr) => *obj = ptr.as_ptr() as usize,
+                Err(e) if i == 0 => return Err(e),
+                Err(_) => return Ok(i),
+            }
+        }
+        Ok(objs.len())
+    }
+
+    /// Gives back objects with the same layout, taking the lock only once.
+    #[cfg(feature = "percpu-cache")]
+    fn dealloc_batch(&self, layout: Layout, objs: &[usize]) {
+        let mut balloc = self.balloc.lock();
+        for &obj in objs {
+            balloc.dealloc(unsafe { NonNull::new_unchecked(obj as *mut u8) }, layout);
+        }
+    }
+
+    /// Gives back all objects cached by the current CPU, and the ones in the
+    /// depots shared by all CPUs, to the byte allocator.
+    #[cfg(feature = "percpu-cache")]
+    pub fn flush_cpu_cache(&self) {
+        magazine::flush(self)
+    }
+
+    /// Returns the statistics of the size classes of the per-CPU caches,
+    /// accumulated over all CPUs.
+    #[cfg(feature = "percpu-cache")]
+    pub fn size_class_stats(&self) -> [SizeClassStats; NUM_SIZE_CLASSES] {
+        magazine::stats()
     }
 
     /// Allocates contiguous pages.
@@ -142,8 +331,40 @@ impl GlobalAllocator {
     ///
     /// `align_pow2` must be a power of 2, and the returned region bound will be
     /// aligned to it.
+    ///
+    /// If it fails, the reclaim callbacks are invoked and the allocation is
+    /// retried (see [`oom`]).
     pub fn alloc_pages(&self, num_pages: usize, align_pow2: usize) -> AllocResult<usize> {
-        self.palloc.lock().alloc_pages(num_pages, align_pow2)
+        self.alloc_pages_hint(num_pages, align_pow2, PageHint::ANY)
+    }
+
+    /// Allocates contiguous pages from the zone and NUMA node given by
+    /// `hint`.
+    ///
+    /// See [`alloc_pages`](GlobalAllocator::alloc_pages) for details.
+    pub fn alloc_pages_hint(
+        &self,
+        num_pages: usize,
+        align_pow2: usize,
+        hint: PageHint,
+    ) -> AllocResult<usize> {
+        oom::alloc_with_reclaim(num_pages * PAGE_SIZE, || {
+            let res = self
+                .pools
+                .alloc(hint, |palloc| palloc.alloc_pages(num_pages, align_pow2));
+            oom::update_pressure(self.available_pages());
+            res
+        })
+    }
+
+    /// Allocates contiguous pages without invoking the reclaim callbacks, as
+    /// the byte allocator lock may be held.
+    fn alloc_pages_raw(&self, num_pages: usize, align_pow2: usize) -> AllocResult<usize> {
+        let res = self.pools.alloc(PageHint::ANY, |palloc| {
+            palloc.alloc_pages(num_pages, align_pow2)
+        });
+        oom::update_pressure(self.available_pages());
+        res
     }
 
     /// Gives back the allocated pages starts from `pos` to the page allocator.
@@ -154,7 +375,51 @@ impl GlobalAllocator {
     ///
     /// [`alloc_pages`]: GlobalAllocator::alloc_pages
     pub fn dealloc_pages(&self, pos: usize, num_pages: usize) {
-        self.palloc.lock().dealloc_pages(pos, num_pages)
+        self.pools
+            .with_pool_of(pos, |palloc| palloc.dealloc_pages(pos, num_pages));
+        oom::update_pressure(self.available_pages());
+    }
+
+    /// Allocates a block of `2^order` contiguous pages, aligned to its size,
+    /// from the zone and NUMA node given by `hint`.
+    #[cfg(feature = "page-buddy")]
+    pub fn alloc_pages_order(&self, order: usize, hint: PageHint) -> AllocResult<usize> {
+        oom::alloc_with_reclaim(PAGE_SIZE << order, || {
+            let res = self.pools.alloc(hint, |palloc| palloc.alloc_order(order));
+            oom::update_pressure(self.available_pages());
+            res
+        })
+    }
+
+    /// Gives back the block allocated by [`alloc_pages_order`] with the same
+    /// `order`.
+    ///
+    /// [`alloc_pages_order`]: GlobalAllocator::alloc_pages_order
+    #[cfg(feature = "page-buddy")]
+    pub fn dealloc_pages_order(&self, pos: usize, order: usize) {
+        self.pools
+            .with_pool_of(pos, |palloc| palloc.dealloc_order(pos, order));
+        oom::update_pressure(self.available_pages());
+    }
+
+    /// Returns the fragmentation statistics of the page allocator, summed
+    /// over all page pools.
+    #[cfg(feature = "page-buddy")]
+    pub fn page_fragmentation(&self) -> FragmentationStats {
+        let mut stats = FragmentationStats::default();
+        self.pools.for_each_allocator(|palloc| {
+            let s = palloc.fragmentation();
+            for (n, m) in stats.free_blocks.iter_mut().zip(s.free_blocks.iter()) {
+                *n += m;
+            }
+            stats.free_pages += s.free_pages;
+        });
+        stats
+    }
+
+    /// Calls `f` with the statistics of each page pool.
+    pub fn for_each_page_pool(&self, f: impl FnMut(PoolStats)) {
+        self.pools.for_each(f)
     }
 
     /// Returns the number of allocated bytes in the byte allocator.
@@ -167,14 +432,28 @@ impl GlobalAllocator {
         self.balloc.lock().available_bytes()
     }
 
+    /// Returns the number of pages in the page allocator.
+    pub fn total_pages(&self) -> usize {
+        let mut total = 0;
+        self.pools
+            .for_each_allocator(|palloc| total += palloc.total_pages());
+        total
+    }
+
     /// Returns the number of allocated pages in the page allocator.
     pub fn used_pages(&self) -> usize {
-        self.palloc.lock().used_pages()
+        let mut used = 0;
+        self.pools
+            .for_each_allocator(|palloc| used += palloc.used_pages());
+        used
     }
 
     /// Returns the number of available pages in the page allocator.
     pub fn available_pages(&self) -> usize {
-        self.palloc.lock().available_pages()
+        let mut available = 0;
+        self.pools
+            .for_each_allocator(|palloc| available += palloc.available_pages());
+        available
     }
 }
 
@@ -231,3 +510,39 @@ pub fn global_add_memory(start_vaddr: usize, size: usize) -> AllocResult {
     );
     GLOBAL_ALLOCATOR.add_memory(start_vaddr, size)
 }
+
+/// Initializes the global allocator with the given memory region, which is in
+/// the given zone and NUMA node.
+///
+/// It's similar to [`global_init`].
+pub fn global_init_zone(start_vaddr: usize, size: usize, zone: MemZone, node: usize) {
+    debug!(
+        "initialize global allocator at: [{:#x}, {:#x}) ({:?}, node {})",
+        start_vaddr,
+        start_vaddr + size,
+        zone,
+        node
+    );
+    GLOBAL_ALLOCATOR.init_zone(start_vaddr, size, zone, node);
+}
+
+/// Add the given memory region to the global allocator as a new page pool in
+/// the given zone and NUMA node.
+///
+/// Returns an error if the `zones` feature is disabled or there are too many
+/// pools, see [`GlobalAllocator::add_pages`].
+pub fn global_add_pages(
+    start_vaddr: usize,
+    size: usize,
+    zone: MemZone,
+    node: usize,
+) -> AllocResult {
+    debug!(
+        "add a page pool to global allocator: [{:#x}, {:#x}) ({:?}, node {})",
+        start_vaddr,
+        start_vaddr + size,
+        zone,
+        node
+    );
+    GLOBAL_ALLOCATOR.add_pages(start_vaddr, size, zone, node)
+}
@@ -0,0 +1,570 @@
+//! Per-CPU magazine caches for small allocations.
+//!
+//! Each CPU has a magazine (a small stack of free objects) for every size
+//! class. Allocations and deallocations of small objects are served by the
+//! magazine of the current CPU without taking the global lock. When a
+//! magazine becomes empty (or full), half of it is refilled from (or flushed
+//! to) the depot of the size class in a batch.
+//!
+//! The depot keeps a few full batches shared by all CPUs, so that objects
+//! freed on a CPU can be reused by another without going through the backend
+//! byte allocator. Only when the depot is empty (or full) is the batch
+//! allocated from (or freed to) the backend, with the global lock taken only
+//! once.
+//!
+//! Objects in the magazines and the depots are counted as used in the backend
+//! allocator.
+
+use core::alloc::Layout;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+use allocator::AllocResult;
+use kernel_guard::NoPreemptIrqSave;
+use kspin::SpinNoIrq;
+
+use crate::GlobalAllocator;
+
+/// Object sizes of all size classes.
+const SIZE_CLASSES: [usize; NUM_SIZE_CLASSES] = [16, 32, 64, 128, 256, 512, 1024, 2048];
+
+/// Number of size classes cached by the per-CPU magazines.
+pub const NUM_SIZE_CLASSES: usize = 8;
+
+/// Capacity of a magazine.
+const MAGAZINE_SIZE: usize = 32;
+
+/// Number of objects moved between a magazine and the depot at a time.
+const BATCH_SIZE: usize = MAGAZINE_SIZE / 2;
+
+/// Maximum number of full batches kept in the depot of a size class.
+const DEPOT_SIZE: usize = 8;
+
+const MAX_CPUS: usize = axconfig::SMP;
+
+/// Statistics of a size class, accumulated over all CPUs.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SizeClassStats {
+    /// Object size of the size class.
+    pub size: usize,
+    /// Number of allocations served by the magazines.
+    pub alloc_hits: usize,
+    /// Number of allocations that needed a refill from the backend.
+    pub alloc_misses: usize,
+    /// Number of deallocations put into the magazines.
+    pub free_hits: usize,
+    /// Number of deallocations that needed a flush to the backend.
+    pub free_misses: usize,
+    /// Number of free objects currently cached in the magazines and the
+    /// depot.
+    pub cached: usize,
+}
+
+/// Where the objects of a size class come from (and go back to) when both
+/// the magazine and the depot are empty (or full).
+trait Backend {
+    /// Allocates objects into `objs`, returns the number of objects
+    /// allocated (at least 1).
+    fn alloc_batch(&self, objs: &mut [usize]) -> AllocResult<usize>;
+
+    /// Frees the objects.
+    fn dealloc_batch(&self, objs: &[usize]);
+}
+
+/// The backend byte allocator, for a size class.
+struct ClassBackend<'a> {
+    ga: &'a GlobalAllocator,
+    class: usize,
+}
+
+struct Magazine {
+    objs: [usize; MAGAZINE_SIZE],
+    len: usize,
+}
+
+/// Full batches of free objects of a size class, shared by all CPUs.
+struct Depot {
+    batches: [[usize; BATCH_SIZE]; DEPOT_SIZE],
+    len: usize,
+}
+
+struct ClassCounters {
+    alloc_hits: AtomicUsize,
+    alloc_misses: AtomicUsize,
+    free_hits: AtomicUsize,
+    free_misses: AtomicUsize,
+    cached: AtomicUsize,
+}
+
+/// The caches of a CPU.
+///
+/// The counters are only updated by the owner CPU, but can be read by others.
+struct CpuCache {
+    registered: bool,
+    mags: [Magazine; NUM_SIZE_CLASSES],
+    counters: [ClassCounters; NUM_SIZE_CLASSES],
+}
+
+#[percpu::def_percpu]
+static CPU_CACHE: CpuCache = CpuCache::new();
+
+/// Caches of all CPUs that have been used, for collecting statistics.
+#[allow(clippy::declare_interior_mutable_const)]
+static ALL_CACHES: [AtomicPtr<CpuCache>; MAX_CPUS] = {
+    const NULL: AtomicPtr<CpuCache> = AtomicPtr::new(core::ptr::null_mut());
+    [NULL; MAX_CPUS]
+};
+static NUM_CACHES: AtomicUsize = AtomicUsize::new(0);
+
+#[allow(clippy::declare_interior_mutable_const)]
+static DEPOTS: [SpinNoIrq<Depot>; NUM_SIZE_CLASSES] = {
+    const DEPOT: SpinNoIrq<Depot> = SpinNoIrq::new(Depot::new());
+    [DEPOT; NUM_SIZE_CLASSES]
+};
+
+impl Backend for ClassBackend<'_> {
+    fn alloc_batch(&self, objs: &mut [usize]) -> AllocResult<usize> {
+        self.ga.alloc_batch(class_layout(self.class), objs)
+    }
+
+    fn dealloc_batch(&self, objs: &[usize]) {
+        self.ga.dealloc_batch(class_layout(self.class), objs)
+    }
+}
+
+impl Magazine {
+    const fn new() -> Self {
+        Self {
+            objs: [0; MAGAZINE_SIZE],
+            len: 0,
+        }
+    }
+
+    /// Takes an object. If the magazine is empty, it is refilled with a batch
+    /// from the depot, or from the backend if the depot is empty too.
+    ///
+    /// Returns the object, and whether the magazine has been refilled.
+    fn pop(
+        &mut self,
+        depot: &SpinNoIrq<Depot>,
+        backend: &impl Backend,
+    ) -> AllocResult<(usize, bool)> {
+        let refilled = self.len == 0;
+        if refilled {
+            let batch = &mut self.objs[..BATCH_SIZE];
+            self.len = if depot.lock().take(batch) {
+                BATCH_SIZE
+            } else {
+                backend.alloc_batch(batch)?
+            };
+        }
+        self.len -= 1;
+        Ok((self.objs[self.len], refilled))
+    }
+
+    /// Puts an object. If the magazine is full, its upper half is moved to
+    /// the depot first, or freed to the backend if the depot is full too.
+    ///
+    /// Returns whether the magazine has been flushed.
+    fn push(&mut self, obj: usize, depot: &SpinNoIrq<Depot>, backend: &impl Backend) -> bool {
+        let flushed = self.len == MAGAZINE_SIZE;
+        if flushed {
+            let batch = &self.objs[BATCH_SIZE..];
+            if !depot.lock().put(batch) {
+                backend.dealloc_batch(batch);
+            }
+            self.len = BATCH_SIZE;
+        }
+        self.objs[self.len] = obj;
+        self.len += 1;
+        flushed
+    }
+
+    /// Frees all objects to the backend.
+    fn drain(&mut self, backend: &impl Backend) {
+        if self.len > 0 {
+            backend.dealloc_batch(&self.objs[..self.len]);
+            self.len = 0;
+        }
+    }
+}
+
+impl Depot {
+    const fn new() -> Self {
+        Self {
+            batches: [[0; BATCH_SIZE]; DEPOT_SIZE],
+            len: 0,
+        }
+    }
+
+    /// Moves a full batch into `objs`, returns `false` if there is none.
+    fn take(&mut self, objs: &mut [usize]) -> bool {
+        if self.len == 0 {
+            return false;
+        }
+        self.len -= 1;
+        objs.copy_from_slice(&self.batches[self.len]);
+        true
+    }
+
+    /// Stores a full batch, returns `false` if the depot is full.
+    fn put(&mut self, objs: &[usize]) -> bool {
+        if self.len == DEPOT_SIZE {
+            return false;
+        }
+        self.batches[self.len].copy_from_slice(objs);
+        self.len += 1;
+        true
+    }
+
+    /// Frees all batches to the backend.
+    fn drain(&mut self, backend: &impl Backend) {
+        for batch in &self.batches[..self.len] {
+            backend.dealloc_batch(batch);
+        }
+        self.len = 0;
+    }
+}
+
+impl ClassCounters {
+    const fn new() -> Self {
+        Self {
+            alloc_hits: AtomicUsize::new(0),
+            alloc_misses: AtomicUsize::new(0),
+            free_hits: AtomicUsize::new(0),
+            free_misses: AtomicUsize::new(0),
+            cached: AtomicUsize::new(0),
+        }
+    }
+
+    fn inc(counter: &AtomicUsize) {
+        // Only the owner CPU writes, so no atomic read-modify-write is needed.
+        counter.store(counter.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+    }
+}
+
+impl CpuCache {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const fn new() -> Self {
+        const MAG: Magazine = Magazine::new();
+        const COUNTERS: ClassCounters = ClassCounters::new();
+        Self {
+            registered: false,
+            mags: [MAG; NUM_SIZE_CLASSES],
+            counters: [COUNTERS; NUM_SIZE_CLASSES],
+        }
+    }
+
+    fn register(&mut self) {
+        let idx = NUM_CACHES.fetch_add(1, Ordering::AcqRel);
+        if idx < MAX_CPUS {
+            ALL_CACHES[idx].store(self, Ordering::Release);
+        }
+        self.registered = true;
+    }
+}
+
+/// Runs `f` with the cache of the current CPU, with IRQs and preemption
+/// disabled.
+fn with_cpu_cache<R>(f: impl FnOnce(&mut CpuCache) -> R) -> R {
+    let _guard = NoPreemptIrqSave::new();
+    // Safety: IRQs and preemption are disabled, so the cache can not be
+    // accessed by others on this CPU.
+    let cache = unsafe { CPU_CACHE.current_ref_mut_raw() };
+    if !cache.registered {
+        cache.register();
+    }
+    f(cache)
+}
+
+/// Returns the size class index of the layout, or `None` if it is too large
+/// to be cached.
+pub(crate) fn size_class(layout: Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align());
+    SIZE_CLASSES.iter().position(|&s| size <= s)
+}
+
+/// The layout used to allocate objects of the size class from the backend.
+///
+/// Objects are aligned to their size, so they satisfy the alignment of all
+/// layouts mapped to the class.
+fn class_layout(class: usize) -> Layout {
+    let size = SIZE_CLASSES[class];
+    Layout::from_size_align(size, size).unwrap()
+}
+
+/// Allocates an object of the size class.
+pub(crate) fn alloc(ga: &GlobalAllocator, class: usize) -> AllocResult<NonNull<u8>> {
+    with_cpu_cache(|cache| {
+        let mag = &mut cache.mags[class];
+        let counters = &cache.counters[class];
+        let (obj, refilled) = mag.pop(&DEPOTS[class], &ClassBackend { ga, class })?;
+        ClassCounters::inc(if refilled {
+            &counters.alloc_misses
+        } else {
+            &counters.alloc_hits
+        });
+        counters.cached.store(mag.len, Ordering::Relaxed);
+        Ok(unsafe { NonNull::new_unchecked(obj as *mut u8) })
+    })
+}
+
+/// Gives back an object of the size class.
+pub(crate) fn dealloc(ga: &GlobalAllocator, pos: NonNull<u8>, class: usize) {
+    with_cpu_cache(|cache| {
+        let mag = &mut cache.mags[class];
+        let counters = &cache.counters[class];
+        let obj = pos.as_ptr() as usize;
+        let flushed = mag.push(obj, &DEPOTS[class], &ClassBackend { ga, class });
+        ClassCounters::inc(if flushed {
+            &counters.free_misses
+        } else {
+            &counters.free_hits
+        });
+        counters.cached.store(mag.len, Ordering::Relaxed);
+    })
+}
+
+/// Gives back all cached objects of the current CPU and the depots to the
+/// backend.
+pub(crate) fn flush(ga: &GlobalAllocator) {
+    with_cpu_cache(|cache| {
+        for (class, mag) in cache.mags.iter_mut().enumerate() {
+            let backend = ClassBackend { ga, class };
+            mag.drain(&backend);
+            cache.counters[class].cached.store(0, Ordering::Relaxed);
+            DEPOTS[class].lock().drain(&backend);
+        }
+    })
+}
+
+/// Collects the statistics of all size classes over all CPUs.
+pub(crate) fn stats() -> [SizeClassStats; NUM_SIZE_CLASSES] {
+    let mut stats = [SizeClassStats::default(); NUM_SIZE_CLASSES];
+    for (class, s) in stats.iter_mut().enumerate() {
+        s.size = SIZE_CLASSES[class];
+        s.cached = DEPOTS[class].lock().len * BATCH_SIZE;
+    }
+    let num_caches = NUM_CACHES.load(Ordering::Acquire).min(MAX_CPUS);
+    for cache in ALL_CACHES[..num_caches].iter() {
+        let cache = cache.load(Ordering::Acquire);
+        if cache.is_null() {
+            continue;
+        }
+        // Safety: the per-CPU cache lives forever, and only the atomic
+        // counters are read.
+        let counters = unsafe { &(*cache).counters };
+        for (s, c) in stats.iter_mut().zip(counters.iter()) {
+            s.alloc_hits += c.alloc_hits.load(Ordering::Relaxed);
+            s.alloc_misses += c.alloc_misses.load(Ordering::Relaxed);
+            s.free_hits += c.free_hits.load(Ordering::Relaxed);
+            s.free_misses += c.free_misses.load(Ordering::Relaxed);
+            s.cached += c.cached.load(Ordering::Relaxed);
+        }
+    }
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use allocator::AllocError;
+    use core::cell::{Cell, RefCell};
+
+    fn layout(size: usize, align: usize) -> Layout {
+        Layout::from_size_align(size, align).unwrap()
+    }
+
+    #[test]
+    fn test_size_class() {
+        assert_eq!(size_class(layout(1, 1)), Some(0));
+        assert_eq!(size_class(layout(16, 8)), Some(0));
+        assert_eq!(size_class(layout(17, 1)), Some(1));
+        assert_eq!(size_class(layout(100, 8)), Some(3));
+        assert_eq!(size_class(layout(2048, 8)), Some(NUM_SIZE_CLASSES - 1));
+        assert_eq!(size_class(layout(2049, 8)), None);
+        // The alignment counts as the size.
+        assert_eq!(size_class(layout(8, 256)), Some(4));
+        assert_eq!(size_class(layout(8, 4096)), None);
+    }
+
+    #[test]
+    fn test_class_layout_fits() {
+        for class in 0..NUM_SIZE_CLASSES {
+            let cl = class_layout(class);
+            assert_eq!(cl.size(), SIZE_CLASSES[class]);
+            assert_eq!(cl.align(), SIZE_CLASSES[class]);
+        }
+        // Every layout mapped to a class fits in an object of the class.
+        for size in 1..=2048 {
+            for align in [1, 8, 64, 1024] {
+                let l = layout(size, align);
+                if let Some(class) = size_class(l) {
+                    let cl = class_layout(class);
+                    assert!(l.size() <= cl.size() && l.align() <= cl.align());
+                }
+            }
+        }
+    }
+
+    /// A backend that hands out fake object addresses.
+    struct MockBackend {
+        next: Cell<usize>,
+        remaining: Cell<usize>,
+        alloc_calls: Cell<usize>,
+        freed: RefCell<Vec<usize>>,
+    }
+
+    impl MockBackend {
+        fn new(capacity: usize) -> Self {
+            Self {
+                next: Cell::new(0x1000),
+                remaining: Cell::new(capacity),
+                alloc_calls: Cell::new(0),
+                freed: RefCell::new(Vec::new()),
+            }
+        }
+    }
+
+    impl Backend for MockBackend {
+        fn alloc_batch(&self, objs: &mut [usize]) -> AllocResult<usize> {
+            self.alloc_calls.set(self.alloc_calls.get() + 1);
+            let n = objs.len().min(self.remaining.get());
+            if n == 0 {
+                return Err(AllocError::NoMemory);
+            }
+            for obj in &mut objs[..n] {
+                *obj = self.next.get();
+                self.next.set(self.next.get() + 16);
+            }
+            self.remaining.set(self.remaining.get() - n);
+            Ok(n)
+        }
+
+        fn dealloc_batch(&self, objs: &[usize]) {
+            self.freed.borrow_mut().extend_from_slice(objs);
+        }
+    }
+
+    fn sorted(mut objs: Vec<usize>) -> Vec<usize> {
+        objs.sort();
+        objs
+    }
+
+    #[test]
+    fn test_refill_at_batch_boundary() {
+        let backend = MockBackend::new(usize::MAX);
+        let depot = SpinNoIrq::new(Depot::new());
+        let mut mag = Magazine::new();
+
+        let mut objs = Vec::new();
+        for i in 0..2 * BATCH_SIZE {
+            let (obj, refilled) = mag.pop(&depot, &backend).unwrap();
+            // Refilled with a batch only when empty.
+            assert_eq!(refilled, i % BATCH_SIZE == 0);
+            assert_eq!(mag.len, BATCH_SIZE - 1 - i % BATCH_SIZE);
+            objs.push(obj);
+        }
+        assert_eq!(backend.alloc_calls.get(), 2);
+        let mut objs = sorted(objs);
+        objs.dedup();
+        assert_eq!(objs.len(), 2 * BATCH_SIZE);
+    }
+
+    #[test]
+    fn test_flush_at_batch_boundary() {
+        let backend = MockBackend::new(0);
+        let depot = SpinNoIrq::new(Depot::new());
+        let mut mag = Magazine::new();
+
+        for obj in 0..MAGAZINE_SIZE {
+            assert!(!mag.push(obj, &depot, &backend));
+        }
+        assert_eq!(mag.len, MAGAZINE_SIZE);
+        // The upper half goes to the depot when the magazine is full.
+        assert!(mag.push(MAGAZINE_SIZE, &depot, &backend));
+        assert_eq!(mag.len, BATCH_SIZE + 1);
+        assert_eq!(depot.lock().len, 1);
+        assert!(backend.freed.borrow().is_empty());
+        // The lower half and the new object are kept.
+        assert_eq!(mag.objs[BATCH_SIZE - 1], BATCH_SIZE - 1);
+        assert_eq!(mag.objs[BATCH_SIZE], MAGAZINE_SIZE);
+    }
+
+    #[test]
+    fn test_depot_exchange() {
+        let backend = MockBackend::new(0);
+        let depot = SpinNoIrq::new(Depot::new());
+        let mut mag = Magazine::new();
+
+        // Fill the depot, then the batches overflow to the backend.
+        let mut obj = 0;
+        while depot.lock().len < DEPOT_SIZE {
+            mag.push(obj, &depot, &backend);
+            obj += 1;
+        }
+        assert!(backend.freed.borrow().is_empty());
+        while !mag.push(obj, &depot, &backend) {
+            obj += 1;
+        }
+        assert_eq!(backend.freed.borrow().len(), BATCH_SIZE);
+
+        // An empty magazine is refilled from the depot, not the backend.
+        let mut other = Magazine::new();
+        let (_, refilled) = other.pop(&depot, &backend).unwrap();
+        assert!(refilled);
+        assert_eq!(other.len, BATCH_SIZE - 1);
+        assert_eq!(depot.lock().len, DEPOT_SIZE - 1);
+        assert_eq!(backend.alloc_calls.get(), 0);
+    }
+
+    #[test]
+    fn test_free_on_other_cpu() {
+        let backend = MockBackend::new(usize::MAX);
+        let depot = SpinNoIrq::new(Depot::new());
+        let mut mag0 = Magazine::new();
+        let mut mag1 = Magazine::new();
+
+        // Allocate on CPU 0 and free on CPU 1.
+        let objs: Vec<_> = (0..MAGAZINE_SIZE + BATCH_SIZE)
+            .map(|_| mag0.pop(&depot, &backend).unwrap().0)
+            .collect();
+        assert_eq!(mag0.len, 0);
+        for &obj in &objs {
+            mag1.push(obj, &depot, &backend);
+        }
+        assert_eq!(depot.lock().len, 1);
+        let calls = backend.alloc_calls.get();
+
+        // CPU 0 gets the objects freed on CPU 1 back through the depot.
+        let reused: Vec<_> = (0..BATCH_SIZE)
+            .map(|_| mag0.pop(&depot, &backend).unwrap().0)
+            .collect();
+        assert_eq!(backend.alloc_calls.get(), calls);
+        assert!(reused.iter().all(|obj| objs.contains(obj)));
+
+        // Nothing is lost when all caches are drained.
+        mag0.drain(&backend);
+        mag1.drain(&backend);
+        depot.lock().drain(&backend);
+        let mut all = reused;
+        all.extend(backend.freed.borrow().iter());
+        assert_eq!(sorted(all), sorted(objs));
+        assert_eq!(depot.lock().len, 0);
+    }
+
+    #[test]
+    fn test_refill_failure() {
+        let backend = MockBackend::new(3);
+        let depot = SpinNoIrq::new(Depot::new());
+        let mut mag = Magazine::new();
+
+        // A partial batch is accepted.
+        assert!(mag.pop(&depot, &backend).is_ok());
+        assert_eq!(mag.len, 2);
+        mag.pop(&depot, &backend).unwrap();
+        mag.pop(&depot, &backend).unwrap();
+        assert_eq!(mag.pop(&depot, &backend), Err(AllocError::NoMemory));
+        assert_eq!(mag.len, 0);
+    }
+}
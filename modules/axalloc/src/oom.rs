@@ -0,0 +1,260 @@
+//! Out-of-memory handling and memory pressure notification.
+//!
+//! When an allocation fails, the registered reclaim callbacks (e.g., of a
+//! page cache or a buffer pool) are invoked to give back some memory, and the
+//! allocation is retried (a limited number of times). If nothing can be
+//! reclaimed, the [`OomPolicy`] decides what happens.
+//!
+//! The allocator also watches the number of available pages. When it drops
+//! below the low watermark, a low memory event is recorded, which can be
+//! polled with [`is_low_memory`] and [`low_memory_events`].
+
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+
+use allocator::{AllocError, AllocResult};
+use kernel_guard::NoPreempt;
+
+/// Maximum number of reclaim callbacks.
+const MAX_RECLAIMERS: usize = 16;
+
+/// Maximum number of times a failed allocation is retried after reclaiming
+/// memory, in case the reclaimed memory keeps being taken by others.
+const MAX_RECLAIM_RETRIES: usize = 16;
+
+/// A reclaim callback.
+///
+/// It is called with the number of bytes the failed allocation requested,
+/// and returns the number of bytes it has given back to the allocator (0 if
+/// nothing can be reclaimed).
+///
+/// It is called in the context of the failed allocation, with preemption
+/// disabled, so it must not block, and must not take locks that may be held
+/// while allocating. It may be called on several CPUs at the same time.
+pub type ReclaimFn = fn(usize) -> usize;
+
+/// An OOM killer, see [`OomPolicy::KillLargest`].
+///
+/// It returns `true` if a victim has been selected to be killed.
+pub type OomKillerFn = fn() -> bool;
+
+/// What to do when an allocation fails and nothing can be reclaimed.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OomPolicy {
+    /// Return the error to the caller (the default).
+    ///
+    /// Note that allocations through [`GlobalAlloc`] still panic in
+    /// [`handle_alloc_error`].
+    ///
+    /// [`GlobalAlloc`]: core::alloc::GlobalAlloc
+    /// [`handle_alloc_error`]: alloc::alloc::handle_alloc_error
+    ReturnError = 0,
+    /// Panic immediately.
+    Panic = 1,
+    /// Kill the task that uses the most memory with the OOM killer set by
+    /// [`set_oom_killer`], then return the error. The memory of the victim is
+    /// released when it exits.
+    ///
+    /// The failed allocation is not retried, as the victim has not exited
+    /// yet, so allocations through [`GlobalAlloc`] still panic in
+    /// [`handle_alloc_error`], as with [`ReturnError`](Self::ReturnError).
+    /// Only the callers that handle the error (e.g., of page allocations)
+    /// survive.
+    ///
+    /// [`GlobalAlloc`]: core::alloc::GlobalAlloc
+    /// [`handle_alloc_error`]: alloc::alloc::handle_alloc_error
+    KillLargest = 2,
+}
+
+/// The overcommit mode of the memory mappings, as `vm.overcommit_memory` of
+/// Linux.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OvercommitMode {
+    /// Refuse only the obvious overcommits (the default).
+    Heuristic = 0,
+    /// Always overcommit.
+    Always = 1,
+    /// Never overcommit.
+    Never = 2,
+}
+
+static POLICY: AtomicU8 = AtomicU8::new(OomPolicy::ReturnError as u8);
+static OVERCOMMIT_MODE: AtomicU8 = AtomicU8::new(OvercommitMode::Heuristic as u8);
+static OOM_KILLER: AtomicUsize = AtomicUsize::new(0);
+
+#[allow(clippy::declare_interior_mutable_const)]
+static RECLAIMERS: [AtomicUsize; MAX_RECLAIMERS] = {
+    const NONE: AtomicUsize = AtomicUsize::new(0);
+    [NONE; MAX_RECLAIMERS]
+};
+
+/// Whether the reclaim callbacks are running on the current CPU, to avoid
+/// recursion if they allocate memory themselves.
+#[percpu::def_percpu]
+static IN_RECLAIM: bool = false;
+
+static LOW_WATERMARK: AtomicUsize = AtomicUsize::new(0);
+static LOW_MEMORY: AtomicBool = AtomicBool::new(false);
+static LOW_MEMORY_EVENTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Registers a reclaim callback.
+///
+/// Returns [`AllocError::NoMemory`] if too many callbacks are registered.
+pub fn register_reclaimer(f: ReclaimFn) -> AllocResult {
+    for slot in RECLAIMERS.iter() {
+        if slot
+            .compare_exchange(0, f as usize, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+        {
+            return Ok(());
+        }
+    }
+    Err(AllocError::NoMemory)
+}
+
+/// Unregisters a reclaim callback registered by [`register_reclaimer`].
+pub fn unregister_reclaimer(f: ReclaimFn) {
+    for slot in RECLAIMERS.iter() {
+        let _ = slot.compare_exchange(f as usize, 0, Ordering::AcqRel, Ordering::Acquire);
+    }
+}
+
+/// Sets the OOM policy.
+pub fn set_policy(policy: OomPolicy) {
+    POLICY.store(policy as u8, Ordering::Release);
+}
+
+/// Returns the current OOM policy.
+pub fn policy() -> OomPolicy {
+    match POLICY.load(Ordering::Acquire) {
+        1 => OomPolicy::Panic,
+        2 => OomPolicy::KillLargest,
+        _ => OomPolicy::ReturnError,
+    }
+}
+
+/// Sets the OOM killer used by [`OomPolicy::KillLargest`].
+pub fn set_oom_killer(f: OomKillerFn) {
+    OOM_KILLER.store(f as usize, Ordering::Release);
+}
+
+/// Sets the overcommit mode.
+pub fn set_overcommit_mode(mode: OvercommitMode) {
+    OVERCOMMIT_MODE.store(mode as u8, Ordering::Release);
+}
+
+/// Returns the current overcommit mode.
+pub fn overcommit_mode() -> OvercommitMode {
+    match OVERCOMMIT_MODE.load(Ordering::Acquire) {
+        1 => OvercommitMode::Always,
+        2 => OvercommitMode::Never,
+        _ => OvercommitMode::Heuristic,
+    }
+}
+
+/// Sets the low watermark, in pages. 0 disables low memory events.
+pub fn set_low_watermark(pages: usize) {
+    LOW_WATERMARK.store(pages, Ordering::Release);
+}
+
+/// Returns the low watermark, in pages.
+pub fn low_watermark() -> usize {
+    LOW_WATERMARK.load(Ordering::Acquire)
+}
+
+/// Whether the number of available pages is below the low watermark.
+pub fn is_low_memory() -> bool {
+    LOW_MEMORY.load(Ordering::Acquire)
+}
+
+/// Returns the number of times the available pages dropped below the low
+/// watermark.
+pub fn low_memory_events() -> usize {
+    LOW_MEMORY_EVENTS.load(Ordering::Acquire)
+}
+
+/// Updates the memory pressure state with the number of available pages.
+pub(crate) fn update_pressure(available_pages: usize) {
+    let low = available_pages < LOW_WATERMARK.load(Ordering::Relaxed);
+    if LOW_MEMORY.load(Ordering::Relaxed) == low {
+        return;
+    }
+    if LOW_MEMORY.swap(low, Ordering::AcqRel) != low && low {
+        LOW_MEMORY_EVENTS.fetch_add(1, Ordering::AcqRel);
+    }
+}
+
+/// Invokes the reclaim callbacks, until one of them gives back some memory.
+///
+/// Returns `true` if any memory has been reclaimed.
+fn reclaim(size: usize) -> bool {
+    let _guard = NoPreempt::new();
+    if IN_RECLAIM.read_current() {
+        return false;
+    }
+    IN_RECLAIM.write_current(true);
+    let mut reclaimed = 0;
+    for slot in RECLAIMERS.iter() {
+        let f = slot.load(Ordering::Acquire);
+        if f != 0 {
+            let f: ReclaimFn = unsafe { core::mem::transmute(f) };
+            reclaimed += f(size);
+            if reclaimed > 0 {
+                break;
+            }
+        }
+    }
+    IN_RECLAIM.write_current(false);
+    if reclaimed > 0 {
+        debug!(
+            "reclaimed {} bytes for allocation of {} bytes",
+            reclaimed, size
+        );
+    }
+    reclaimed > 0
+}
+
+/// Runs the allocation `f` of `size` bytes, and retries it after reclaiming
+/// memory if it fails, at most [`MAX_RECLAIM_RETRIES`] times.
+///
+/// If it still fails, the OOM policy is applied and the error is returned.
+pub(crate) fn alloc_with_reclaim<T>(
+    size: usize,
+    mut f: impl FnMut() -> AllocResult<T>,
+) -> AllocResult<T> {
+    let mut retries = 0;
+    loop {
+        match f() {
+            Ok(res) => return Ok(res),
+            Err(AllocError::NoMemory) if retries < MAX_RECLAIM_RETRIES && reclaim(size) => {
+                retries += 1;
+            }
+            Err(e) => return Err(handle_alloc_failure(size, e)),
+        }
+    }
+}
+
+/// Handles a failed allocation of `size` bytes that can not be retried, and
+/// returns the error to return.
+fn handle_alloc_failure(size: usize, err: AllocError) -> AllocError {
+    if !matches!(err, AllocError::NoMemory) {
+        return err;
+    }
+    match policy() {
+        OomPolicy::ReturnError => {}
+        OomPolicy::Panic => panic!("out of memory: failed to allocate {} bytes", size),
+        OomPolicy::KillLargest => {
+            let killer = OOM_KILLER.load(Ordering::Acquire);
+            if killer == 0 {
+                warn!("out of memory: no OOM killer registered");
+            } else {
+                let killer: OomKillerFn = unsafe { core::mem::transmute(killer) };
+                if !killer() {
+                    warn!("out of memory: no task to kill");
+                }
+            }
+        }
+    }
+    err
+}
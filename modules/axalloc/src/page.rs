@@ -2,7 +2,7 @@ use allocator::AllocError;
 use axerrno::{AxError, AxResult};
 use memory_addr::{PhysAddr, VirtAddr};
 
-use crate::{global_allocator, PAGE_SIZE};
+use crate::{global_allocator, PageHint, PAGE_SIZE};
 
 /// A RAII wrapper of contiguous 4K-sized pages.
 ///
@@ -34,8 +34,18 @@ impl GlobalPage {
 
     /// Allocate contiguous 4K-sized pages.
     pub fn alloc_contiguous(num_pages: usize, align_pow2: usize) -> AxResult<Self> {
+        Self::alloc_contiguous_hint(num_pages, align_pow2, PageHint::ANY)
+    }
+
+    /// Allocate contiguous 4K-sized pages from the zone and NUMA node given
+    /// by `hint`.
+    pub fn alloc_contiguous_hint(
+        num_pages: usize,
+        align_pow2: usize,
+        hint: PageHint,
+    ) -> AxResult<Self> {
         global_allocator()
-            .alloc_pages(num_pages, align_pow2)
+            .alloc_pages_hint(num_pages, align_pow2, hint)
             .map(|vaddr| Self {
                 start_vaddr: vaddr.into(),
                 num_pages,
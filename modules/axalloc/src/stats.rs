@@ -0,0 +1,465 @@
+//! Allocation statistics and leak tracking.
+//!
+//! Every allocation is attributed to a *call site*, which is either an
+//! explicit subsystem tag set by [`with_tag`], or the return address of the
+//! caller found by walking the frame pointers (it requires the kernel to be
+//! built with `-C force-frame-pointers=yes`, otherwise the address is 0). The
+//! site index is kept in a small header in front of each allocated block, so
+//! that deallocations are accounted to the same site.
+//!
+//! A [`Snapshot`] of all sites can be taken at any time, and two snapshots can
+//! be diffed to find the sites whose live allocations keep growing.
+
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use core::fmt;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use kernel_guard::NoPreempt;
+use kspin::SpinNoIrq;
+
+/// Maximum number of tracked call sites. Allocations from more sites are
+/// accounted to an "other" site.
+const MAX_SITES: usize = 256;
+
+/// Index of the site for untracked allocations.
+const OTHER_SITE: usize = 0;
+
+/// The maximum distance of a frame pointer from the stack pointer, to reject
+/// bogus frame pointers when walking the stack.
+const MAX_STACK_WINDOW: usize = 0x10_0000;
+
+/// The tag of allocations in interrupt handlers, see [`with_irq_tag`].
+pub const IRQ_TAG: &str = "irq";
+
+/// Number of frames to skip to find the caller of the allocator.
+static CALLER_DEPTH: AtomicUsize = AtomicUsize::new(4);
+
+/// The source of an allocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallSite {
+    /// An explicit subsystem tag.
+    Tag(&'static str),
+    /// The return address of the caller.
+    Addr(usize),
+}
+
+/// Allocation counters of a call site.
+#[derive(Debug, Clone, Copy)]
+pub struct SiteStats {
+    /// The call site.
+    pub site: CallSite,
+    /// Number of live (not yet freed) allocations.
+    pub live_count: usize,
+    /// Number of bytes in live allocations.
+    pub live_bytes: usize,
+    /// Number of allocations since boot.
+    pub total_count: usize,
+    /// Number of bytes allocated since boot.
+    pub total_bytes: usize,
+    /// The peak of `live_bytes`.
+    pub peak_bytes: usize,
+}
+
+/// The change of a call site between two snapshots.
+#[derive(Debug, Clone, Copy)]
+pub struct SiteDiff {
+    /// The call site.
+    pub site: CallSite,
+    /// Change of the number of live allocations.
+    pub live_count: isize,
+    /// Change of the number of bytes in live allocations.
+    pub live_bytes: isize,
+    /// Number of allocations made in between.
+    pub new_count: usize,
+}
+
+/// The statistics of all call sites at a point in time.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    sites: Vec<SiteStats>,
+}
+
+struct SiteTable {
+    sites: [Option<SiteStats>; MAX_SITES],
+    live_bytes: usize,
+    peak_bytes: usize,
+}
+
+static TABLE: SpinNoIrq<SiteTable> = SpinNoIrq::new(SiteTable::new());
+
+/// The site index of [`IRQ_TAG`] + 1, or 0 if not inserted yet.
+static IRQ_SITE: AtomicUsize = AtomicUsize::new(0);
+
+/// The tag of the current CPU (site index + 1), or 0 if not set.
+#[percpu::def_percpu]
+static CURRENT_TAG: usize = 0;
+
+impl SiteStats {
+    const fn new(site: CallSite) -> Self {
+        Self {
+            site,
+            live_count: 0,
+            live_bytes: 0,
+            total_count: 0,
+            total_bytes: 0,
+            peak_bytes: 0,
+        }
+    }
+}
+
+impl SiteTable {
+    const fn new() -> Self {
+        Self {
+            sites: [None; MAX_SITES],
+            live_bytes: 0,
+            peak_bytes: 0,
+        }
+    }
+
+    fn find_or_insert(&mut self, site: CallSite) -> usize {
+        let start = match site {
+            CallSite::Addr(addr) => addr,
+            CallSite::Tag(tag) => tag.as_ptr() as usize,
+        };
+        // Open addressing with linear probing, slot 0 is reserved.
+        let slots = MAX_SITES - 1;
+        for i in 0..slots {
+            let idx = 1 + (start.wrapping_add(i) % slots);
+            match &self.sites[idx] {
+                Some(s) if s.site == site => return idx,
+                Some(_) => continue,
+                None => {
+                    self.sites[idx] = Some(SiteStats::new(site));
+                    return idx;
+                }
+            }
+        }
+        OTHER_SITE
+    }
+
+    fn on_alloc(&mut self, idx: usize, size: usize) {
+        // Only the "other" site may be not initialized yet.
+        let s = self.sites[idx].get_or_insert(SiteStats::new(CallSite::Tag("<other>")));
+        s.live_count += 1;
+        s.live_bytes += size;
+        s.total_count += 1;
+        s.total_bytes += size;
+        s.peak_bytes = s.peak_bytes.max(s.live_bytes);
+        self.live_bytes += size;
+        self.peak_bytes = self.peak_bytes.max(self.live_bytes);
+    }
+
+    fn on_dealloc(&mut self, idx: usize, size: usize) {
+        if let Some(s) = self.sites.get_mut(idx).and_then(Option::as_mut) {
+            s.live_count -= 1;
+            s.live_bytes -= size;
+            self.live_bytes -= size;
+        }
+    }
+}
+
+/// Returns the current frame pointer and stack pointer.
+#[inline(always)]
+fn frame_and_stack_pointer() -> (usize, usize) {
+    let fp: usize;
+    let sp: usize;
+    unsafe {
+        #[cfg(target_arch = "x86_64")]
+        core::arch::asm!("mov {}, rbp; mov {}, rsp", out(reg) fp, out(reg) sp);
+        #[cfg(target_arch = "aarch64")]
+        core::arch::asm!("mov {}, x29; mov {}, sp", out(reg) fp, out(reg) sp);
+        #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+        core::arch::asm!("mv {}, s0; mv {}, sp", out(reg) fp, out(reg) sp);
+    }
+    (fp, sp)
+}
+
+/// Walks `depth` frames up from the current one, and returns the return
+/// address of the last frame, or 0 if the frame chain looks invalid.
+#[inline(always)]
+fn caller_addr(depth: usize) -> usize {
+    const WORD: usize = core::mem::size_of::<usize>();
+    let (mut fp, sp) = frame_and_stack_pointer();
+    let valid = |fp: usize| fp % WORD == 0 && fp > sp && fp - sp < MAX_STACK_WINDOW;
+    let mut ra = 0;
+    for _ in 0..depth {
+        if !valid(fp) {
+            return 0;
+        }
+        // On RISC-V, the frame pointer points to the top of the frame,
+        // followed by the return address and the previous frame pointer.
+        let (prev_fp, ret) = unsafe {
+            if cfg!(any(target_arch = "riscv32", target_arch = "riscv64")) {
+                (
+                    *((fp - 2 * WORD) as *const usize),
+                    *((fp - WORD) as *const usize),
+                )
+            } else {
+                (*(fp as *const usize), *((fp + WORD) as *const usize))
+            }
+        };
+        if prev_fp != 0 && prev_fp <= fp {
+            return 0;
+        }
+        ra = ret;
+        fp = prev_fp;
+    }
+    ra
+}
+
+/// Sets the number of frames to skip when looking for the caller address.
+///
+/// The default value fits the usual path from `alloc::alloc::alloc` to the
+/// global allocator, but it depends on inlining.
+pub fn set_caller_depth(depth: usize) {
+    CALLER_DEPTH.store(depth, Ordering::Relaxed);
+}
+
+/// Runs `f` with allocations on the current CPU accounted to the site `idx`.
+///
+/// Preemption must be disabled.
+fn with_site<R>(idx: usize, f: impl FnOnce() -> R) -> R {
+    let old = CURRENT_TAG.read_current();
+    CURRENT_TAG.write_current(idx + 1);
+    let ret = f();
+    CURRENT_TAG.write_current(old);
+    ret
+}
+
+/// Runs `f` with allocations on the current CPU accounted to `tag`.
+///
+/// Preemption is disabled while `f` runs, so `f` must not block. Allocations
+/// in interrupt handlers are still accounted to [`IRQ_TAG`].
+pub fn with_tag<R>(tag: &'static str, f: impl FnOnce() -> R) -> R {
+    let idx = TABLE.lock().find_or_insert(CallSite::Tag(tag));
+    let _guard = NoPreempt::new();
+    with_site(idx, f)
+}
+
+/// Runs the interrupt handler `f` with allocations accounted to [`IRQ_TAG`].
+///
+/// Otherwise they would be accounted to the tag of the interrupted code, if
+/// it runs in [`with_tag`]. It must be called with IRQs disabled.
+pub fn with_irq_tag<R>(f: impl FnOnce() -> R) -> R {
+    let mut site = IRQ_SITE.load(Ordering::Relaxed);
+    if site == 0 {
+        site = TABLE.lock().find_or_insert(CallSite::Tag(IRQ_TAG)) + 1;
+        IRQ_SITE.store(site, Ordering::Relaxed);
+    }
+    with_site(site - 1, f)
+}
+
+fn header_size(layout: Layout) -> usize {
+    layout.align().max(core::mem::size_of::<usize>() * 2)
+}
+
+/// Returns the layout of the block with the header actually allocated for
+/// `layout`.
+pub(crate) fn outer_layout(layout: Layout) -> Layout {
+    let size = layout.size() + header_size(layout);
+    Layout::from_size_align(size, layout.align()).unwrap()
+}
+
+/// Records a new allocation of `layout` at the block `outer`, returns the
+/// pointer given to the user.
+#[inline(always)]
+pub(crate) fn on_alloc(outer: NonNull<u8>, layout: Layout) -> NonNull<u8> {
+    let tag = CURRENT_TAG.read_current();
+    let site = if tag != 0 {
+        None
+    } else {
+        Some(CallSite::Addr(caller_addr(
+            CALLER_DEPTH.load(Ordering::Relaxed),
+        )))
+    };
+    let mut table = TABLE.lock();
+    let idx = match site {
+        Some(site) => table.find_or_insert(site),
+        None => tag - 1,
+    };
+    table.on_alloc(idx, layout.size());
+    drop(table);
+
+    unsafe {
+        let ptr = outer.as_ptr().add(header_size(layout));
+        (ptr as *mut usize).sub(1).write(idx);
+        NonNull::new_unchecked(ptr)
+    }
+}
+
+/// Records the deallocation of `ptr` with `layout`, returns the block to be
+/// freed.
+pub(crate) fn on_dealloc(ptr: NonNull<u8>, layout: Layout) -> NonNull<u8> {
+    unsafe {
+        let idx = (ptr.as_ptr() as *const usize).sub(1).read();
+        TABLE.lock().on_dealloc(idx, layout.size());
+        NonNull::new_unchecked(ptr.as_ptr().sub(header_size(layout)))
+    }
+}
+
+/// Returns the number of bytes in live allocations, and the peak of it.
+pub fn live_and_peak_bytes() -> (usize, usize) {
+    let table = TABLE.lock();
+    (table.live_bytes, table.peak_bytes)
+}
+
+/// Takes a snapshot of all call sites.
+pub fn snapshot() -> Snapshot {
+    // Allocate before taking the lock, as the allocation is also recorded.
+    let mut sites = Vec::with_capacity(MAX_SITES);
+    let table = TABLE.lock();
+    sites.extend(table.sites.iter().flatten().copied());
+    drop(table);
+    Snapshot { sites }
+}
+
+impl Snapshot {
+    /// Returns the statistics of all call sites.
+    pub fn sites(&self) -> &[SiteStats] {
+        &self.sites
+    }
+
+    /// Returns the changes of the call sites since the `earlier` snapshot.
+    ///
+    /// Sites without any allocation in between are omitted. The result is
+    /// sorted by the change of live bytes, in descending order.
+    pub fn diff(&self, earlier: &Snapshot) -> Vec<SiteDiff> {
+        let mut diffs = Vec::new();
+        for s in self.sites.iter() {
+            let old = earlier.sites.iter().find(|o| o.site == s.site);
+            let (count, bytes, total) =
+                old.map_or((0, 0, 0), |o| (o.live_count, o.live_bytes, o.total_count));
+            if s.total_count == total && s.live_count == count {
+                continue;
+            }
+            diffs.push(SiteDiff {
+                site: s.site,
+                live_count: s.live_count as isize - count as isize,
+                live_bytes: s.live_bytes as isize - bytes as isize,
+                new_count: s.total_count - total,
+            });
+        }
+        diffs.sort_unstable_by(|a, b| b.live_bytes.cmp(&a.live_bytes));
+        diffs
+    }
+}
+
+impl fmt::Display for CallSite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Tag(tag) => write!(f, "{}", tag),
+            Self::Addr(addr) => write!(f, "{:#x}", addr),
+        }
+    }
+}
+
+/// Writes the statistics of all call sites, or the changes since the `since`
+/// snapshot if it is given, in a human-readable table.
+pub fn dump(w: &mut dyn fmt::Write, since: Option<&Snapshot>) -> fmt::Result {
+    let snap = snapshot();
+    let (live, peak) = live_and_peak_bytes();
+    writeln!(w, "live: {} bytes, peak: {} bytes", live, peak)?;
+    if let Some(since) = since {
+        writeln!(
+            w,
+            "{:>20} {:>10} {:>12} {:>10}",
+            "SITE", "LIVE", "LIVE_BYTES", "NEW"
+        )?;
+        for d in snap.diff(since) {
+            writeln!(
+                w,
+                "{:>20} {:>+10} {:>+12} {:>10}",
+                d.site, d.live_count, d.live_bytes, d.new_count
+            )?;
+        }
+    } else {
+        writeln!(
+            w,
+            "{:>20} {:>10} {:>12} {:>10} {:>12} {:>12}",
+            "SITE", "LIVE", "LIVE_BYTES", "TOTAL", "TOTAL_BYTES", "PEAK_BYTES"
+        )?;
+        let mut sites = snap.sites;
+        sites.sort_unstable_by(|a, b| b.live_bytes.cmp(&a.live_bytes));
+        for s in sites.iter().filter(|s| s.total_count > 0) {
+            writeln!(
+                w,
+                "{:>20} {:>10} {:>12} {:>10} {:>12} {:>12}",
+                s.site, s.live_count, s.live_bytes, s.total_count, s.total_bytes, s.peak_bytes
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn site(snap: &Snapshot, site: CallSite) -> SiteStats {
+        *snap.sites().iter().find(|s| s.site == site).unwrap()
+    }
+
+    #[test]
+    fn test_site_table() {
+        let mut table = SiteTable::new();
+        let a = table.find_or_insert(CallSite::Addr(0x1000));
+        let b = table.find_or_insert(CallSite::Tag("b"));
+        assert_ne!(a, OTHER_SITE);
+        assert_ne!(a, b);
+        assert_eq!(table.find_or_insert(CallSite::Addr(0x1000)), a);
+
+        table.on_alloc(a, 100);
+        table.on_alloc(a, 50);
+        table.on_alloc(b, 10);
+        table.on_dealloc(a, 100);
+        let s = table.sites[a].unwrap();
+        assert_eq!((s.live_count, s.live_bytes), (1, 50));
+        assert_eq!((s.total_count, s.total_bytes, s.peak_bytes), (2, 150, 150));
+        assert_eq!((table.live_bytes, table.peak_bytes), (60, 160));
+    }
+
+    #[test]
+    fn test_site_table_full() {
+        let mut table = SiteTable::new();
+        for addr in 0..MAX_SITES - 1 {
+            assert_ne!(table.find_or_insert(CallSite::Addr(addr)), OTHER_SITE);
+        }
+        // No slot left, accounted to the "other" site.
+        assert_eq!(table.find_or_insert(CallSite::Addr(MAX_SITES)), OTHER_SITE);
+        table.on_alloc(OTHER_SITE, 8);
+        assert_eq!(table.sites[OTHER_SITE].unwrap().live_bytes, 8);
+    }
+
+    #[test]
+    fn test_snapshot_diff() {
+        let mut table = SiteTable::new();
+        let (a, b, c) = (CallSite::Tag("a"), CallSite::Tag("b"), CallSite::Tag("c"));
+        let (ia, ib) = (table.find_or_insert(a), table.find_or_insert(b));
+        table.on_alloc(ia, 10);
+        table.on_alloc(ib, 10);
+        let earlier = Snapshot {
+            sites: table.sites.iter().flatten().copied().collect(),
+        };
+
+        table.on_alloc(ia, 100);
+        table.on_dealloc(ib, 10);
+        let ic = table.find_or_insert(c);
+        table.on_alloc(ic, 20);
+        table.on_dealloc(ic, 20);
+        let later = Snapshot {
+            sites: table.sites.iter().flatten().copied().collect(),
+        };
+        assert_eq!(site(&later, a).live_bytes, 110);
+
+        let diffs = later.diff(&earlier);
+        // Sorted by the change of live bytes.
+        let sites: Vec<_> = diffs.iter().map(|d| d.site).collect();
+        assert_eq!(sites, [a, c, b]);
+        assert_eq!((diffs[0].live_count, diffs[0].live_bytes), (1, 100));
+        assert_eq!((diffs[1].live_count, diffs[1].new_count), (0, 1));
+        assert_eq!((diffs[2].live_count, diffs[2].live_bytes), (-1, -10));
+        assert!(later.diff(&later).is_empty());
+    }
+}
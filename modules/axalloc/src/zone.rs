@@ -0,0 +1,236 @@
+//! Memory zones and NUMA-aware page pools.
+//!
+//! The physical memory is divided into page pools, each covering a range of
+//! memory in a single [`MemZone`] and NUMA node. Page allocations take a
+//! [`PageHint`] to select the pools to allocate from.
+//!
+//! Without the `zones` feature, there is only one pool, and the hints are
+//! ignored.
+
+use allocator::{AllocError, AllocResult, BaseAllocator, PageAllocator};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use kspin::SpinNoIrq;
+
+use crate::DefaultPageAllocator;
+
+#[cfg(feature = "zones")]
+const MAX_POOLS: usize = 8;
+#[cfg(not(feature = "zones"))]
+const MAX_POOLS: usize = 1;
+
+/// A memory zone, i.e., a class of physical memory with the same addressing
+/// restrictions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemZone {
+    /// Memory below 4 GiB, usable by devices with 32-bit DMA addressing.
+    Dma32,
+    /// Any other memory.
+    Normal,
+}
+
+/// Where to allocate pages from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageHint {
+    /// The zone the pages must be in.
+    ///
+    /// [`MemZone::Normal`] allocations fall back to [`MemZone::Dma32`] if
+    /// the normal zone runs out of memory. [`MemZone::Dma32`] allocations are
+    /// strict, unless there is no DMA32 memory at all.
+    pub zone: MemZone,
+    /// The preferred NUMA node. Other nodes are used if it runs out of
+    /// memory.
+    pub node: Option<usize>,
+}
+
+impl PageHint {
+    /// Allocates from any memory.
+    pub const ANY: Self = Self {
+        zone: MemZone::Normal,
+        node: None,
+    };
+
+    /// Allocates from the DMA32 zone.
+    pub const fn dma32() -> Self {
+        Self {
+            zone: MemZone::Dma32,
+            node: None,
+        }
+    }
+
+    /// Prefers the memory of the given NUMA node.
+    pub const fn node(node: usize) -> Self {
+        Self {
+            zone: MemZone::Normal,
+            node: Some(node),
+        }
+    }
+}
+
+impl Default for PageHint {
+    fn default() -> Self {
+        Self::ANY
+    }
+}
+
+/// Statistics of a page pool.
+#[derive(Debug, Clone, Copy)]
+pub struct PoolStats {
+    /// Start virtual address of the pool.
+    pub start: usize,
+    /// End virtual address of the pool.
+    pub end: usize,
+    /// The zone of the pool.
+    pub zone: MemZone,
+    /// The NUMA node of the pool.
+    pub node: usize,
+    /// Total number of pages.
+    pub total_pages: usize,
+    /// Number of allocated pages.
+    pub used_pages: usize,
+}
+
+pub(crate) struct PagePool {
+    palloc: DefaultPageAllocator,
+    start: usize,
+    end: usize,
+    zone: MemZone,
+    node: usize,
+}
+
+impl PagePool {
+    const fn new() -> Self {
+        Self {
+            palloc: DefaultPageAllocator::new(),
+            start: 0,
+            end: 0,
+            zone: MemZone::Normal,
+            node: 0,
+        }
+    }
+
+    fn init(&mut self, start: usize, size: usize, zone: MemZone, node: usize) {
+        self.palloc.init(start, size);
+        self.start = start;
+        self.end = start + size;
+        self.zone = zone;
+        self.node = node;
+    }
+
+    fn stats(&self) -> PoolStats {
+        PoolStats {
+            start: self.start,
+            end: self.end,
+            zone: self.zone,
+            node: self.node,
+            total_pages: self.palloc.total_pages(),
+            used_pages: self.palloc.used_pages(),
+        }
+    }
+}
+
+/// All page pools.
+pub(crate) struct PagePools {
+    pools: [SpinNoIrq<PagePool>; MAX_POOLS],
+    len: AtomicUsize,
+}
+
+impl PagePools {
+    #[allow(clippy::declare_interior_mutable_const)]
+    pub(crate) const fn new() -> Self {
+        const POOL: SpinNoIrq<PagePool> = SpinNoIrq::new(PagePool::new());
+        Self {
+            pools: [POOL; MAX_POOLS],
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    fn active(&self) -> &[SpinNoIrq<PagePool>] {
+        &self.pools[..self.len.load(Ordering::Acquire)]
+    }
+
+    /// Initializes the first pool.
+    pub(crate) fn init(&self, start: usize, size: usize, zone: MemZone, node: usize) {
+        self.pools[0].lock().init(start, size, zone, node);
+        self.len.store(1, Ordering::Release);
+    }
+
+    /// Adds a new pool. Returns [`AllocError::NoMemory`] if there are too
+    /// many pools.
+    pub(crate) fn add(&self, start: usize, size: usize, zone: MemZone, node: usize) -> AllocResult {
+        let idx = self.len.load(Ordering::Acquire);
+        if idx == 0 || idx >= MAX_POOLS {
+            return Err(AllocError::NoMemory);
+        }
+        self.pools[idx].lock().init(start, size, zone, node);
+        self.len.store(idx + 1, Ordering::Release);
+        Ok(())
+    }
+
+    /// Allocates from the pools selected by `hint`, in order of preference,
+    /// with the allocation function `f`.
+    pub(crate) fn alloc(
+        &self,
+        hint: PageHint,
+        mut f: impl FnMut(&mut DefaultPageAllocator) -> AllocResult<usize>,
+    ) -> AllocResult<usize> {
+        let pools = self.active();
+        let has_dma32 = pools.iter().any(|p| p.lock().zone == MemZone::Dma32);
+        let zones: &[MemZone] = match hint.zone {
+            MemZone::Dma32 if has_dma32 => &[MemZone::Dma32],
+            _ => &[MemZone::Normal, MemZone::Dma32],
+        };
+        for &zone in zones {
+            // Pools on the preferred node first, then the others.
+            for local in [true, false] {
+                for pool in pools {
+                    let mut pool = pool.lock();
+                    let node_matches = match hint.node {
+                        Some(node) => (pool.node == node) == local,
+                        None => local,
+                    };
+                    if pool.zone != zone || !node_matches {
+                        continue;
+                    }
+                    match f(&mut pool.palloc) {
+                        Err(AllocError::NoMemory) => continue,
+                        res => return res,
+                    }
+                }
+            }
+        }
+        Err(AllocError::NoMemory)
+    }
+
+    /// Runs `f` with the page allocator of the pool containing `pos`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pos` is not in any pool.
+    pub(crate) fn with_pool_of<R>(
+        &self,
+        pos: usize,
+        f: impl FnOnce(&mut DefaultPageAllocator) -> R,
+    ) -> R {
+        for pool in self.active() {
+            let mut pool = pool.lock();
+            if (pool.start..pool.end).contains(&pos) {
+                return f(&mut pool.palloc);
+            }
+        }
+        panic!("page {:#x} does not belong to any pool", pos);
+    }
+
+    /// Calls `f` with the statistics of each pool.
+    pub(crate) fn for_each(&self, mut f: impl FnMut(PoolStats)) {
+        for pool in self.active() {
+            f(pool.lock().stats());
+        }
+    }
+
+    /// Calls `f` with the page allocator of each pool.
+    pub(crate) fn for_each_allocator(&self, mut f: impl FnMut(&DefaultPageAllocator)) {
+        for pool in self.active() {
+            f(&pool.lock().palloc);
+        }
+    }
+}
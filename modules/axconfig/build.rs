@@ -101,6 +101,39 @@ fn gen_config_rs(config_path: &Path) -> Result<Vec<u8>> {
         toml_edit::value(std::env::var("AX_SMP").unwrap_or("1".into())),
         Some("# Number of CPUs"),
     );
+    if let Some(aslr) = std::env::var("AX_ASLR").ok().filter(|s| !s.is_empty()) {
+        let comments = get_comments(&config, "aslr").map(str::to_owned);
+        add_config(
+            &mut config,
+            "aslr",
+            toml_edit::value(aslr == "y"),
+            comments.as_deref(),
+        );
+    }
+    if let Some(gic_version) = std::env::var("AX_GIC_VERSION")
+        .ok()
+        .filter(|s| !s.is_empty())
+    {
+        let comments = get_comments(&config, "gic-version").map(str::to_owned);
+        add_config(
+            &mut config,
+            "gic-version",
+            toml_edit::value(gic_version),
+            comments.as_deref(),
+        );
+    }
+    if let Some(irq_controller) = std::env::var("AX_IRQ_CONTROLLER")
+        .ok()
+        .filter(|s| !s.is_empty())
+    {
+        let comments = get_comments(&config, "irq-controller").map(str::to_owned);
+        add_config(
+            &mut config,
+            "irq-controller",
+            toml_edit::value(irq_controller),
+            comments.as_deref(),
+        );
+    }
 
     // Generate config.rs
     let mut output = Vec::new();
@@ -127,6 +160,10 @@ fn gen_config_rs(config_path: &Path) -> Result<Vec<u8>> {
                         writeln!(output, "pub const {var_name}: &str = \"{s}\";")?;
                     }
                 }
+                Value::Boolean(b) => {
+                    writeln!(output, "{comments}")?;
+                    writeln!(output, "pub const {var_name}: bool = {};", b.value())?;
+                }
                 Value::Array(regions) => {
                     if key != "mmio-regions" && key != "virtio-mmio-regions" && key != "pci-ranges"
                     {
@@ -168,5 +205,8 @@ fn main() -> Result<()> {
     println!("cargo:rerun-if-changed={}", config_path.display());
     println!("cargo:rerun-if-env-changed=AX_PLATFORM");
     println!("cargo:rerun-if-env-changed=AX_SMP");
+    println!("cargo:rerun-if-env-changed=AX_ASLR");
+    println!("cargo:rerun-if-env-changed=AX_GIC_VERSION");
+    println!("cargo:rerun-if-env-changed=AX_IRQ_CONTROLLER");
     Ok(())
 }
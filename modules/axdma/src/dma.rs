@@ -1,7 +1,7 @@
 use core::{alloc::Layout, ptr::NonNull};
 
 use allocator::{AllocError, AllocResult, BaseAllocator, ByteAllocator};
-use axalloc::{global_allocator, DefaultByteAllocator};
+use axalloc::{global_allocator, DefaultByteAllocator, PageHint};
 use axhal::{mem::virt_to_phys, paging::MappingFlags};
 use kspin::SpinNoIrq;
 use log::{debug, error};
@@ -54,7 +54,11 @@ impl DmaAllocator {
                 // 4 pages or available pages.
                 let num_pages = 4.min(available_pages);
                 let expand_size = num_pages * PAGE_SIZE_4K;
-                let vaddr_raw = global_allocator().alloc_pages(num_pages, PAGE_SIZE_4K)?;
+                let vaddr_raw = global_allocator().alloc_pages_hint(
+                    num_pages,
+                    PAGE_SIZE_4K,
+                    PageHint::dma32(),
+                )?;
                 let vaddr = va!(vaddr_raw);
                 self.update_flags(
                     vaddr,
@@ -71,8 +75,11 @@ impl DmaAllocator {
 
     fn alloc_coherent_pages(&mut self, layout: Layout) -> AllocResult<DMAInfo> {
         let num_pages = layout_pages(&layout);
-        let vaddr_raw =
-            global_allocator().alloc_pages(num_pages, PAGE_SIZE_4K.max(layout.align()))?;
+        let vaddr_raw = global_allocator().alloc_pages_hint(
+            num_pages,
+            PAGE_SIZE_4K.max(layout.align()),
+            PageHint::dma32(),
+        )?;
         let vaddr = va!(vaddr_raw);
         self.update_flags(
             vaddr,
@@ -3,9 +3,8 @@ use crate::{prelude::*, AllDevices};
 
 impl AllDevices {
     pub(crate) fn probe_bus_devices(&mut self) {
-        // TODO: parse device tree
         #[cfg(feature = "virtio")]
-        for reg in axconfig::VIRTIO_MMIO_REGIONS {
+        for reg in axhal::mem::virtio_mmio_regions() {
             for_each_drivers!(type Driver, {
                 if let Some(dev) = Driver::probe_mmio(reg.0, reg.1) {
                     info!(
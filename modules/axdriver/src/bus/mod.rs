@@ -1,4 +1,6 @@
 #[cfg(bus = "mmio")]
 mod mmio;
+#[cfg(all(bus = "pci", feature = "irq"))]
+pub(crate) mod msi;
 #[cfg(bus = "pci")]
 mod pci;
@@ -0,0 +1,236 @@
+//! PCI interrupts with MSI-X and MSI.
+//!
+//! The vectors are allocated from the interrupt controller by
+//! [`axhal::irq::alloc_msi_irq`] and programmed in the function first. Once
+//! the driver is initialized, they are either registered, and the interrupts
+//! are forwarded to the hook of the device category (see [`crate::irq`]), or
+//! released if the device can not use them.
+
+use axdriver_base::DeviceType;
+use axdriver_pci::{BarInfo, Command, DeviceFunction, PciRoot};
+use axhal::irq::MsiMessage;
+use axhal::mem::phys_to_virt;
+
+const PCI_CAP_ID_MSI: u8 = 0x05;
+const PCI_CAP_ID_MSIX: u8 = 0x11;
+
+/// The bits of the message control register of the MSI capability.
+const MSI_CTRL_ENABLE: u16 = 1 << 0;
+const MSI_CTRL_64BIT: u16 = 1 << 7;
+
+/// The bits of the message control register of the MSI-X capability.
+const MSIX_CTRL_TABLE_SIZE_MASK: u16 = 0x7ff;
+const MSIX_CTRL_FUNCTION_MASK: u16 = 1 << 14;
+const MSIX_CTRL_ENABLE: u16 = 1 << 15;
+
+/// The maximum number of vectors enabled for a PCI function.
+pub(crate) const MAX_VECTORS: usize = 8;
+
+const MSIX_ENTRY_SIZE: usize = 16;
+/// The mask bit of the vector control of an MSI-X table entry.
+const MSIX_ENTRY_MASKED: u32 = 1 << 0;
+
+/// The interrupt capability of a PCI function.
+#[derive(Clone, Copy)]
+enum MsiCap {
+    Msi {
+        offset: u8,
+        control: u16,
+    },
+    MsiX {
+        offset: u8,
+        control: u16,
+        /// The virtual address of the MSI-X table.
+        table: usize,
+    },
+}
+
+/// Returns the PCI requester ID of the function, which identifies it in MSIs.
+fn requester_id(bdf: DeviceFunction) -> u32 {
+    ((bdf.bus as u32) << 8) | ((bdf.device as u32) << 3) | bdf.function as u32
+}
+
+/// Returns the virtual address of `offset` in the memory BAR `bar`.
+pub(crate) fn bar_vaddr(
+    root: &PciRoot,
+    bdf: DeviceFunction,
+    bar: u8,
+    offset: u32,
+) -> Option<usize> {
+    match root.bar_info(bdf, bar).ok()? {
+        BarInfo::Memory { address, .. } if address != 0 => {
+            let paddr = address as usize + offset as usize;
+            Some(phys_to_virt(paddr.into()).as_usize())
+        }
+        _ => None,
+    }
+}
+
+/// Finds the MSI-X capability of the function, otherwise the MSI capability.
+fn find_msi_cap(root: &PciRoot, bdf: DeviceFunction) -> Option<MsiCap> {
+    let mut msi = None;
+    for cap in root.capabilities(bdf) {
+        match cap.id {
+            PCI_CAP_ID_MSIX => {
+                // The BAR indicator (BIR) and the offset of the table.
+                let table = root.config_read_word(bdf, cap.offset + 4);
+                if let Some(table) = bar_vaddr(root, bdf, (table & 0x7) as u8, table & !0x7) {
+                    return Some(MsiCap::MsiX {
+                        offset: cap.offset,
+                        control: cap.private_header,
+                        table,
+                    });
+                }
+            }
+            PCI_CAP_ID_MSI => {
+                msi = Some(MsiCap::Msi {
+                    offset: cap.offset,
+                    control: cap.private_header,
+                });
+            }
+            _ => {}
+        }
+    }
+    msi
+}
+
+/// Writes the message control register of the capability at `offset`.
+fn set_control(root: &mut PciRoot, bdf: DeviceFunction, offset: u8, control: u16) {
+    let header = root.config_read_word(bdf, offset) & 0xffff;
+    root.config_write_word(bdf, offset, header | ((control as u32) << 16));
+}
+
+fn write_msi(root: &mut PciRoot, bdf: DeviceFunction, offset: u8, control: u16, msg: MsiMessage) {
+    root.config_write_word(bdf, offset + 4, msg.address as u32);
+    if control & MSI_CTRL_64BIT != 0 {
+        root.config_write_word(bdf, offset + 8, (msg.address >> 32) as u32);
+        root.config_write_word(bdf, offset + 12, msg.data);
+    } else {
+        root.config_write_word(bdf, offset + 8, msg.data);
+    }
+}
+
+fn write_msix_entry(table: usize, idx: usize, msg: MsiMessage) {
+    let entry = (table + idx * MSIX_ENTRY_SIZE) as *mut u32;
+    unsafe {
+        entry.write_volatile(msg.address as u32);
+        entry.add(1).write_volatile((msg.address >> 32) as u32);
+        entry.add(2).write_volatile(msg.data);
+        let ctrl = entry.add(3).read_volatile();
+        entry.add(3).write_volatile(ctrl & !MSIX_ENTRY_MASKED);
+    }
+}
+
+/// The interrupt vectors enabled for a PCI function by [`enable_msi`].
+pub(crate) struct MsiVectors {
+    cap: MsiCap,
+    irqs: [usize; MAX_VECTORS],
+    len: usize,
+    /// The number of vectors whose IRQs are registered, from the first one.
+    registered: usize,
+}
+
+impl MsiVectors {
+    /// Whether they are MSI-X vectors, otherwise there is a single MSI vector.
+    pub fn is_msix(&self) -> bool {
+        matches!(self.cap, MsiCap::MsiX { .. })
+    }
+
+    /// Returns the number of vectors.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Registers the IRQs of the vectors as the interrupts of a device of the
+    /// category `ty`. Returns `false` if any registration failed.
+    pub fn register(&mut self, ty: DeviceType) -> bool {
+        while self.registered < self.len {
+            if !crate::irq::register_device_irq(self.irqs[self.registered], ty) {
+                return false;
+            }
+            self.registered += 1;
+        }
+        true
+    }
+
+    /// Disables MSI-X or MSI in the function, re-enables the legacy INTx
+    /// interrupt, and frees the vectors which are not registered.
+    pub fn release(self, root: &mut PciRoot, bdf: DeviceFunction) {
+        match self.cap {
+            MsiCap::MsiX {
+                offset, control, ..
+            } => set_control(root, bdf, offset, control & !MSIX_CTRL_ENABLE),
+            MsiCap::Msi { offset, control } => {
+                set_control(root, bdf, offset, control & !MSI_CTRL_ENABLE)
+            }
+        }
+        let (_status, cmd) = root.get_status_command(bdf);
+        root.set_command(bdf, cmd - Command::INTERRUPT_DISABLE);
+        for &irq_num in &self.irqs[self.registered..self.len] {
+            axhal::irq::free_msi_irq(irq_num);
+        }
+    }
+}
+
+/// Enables up to `count` (at most [`MAX_VECTORS`]) interrupt vectors of the
+/// PCI function with MSI-X, or one vector with MSI. The legacy INTx interrupt
+/// is disabled.
+///
+/// Vector `i` is the entry `i` of the MSI-X table. The IRQs of the vectors
+/// are not registered yet, see [`MsiVectors::register`]. Returns [`None`] if
+/// the function supports neither MSI-X nor MSI, or the interrupt controller
+/// does not support MSIs.
+pub(crate) fn enable_msi(
+    root: &mut PciRoot,
+    bdf: DeviceFunction,
+    count: usize,
+) -> Option<MsiVectors> {
+    let cap = find_msi_cap(root, bdf)?;
+    let device_id = requester_id(bdf);
+    let mut vectors = MsiVectors {
+        cap,
+        irqs: [0; MAX_VECTORS],
+        len: 0,
+        registered: 0,
+    };
+
+    match cap {
+        MsiCap::MsiX {
+            offset,
+            control,
+            table,
+        } => {
+            let table_size = (control & MSIX_CTRL_TABLE_SIZE_MASK) as usize + 1;
+            // Mask all vectors while programming the table.
+            let control = control | MSIX_CTRL_ENABLE;
+            set_control(root, bdf, offset, control | MSIX_CTRL_FUNCTION_MASK);
+            while vectors.len < count.min(table_size).min(MAX_VECTORS) {
+                let Some((irq_num, msg)) = axhal::irq::alloc_msi_irq(device_id) else {
+                    break;
+                };
+                debug!("  MSI-X vector {}: IRQ {}", vectors.len, irq_num);
+                write_msix_entry(table, vectors.len, msg);
+                vectors.irqs[vectors.len] = irq_num;
+                vectors.len += 1;
+            }
+            if vectors.len == 0 {
+                set_control(root, bdf, offset, control & !MSIX_CTRL_ENABLE);
+                return None;
+            }
+            set_control(root, bdf, offset, control & !MSIX_CTRL_FUNCTION_MASK);
+        }
+        MsiCap::Msi { offset, control } => {
+            let (irq_num, msg) = axhal::irq::alloc_msi_irq(device_id)?;
+            debug!("  MSI: IRQ {}", irq_num);
+            write_msi(root, bdf, offset, control, msg);
+            // Only one message is enabled (`Multiple Message Enable` = 0).
+            set_control(root, bdf, offset, (control & !(0x7 << 4)) | MSI_CTRL_ENABLE);
+            vectors.irqs[0] = irq_num;
+            vectors.len = 1;
+        }
+    }
+
+    let (_status, cmd) = root.get_status_command(bdf);
+    root.set_command(bdf, cmd | Command::INTERRUPT_DISABLE);
+    Some(vectors)
+}
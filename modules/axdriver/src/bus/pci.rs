@@ -84,7 +84,8 @@ fn config_pci_device(
 
 impl AllDevices {
     pub(crate) fn probe_bus_devices(&mut self) {
-        let base_vaddr = phys_to_virt(axconfig::PCI_ECAM_BASE.into());
+        let (ecam_base, buses) = axhal::mem::pci_ecam();
+        let base_vaddr = phys_to_virt(ecam_base);
         let mut root = unsafe { PciRoot::new(base_vaddr.as_mut_ptr(), Cam::Ecam) };
 
         // PCI 32-bit MMIO space
@@ -92,7 +93,7 @@ impl AllDevices {
             .get(1)
             .map(|range| PciRangeAllocator::new(range.0 as u64, range.1 as u64));
 
-        for bus in 0..=axconfig::PCI_BUS_END as u8 {
+        for bus in buses {
             for (bdf, dev_info) in root.enumerate_bus(bus) {
                 debug!("PCI {}: {}", bdf, dev_info);
                 if dev_info.header_type != HeaderType::Standard {
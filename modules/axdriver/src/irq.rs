@@ -0,0 +1,129 @@
+//! Device interrupts.
+//!
+//! Drivers register the interrupts of their devices by device category, and
+//! the upper-layer subsystems set a hook for each category to be notified of
+//! the interrupts (e.g., to wake up the tasks waiting for the devices). The
+//! devices of a category without interrupts must be polled.
+//!
+//! Drivers that wait for their devices to complete requests (e.g., virtio-blk)
+//! block the current task with the wait hook of the category instead of
+//! spinning, if it is set (see [`set_wait_hook`]).
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use axdriver_base::DeviceType;
+
+/// The number of device categories ([`DeviceType`]).
+const NUM_DEVICE_TYPES: usize = 4;
+
+/// The hook of each device category, see [`set_irq_hook`].
+static IRQ_HOOKS: [AtomicUsize; NUM_DEVICE_TYPES] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+];
+
+/// The wait hook of each device category, see [`set_wait_hook`].
+static WAIT_HOOKS: [AtomicUsize; NUM_DEVICE_TYPES] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+];
+
+/// The number of interrupts of each device category, see [`irq_count`].
+static IRQ_COUNTS: [AtomicUsize; NUM_DEVICE_TYPES] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+];
+
+/// Whether each device category has any device with interrupts.
+static HAS_IRQ: [AtomicBool; NUM_DEVICE_TYPES] = [
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+];
+
+/// Sets the function called on each interrupt of the devices of the category
+/// `ty`.
+///
+/// The hook is called in the interrupt context, so it must not block.
+pub fn set_irq_hook(ty: DeviceType, hook: fn()) {
+    IRQ_HOOKS[ty as usize].store(hook as usize, Ordering::Release);
+}
+
+/// Sets the function that blocks the current task until the next interrupt
+/// of the devices of the category `ty`.
+///
+/// It is called with the [`irq_count`] of the category from before the
+/// request to the device, and must return once the count has changed (so the
+/// IRQ hook should wake it up), or after a short timeout in case the
+/// interrupt is lost. It is only called in the task context with IRQs
+/// enabled.
+pub fn set_wait_hook(ty: DeviceType, hook: fn(usize)) {
+    WAIT_HOOKS[ty as usize].store(hook as usize, Ordering::Release);
+}
+
+/// Returns the number of interrupts of the devices of the category `ty` so
+/// far.
+pub fn irq_count(ty: DeviceType) -> usize {
+    IRQ_COUNTS[ty as usize].load(Ordering::Acquire)
+}
+
+/// Returns whether the devices of the category `ty` raise interrupts.
+/// Otherwise, they must be polled.
+pub fn has_irq(ty: DeviceType) -> bool {
+    HAS_IRQ[ty as usize].load(Ordering::Acquire)
+}
+
+/// Waits for an interrupt of the devices of the category `ty` with its wait
+/// hook, if the [`irq_count`] was `count` before the request to the device.
+///
+/// It returns immediately if the category has no interrupts or no wait hook,
+/// or if IRQs are disabled (e.g., at boot), in which case the caller polls
+/// the device.
+pub(crate) fn wait_for_irq(ty: DeviceType, count: usize) {
+    if !has_irq(ty) || !axhal::arch::irqs_enabled() {
+        return;
+    }
+    let hook = WAIT_HOOKS[ty as usize].load(Ordering::Acquire);
+    if hook != 0 {
+        let hook: fn(usize) = unsafe { core::mem::transmute(hook) };
+        hook(count);
+    }
+}
+
+fn call_hook(ty: DeviceType) {
+    IRQ_COUNTS[ty as usize].fetch_add(1, Ordering::Release);
+    let hook = IRQ_HOOKS[ty as usize].load(Ordering::Acquire);
+    if hook != 0 {
+        let hook: fn() = unsafe { core::mem::transmute(hook) };
+        hook();
+    }
+}
+
+/// Registers the IRQ `irq_num` of a device of the category `ty`, whose
+/// interrupts are forwarded to the hook of the category.
+///
+/// It returns `false` if the registration failed. The category is marked as
+/// having interrupts by [`set_has_irq`], once all the IRQs of the device are
+/// registered.
+pub(crate) fn register_device_irq(irq_num: usize, ty: DeviceType) -> bool {
+    let handler: axhal::irq::IrqHandler = match ty {
+        DeviceType::Block => || call_hook(DeviceType::Block),
+        DeviceType::Char => || call_hook(DeviceType::Char),
+        DeviceType::Net => || call_hook(DeviceType::Net),
+        DeviceType::Display => || call_hook(DeviceType::Display),
+    };
+    axhal::irq::register_handler(irq_num, handler)
+}
+
+/// Marks the devices of the category `ty` as raising interrupts, see
+/// [`has_irq`].
+pub(crate) fn set_has_irq(ty: DeviceType) {
+    HAS_IRQ[ty as usize].store(true, Ordering::Release);
+}
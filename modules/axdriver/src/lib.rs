@@ -41,6 +41,8 @@
 //! - `bus-mmio`: use device tree to probe all MMIO devices.
 //! - `bus-pci`: use PCI bus to probe all PCI devices. This feature is
 //!    enabeld by default.
+//! - `irq`: use interrupts of the devices, e.g., MSI-X or MSI of PCI devices.
+//!   The upper-layer subsystems are notified by the hooks in [`irq`].
 //! - `virtio`: use VirtIO devices. This is enabled if any of `virtio-blk`,
 //!   `virtio-net` or `virtio-gpu` is enabled.
 //! - `net`: use network devices. This is enabled if any feature of network
@@ -78,6 +80,9 @@ mod virtio;
 #[cfg(feature = "ixgbe")]
 mod ixgbe;
 
+#[cfg(feature = "irq")]
+pub mod irq;
+
 pub mod prelude;
 
 #[allow(unused_imports)]
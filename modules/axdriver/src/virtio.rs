@@ -12,6 +12,9 @@ use crate::{drivers::DriverProbe, AxDeviceEnum};
 cfg_if! {
     if #[cfg(bus = "pci")] {
         use axdriver_pci::{PciRoot, DeviceFunction, DeviceFunctionInfo};
+        #[cfg(feature = "irq")]
+        type VirtIoTransport = self::msix::MsixPciTransport;
+        #[cfg(not(feature = "irq"))]
         type VirtIoTransport = axdriver_virtio::PciTransport;
     } else if #[cfg(bus =  "mmio")] {
         type VirtIoTransport = axdriver_virtio::MmioTransport;
@@ -121,9 +124,21 @@ impl<D: VirtIoDevMeta> DriverProbe for VirtIoDriver<D> {
             axdriver_virtio::probe_pci_device::<VirtIoHalImpl>(root, bdf, dev_info)
         {
             if ty == D::DEVICE_TYPE {
+                #[cfg(feature = "irq")]
+                let (transport, irqs) = msix::MsixPciTransport::new(root, bdf, transport, ty);
                 match D::try_new(transport) {
-                    Ok(dev) => return Some(dev),
+                    Ok(dev) => {
+                        #[cfg(feature = "irq")]
+                        if let Some(irqs) = irqs {
+                            irqs.finish(root, bdf, ty);
+                        }
+                        return Some(dev);
+                    }
                     Err(e) => {
+                        #[cfg(feature = "irq")]
+                        if let Some(irqs) = irqs {
+                            irqs.release(root, bdf);
+                        }
                         warn!(
                             "failed to initialize PCI device at {}({}): {:?}",
                             bdf, dev_info, e
@@ -170,3 +185,267 @@ unsafe impl VirtIoHal for VirtIoHalImpl {
     #[inline]
     unsafe fn unshare(_paddr: PhysAddr, _buffer: NonNull<[u8]>, _direction: BufferDirection) {}
 }
+
+/// Interrupts of virtio-pci devices with MSI-X (or MSI).
+#[cfg(all(bus = "pci", feature = "irq"))]
+mod msix {
+    use core::ptr::NonNull;
+
+    use axdriver_base::DeviceType;
+    use axdriver_pci::{DeviceFunction, PciRoot};
+    use axdriver_virtio::virtio_drivers::transport::{
+        DeviceStatus, DeviceType as VirtIoDeviceType,
+    };
+    use axdriver_virtio::virtio_drivers::Error;
+    use axdriver_virtio::{PciTransport, PhysAddr, Transport};
+
+    use crate::bus::msi::{self, MsiVectors};
+
+    /// The offset of `msix_config` in the virtio-pci common configuration.
+    const VIRTIO_PCI_COMMON_MSIX_CONFIG: usize = 0x10;
+    const VIRTIO_PCI_COMMON_NUM_QUEUES: usize = 0x12;
+    const VIRTIO_PCI_COMMON_QUEUE_SELECT: usize = 0x16;
+    const VIRTIO_PCI_COMMON_QUEUE_MSIX_VECTOR: usize = 0x1a;
+    /// No vector, which is also read back if the device failed to map one.
+    const VIRTIO_MSI_NO_VECTOR: u16 = 0xffff;
+
+    /// Returns the virtual address of the common configuration of the
+    /// virtio-pci device, from its vendor-specific capability.
+    fn virtio_pci_common_cfg(root: &PciRoot, bdf: DeviceFunction) -> Option<usize> {
+        const PCI_CAP_ID_VNDR: u8 = 0x09;
+        const VIRTIO_PCI_CAP_COMMON_CFG: u16 = 1;
+        let cap = root.capabilities(bdf).find(|cap| {
+            cap.id == PCI_CAP_ID_VNDR && cap.private_header >> 8 == VIRTIO_PCI_CAP_COMMON_CFG
+        })?;
+        let bar = root.config_read_word(bdf, cap.offset + 4) & 0xff;
+        let offset = root.config_read_word(bdf, cap.offset + 8);
+        msi::bar_vaddr(root, bdf, bar as u8, offset)
+    }
+
+    /// The mapping of the MSI-X vectors of a virtio-pci device: vector 0 for
+    /// the configuration changes, and one vector for each virtqueue if
+    /// possible. Otherwise, the virtqueues share the last vector.
+    #[derive(Clone, Copy)]
+    struct MsixMapping {
+        common_cfg: usize,
+        num_vectors: usize,
+    }
+
+    impl MsixMapping {
+        fn reg(&self, offset: usize) -> *mut u16 {
+            (self.common_cfg + offset) as *mut u16
+        }
+
+        fn num_queues(&self) -> usize {
+            unsafe { self.reg(VIRTIO_PCI_COMMON_NUM_QUEUES).read_volatile() as usize }
+        }
+
+        fn queue_vector(&self, queue: usize) -> u16 {
+            (queue + 1).min(self.num_vectors - 1) as u16
+        }
+
+        /// Writes the mapping to the device.
+        fn map(&self) {
+            unsafe {
+                self.reg(VIRTIO_PCI_COMMON_MSIX_CONFIG).write_volatile(0);
+                for queue in 0..self.num_queues() {
+                    self.reg(VIRTIO_PCI_COMMON_QUEUE_SELECT)
+                        .write_volatile(queue as u16);
+                    self.reg(VIRTIO_PCI_COMMON_QUEUE_MSIX_VECTOR)
+                        .write_volatile(self.queue_vector(queue));
+                }
+            }
+        }
+
+        /// Reads the mapping back, and returns whether the device accepted
+        /// all the vectors.
+        fn is_mapped(&self) -> bool {
+            unsafe {
+                if self.reg(VIRTIO_PCI_COMMON_MSIX_CONFIG).read_volatile() == VIRTIO_MSI_NO_VECTOR {
+                    return false;
+                }
+                (0..self.num_queues()).all(|queue| {
+                    self.reg(VIRTIO_PCI_COMMON_QUEUE_SELECT)
+                        .write_volatile(queue as u16);
+                    self.reg(VIRTIO_PCI_COMMON_QUEUE_MSIX_VECTOR)
+                        .read_volatile()
+                        != VIRTIO_MSI_NO_VECTOR
+                })
+            }
+        }
+    }
+
+    /// The virtio-pci transport, which maps the MSI-X vectors of the device
+    /// right before the driver sets `DRIVER_OK`. They can not be mapped
+    /// earlier, as the driver resets the device (which clears the mapping)
+    /// at the beginning of its initialization.
+    ///
+    /// The block driver spins until each request is completed after
+    /// notifying the device, so the transport of a block device waits for
+    /// the interrupt of the completion in [`Transport::notify`] instead (see
+    /// [`crate::irq::set_wait_hook`]).
+    pub struct MsixPciTransport {
+        inner: PciTransport,
+        msix: Option<MsixMapping>,
+        /// The device category to wait for the interrupts of on notification.
+        wait_irq: Option<DeviceType>,
+    }
+
+    /// The interrupt vectors of a virtio-pci device being initialized.
+    pub struct PciIrqs {
+        vectors: MsiVectors,
+        msix: Option<MsixMapping>,
+    }
+
+    impl MsixPciTransport {
+        /// Wraps the transport of the device at `bdf` of the category `ty`.
+        /// For network and block devices, MSI-X (or MSI) vectors are enabled,
+        /// and returned to be registered after the driver is initialized (see
+        /// [`PciIrqs::finish`]).
+        pub fn new(
+            root: &mut PciRoot,
+            bdf: DeviceFunction,
+            inner: PciTransport,
+            ty: DeviceType,
+        ) -> (Self, Option<PciIrqs>) {
+            let mut transport = Self {
+                inner,
+                msix: None,
+                wait_irq: None,
+            };
+            let use_irqs = matches!(ty, DeviceType::Net | DeviceType::Block);
+            let common_cfg = virtio_pci_common_cfg(root, bdf).filter(|_| use_irqs);
+            let Some(common_cfg) = common_cfg else {
+                return (transport, None);
+            };
+            let num_queues = MsixMapping {
+                common_cfg,
+                num_vectors: 0,
+            }
+            .num_queues();
+            let Some(vectors) = msi::enable_msi(root, bdf, num_queues + 1) else {
+                warn!("no MSI-X or MSI vectors for PCI device {}, polling it", bdf);
+                return (transport, None);
+            };
+            // With MSI, the single vector is not mapped in the device.
+            if vectors.is_msix() {
+                transport.msix = Some(MsixMapping {
+                    common_cfg,
+                    num_vectors: vectors.len(),
+                });
+            }
+            if ty == DeviceType::Block {
+                transport.wait_irq = Some(ty);
+            }
+            let msix = transport.msix;
+            (transport, Some(PciIrqs { vectors, msix }))
+        }
+    }
+
+    impl PciIrqs {
+        /// Registers the vectors as the interrupts of the device of the
+        /// category `ty`, after the driver is initialized. They are released
+        /// if the device did not accept the MSI-X vectors.
+        pub fn finish(mut self, root: &mut PciRoot, bdf: DeviceFunction, ty: DeviceType) {
+            if self.msix.is_some_and(|msix| !msix.is_mapped()) {
+                warn!("PCI device {} rejected the MSI-X vectors, polling it", bdf);
+                self.vectors.release(root, bdf);
+            } else if !self.vectors.register(ty) {
+                warn!(
+                    "failed to register the IRQs of PCI device {}, polling it",
+                    bdf
+                );
+                self.vectors.release(root, bdf);
+            } else {
+                crate::irq::set_has_irq(ty);
+                info!(
+                    "enabled {} {} vectors for PCI device {}",
+                    self.vectors.len(),
+                    if self.msix.is_some() { "MSI-X" } else { "MSI" },
+                    bdf
+                );
+            }
+        }
+
+        /// Releases the vectors, if the driver failed to initialize.
+        pub fn release(self, root: &mut PciRoot, bdf: DeviceFunction) {
+            self.vectors.release(root, bdf);
+        }
+    }
+
+    impl Transport for MsixPciTransport {
+        fn device_type(&self) -> VirtIoDeviceType {
+            self.inner.device_type()
+        }
+
+        fn read_device_features(&mut self) -> u64 {
+            self.inner.read_device_features()
+        }
+
+        fn write_driver_features(&mut self, driver_features: u64) {
+            self.inner.write_driver_features(driver_features)
+        }
+
+        fn max_queue_size(&mut self, queue: u16) -> u32 {
+            self.inner.max_queue_size(queue)
+        }
+
+        fn notify(&mut self, queue: u16) {
+            let Some(ty) = self.wait_irq else {
+                return self.inner.notify(queue);
+            };
+            let count = crate::irq::irq_count(ty);
+            self.inner.notify(queue);
+            crate::irq::wait_for_irq(ty, count);
+        }
+
+        fn get_status(&self) -> DeviceStatus {
+            self.inner.get_status()
+        }
+
+        fn set_status(&mut self, status: DeviceStatus) {
+            if status.contains(DeviceStatus::DRIVER_OK) {
+                if let Some(msix) = self.msix.take() {
+                    msix.map();
+                }
+            }
+            self.inner.set_status(status)
+        }
+
+        fn set_guest_page_size(&mut self, guest_page_size: u32) {
+            self.inner.set_guest_page_size(guest_page_size)
+        }
+
+        fn requires_legacy_layout(&self) -> bool {
+            self.inner.requires_legacy_layout()
+        }
+
+        fn queue_set(
+            &mut self,
+            queue: u16,
+            size: u32,
+            descriptors: PhysAddr,
+            driver_area: PhysAddr,
+            device_area: PhysAddr,
+        ) {
+            self.inner
+                .queue_set(queue, size, descriptors, driver_area, device_area)
+        }
+
+        fn queue_unset(&mut self, queue: u16) {
+            self.inner.queue_unset(queue)
+        }
+
+        fn queue_used(&mut self, queue: u16) -> bool {
+            self.inner.queue_used(queue)
+        }
+
+        fn ack_interrupt(&mut self) -> bool {
+            self.inner.ack_interrupt()
+        }
+
+        fn config_space<T: 'static>(&self) -> Result<NonNull<T>, Error> {
+            self.inner.config_space()
+        }
+    }
+}
@@ -4,7 +4,7 @@ use alloc::string::{String, ToString};
 use axerrno::AxResult;
 use core::ffi::{c_char, c_void, CStr};
 
-pub fn mount(source: &str, target: &str, _fstype: &str, _flags: u64, _data: *const c_void) -> i32 {
+pub fn mount(source: &str, target: &str, fstype: &str, _flags: u64, _data: *const c_void) -> i32 {
     let file_system = match crate::root::find_mounted_fs(source) {
         Ok(fs) => fs,
         Err(e) => {
@@ -16,8 +16,9 @@ pub fn mount(source: &str, target: &str, _fstype: &str, _flags: u64, _data: *con
     let target = to_root_path(target).unwrap();
 
     let target = Box::leak(target.into_boxed_str());
+    let fstype = Box::leak(fstype.to_string().into_boxed_str());
 
-    match crate::root::mount_fs(target, file_system) {
+    match crate::root::mount_fs(target, fstype, file_system) {
         Ok(()) => {}
         Err(e) => {
             warn!("mount: mount_fs failed: {:?}", e);
@@ -2,6 +2,24 @@ use axdriver::prelude::*;
 
 const BLOCK_SIZE: usize = 512;
 
+/// How long a block request waits for the interrupt, in case it is lost.
+#[cfg(all(feature = "irq", feature = "multitask"))]
+const IRQ_WAIT_TIMEOUT: core::time::Duration = core::time::Duration::from_millis(10);
+
+#[cfg(all(feature = "irq", feature = "multitask"))]
+static BLOCK_IRQ_WQ: axtask::WaitQueue = axtask::WaitQueue::new();
+
+/// Lets the block drivers sleep until the interrupts of the devices, see
+/// [`axdriver::irq::set_wait_hook`].
+#[cfg(all(feature = "irq", feature = "multitask"))]
+pub(crate) fn init_irq_hooks() {
+    use axdriver::irq::{irq_count, set_irq_hook, set_wait_hook};
+    set_irq_hook(DeviceType::Block, || BLOCK_IRQ_WQ.notify_all(false));
+    set_wait_hook(DeviceType::Block, |count| {
+        BLOCK_IRQ_WQ.wait_timeout_until(IRQ_WAIT_TIMEOUT, || irq_count(DeviceType::Block) != count);
+    });
+}
+
 /// A disk device with a cursor.
 pub struct Disk {
     block_id: u64,
@@ -174,6 +174,8 @@ impl File {
         if opts.truncate {
             node.truncate(0)?;
         }
+        #[cfg(feature = "procfs")]
+        let node = crate::fs::procfs::open_handle(node);
 
         Ok(Self {
             node: WithCap::new(node, access_cap),
@@ -0,0 +1,627 @@
+//! The process filesystem (procfs), whose files are generated from the kernel
+//! state when they are read.
+//!
+//! Other modules publish their files by [`register`], and the directories
+//! whose entries change over time (e.g., one per task) by
+//! [`register_generator`]. Files created by [`ProcFile::new_writable`] are
+//! tunables: the written value is passed to the kernel module that owns it.
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use alloc::{boxed::Box, vec::Vec};
+
+use axerrno::{ax_err, AxResult};
+use axfs_vfs::{VfsDirEntry, VfsNodeAttr, VfsNodePerm, VfsNodeType, VfsOps};
+use axsync::Mutex;
+use lazyinit::LazyInit;
+
+pub use axfs_vfs::{VfsError, VfsNodeOps, VfsNodeRef, VfsResult};
+
+type ReadFn = Box<dyn Fn() -> String + Send + Sync>;
+type WriteFn = Box<dyn Fn(&str) -> VfsResult + Send + Sync>;
+type GeneratorFn = Arc<dyn Fn() -> Vec<(String, VfsNodeRef)> + Send + Sync>;
+
+static PROCFS: LazyInit<Arc<ProcFileSystem>> = LazyInit::new();
+
+/// The process filesystem.
+pub struct ProcFileSystem {
+    root: Arc<ProcDir>,
+}
+
+impl ProcFileSystem {
+    /// Create a new instance.
+    pub fn new() -> Self {
+        Self {
+            root: ProcDir::new(None),
+        }
+    }
+
+    /// Returns the root directory node in [`Arc<ProcDir>`](ProcDir).
+    pub fn root(&self) -> Arc<ProcDir> {
+        self.root.clone()
+    }
+}
+
+impl VfsOps for ProcFileSystem {
+    fn mount(&self, _path: &str, mount_point: VfsNodeRef) -> VfsResult {
+        if let Some(parent) = mount_point.parent() {
+            self.root.set_parent(Some(&parent));
+        }
+        Ok(())
+    }
+
+    fn root_dir(&self) -> VfsNodeRef {
+        self.root.clone()
+    }
+}
+
+impl Default for ProcFileSystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A file of the procfs, whose content is generated when it is read.
+///
+/// Each file opened by [`File`](crate::fops::File) keeps its own snapshot of
+/// the content, see `ProcFileHandle`. Reading the node itself generates the
+/// content on each read.
+pub struct ProcFile {
+    read: ReadFn,
+    write: Option<WriteFn>,
+}
+
+impl ProcFile {
+    /// Creates a read-only file, whose content is generated by `read`.
+    pub fn new(read: impl Fn() -> String + Send + Sync + 'static) -> Self {
+        Self {
+            read: Box::new(read),
+            write: None,
+        }
+    }
+
+    /// Creates a writable file, e.g., a tunable. The written value, with the
+    /// surrounding whitespaces trimmed, is passed to `write`.
+    pub fn new_writable(
+        read: impl Fn() -> String + Send + Sync + 'static,
+        write: impl Fn(&str) -> VfsResult + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            read: Box::new(read),
+            write: Some(Box::new(write)),
+        }
+    }
+}
+
+/// Copies the bytes of `content` at `offset` into `buf`.
+fn read_content(content: &str, offset: u64, buf: &mut [u8]) -> usize {
+    let bytes = content.as_bytes();
+    let start = bytes.len().min(offset as usize);
+    let len = buf.len().min(bytes.len() - start);
+    buf[..len].copy_from_slice(&bytes[start..start + len]);
+    len
+}
+
+impl VfsNodeOps for ProcFile {
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        let perm = if self.write.is_some() { 0o644 } else { 0o444 };
+        // The size is unknown until the content is generated, as on Linux.
+        Ok(VfsNodeAttr::new(
+            VfsNodePerm::from_bits_truncate(perm),
+            VfsNodeType::File,
+            0,
+            0,
+        ))
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        Ok(read_content(&(self.read)(), offset, buf))
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        let Some(write) = &self.write else {
+            return Err(VfsError::PermissionDenied);
+        };
+        if offset != 0 {
+            return Err(VfsError::InvalidInput);
+        }
+        let value = core::str::from_utf8(buf).map_err(|_| VfsError::InvalidData)?;
+        write(value.trim())?;
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, _size: u64) -> VfsResult {
+        match self.write {
+            Some(_) => Ok(()),
+            None => Err(VfsError::PermissionDenied),
+        }
+    }
+
+    fn as_any(&self) -> &dyn core::any::Any {
+        self
+    }
+
+    axfs_vfs::impl_vfs_non_dir_default! {}
+}
+
+/// An open [`ProcFile`], with its own snapshot of the content.
+///
+/// The content is generated when it is read from the beginning, and the
+/// following reads at nonzero offsets see the same snapshot, so that a file
+/// read in several chunks is consistent, regardless of the other openers.
+struct ProcFileHandle {
+    file: VfsNodeRef,
+    snapshot: Mutex<Option<String>>,
+}
+
+impl ProcFileHandle {
+    fn proc_file(&self) -> &ProcFile {
+        // Only created by `open_handle` for a `ProcFile`.
+        self.file.as_any().downcast_ref::<ProcFile>().unwrap()
+    }
+}
+
+impl VfsNodeOps for ProcFileHandle {
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        self.file.get_attr()
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        let mut snapshot = self.snapshot.lock();
+        if offset == 0 || snapshot.is_none() {
+            *snapshot = Some((self.proc_file().read)());
+        }
+        Ok(read_content(
+            snapshot.as_deref().unwrap_or_default(),
+            offset,
+            buf,
+        ))
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        self.file.write_at(offset, buf)
+    }
+
+    fn truncate(&self, size: u64) -> VfsResult {
+        self.file.truncate(size)
+    }
+
+    fn as_any(&self) -> &dyn core::any::Any {
+        self
+    }
+
+    axfs_vfs::impl_vfs_non_dir_default! {}
+}
+
+/// Returns the node to read an opened file through: a `ProcFileHandle` with
+/// its own snapshot for a [`ProcFile`], or the node itself otherwise.
+pub(crate) fn open_handle(node: VfsNodeRef) -> VfsNodeRef {
+    if node.as_any().is::<ProcFile>() {
+        Arc::new(ProcFileHandle {
+            file: node,
+            snapshot: Mutex::new(None),
+        })
+    } else {
+        node
+    }
+}
+
+/// A directory of the procfs.
+///
+/// Its entries are the nodes added by [`ProcDir::add`], followed by the ones
+/// generated by the generators when it is looked up or listed.
+pub struct ProcDir {
+    this: Weak<ProcDir>,
+    parent: Mutex<Weak<dyn VfsNodeOps>>,
+    children: Mutex<BTreeMap<String, VfsNodeRef>>,
+    generators: Mutex<Vec<GeneratorFn>>,
+}
+
+impl ProcDir {
+    /// Creates an empty directory.
+    pub fn new(parent: Option<Weak<dyn VfsNodeOps>>) -> Arc<Self> {
+        Arc::new_cyclic(|this| Self {
+            this: this.clone(),
+            parent: Mutex::new(parent.unwrap_or_else(|| Weak::<Self>::new() as _)),
+            children: Mutex::new(BTreeMap::new()),
+            generators: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Creates a directory with the given entries, e.g., by a generator.
+    pub fn with_entries(entries: impl IntoIterator<Item = (String, VfsNodeRef)>) -> Arc<Self> {
+        let dir = Self::new(None);
+        dir.children.lock().extend(entries);
+        dir
+    }
+
+    pub(super) fn set_parent(&self, parent: Option<&VfsNodeRef>) {
+        *self.parent.lock() = parent.map_or(Weak::<Self>::new() as _, Arc::downgrade);
+    }
+
+    /// Adds the node as the entry `name`, replacing the old one if exists.
+    pub fn add(&self, name: &str, node: VfsNodeRef) {
+        if let Some(dir) = node.as_any().downcast_ref::<ProcDir>() {
+            *dir.parent.lock() = self.this.clone();
+        }
+        self.children.lock().insert(name.into(), node);
+    }
+
+    /// Returns the subdirectory `name`, which is created if not exists.
+    ///
+    /// Returns [`NotADirectory`](VfsError::NotADirectory) if the entry
+    /// exists and is not a directory.
+    pub fn mkdir(&self, name: &str) -> VfsResult<Arc<ProcDir>> {
+        let mut children = self.children.lock();
+        if let Some(node) = children.get(name) {
+            return node
+                .as_any()
+                .downcast_ref::<ProcDir>()
+                .and_then(|dir| dir.this.upgrade())
+                .ok_or(VfsError::NotADirectory);
+        }
+        let dir = Self::new(Some(self.this.clone()));
+        children.insert(name.into(), dir.clone());
+        Ok(dir)
+    }
+
+    /// Adds a generator of the entries, which is called each time the
+    /// directory is looked up or listed.
+    pub fn add_generator(&self, f: impl Fn() -> Vec<(String, VfsNodeRef)> + Send + Sync + 'static) {
+        self.generators.lock().push(Arc::new(f));
+    }
+
+    /// Returns the generated entries. The generators are called without the
+    /// lock held, as they may access the procfs themselves.
+    fn generated_entries(&self) -> Vec<(String, VfsNodeRef)> {
+        let generators = self.generators.lock().clone();
+        let mut entries = Vec::new();
+        for f in generators {
+            for (name, node) in f() {
+                if let Some(dir) = node.as_any().downcast_ref::<ProcDir>() {
+                    *dir.parent.lock() = self.this.clone();
+                }
+                entries.push((name, node));
+            }
+        }
+        entries
+    }
+
+    fn find(&self, name: &str) -> Option<VfsNodeRef> {
+        if let Some(node) = self.children.lock().get(name) {
+            return Some(node.clone());
+        }
+        self.generated_entries()
+            .into_iter()
+            .find_map(|(n, node)| (n == name).then_some(node))
+    }
+}
+
+impl VfsNodeOps for ProcDir {
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        Ok(VfsNodeAttr::new(
+            VfsNodePerm::from_bits_truncate(0o555),
+            VfsNodeType::Dir,
+            0,
+            0,
+        ))
+    }
+
+    fn parent(&self) -> Option<VfsNodeRef> {
+        self.parent.lock().upgrade()
+    }
+
+    fn lookup(self: Arc<Self>, path: &str) -> VfsResult<VfsNodeRef> {
+        let (name, rest) = split_path(path);
+        let node = match name {
+            "" | "." => Ok(self.clone() as VfsNodeRef),
+            ".." => self.parent().ok_or(VfsError::NotFound),
+            _ => self.find(name).ok_or(VfsError::NotFound),
+        }?;
+
+        if let Some(rest) = rest {
+            node.lookup(rest)
+        } else {
+            Ok(node)
+        }
+    }
+
+    fn read_dir(&self, start_idx: usize, dirents: &mut [VfsDirEntry]) -> VfsResult<usize> {
+        let mut entries = self
+            .children
+            .lock()
+            .iter()
+            .map(|(name, node)| (name.clone(), node.clone()))
+            .collect::<Vec<_>>();
+        entries.extend(self.generated_entries());
+
+        let mut entries = [".", ".."]
+            .into_iter()
+            .map(|name| VfsDirEntry::new(name, VfsNodeType::Dir))
+            .chain(entries.iter().map(|(name, node)| {
+                let ty = node
+                    .get_attr()
+                    .map_or(VfsNodeType::File, |attr| attr.file_type());
+                VfsDirEntry::new(name, ty)
+            }))
+            .skip(start_idx);
+        for (i, out_entry) in dirents.iter_mut().enumerate() {
+            match entries.next() {
+                Some(entry) => *out_entry = entry,
+                None => return Ok(i),
+            }
+        }
+        Ok(dirents.len())
+    }
+
+    fn create(&self, _path: &str, _ty: VfsNodeType) -> VfsResult {
+        Err(VfsError::PermissionDenied)
+    }
+
+    fn remove(&self, _path: &str) -> VfsResult {
+        Err(VfsError::PermissionDenied)
+    }
+
+    fn as_any(&self) -> &dyn core::any::Any {
+        self
+    }
+
+    axfs_vfs::impl_vfs_dir_default! {}
+}
+
+fn split_path(path: &str) -> (&str, Option<&str>) {
+    let trimmed_path = path.trim_start_matches('/');
+    trimmed_path.find('/').map_or((trimmed_path, None), |n| {
+        (&trimmed_path[..n], Some(&trimmed_path[n + 1..]))
+    })
+}
+
+/// Creates the procfs, which is mounted on `/proc`.
+pub(crate) fn init() -> Arc<ProcFileSystem> {
+    PROCFS.init_once(Arc::new(ProcFileSystem::new()));
+    PROCFS.clone()
+}
+
+/// Returns the directory at `path` in the procfs, creating it and its parents
+/// if not exist.
+fn mkdir_all(path: &str) -> AxResult<Arc<ProcDir>> {
+    if !PROCFS.is_inited() {
+        return ax_err!(NotFound, "procfs is not mounted");
+    }
+    let mut dir = PROCFS.root();
+    for name in path.split('/').filter(|name| !name.is_empty()) {
+        dir = dir.mkdir(name)?;
+    }
+    Ok(dir)
+}
+
+/// Adds the node at `path` in the procfs (e.g., `sys/vm/overcommit_memory`
+/// for `/proc/sys/vm/overcommit_memory`), creating the parent directories if
+/// not exist.
+pub fn register(path: &str, node: VfsNodeRef) -> AxResult {
+    let path = path.trim_matches('/');
+    let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+    if name.is_empty() {
+        return ax_err!(InvalidInput, "empty procfs entry name");
+    }
+    mkdir_all(parent)?.add(name, node);
+    Ok(())
+}
+
+/// Adds a generator of the entries to the directory at `path` in the procfs
+/// (`""` for `/proc` itself), see [`ProcDir::add_generator`].
+pub fn register_generator(
+    path: &str,
+    f: impl Fn() -> Vec<(String, VfsNodeRef)> + Send + Sync + 'static,
+) -> AxResult {
+    mkdir_all(path)?.add_generator(f);
+    Ok(())
+}
+
+/// Creates a read-only [`ProcFile`] whose content is `content`.
+pub fn static_file(content: &str) -> VfsNodeRef {
+    let content = content.to_string();
+    Arc::new(ProcFile::new(move || content.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Once;
+
+    static INIT: Once = Once::new();
+
+    fn init() {
+        // The scheduler is required to use `axsync::Mutex`.
+        INIT.call_once(axtask::init_scheduler);
+    }
+
+    fn names(dir: &ProcDir) -> Vec<String> {
+        let mut dirents = [(); 16].map(|_| VfsDirEntry::default());
+        let n = dir.read_dir(0, &mut dirents).unwrap();
+        dirents[..n]
+            .iter()
+            .map(|e| String::from_utf8(e.name_as_bytes().to_vec()).unwrap())
+            .collect()
+    }
+
+    fn read_all(node: &VfsNodeRef, chunk: usize) -> String {
+        let mut out = Vec::new();
+        let mut buf = vec![0; chunk];
+        loop {
+            let n = node.read_at(out.len() as u64, &mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            out.extend_from_slice(&buf[..n]);
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_split_path() {
+        assert_eq!(split_path("a"), ("a", None));
+        assert_eq!(split_path("/a/b/c"), ("a", Some("b/c")));
+        assert_eq!(split_path("a/"), ("a", Some("")));
+        assert_eq!(split_path(""), ("", None));
+    }
+
+    #[test]
+    fn test_lookup() {
+        init();
+        let root = ProcDir::new(None);
+        let vm = root.mkdir("sys").unwrap().mkdir("vm").unwrap();
+        vm.add("foo", static_file("1\n"));
+
+        let foo = root.clone().lookup("sys/vm/foo").unwrap();
+        assert_eq!(read_all(&foo, 16), "1\n");
+        let foo2 = root.clone().lookup("/sys/./vm/../vm/foo").unwrap();
+        assert!(Arc::ptr_eq(&foo, &foo2));
+        let root2 = root.clone().lookup("sys/vm/../..").unwrap();
+        assert!(core::ptr::eq(
+            root2.as_any().downcast_ref::<ProcDir>().unwrap(),
+            &*root
+        ));
+
+        assert_eq!(root.clone().lookup("..").err(), Some(VfsError::NotFound));
+        assert_eq!(
+            root.clone().lookup("sys/bar").err(),
+            Some(VfsError::NotFound)
+        );
+    }
+
+    #[test]
+    fn test_mkdir() {
+        init();
+        let root = ProcDir::new(None);
+        let sys = root.mkdir("sys").unwrap();
+        assert!(Arc::ptr_eq(&sys, &root.mkdir("sys").unwrap()));
+        root.add("file", static_file(""));
+        assert_eq!(root.mkdir("file").err(), Some(VfsError::NotADirectory));
+    }
+
+    #[test]
+    fn test_generators() {
+        init();
+        let root = ProcDir::new(None);
+        root.add("b", static_file(""));
+        root.add("a", static_file(""));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let calls2 = calls.clone();
+        root.add_generator(move || {
+            let n = calls2.fetch_add(1, Ordering::Relaxed);
+            let dir = ProcDir::with_entries([("status".into(), static_file("ok\n"))]);
+            vec![(format!("{}", n), dir as VfsNodeRef)]
+        });
+
+        // The static entries are listed in order, followed by the generated
+        // ones, which are regenerated each time.
+        assert_eq!(names(&root), [".", "..", "a", "b", "0"]);
+        assert_eq!(names(&root), [".", "..", "a", "b", "1"]);
+
+        let status = root.clone().lookup("2/status").unwrap();
+        assert_eq!(read_all(&status, 16), "ok\n");
+        // The generated directories link back to the parent.
+        let parent = root.clone().lookup("3/..").unwrap();
+        assert!(core::ptr::eq(
+            parent.as_any().downcast_ref::<ProcDir>().unwrap(),
+            &*root
+        ));
+        assert_eq!(root.clone().lookup("0").err(), Some(VfsError::NotFound));
+    }
+
+    #[test]
+    fn test_read_dir_start_idx() {
+        init();
+        let root = ProcDir::new(None);
+        root.add("a", static_file(""));
+        root.mkdir("b").unwrap();
+        let mut dirents = [(); 2].map(|_| VfsDirEntry::default());
+        assert_eq!(root.read_dir(2, &mut dirents), Ok(2));
+        assert_eq!(dirents[0].name_as_bytes(), b"a");
+        assert_eq!(dirents[0].entry_type(), VfsNodeType::File);
+        assert_eq!(dirents[1].name_as_bytes(), b"b");
+        assert_eq!(dirents[1].entry_type(), VfsNodeType::Dir);
+        assert_eq!(root.read_dir(4, &mut dirents), Ok(0));
+    }
+
+    #[test]
+    fn test_read_snapshot() {
+        init();
+        let gen = Arc::new(AtomicUsize::new(0));
+        let gen2 = gen.clone();
+        let file: VfsNodeRef = Arc::new(ProcFile::new(move || {
+            let n = gen2.fetch_add(1, Ordering::Relaxed);
+            format!("{}\n", n).repeat(16 + n)
+        }));
+        let a = open_handle(file.clone());
+        let b = open_handle(file.clone());
+
+        // A file read in chunks is generated once.
+        assert_eq!(read_all(&a, 5), "0\n".repeat(16));
+        assert_eq!(gen.load(Ordering::Relaxed), 1);
+        // Each open file keeps its own snapshot.
+        let mut buf = [0; 5];
+        assert_eq!(a.read_at(0, &mut buf), Ok(5));
+        assert_eq!(b.read_at(0, &mut buf), Ok(5));
+        assert_eq!(&buf, b"2\n2\n2");
+        assert_eq!(a.read_at(32, &mut buf), Ok(2));
+        assert_eq!(&buf[..2], b"1\n");
+        assert_eq!(b.read_at(32, &mut buf), Ok(4));
+        assert_eq!(gen.load(Ordering::Relaxed), 3);
+        // Reading the node itself regenerates the content each time.
+        assert_eq!(file.read_at(0, &mut buf), Ok(5));
+        assert_eq!(file.read_at(5, &mut buf), Ok(5));
+        assert_eq!(gen.load(Ordering::Relaxed), 5);
+        // Other nodes are not wrapped.
+        let dir: VfsNodeRef = ProcDir::new(None);
+        assert!(Arc::ptr_eq(&open_handle(dir.clone()), &dir));
+    }
+
+    #[test]
+    fn test_write() {
+        init();
+        let value = Arc::new(AtomicUsize::new(0));
+        let value2 = value.clone();
+        let value3 = value.clone();
+        let file: VfsNodeRef = Arc::new(ProcFile::new_writable(
+            move || format!("{}\n", value2.load(Ordering::Relaxed)),
+            move |s| {
+                let v = s.parse().map_err(|_| VfsError::InvalidInput)?;
+                value3.store(v, Ordering::Relaxed);
+                Ok(())
+            },
+        ));
+        assert_eq!(file.write_at(0, b"42\n"), Ok(3));
+        assert_eq!(read_all(&file, 16), "42\n");
+        assert_eq!(file.write_at(0, b"x"), Err(VfsError::InvalidInput));
+        assert_eq!(file.write_at(1, b"1"), Err(VfsError::InvalidInput));
+        assert_eq!(value.load(Ordering::Relaxed), 42);
+
+        let ro = static_file("0\n");
+        assert_eq!(ro.write_at(0, b"1"), Err(VfsError::PermissionDenied));
+    }
+
+    #[test]
+    fn test_register() {
+        init();
+        PROCFS.call_once(|| Arc::new(ProcFileSystem::new()));
+        register("/test/a/b/file", static_file("b\n")).unwrap();
+        register("test/a/c", static_file("c\n")).unwrap();
+        assert!(register("/", static_file("")).is_err());
+        assert!(register("test/a/c/d", static_file("")).is_err());
+        register_generator("test/gen", || vec![("x".into(), static_file(""))]).unwrap();
+
+        let root = PROCFS.root();
+        let file = root.clone().lookup("test/a/b/file").unwrap();
+        assert_eq!(read_all(&file, 16), "b\n");
+        assert!(root.clone().lookup("test/gen/x").is_ok());
+        let a = root.clone().lookup("test/a").unwrap();
+        assert_eq!(
+            names(a.as_any().downcast_ref::<ProcDir>().unwrap()),
+            [".", "..", "b", "c"]
+        );
+    }
+}
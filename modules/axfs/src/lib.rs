@@ -6,10 +6,19 @@
 //!
 //! - `fatfs`: Use [FAT] as the main filesystem and mount it on `/`. This feature
 //!    is **enabled** by default.
-//! - `devfs`: Mount [`axfs_devfs::DeviceFileSystem`] on `/dev`. This feature is
-//!    **enabled** by default.
+//! - `devfs`: Mount [`axfs_devfs::DeviceFileSystem`] on `/dev`, with the
+//!    terminal devices (`console`, `ttyS0`, `ptmx` and `pts/N`) of [`axtty`].
+//!    This feature is **enabled** by default.
 //! - `ramfs`: Mount [`axfs_ramfs::RamFileSystem`] on `/tmp`. This feature is
 //!    **enabled** by default.
+//! - `procfs`: Mount the process filesystem ([`procfs`]) on `/proc`, whose
+//!    files are generated from the kernel state. This feature is **enabled**
+//!    by default.
+//! - `sysfs`: Mount another [`axfs_ramfs::RamFileSystem`] on `/sys`. This
+//!    feature is **enabled** by default.
+//! - `irq`, `multitask`: With both, block requests put the current task to
+//!    sleep until the interrupt of the block device, instead of spinning. They
+//!    are **disabled** by default.
 //! - `myfs`: Allow users to define their custom filesystems to override the
 //!    default. In this case, [`MyFileSystemIf`] is required to be implemented
 //!    to create and initialize other filesystems. This feature is **disabled** by
@@ -34,6 +43,9 @@ mod root;
 pub mod api;
 pub mod fops;
 
+#[cfg(feature = "procfs")]
+pub use fs::procfs;
+
 use axdriver::{prelude::*, AxDeviceContainer};
 pub use root::{CURRENT_DIR, CURRENT_DIR_PATH};
 
@@ -43,5 +55,7 @@ pub fn init_filesystems(mut blk_devs: AxDeviceContainer<AxBlockDevice>) {
 
     let dev = blk_devs.take_one().expect("No block device found!");
     info!("  use block device 0: {:?}", dev.device_name());
+    #[cfg(all(feature = "irq", feature = "multitask"))]
+    self::dev::init_irq_hooks();
     self::root::init_rootfs(self::dev::Disk::new(dev));
 }
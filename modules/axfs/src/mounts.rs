@@ -13,6 +13,10 @@ pub(crate) fn devfs() -> Arc<fs::devfs::DeviceFileSystem> {
     devfs.add("null", Arc::new(null));
     devfs.add("zero", Arc::new(zero));
     foo_dir.add("bar", Arc::new(bar));
+    devfs.add("console", Arc::new(axtty::dev::TtyNode::console()));
+    devfs.add("ttyS0", Arc::new(axtty::dev::TtyNode::console()));
+    devfs.add("ptmx", Arc::new(axtty::dev::PtmxNode));
+    devfs.add("pts", Arc::new(axtty::dev::PtsDir));
     Arc::new(devfs)
 }
 
@@ -22,29 +26,17 @@ pub(crate) fn ramfs() -> Arc<fs::ramfs::RamFileSystem> {
 }
 
 #[cfg(feature = "procfs")]
-pub(crate) fn procfs() -> VfsResult<Arc<fs::ramfs::RamFileSystem>> {
-    let procfs = fs::ramfs::RamFileSystem::new();
-    let proc_root = procfs.root_dir();
+pub(crate) fn procfs() -> VfsResult<Arc<fs::procfs::ProcFileSystem>> {
+    let procfs = fs::procfs::init();
+    let proc_root = procfs.root();
 
-    // Create /proc/sys/net/core/somaxconn
-    proc_root.create("sys", VfsNodeType::Dir)?;
-    proc_root.create("sys/net", VfsNodeType::Dir)?;
-    proc_root.create("sys/net/core", VfsNodeType::Dir)?;
-    proc_root.create("sys/net/core/somaxconn", VfsNodeType::File)?;
-    let file_somaxconn = proc_root.clone().lookup("./sys/net/core/somaxconn")?;
-    file_somaxconn.write_at(0, b"4096\n")?;
+    // Create /proc/mounts
+    proc_root.add(
+        "mounts",
+        Arc::new(fs::procfs::ProcFile::new(crate::root::mounts_info)),
+    );
 
-    // Create /proc/sys/vm/overcommit_memory
-    proc_root.create("sys/vm", VfsNodeType::Dir)?;
-    proc_root.create("sys/vm/overcommit_memory", VfsNodeType::File)?;
-    let file_over = proc_root.clone().lookup("./sys/vm/overcommit_memory")?;
-    file_over.write_at(0, b"0\n")?;
-
-    // Create /proc/self/stat
-    proc_root.create("self", VfsNodeType::Dir)?;
-    proc_root.create("self/stat", VfsNodeType::File)?;
-
-    Ok(Arc::new(procfs))
+    Ok(procfs)
 }
 
 #[cfg(feature = "sysfs")]
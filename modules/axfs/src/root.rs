@@ -32,19 +32,21 @@ impl CURRENT_DIR {
 
 struct MountPoint {
     path: &'static str,
+    fstype: &'static str,
     fs: Arc<dyn VfsOps>,
 }
 
 struct RootDirectory {
     main_fs: Arc<dyn VfsOps>,
+    main_fstype: &'static str,
     mounts: Mutex<Vec<MountPoint>>,
 }
 
 static ROOT_DIR: LazyInit<Arc<RootDirectory>> = LazyInit::new();
 
 impl MountPoint {
-    pub fn new(path: &'static str, fs: Arc<dyn VfsOps>) -> Self {
-        Self { path, fs }
+    pub fn new(path: &'static str, fstype: &'static str, fs: Arc<dyn VfsOps>) -> Self {
+        Self { path, fstype, fs }
     }
 }
 
@@ -55,14 +57,15 @@ impl Drop for MountPoint {
 }
 
 impl RootDirectory {
-    pub const fn new(main_fs: Arc<dyn VfsOps>) -> Self {
+    pub const fn new(main_fs: Arc<dyn VfsOps>, main_fstype: &'static str) -> Self {
         Self {
             main_fs,
+            main_fstype,
             mounts: Mutex::new(Vec::new()),
         }
     }
 
-    pub fn mount(&self, path: &'static str, fs: Arc<dyn VfsOps>) -> AxResult {
+    pub fn mount(&self, path: &'static str, fstype: &'static str, fs: Arc<dyn VfsOps>) -> AxResult {
         if path == "/" {
             return ax_err!(InvalidInput, "cannot mount root filesystem");
         }
@@ -75,7 +78,7 @@ impl RootDirectory {
         // create the mount point in the main filesystem if it does not exist
         self.main_fs.root_dir().create(path, FileType::Dir)?;
         fs.mount(path, self.main_fs.root_dir().lookup(path)?)?;
-        self.mounts.lock().push(MountPoint::new(path, fs));
+        self.mounts.lock().push(MountPoint::new(path, fstype, fs));
         Ok(())
     }
 
@@ -164,44 +167,47 @@ pub(crate) fn init_rootfs(disk: crate::dev::Disk) {
     cfg_if::cfg_if! {
         if #[cfg(feature = "myfs")] { // override the default filesystem
             let main_fs = fs::myfs::new_myfs(disk);
+            let main_fstype = "myfs";
         } else if #[cfg(feature = "lwext4_rust")] {
             static EXT4_FS: LazyInit<Arc<fs::lwext4_rust::Ext4FileSystem>> = LazyInit::new();
             EXT4_FS.init_once(Arc::new(fs::lwext4_rust::Ext4FileSystem::new(disk)));
             let main_fs = EXT4_FS.clone();
+            let main_fstype = "ext4";
         } else if #[cfg(feature = "ext4_rs")] {
             static EXT4_FS: LazyInit<Arc<fs::ext4_rs::Ext4FileSystem>> = LazyInit::new();
             EXT4_FS.init_once(Arc::new(fs::ext4_rs::Ext4FileSystem::new(disk)));
             let main_fs = EXT4_FS.clone();
+            let main_fstype = "ext4";
         } else if #[cfg(feature = "fatfs")] {
             static FAT_FS: LazyInit<Arc<fs::fatfs::FatFileSystem>> = LazyInit::new();
             FAT_FS.init_once(Arc::new(fs::fatfs::FatFileSystem::new(disk)));
             FAT_FS.init();
             let main_fs = FAT_FS.clone();
+            let main_fstype = "vfat";
         }
     }
 
-    let mut root_dir = RootDirectory::new(main_fs);
+    let mut root_dir = RootDirectory::new(main_fs, main_fstype);
 
     #[cfg(feature = "devfs")]
     root_dir
-        .mount("/dev", mounts::devfs())
+        .mount("/dev", "devtmpfs", mounts::devfs())
         .expect("failed to mount devfs at /dev");
 
     #[cfg(feature = "ramfs")]
     root_dir
-        .mount("/tmp", mounts::ramfs())
+        .mount("/tmp", "tmpfs", mounts::ramfs())
         .expect("failed to mount ramfs at /tmp");
 
-    // Mount another ramfs as procfs
     #[cfg(feature = "procfs")]
     root_dir // should not fail
-        .mount("/proc", mounts::procfs().unwrap())
+        .mount("/proc", "proc", mounts::procfs().unwrap())
         .expect("fail to mount procfs at /proc");
 
     // Mount another ramfs as sysfs
     #[cfg(feature = "sysfs")]
     root_dir // should not fail
-        .mount("/sys", mounts::sysfs().unwrap())
+        .mount("/sys", "sysfs", mounts::sysfs().unwrap())
         .expect("fail to mount sysfs at /sys");
 
     ROOT_DIR.init_once(Arc::new(root_dir));
@@ -338,10 +344,24 @@ pub(crate) fn find_mounted_fs(path: &str) -> AxResult<Arc<dyn VfsOps>> {
     ROOT_DIR.lookup_mounted_fs(path, |fs, _| Ok(fs))
 }
 
-pub(crate) fn mount_fs(path: &'static str, fs: Arc<dyn VfsOps>) -> AxResult {
-    ROOT_DIR.mount(path, fs)
+pub(crate) fn mount_fs(path: &'static str, fstype: &'static str, fs: Arc<dyn VfsOps>) -> AxResult {
+    ROOT_DIR.mount(path, fstype, fs)
 }
 
 pub(crate) fn unmount_fs(path: &str) {
     ROOT_DIR.umount(path);
 }
+
+/// Returns the mount table in the format of `/proc/mounts`: the device, the
+/// mount point, the filesystem type and the options of each mount.
+#[cfg(feature = "procfs")]
+pub(crate) fn mounts_info() -> String {
+    use core::fmt::Write;
+
+    let mut info = String::new();
+    let _ = writeln!(info, "rootfs / {} rw 0 0", ROOT_DIR.main_fstype);
+    for mp in ROOT_DIR.mounts.lock().iter() {
+        let _ = writeln!(info, "{0} {1} {0} rw 0 0", mp.fstype, mp.path);
+    }
+    info
+}
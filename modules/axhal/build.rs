@@ -30,8 +30,10 @@ fn main() {
     let arch = std::env::var("CARGO_CFG_TARGET_ARCH").unwrap();
     let platform = axconfig::PLATFORM;
     if platform != "dummy" {
+        println!("cargo:rerun-if-changed=linker.lds.S");
         gen_linker_script(&arch, platform).unwrap();
     }
+    gen_ksyms().unwrap();
 
     println!("cargo:rustc-cfg=platform=\"{}\"", platform);
     println!("cargo:rustc-cfg=platform_family=\"{}\"", axconfig::FAMILY);
@@ -69,3 +71,22 @@ fn gen_linker_script(arch: &str, platform: &str) -> Result<()> {
     std::fs::write(out_path, ld_content)?;
     Ok(())
 }
+
+/// Copies the symbol table for backtraces, specified by the `AX_KSYMS`
+/// environment variable, to the output directory. It is empty if not
+/// specified.
+///
+/// The symbol table is the output of `nm -n` on the kernel image linked
+/// without it.
+fn gen_ksyms() -> Result<()> {
+    println!("cargo:rerun-if-env-changed=AX_KSYMS");
+    let out_path = Path::new(&std::env::var("OUT_DIR").unwrap()).join("ksyms.txt");
+    match std::env::var("AX_KSYMS") {
+        Ok(path) if !path.is_empty() => {
+            println!("cargo:rerun-if-changed={}", path);
+            std::fs::copy(path, out_path)?;
+        }
+        _ => std::fs::write(out_path, "")?,
+    }
+    Ok(())
+}
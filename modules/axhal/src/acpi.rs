@@ -0,0 +1,446 @@
+//! ACPI table parsing.
+//!
+//! On x86 PCs, the firmware describes the CPUs, the interrupt controllers,
+//! the PCIe ECAM window, the HPET, the NUMA topology and the power management
+//! registers in ACPI tables (the MADT, MCFG, HPET, SRAT and FADT tables).
+//! They are parsed at boot, and the static platform configuration
+//! ([`axconfig`]) is only used as a fallback, if there are no ACPI tables or
+//! the information is missing from them.
+
+use lazyinit::LazyInit;
+use memory_addr::PhysAddr;
+
+use crate::mem::phys_to_virt;
+
+/// Maximum number of CPUs recorded from the MADT.
+const MAX_CPUS: usize = 256;
+
+/// Maximum number of I/O APICs recorded from the MADT.
+pub const MAX_IO_APICS: usize = 8;
+
+const RSDP_SIGNATURE: &[u8] = b"RSD PTR ";
+const RSDP_V1_LEN: usize = 20;
+const SDT_HEADER_LEN: usize = 36;
+/// Maximum length of a table, to reject corrupted length fields. The DSDT,
+/// usually the largest table, is well below it.
+const MAX_TABLE_LEN: usize = 1 << 20;
+
+const MADT_LOCAL_APIC: u8 = 0;
+const MADT_IO_APIC: u8 = 1;
+const MADT_LOCAL_X2APIC: u8 = 9;
+/// The processor is enabled. Online capable processors (which can be
+/// enabled later) are not counted, as they can not be started at boot.
+const MADT_CPU_ENABLED: u32 = 1 << 0;
+
+const SRAT_MEMORY_AFFINITY: u8 = 1;
+const SRAT_MEMORY_ENABLED: u32 = 1 << 0;
+
+/// The `RESET_REG_SUP` flag of the FADT: the reset register is supported.
+const FADT_RESET_REG_SUP: u32 = 1 << 10;
+/// The address space ID of the system I/O space in a generic address
+/// structure (GAS).
+const GAS_SYSTEM_IO: u8 = 1;
+
+const AML_ZERO_OP: u8 = 0x00;
+const AML_ONE_OP: u8 = 0x01;
+const AML_NAME_OP: u8 = 0x08;
+const AML_BYTE_PREFIX: u8 = 0x0a;
+const AML_WORD_PREFIX: u8 = 0x0b;
+const AML_PACKAGE_OP: u8 = 0x12;
+
+/// An I/O APIC described in the MADT.
+#[derive(Debug, Clone, Copy)]
+pub struct IoApicInfo {
+    /// The I/O APIC ID.
+    pub id: u8,
+    /// The physical address of the registers.
+    pub paddr: PhysAddr,
+    /// The first global system interrupt (GSI) number of the I/O APIC.
+    pub gsi_base: u32,
+}
+
+/// The PCIe ECAM window of PCI segment 0, described in the MCFG table.
+#[derive(Debug, Clone, Copy)]
+pub struct PciEcam {
+    /// The physical address of the configuration space of bus 0.
+    pub paddr: PhysAddr,
+    /// The first bus number decoded by the host bridge.
+    pub bus_start: u8,
+    /// The last bus number decoded by the host bridge.
+    pub bus_end: u8,
+}
+
+impl PciEcam {
+    /// Returns the physical address and the size of the configuration space
+    /// of the decoded buses.
+    pub fn region(&self) -> (PhysAddr, usize) {
+        let start = self.paddr + ((self.bus_start as usize) << 20);
+        let size = (self.bus_end as usize - self.bus_start as usize + 1) << 20;
+        (start, size)
+    }
+}
+
+/// The power management registers in the FADT (all in the system I/O space),
+/// and the sleep types of the S5 (soft-off) state in the DSDT.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct AcpiPower {
+    /// The PM1a control register.
+    pub pm1a_cnt: u16,
+    /// The PM1b control register, 0 if not supported.
+    pub pm1b_cnt: u16,
+    /// The SMI command port, 0 if ACPI is always enabled.
+    pub smi_cmd: u16,
+    /// The value to write to `smi_cmd` to enable ACPI.
+    pub acpi_enable: u8,
+    /// The `SLP_TYPa` and `SLP_TYPb` values of the S5 state.
+    pub s5_slp_typ: Option<(u16, u16)>,
+    /// The reset register and the value to write to reset the system.
+    pub reset_reg: Option<(u16, u8)>,
+}
+
+/// The information collected from the ACPI tables at boot.
+pub(crate) struct AcpiInfo {
+    apic_ids: [u32; MAX_CPUS],
+    cpu_count: usize,
+    io_apics: [IoApicInfo; MAX_IO_APICS],
+    io_apic_count: usize,
+    pci_ecam: Option<PciEcam>,
+    hpet_paddr: Option<usize>,
+    power: Option<AcpiPower>,
+}
+
+static ACPI_INFO: LazyInit<AcpiInfo> = LazyInit::new();
+
+/// Returns the information collected from the ACPI tables, or [`None`] if
+/// there are none.
+pub(crate) fn info() -> Option<&'static AcpiInfo> {
+    ACPI_INFO.get()
+}
+
+unsafe fn read<T: Copy>(paddr: usize) -> T {
+    phys_to_virt(paddr.into())
+        .as_ptr()
+        .cast::<T>()
+        .read_unaligned()
+}
+
+unsafe fn bytes(paddr: usize, len: usize) -> &'static [u8] {
+    core::slice::from_raw_parts(phys_to_virt(paddr.into()).as_ptr(), len)
+}
+
+fn checksum_ok(data: &[u8]) -> bool {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+}
+
+/// Searches for the RSDP in the first 1K of the EBDA and in the BIOS ROM
+/// (0xe0000..0x100000), at 16-byte boundaries.
+unsafe fn find_rsdp() -> Option<&'static [u8]> {
+    let ebda = (read::<u16>(0x40e) as usize) << 4;
+    let areas = [(ebda, ebda + 0x400), (0xe_0000, 0x10_0000)];
+    for (start, end) in areas {
+        if start == 0 {
+            continue;
+        }
+        for paddr in (start..end).step_by(16) {
+            let rsdp = bytes(paddr, RSDP_V1_LEN);
+            if rsdp.starts_with(RSDP_SIGNATURE) && checksum_ok(rsdp) {
+                let len = if rsdp[15] == 0 {
+                    RSDP_V1_LEN
+                } else {
+                    crate::bootinfo::ACPI_RSDP_LEN
+                };
+                return Some(bytes(paddr, len));
+            }
+        }
+    }
+    None
+}
+
+/// Returns the table at `paddr` if its length is sane and its checksum is
+/// valid.
+unsafe fn table(paddr: usize) -> Option<&'static [u8]> {
+    if paddr == 0 {
+        return None;
+    }
+    let len = read::<u32>(paddr + 4) as usize;
+    if !(SDT_HEADER_LEN..=MAX_TABLE_LEN).contains(&len) {
+        return None;
+    }
+    let table = bytes(paddr, len);
+    checksum_ok(table).then_some(table)
+}
+
+/// Calls `f` with the signature and the content of each table in the RSDT
+/// (ACPI 1.0) or the XSDT (ACPI 2.0 or later).
+unsafe fn for_each_table(rsdp: &[u8], mut f: impl FnMut(&[u8], &'static [u8])) {
+    if rsdp.len() < RSDP_V1_LEN || !rsdp.starts_with(RSDP_SIGNATURE) {
+        return;
+    }
+    let xsdt = if rsdp[15] >= 2 && rsdp.len() >= 32 {
+        u64::from_le_bytes(rsdp[24..32].try_into().unwrap()) as usize
+    } else {
+        0
+    };
+    let (root, entry_size) = if xsdt != 0 {
+        (xsdt, 8)
+    } else {
+        (
+            u32::from_le_bytes(rsdp[16..20].try_into().unwrap()) as usize,
+            4,
+        )
+    };
+    let Some(root) = table(root) else {
+        return;
+    };
+    for entry in root[SDT_HEADER_LEN..].chunks_exact(entry_size) {
+        let paddr = if entry_size == 8 {
+            u64::from_le_bytes(entry.try_into().unwrap()) as usize
+        } else {
+            u32::from_le_bytes(entry.try_into().unwrap()) as usize
+        };
+        if let Some(table) = table(paddr) {
+            f(&table[..4], table);
+        }
+    }
+}
+
+/// Calls `f` with the type and the content of each entry of the MADT or the
+/// SRAT, which start at `offset`.
+fn for_each_entry(table: &[u8], offset: usize, mut f: impl FnMut(u8, &[u8])) {
+    let mut off = offset;
+    while off + 2 <= table.len() {
+        let (ty, len) = (table[off], table[off + 1] as usize);
+        if len < 2 || off + len > table.len() {
+            break;
+        }
+        f(ty, &table[off..off + len]);
+        off += len;
+    }
+}
+
+fn le_u32(data: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes(data[off..off + 4].try_into().unwrap())
+}
+
+fn le_u64(data: &[u8], off: usize) -> u64 {
+    u64::from_le_bytes(data[off..off + 8].try_into().unwrap())
+}
+
+fn add_cpu(info: &mut AcpiInfo, apic_id: u32, flags: u32) {
+    if flags & MADT_CPU_ENABLED != 0 && info.cpu_count < MAX_CPUS {
+        info.apic_ids[info.cpu_count] = apic_id;
+        info.cpu_count += 1;
+    }
+}
+
+fn parse_madt(info: &mut AcpiInfo, madt: &[u8]) {
+    // The entries start after the local APIC address and the flags.
+    for_each_entry(madt, SDT_HEADER_LEN + 8, |ty, entry| match ty {
+        MADT_LOCAL_APIC if entry.len() >= 8 => add_cpu(info, entry[3] as u32, le_u32(entry, 4)),
+        MADT_LOCAL_X2APIC if entry.len() >= 16 => add_cpu(info, le_u32(entry, 4), le_u32(entry, 8)),
+        MADT_IO_APIC if entry.len() >= 12 && info.io_apic_count < MAX_IO_APICS => {
+            info.io_apics[info.io_apic_count] = IoApicInfo {
+                id: entry[2],
+                paddr: pa!(le_u32(entry, 4) as usize),
+                gsi_base: le_u32(entry, 8),
+            };
+            info.io_apic_count += 1;
+        }
+        _ => {}
+    });
+}
+
+fn parse_mcfg(info: &mut AcpiInfo, mcfg: &[u8]) {
+    // The entries start after 8 reserved bytes.
+    let Some(entries) = mcfg.get(SDT_HEADER_LEN + 8..) else {
+        return;
+    };
+    for entry in entries.chunks_exact(16) {
+        let segment = u16::from_le_bytes([entry[8], entry[9]]);
+        if segment == 0 && entry[10] <= entry[11] {
+            info.pci_ecam = Some(PciEcam {
+                paddr: (le_u64(entry, 0) as usize).into(),
+                bus_start: entry[10],
+                bus_end: entry[11],
+            });
+            break;
+        }
+    }
+}
+
+fn parse_srat(srat: &[u8]) {
+    // The entries start after 12 reserved bytes.
+    for_each_entry(srat, SDT_HEADER_LEN + 12, |ty, entry| {
+        if ty == SRAT_MEMORY_AFFINITY
+            && entry.len() >= 40
+            && le_u32(entry, 28) & SRAT_MEMORY_ENABLED != 0
+        {
+            let node = le_u32(entry, 2) as usize;
+            let base = le_u64(entry, 8) as usize;
+            let len = le_u64(entry, 16) as usize;
+            crate::mem::add_numa_range(base.into(), len, node);
+        }
+    });
+}
+
+/// Finds the `SLP_TYPa` and `SLP_TYPb` values of the S5 state in the DSDT.
+///
+/// They are the first two elements of the `\_S5_` package, which is encoded
+/// in AML as `NameOp ["\"] "_S5_" PackageOp PkgLength NumElements ...`.
+fn parse_s5(dsdt: &[u8]) -> Option<(u16, u16)> {
+    let aml = &dsdt[SDT_HEADER_LEN..];
+    let pos = aml.windows(4).position(|name| name == b"_S5_")?;
+    let is_name = match pos {
+        0 => false,
+        1 => aml[0] == AML_NAME_OP,
+        _ => aml[pos - 1] == AML_NAME_OP || aml[pos - 2..pos] == [AML_NAME_OP, b'\\'],
+    };
+    if !is_name || aml.get(pos + 4) != Some(&AML_PACKAGE_OP) {
+        return None;
+    }
+    // Bits 7:6 of the first byte of PkgLength are the number of the following
+    // bytes. Skip PkgLength and NumElements.
+    let lead = *aml.get(pos + 5)?;
+    let mut off = pos + 5 + 1 + (lead >> 6) as usize + 1;
+    let mut integer = || {
+        let (value, len) = match *aml.get(off)? {
+            AML_ZERO_OP => (0, 1),
+            AML_ONE_OP => (1, 1),
+            AML_BYTE_PREFIX => (*aml.get(off + 1)? as u16, 2),
+            AML_WORD_PREFIX => (
+                u16::from_le_bytes([*aml.get(off + 1)?, *aml.get(off + 2)?]),
+                3,
+            ),
+            _ => return None,
+        };
+        off += len;
+        Some(value)
+    };
+    Some((integer()?, integer()?))
+}
+
+/// Returns the I/O port of a register in the FADT, from the 32-bit field at
+/// `offset`, or from the generic address structure at `x_offset` (ACPI 2.0
+/// or later) if the former is 0.
+fn fadt_port(fadt: &[u8], offset: usize, x_offset: usize) -> u16 {
+    let port = le_u32(fadt, offset) as u16;
+    if port == 0 && fadt.len() >= x_offset + 12 && fadt[x_offset] == GAS_SYSTEM_IO {
+        le_u64(fadt, x_offset + 4) as u16
+    } else {
+        port
+    }
+}
+
+unsafe fn parse_fadt(info: &mut AcpiInfo, fadt: &[u8]) {
+    if fadt.len() < 116 {
+        return;
+    }
+    let dsdt = if fadt.len() >= 148 && le_u64(fadt, 140) != 0 {
+        le_u64(fadt, 140) as usize
+    } else {
+        le_u32(fadt, 40) as usize
+    };
+    let reset_reg = (fadt.len() >= 129
+        && le_u32(fadt, 112) & FADT_RESET_REG_SUP != 0
+        && fadt[116] == GAS_SYSTEM_IO)
+        .then(|| (le_u64(fadt, 120) as u16, fadt[128]));
+    info.power = Some(AcpiPower {
+        pm1a_cnt: fadt_port(fadt, 64, 172),
+        pm1b_cnt: fadt_port(fadt, 68, 184),
+        smi_cmd: le_u32(fadt, 48) as u16,
+        acpi_enable: fadt[52],
+        s5_slp_typ: if dsdt != 0 {
+            table(dsdt).and_then(parse_s5)
+        } else {
+            None
+        },
+        reset_reg,
+    });
+}
+
+/// Parses the ACPI tables, which is called at the early stage of the boot on
+/// the primary CPU.
+///
+/// `rsdp` is the copy of the RSDP passed by the bootloader. If there is none,
+/// the RSDP is searched for in the BIOS memory. The tables must be accessible
+/// in the linear mapping of the boot page table. Memory affinity structures
+/// in the SRAT are added as NUMA memory ranges (see
+/// [`add_numa_range`](crate::mem::add_numa_range)).
+#[allow(dead_code)]
+pub(crate) fn init(rsdp: Option<&[u8]>) {
+    // Safety: the firmware places valid tables in the reserved memory, which
+    // is mapped by the boot page table.
+    let Some(rsdp) = rsdp.or_else(|| unsafe { find_rsdp() }) else {
+        return;
+    };
+    let mut info = AcpiInfo {
+        apic_ids: [0; MAX_CPUS],
+        cpu_count: 0,
+        io_apics: [IoApicInfo {
+            id: 0,
+            paddr: pa!(0),
+            gsi_base: 0,
+        }; MAX_IO_APICS],
+        io_apic_count: 0,
+        pci_ecam: None,
+        hpet_paddr: None,
+        power: None,
+    };
+    unsafe {
+        for_each_table(rsdp, |signature, table| match signature {
+            b"APIC" => parse_madt(&mut info, table),
+            b"MCFG" => parse_mcfg(&mut info, table),
+            // The address of the base address structure (a GAS).
+            b"HPET" if table.len() >= SDT_HEADER_LEN + 16 => {
+                info.hpet_paddr = Some(le_u64(table, SDT_HEADER_LEN + 8) as usize);
+            }
+            b"SRAT" => parse_srat(table),
+            b"FACP" => parse_fadt(&mut info, table),
+            _ => {}
+        });
+    }
+    ACPI_INFO.init_once(info);
+}
+
+/// Returns the number of enabled CPUs in the MADT.
+pub fn cpu_count() -> Option<usize> {
+    info().map(|info| info.cpu_count).filter(|&n| n > 0)
+}
+
+/// Returns the local APIC ID of the CPU, in the order of the MADT.
+pub fn cpu_apic_id(cpu_id: usize) -> Option<u32> {
+    let info = info()?;
+    info.apic_ids[..info.cpu_count].get(cpu_id).copied()
+}
+
+/// Returns the CPU ID (the index in the MADT) of the local APIC ID.
+pub fn cpu_id_of_apic(apic_id: u32) -> Option<usize> {
+    let info = info()?;
+    info.apic_ids[..info.cpu_count]
+        .iter()
+        .position(|&id| id == apic_id)
+}
+
+/// Returns an iterator over the I/O APICs in the MADT.
+pub fn io_apics() -> impl Iterator<Item = IoApicInfo> {
+    info()
+        .map_or(&[][..], |info| &info.io_apics[..info.io_apic_count])
+        .iter()
+        .copied()
+}
+
+/// Returns the PCIe ECAM window of PCI segment 0 in the MCFG table.
+pub fn pci_ecam() -> Option<PciEcam> {
+    info()?.pci_ecam
+}
+
+/// Returns the power management registers in the FADT.
+#[allow(dead_code)]
+pub(crate) fn power() -> Option<&'static AcpiPower> {
+    info()?.power.as_ref()
+}
+
+/// Returns the physical address of the HPET registers in the HPET table.
+pub fn hpet_paddr() -> Option<PhysAddr> {
+    info()?.hpet_paddr.map(PhysAddr::from)
+}
@@ -37,6 +37,17 @@ pub fn wait_for_irqs() {
     aarch64_cpu::asm::wfi();
 }
 
+/// Enables interrupts and waits for them, atomically.
+///
+/// It can be called with interrupts disabled to wait for an interrupt that
+/// may become pending right before it, without missing it.
+#[inline]
+pub fn enable_irqs_and_wait() {
+    // `wfi` also returns on pending interrupts that are masked.
+    aarch64_cpu::asm::wfi();
+    enable_irqs();
+}
+
 /// Halt the current CPU.
 #[inline]
 pub fn halt() {
@@ -80,6 +91,10 @@ pub unsafe fn write_page_table_root(root_paddr: PhysAddr) {
 ///
 /// This function is unsafe as it changes the virtual memory address space.
 pub unsafe fn write_page_table_root0(root_paddr: PhysAddr) {
+    // Publish the new root first, so that TLB shootdowns issued while
+    // switching also reach this CPU.
+    #[cfg(feature = "paging")]
+    crate::paging::set_active_page_table_root(root_paddr);
     TTBR0_EL1.set(root_paddr.as_usize() as _);
     flush_tlb(None);
 }
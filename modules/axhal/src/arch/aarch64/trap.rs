@@ -5,6 +5,7 @@ use page_table_entry::MappingFlags;
 use tock_registers::interfaces::Readable;
 
 use super::TrapFrame;
+use crate::backtrace::Backtrace;
 
 global_asm!(include_str!("trap.S"), cache_current_task_ptr = sym crate::cpu::cache_current_task_ptr);
 
@@ -31,14 +32,23 @@ enum TrapSource {
 #[no_mangle]
 fn invalid_exception(tf: &TrapFrame, kind: TrapKind, source: TrapSource) {
     panic!(
-        "Invalid exception {:?} from {:?}:\n{:#x?}",
-        kind, source, tf
+        "Invalid exception {:?} from {:?}:\n{:#x?}{}",
+        kind,
+        source,
+        tf,
+        Backtrace::from_trap(tf)
     );
 }
 
+/// Whether the trap is from EL0, i.e., the mode field of SPSR is EL0t.
+#[cfg(feature = "uspace")]
+fn is_from_user(tf: &TrapFrame) -> bool {
+    tf.spsr & 0b1111 == 0
+}
+
 #[no_mangle]
-fn handle_irq_exception(_tf: &TrapFrame) {
-    handle_trap!(IRQ, 0);
+fn handle_irq_exception(tf: &TrapFrame) {
+    handle_trap!(IRQ, 0, tf);
 }
 
 fn handle_instruction_abort(tf: &TrapFrame, iss: u64, is_user: bool) {
@@ -53,13 +63,14 @@ fn handle_instruction_abort(tf: &TrapFrame, iss: u64, is_user: bool) {
         || !handle_trap!(PAGE_FAULT, vaddr, access_flags, is_user)
     {
         panic!(
-            "Unhandled {} Instruction Abort @ {:#x}, fault_vaddr={:#x}, ISS={:#x} ({:?}):\n{:#x?}",
+            "Unhandled {} Instruction Abort @ {:#x}, fault_vaddr={:#x}, ISS={:#x} ({:?}):\n{:#x?}{}",
             if is_user { "EL0" } else { "EL1" },
             tf.elr,
             vaddr,
             iss,
             access_flags,
             tf,
+            Backtrace::from_trap(tf),
         );
     }
 }
@@ -82,13 +93,14 @@ fn handle_data_abort(tf: &TrapFrame, iss: u64, is_user: bool) {
         || !handle_trap!(PAGE_FAULT, vaddr, access_flags, is_user)
     {
         panic!(
-            "Unhandled {} Data Abort @ {:#x}, fault_vaddr={:#x}, ISS=0b{:08b} ({:?}):\n{:#x?}",
+            "Unhandled {} Data Abort @ {:#x}, fault_vaddr={:#x}, ISS=0b{:08b} ({:?}):\n{:#x?}{}",
             if is_user { "EL0" } else { "EL1" },
             tf.elr,
             vaddr,
             iss,
             access_flags,
             tf,
+            Backtrace::from_trap(tf),
         );
     }
 }
@@ -106,18 +118,33 @@ fn handle_sync_exception(tf: &mut TrapFrame) {
         Some(ESR_EL1::EC::Value::InstrAbortCurrentEL) => handle_instruction_abort(tf, iss, false),
         Some(ESR_EL1::EC::Value::DataAbortLowerEL) => handle_data_abort(tf, iss, true),
         Some(ESR_EL1::EC::Value::DataAbortCurrentEL) => handle_data_abort(tf, iss, false),
+        // Breakpoints in the kernel, i.e., the mode field of SPSR is not EL0t.
+        #[cfg(feature = "gdbstub")]
+        Some(ESR_EL1::EC::Value::Brk64) if tf.spsr & 0b1111 != 0 => {
+            crate::gdbstub::handle_trap(tf, crate::gdbstub::StopReason::Breakpoint)
+        }
+        #[cfg(feature = "gdbstub")]
+        Some(ESR_EL1::EC::Value::SoftwareStepCurrentEL) => {
+            crate::gdbstub::handle_trap(tf, crate::gdbstub::StopReason::Step)
+        }
         Some(ESR_EL1::EC::Value::Brk64) => {
             debug!("BRK #{:#x} @ {:#x} ", iss, tf.elr);
             tf.elr += 4;
         }
         _ => {
             panic!(
-                "Unhandled synchronous exception @ {:#x}: ESR={:#x} (EC {:#08b}, ISS {:#x})",
+                "Unhandled synchronous exception @ {:#x}: ESR={:#x} (EC {:#08b}, ISS {:#x}){}",
                 tf.elr,
                 esr.get(),
                 esr.read(ESR_EL1::EC),
                 esr.read(ESR_EL1::ISS),
+                Backtrace::from_trap(tf),
             );
         }
     }
+
+    #[cfg(feature = "uspace")]
+    if is_from_user(tf) {
+        crate::trap::handle_return_to_user();
+    }
 }
@@ -41,6 +41,17 @@ pub fn wait_for_irqs() {
     riscv::asm::wfi()
 }
 
+/// Enables interrupts and waits for them, atomically.
+///
+/// It can be called with interrupts disabled to wait for an interrupt that
+/// may become pending right before it, without missing it.
+#[inline]
+pub fn enable_irqs_and_wait() {
+    // `wfi` also returns on pending interrupts that are masked.
+    riscv::asm::wfi();
+    enable_irqs();
+}
+
 /// Halt the current CPU.
 #[inline]
 pub fn halt() {
@@ -64,6 +75,10 @@ pub fn read_page_table_root() -> PhysAddr {
 pub unsafe fn write_page_table_root(root_paddr: PhysAddr) {
     let old_root = read_page_table_root();
     trace!("set page table root: {:#x} => {:#x}", old_root, root_paddr);
+    // Publish the new root first, so that TLB shootdowns issued while
+    // switching also reach this CPU.
+    #[cfg(feature = "paging")]
+    crate::paging::set_active_page_table_root(root_paddr);
     if old_root != root_paddr {
         satp::set(satp::Mode::Sv39, 0, root_paddr.as_usize() >> 12);
         asm::sfence_vma_all();
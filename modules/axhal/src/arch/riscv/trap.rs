@@ -3,6 +3,7 @@ use riscv::register::scause::{self, Exception as E, Trap};
 use riscv::register::stval;
 
 use super::TrapFrame;
+use crate::backtrace::Backtrace;
 
 cfg_if::cfg_if! {
     if #[cfg(feature = "multitask")] {
@@ -30,12 +31,13 @@ fn handle_page_fault(tf: &TrapFrame, mut access_flags: MappingFlags, is_user: bo
     let vaddr = va!(stval::read());
     if !handle_trap!(PAGE_FAULT, vaddr, access_flags, is_user) {
         panic!(
-            "Unhandled {} Page Fault @ {:#x}, fault_vaddr={:#x} ({:?}):\n{:#x?}",
+            "Unhandled {} Page Fault @ {:#x}, fault_vaddr={:#x} ({:?}):\n{:#x?}{}",
             if is_user { "User" } else { "Supervisor" },
             tf.sepc,
             vaddr,
             access_flags,
             tf,
+            Backtrace::from_trap(tf),
         );
     }
 }
@@ -60,20 +62,30 @@ fn riscv_trap_handler(tf: &mut TrapFrame, from_user: bool) {
         Trap::Exception(E::InstructionPageFault) => {
             handle_page_fault(tf, MappingFlags::EXECUTE, from_user)
         }
+        #[cfg(feature = "gdbstub")]
+        Trap::Exception(E::Breakpoint) if !from_user => {
+            crate::gdbstub::handle_trap(tf, crate::gdbstub::StopReason::Breakpoint)
+        }
         Trap::Exception(E::Breakpoint) => handle_breakpoint(&mut tf.sepc),
         Trap::Interrupt(_) => {
-            handle_trap!(IRQ, scause.bits());
+            handle_trap!(IRQ, scause.bits(), tf);
         }
         _ => {
             panic!(
-                "Unhandled trap {:?} @ {:#x}:\n{:#x?}",
+                "Unhandled trap {:?} @ {:#x}:\n{:#x?}{}",
                 scause.cause(),
                 tf.sepc,
-                tf
+                tf,
+                Backtrace::from_trap(tf)
             );
         }
     }
 
+    #[cfg(feature = "uspace")]
+    if from_user && !matches!(scause.cause(), Trap::Interrupt(_)) {
+        crate::trap::handle_return_to_user();
+    }
+
     #[cfg(feature = "multitask")]
     if from_user {
         into_user();
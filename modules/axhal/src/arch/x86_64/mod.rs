@@ -53,6 +53,20 @@ pub fn wait_for_irqs() {
     }
 }
 
+/// Enables interrupts and waits for them, atomically.
+///
+/// It can be called with interrupts disabled to wait for an interrupt that
+/// may become pending right before it, without missing it.
+#[inline]
+pub fn enable_irqs_and_wait() {
+    if cfg!(target_os = "none") {
+        // `hlt` runs before any interrupt is taken after `sti`.
+        interrupts::enable_and_hlt()
+    } else {
+        core::hint::spin_loop()
+    }
+}
+
 /// Halt the current CPU.
 #[inline]
 pub fn halt() {
@@ -76,6 +90,10 @@ pub fn read_page_table_root() -> PhysAddr {
 pub unsafe fn write_page_table_root(root_paddr: PhysAddr) {
     let old_root = read_page_table_root();
     trace!("set page table root: {:#x} => {:#x}", old_root, root_paddr);
+    // Publish the new root first, so that TLB shootdowns issued while
+    // switching also reach this CPU.
+    #[cfg(feature = "paging")]
+    crate::paging::set_active_page_table_root(root_paddr);
     if old_root != root_paddr {
         controlregs::cr3_write(root_paddr.as_usize() as _)
     }
@@ -3,6 +3,7 @@ use x86::{controlregs::cr2, irq::*};
 use x86_64::structures::idt::PageFaultErrorCode;
 
 use super::context::TrapFrame;
+use crate::backtrace::Backtrace;
 
 core::arch::global_asm!(include_str!("trap.S"));
 
@@ -18,13 +19,14 @@ fn handle_page_fault(tf: &TrapFrame) {
     let vaddr = va!(unsafe { cr2() });
     if !handle_trap!(PAGE_FAULT, vaddr, access_flags, tf.is_user()) {
         panic!(
-            "Unhandled {} #PF @ {:#x}, fault_vaddr={:#x}, error_code={:#x} ({:?}):\n{:#x?}",
+            "Unhandled {} #PF @ {:#x}, fault_vaddr={:#x}, error_code={:#x} ({:?}):\n{:#x?}{}",
             if tf.is_user() { "user" } else { "kernel" },
             tf.rip,
             vaddr,
             tf.error_code,
             access_flags,
             tf,
+            Backtrace::from_trap(tf),
         );
     }
 }
@@ -33,29 +35,52 @@ fn handle_page_fault(tf: &TrapFrame) {
 fn x86_trap_handler(tf: &mut TrapFrame) {
     match tf.vector as u8 {
         PAGE_FAULT_VECTOR => handle_page_fault(tf),
+        #[cfg(feature = "gdbstub")]
+        BREAKPOINT_VECTOR if !tf.is_user() => {
+            crate::gdbstub::handle_trap(tf, crate::gdbstub::StopReason::Breakpoint)
+        }
+        #[cfg(feature = "gdbstub")]
+        DEBUG_VECTOR if !tf.is_user() => {
+            crate::gdbstub::handle_trap(tf, crate::gdbstub::StopReason::Step)
+        }
         BREAKPOINT_VECTOR => debug!("#BP @ {:#x} ", tf.rip),
         GENERAL_PROTECTION_FAULT_VECTOR => {
             panic!(
-                "#GP @ {:#x}, error_code={:#x}:\n{:#x?}",
-                tf.rip, tf.error_code, tf
+                "#GP @ {:#x}, error_code={:#x}:\n{:#x?}{}",
+                tf.rip,
+                tf.error_code,
+                tf,
+                Backtrace::from_trap(tf)
             );
         }
         #[cfg(feature = "uspace")]
-        LEGACY_SYSCALL_VECTOR => super::syscall::x86_syscall_handler(tf),
+        LEGACY_SYSCALL_VECTOR => {
+            tf.rax = crate::trap::handle_syscall(tf, tf.rax as usize) as u64;
+        }
         IRQ_VECTOR_START..=IRQ_VECTOR_END => {
-            handle_trap!(IRQ, tf.vector as _);
+            handle_trap!(IRQ, tf.vector as _, tf);
         }
         _ => {
             panic!(
-                "Unhandled exception {} ({}, error_code={:#x}) @ {:#x}:\n{:#x?}",
+                "Unhandled exception {} ({}, error_code={:#x}) @ {:#x}:\n{:#x?}{}",
                 tf.vector,
                 vec_to_str(tf.vector),
                 tf.error_code,
                 tf.rip,
-                tf
+                tf,
+                Backtrace::from_trap(tf)
             );
         }
     }
+
+    // Not on the return from IRQs (the legacy syscall vector is in the IRQ
+    // range, but matched before).
+    #[cfg(feature = "uspace")]
+    if tf.is_user()
+        && (tf.vector as u8 == LEGACY_SYSCALL_VECTOR || (tf.vector as u8) < IRQ_VECTOR_START)
+    {
+        crate::trap::handle_return_to_user();
+    }
 }
 
 fn vec_to_str(vec: u64) -> &'static str {
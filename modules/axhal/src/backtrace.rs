@@ -0,0 +1,281 @@
+//! Stack backtraces.
+//!
+//! The stack is unwound by following the chain of frame pointers, so the
+//! kernel must be built with `-C force-frame-pointers=yes` (which is done by
+//! the build scripts if the `backtrace` feature is enabled). Without the
+//! feature, backtraces are always empty.
+//!
+//! Return addresses are symbolized with the symbol table embedded in the
+//! `.ksyms` section. It is the text output of `nm -n` on the kernel image,
+//! and is generated at build time by linking the kernel twice (see the
+//! `AX_KSYMS` environment variable of the build script).
+
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::arch::TrapFrame;
+
+/// Maximum number of frames in a backtrace.
+pub const MAX_FRAMES: usize = 32;
+
+/// A function that returns the bounds `[bottom, top)` of the stack of the
+/// current task, see [`set_stack_bounds_hook`].
+pub type StackBoundsFn = fn() -> Option<(usize, usize)>;
+
+static STACK_BOUNDS_HOOK: AtomicUsize = AtomicUsize::new(0);
+
+/// Sets the function to get the stack bounds of the current task, which
+/// limit the frame pointers followed when unwinding.
+///
+/// Without the hook, or if the stack of the current task is unknown (e.g.,
+/// on the boot stack), frame records are only read from the
+/// [`TASK_STACK_SIZE`](axconfig::TASK_STACK_SIZE) bytes above the first one.
+pub fn set_stack_bounds_hook(f: StackBoundsFn) {
+    STACK_BOUNDS_HOOK.store(f as usize, Ordering::Release);
+}
+
+/// A captured stack backtrace: the PCs of the frames, innermost first.
+#[derive(Clone)]
+pub struct Backtrace {
+    pcs: [usize; MAX_FRAMES],
+    len: usize,
+}
+
+impl Backtrace {
+    const fn empty() -> Self {
+        Self {
+            pcs: [0; MAX_FRAMES],
+            len: 0,
+        }
+    }
+
+    /// Captures a backtrace of the caller.
+    #[inline(never)]
+    pub fn capture() -> Self {
+        #[cfg_attr(not(feature = "backtrace"), allow(unused_mut))]
+        let mut bt = Self::empty();
+        #[cfg(feature = "backtrace")]
+        bt.unwind(read_frame_pointer());
+        bt
+    }
+
+    /// Captures a backtrace of the code interrupted by a trap, whose first
+    /// frame is the trapped PC.
+    ///
+    /// It is empty if the trap is not from the kernel code.
+    pub fn from_trap(tf: &TrapFrame) -> Self {
+        let mut bt = Self::empty();
+        let (pc, fp) = trap_pc_fp(tf);
+        if cfg!(feature = "backtrace") && is_kernel_text(pc) {
+            bt.push(pc);
+            bt.unwind(fp);
+        }
+        bt
+    }
+
+    /// Returns the PCs of the frames, innermost first.
+    pub fn frames(&self) -> &[usize] {
+        &self.pcs[..self.len]
+    }
+
+    fn push(&mut self, pc: usize) -> bool {
+        if self.len == MAX_FRAMES {
+            return false;
+        }
+        self.pcs[self.len] = pc;
+        self.len += 1;
+        true
+    }
+
+    /// Follows the frame pointer chain from `fp`.
+    fn unwind(&mut self, mut fp: usize) {
+        let bounds = stack_bounds(fp);
+        while is_valid_fp(fp, bounds) {
+            // Safety: `fp` points to a valid frame record on the kernel stack.
+            let (next_fp, ra) = unsafe { read_frame_record(fp) };
+            if ra == 0 || !self.push(ra) {
+                break;
+            }
+            // The stack grows downwards, so the caller's frame is above.
+            if next_fp <= fp {
+                break;
+            }
+            fp = next_fp;
+        }
+    }
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.len == 0 {
+            return Ok(());
+        }
+        write!(f, "\nBacktrace:")?;
+        for (i, &pc) in self.frames().iter().enumerate() {
+            write!(f, "\n  #{:<2} {:#018x}", i, pc)?;
+            if let Some((name, offset)) = symbolize(pc) {
+                write!(f, " {}+{:#x}", name, offset)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Captures a backtrace of the caller.
+///
+/// It is a shortcut for [`Backtrace::capture`].
+#[inline(always)]
+pub fn backtrace() -> Backtrace {
+    Backtrace::capture()
+}
+
+/// Finds the symbol containing `addr` in the embedded symbol table.
+///
+/// Returns the symbol name and the offset of `addr` in it, or `None` if the
+/// symbol table is not embedded or `addr` is not in the kernel text.
+pub fn symbolize(addr: usize) -> Option<(&'static str, usize)> {
+    if !is_kernel_text(addr) {
+        return None;
+    }
+    let mut found = None;
+    // Each line is "<address> <type> <name>", sorted by address.
+    for line in ksyms().lines() {
+        let mut fields = line.splitn(3, ' ');
+        let (Some(sym_addr), Some(_), Some(name)) = (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        let Ok(sym_addr) = usize::from_str_radix(sym_addr, 16) else {
+            continue;
+        };
+        if sym_addr > addr {
+            break;
+        }
+        found = Some((name, addr - sym_addr));
+    }
+    found
+}
+
+/// The embedded symbol table, see the module documentation.
+#[cfg(feature = "backtrace")]
+#[used]
+#[link_section = ".ksyms"]
+static KSYMS: [u8; include_bytes!(concat!(env!("OUT_DIR"), "/ksyms.txt")).len()] =
+    *include_bytes!(concat!(env!("OUT_DIR"), "/ksyms.txt"));
+
+fn ksyms() -> &'static str {
+    extern "C" {
+        fn _sksyms();
+        fn _eksyms();
+    }
+    // Use the linker symbols rather than `KSYMS`, so that the code does not
+    // depend on the size of the table.
+    let table = unsafe {
+        core::slice::from_raw_parts(
+            _sksyms as usize as *const u8,
+            _eksyms as usize - _sksyms as usize,
+        )
+    };
+    core::str::from_utf8(table).unwrap_or_default()
+}
+
+fn is_kernel_text(addr: usize) -> bool {
+    extern "C" {
+        fn _stext();
+        fn _etext();
+    }
+    (_stext as usize.._etext as usize).contains(&addr)
+}
+
+/// Size of a frame record: the caller's frame pointer and the return address.
+const RECORD_SIZE: usize = 2 * core::mem::size_of::<usize>();
+
+/// Returns the address of the frame record of the frame pointer `fp`.
+fn record_addr(fp: usize) -> Option<usize> {
+    if cfg!(any(target_arch = "riscv32", target_arch = "riscv64")) {
+        fp.checked_sub(RECORD_SIZE)
+    } else {
+        Some(fp)
+    }
+}
+
+/// Returns the bounds of the stack that contains the frame pointer `fp`.
+fn stack_bounds(fp: usize) -> (usize, usize) {
+    let hook = STACK_BOUNDS_HOOK.load(Ordering::Acquire);
+    if hook != 0 {
+        let hook: StackBoundsFn = unsafe { core::mem::transmute(hook) };
+        if let Some((bottom, top)) = hook() {
+            if (bottom..=top).contains(&fp) {
+                return (bottom, top);
+            }
+        }
+    }
+    let bottom = record_addr(fp).unwrap_or(fp);
+    (bottom, bottom.saturating_add(axconfig::TASK_STACK_SIZE))
+}
+
+/// Whether the frame record of `fp` is within the stack `[bottom, top)`.
+fn is_valid_fp(fp: usize, (bottom, top): (usize, usize)) -> bool {
+    fp % core::mem::size_of::<usize>() == 0
+        && record_addr(fp).is_some_and(|rec| rec >= bottom && rec + RECORD_SIZE <= top)
+}
+
+#[cfg(feature = "backtrace")]
+#[inline(always)]
+fn read_frame_pointer() -> usize {
+    let fp: usize;
+    unsafe {
+        #[cfg(target_arch = "x86_64")]
+        core::arch::asm!("mov {}, rbp", out(reg) fp);
+        #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+        core::arch::asm!("mv {}, s0", out(reg) fp);
+        #[cfg(target_arch = "aarch64")]
+        core::arch::asm!("mov {}, x29", out(reg) fp);
+    }
+    fp
+}
+
+/// Reads the frame record at `fp`, returns the caller's frame pointer and
+/// the return address.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+unsafe fn read_frame_record(fp: usize) -> (usize, usize) {
+    // `fp` points to the record.
+    let fp = fp as *const usize;
+    (*fp, *fp.add(1))
+}
+
+/// Reads the frame record at `fp`, returns the caller's frame pointer and
+/// the return address.
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+unsafe fn read_frame_record(fp: usize) -> (usize, usize) {
+    // `fp` points to the top of the frame, right above the record.
+    let fp = fp as *const usize;
+    (*fp.sub(2), *fp.sub(1))
+}
+
+/// Returns the PC of the code interrupted by a trap.
+#[cfg(all(feature = "perf", feature = "irq"))]
+pub(crate) fn trap_pc(tf: &TrapFrame) -> usize {
+    trap_pc_fp(tf).0
+}
+
+#[cfg(target_arch = "x86_64")]
+fn trap_pc_fp(tf: &TrapFrame) -> (usize, usize) {
+    (tf.rip as usize, tf.rbp as usize)
+}
+
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+fn trap_pc_fp(tf: &TrapFrame) -> (usize, usize) {
+    (tf.sepc, tf.regs.s0)
+}
+
+#[cfg(target_arch = "aarch64")]
+fn trap_pc_fp(tf: &TrapFrame) -> (usize, usize) {
+    (tf.elr as usize, tf.r[29] as usize)
+}
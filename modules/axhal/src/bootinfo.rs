@@ -0,0 +1,199 @@
+//! Information passed by the bootloader.
+//!
+//! The bootloader may pass a kernel command line and some modules (files
+//! loaded into memory, e.g., an initial ramdisk) to the kernel, and describe
+//! the usable RAM with a memory map. On x86, it may also pass the framebuffer
+//! it set up and the ACPI RSDP. They are collected at boot from the multiboot
+//! or multiboot2 information or the Linux boot parameters on x86, or from the
+//! `/chosen` node of the device tree on other platforms. The information is
+//! copied, so the original structures can be overwritten after boot.
+
+use lazyinit::LazyInit;
+use memory_addr::PhysAddr;
+
+use crate::mem::RangeList;
+
+/// Maximum length in bytes of the kernel command line. Longer command lines
+/// are truncated.
+pub const MAX_CMDLINE_LEN: usize = 1024;
+
+/// Maximum number of boot modules.
+pub const MAX_MODULES: usize = 8;
+
+/// Maximum length in bytes of a module name.
+const MAX_NAME_LEN: usize = 64;
+
+/// Length in bytes of the ACPI RSDP (version 2.0 or later).
+pub const ACPI_RSDP_LEN: usize = 36;
+
+/// A module loaded into memory by the bootloader.
+#[derive(Debug, Clone, Copy)]
+pub struct BootModule {
+    /// The start physical address of the module.
+    pub paddr: PhysAddr,
+    /// The size in bytes of the module.
+    pub size: usize,
+    /// The module name (usually its command line), may be empty.
+    pub name: &'static str,
+}
+
+/// A linear framebuffer set up by the bootloader.
+#[derive(Debug, Clone, Copy)]
+pub struct Framebuffer {
+    /// The start physical address of the framebuffer.
+    pub paddr: PhysAddr,
+    /// The width in pixels.
+    pub width: u32,
+    /// The height in pixels.
+    pub height: u32,
+    /// The number of bytes of each line.
+    pub pitch: u32,
+    /// The number of bits of each pixel.
+    pub bpp: u8,
+}
+
+impl Framebuffer {
+    /// The size in bytes of the framebuffer.
+    pub const fn size(&self) -> usize {
+        self.pitch as usize * self.height as usize
+    }
+}
+
+#[derive(Clone, Copy)]
+struct RawModule {
+    paddr: usize,
+    size: usize,
+    name: [u8; MAX_NAME_LEN],
+    name_len: usize,
+}
+
+/// The information collected from the bootloader.
+pub(crate) struct BootInfo {
+    cmdline: [u8; MAX_CMDLINE_LEN],
+    cmdline_len: usize,
+    modules: [RawModule; MAX_MODULES],
+    num_modules: usize,
+    /// The usable RAM in the memory map.
+    pub memory: RangeList,
+    /// The reserved ranges in the memory map.
+    pub reserved: RangeList,
+    /// The framebuffer set up by the bootloader.
+    pub framebuffer: Option<Framebuffer>,
+    acpi_rsdp: [u8; ACPI_RSDP_LEN],
+    acpi_rsdp_len: usize,
+}
+
+/// Copies `src` to `dst` as much as possible, returns the copied length.
+fn copy_bytes(dst: &mut [u8], src: &[u8]) -> usize {
+    let len = src.len().min(dst.len());
+    dst[..len].copy_from_slice(&src[..len]);
+    len
+}
+
+/// Returns the longest valid UTF-8 prefix of `bytes`, as the copied strings
+/// may be truncated in the middle of a character.
+fn utf8_prefix(bytes: &[u8]) -> &str {
+    match core::str::from_utf8(bytes) {
+        Ok(s) => s,
+        Err(e) => core::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap_or_default(),
+    }
+}
+
+impl BootInfo {
+    pub const fn new() -> Self {
+        const EMPTY: RawModule = RawModule {
+            paddr: 0,
+            size: 0,
+            name: [0; MAX_NAME_LEN],
+            name_len: 0,
+        };
+        Self {
+            cmdline: [0; MAX_CMDLINE_LEN],
+            cmdline_len: 0,
+            modules: [EMPTY; MAX_MODULES],
+            num_modules: 0,
+            memory: RangeList::new(),
+            reserved: RangeList::new(),
+            framebuffer: None,
+            acpi_rsdp: [0; ACPI_RSDP_LEN],
+            acpi_rsdp_len: 0,
+        }
+    }
+
+    pub fn set_cmdline(&mut self, cmdline: &[u8]) {
+        self.cmdline_len = copy_bytes(&mut self.cmdline, cmdline);
+    }
+
+    /// Records a copy of the ACPI RSDP (20 bytes for ACPI 1.0, or
+    /// [`ACPI_RSDP_LEN`] bytes for later versions).
+    #[allow(dead_code)]
+    pub fn set_acpi_rsdp(&mut self, rsdp: &[u8]) {
+        self.acpi_rsdp_len = copy_bytes(&mut self.acpi_rsdp, rsdp);
+    }
+
+    /// Appends a module, returns `false` if there are too many modules.
+    pub fn add_module(&mut self, paddr: usize, size: usize, name: &[u8]) -> bool {
+        if self.num_modules == MAX_MODULES {
+            return false;
+        }
+        let m = &mut self.modules[self.num_modules];
+        m.paddr = paddr;
+        m.size = size;
+        m.name_len = copy_bytes(&mut m.name, name);
+        self.num_modules += 1;
+        true
+    }
+}
+
+static BOOT_INFO: LazyInit<BootInfo> = LazyInit::new();
+
+/// Records the information collected from the bootloader, which is called at
+/// the early stage of the boot on the primary CPU.
+#[allow(dead_code)]
+pub(crate) fn init(info: BootInfo) {
+    BOOT_INFO.init_once(info);
+}
+
+/// Returns the information collected from the bootloader, or [`None`] if
+/// the bootloader does not pass any.
+pub(crate) fn info() -> Option<&'static BootInfo> {
+    BOOT_INFO.get()
+}
+
+/// Returns the kernel command line, which is empty if there is none.
+pub fn cmdline() -> &'static str {
+    info().map_or("", |info| utf8_prefix(&info.cmdline[..info.cmdline_len]))
+}
+
+/// Returns an iterator over the modules loaded by the bootloader.
+///
+/// Their memory is reserved, and mapped in the linear mapping.
+pub fn modules() -> impl Iterator<Item = BootModule> {
+    info()
+        .map_or(&[][..], |info| &info.modules[..info.num_modules])
+        .iter()
+        .map(|m| BootModule {
+            paddr: m.paddr.into(),
+            size: m.size,
+            name: utf8_prefix(&m.name[..m.name_len]),
+        })
+}
+
+/// Returns the framebuffer set up by the bootloader, if any.
+pub fn framebuffer() -> Option<Framebuffer> {
+    info()?.framebuffer
+}
+
+/// Returns a copy of the ACPI RSDP passed by the bootloader, if any.
+///
+/// Without it, the RSDP has to be searched for in the BIOS memory.
+pub fn acpi_rsdp() -> Option<&'static [u8]> {
+    let info = info()?;
+    (info.acpi_rsdp_len > 0).then(|| &info.acpi_rsdp[..info.acpi_rsdp_len])
+}
+
+/// Returns the initial ramdisk, which is the first boot module (e.g., the
+/// file passed by QEMU `-initrd`).
+pub fn initrd() -> Option<BootModule> {
+    modules().next()
+}
@@ -0,0 +1,151 @@
+//! Console input and output.
+//!
+//! The console input is polled from the UART, or buffered by its receive
+//! interrupt handler on the platforms that support it (with the `irq`
+//! feature). The handler calls the hook set by `set_input_hook` to notify
+//! the upper layers, e.g., to wake up the tasks waiting for input.
+//!
+//! The output written by [`write_bytes`] is passed to the hook set by
+//! [`set_output_hook`], e.g., to mirror the console to remote sessions.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use kspin::SpinNoIrq;
+
+pub use crate::platform::console::putchar;
+
+/// The size of the buffer of the received bytes. Bytes received when it is
+/// full are dropped.
+const INPUT_BUF_SIZE: usize = 256;
+
+/// A ring buffer of the received bytes.
+struct InputBuffer {
+    buf: [u8; INPUT_BUF_SIZE],
+    head: usize,
+    len: usize,
+}
+
+impl InputBuffer {
+    const fn new() -> Self {
+        Self {
+            buf: [0; INPUT_BUF_SIZE],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    #[cfg(feature = "irq")]
+    fn push(&mut self, c: u8) -> bool {
+        if self.len == INPUT_BUF_SIZE {
+            return false;
+        }
+        self.buf[(self.head + self.len) % INPUT_BUF_SIZE] = c;
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let c = self.buf[self.head];
+        self.head = (self.head + 1) % INPUT_BUF_SIZE;
+        self.len -= 1;
+        Some(c)
+    }
+}
+
+static INPUT_BUF: SpinNoIrq<InputBuffer> = SpinNoIrq::new(InputBuffer::new());
+
+static OUTPUT_HOOK: AtomicUsize = AtomicUsize::new(0);
+
+/// Sets the function called with the bytes written by [`write_bytes`], after
+/// they are written to the console.
+///
+/// The hook may be called in the interrupt context (e.g., by logging), so it
+/// must not block, allocate memory, or write to the console.
+pub fn set_output_hook(hook: fn(&[u8])) {
+    OUTPUT_HOOK.store(hook as usize, Ordering::Release);
+}
+
+/// Write a slice of bytes to the console.
+pub fn write_bytes(bytes: &[u8]) {
+    for c in bytes {
+        putchar(*c);
+    }
+    let hook = OUTPUT_HOOK.load(Ordering::Acquire);
+    if hook != 0 {
+        let hook: fn(&[u8]) = unsafe { core::mem::transmute(hook) };
+        hook(bytes);
+    }
+}
+
+/// Reads a byte from the console, or returns [`None`] if no input is available.
+///
+/// The bytes buffered by the receive interrupt handler are returned first.
+pub fn getchar() -> Option<u8> {
+    // Hold the lock while polling the UART, so that the interrupt handler
+    // cannot buffer later bytes before it.
+    let mut buf = INPUT_BUF.lock();
+    buf.pop().or_else(crate::platform::console::getchar)
+}
+
+#[cfg(feature = "irq")]
+mod irq {
+    use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use super::INPUT_BUF;
+
+    static INPUT_HOOK: AtomicUsize = AtomicUsize::new(0);
+    static HAS_INPUT_IRQ: AtomicBool = AtomicBool::new(false);
+
+    /// Sets the function called after the receive interrupt handler of the
+    /// console buffers the received bytes, which can be read by
+    /// [`getchar`](super::getchar).
+    ///
+    /// The hook is called in the interrupt context, so it must not block.
+    pub fn set_input_hook(hook: fn()) {
+        INPUT_HOOK.store(hook as usize, Ordering::Release);
+    }
+
+    /// Returns whether the console input is received by interrupts.
+    /// Otherwise, it must be polled.
+    pub fn has_input_irq() -> bool {
+        HAS_INPUT_IRQ.load(Ordering::Acquire)
+    }
+
+    /// Drains the received bytes of the UART into the input buffer, and
+    /// calls the input hook. It is called by the receive interrupt handler.
+    #[allow(dead_code)]
+    pub(crate) fn handle_input_irq() {
+        {
+            let mut buf = INPUT_BUF.lock();
+            while let Some(c) = crate::platform::console::getchar() {
+                if !buf.push(c) {
+                    warn!("Console input buffer is full");
+                }
+            }
+        }
+        let hook = INPUT_HOOK.load(Ordering::Acquire);
+        if hook != 0 {
+            let hook: fn() = unsafe { core::mem::transmute(hook) };
+            hook();
+        }
+    }
+
+    /// Registers the receive interrupt handler of the console UART, which
+    /// calls [`handle_input_irq`] after acknowledging the UART if necessary.
+    #[allow(dead_code)]
+    pub(crate) fn register_input_irq(irq_num: usize, handler: crate::irq::IrqHandler) {
+        if crate::irq::register_handler(irq_num, handler) {
+            HAS_INPUT_IRQ.store(true, Ordering::Release);
+        }
+    }
+}
+
+#[cfg(feature = "irq")]
+pub use self::irq::{has_input_irq, set_input_hook};
+
+#[cfg(feature = "irq")]
+#[allow(unused_imports)]
+pub(crate) use self::irq::{handle_input_irq, register_input_irq};
@@ -22,6 +22,17 @@ pub fn this_cpu_is_bsp() -> bool {
     IS_BSP.read_current()
 }
 
+/// Returns the number of CPUs to run on.
+///
+/// It is the number of CPUs in the device tree or the ACPI MADT if there is
+/// one, otherwise [`axconfig::SMP`]. It never exceeds [`axconfig::SMP`], which is the
+/// maximum number of CPUs supported by the kernel.
+pub fn cpu_count() -> usize {
+    crate::dtb::cpu_count()
+        .or_else(crate::acpi::cpu_count)
+        .map_or(axconfig::SMP, |n| n.min(axconfig::SMP))
+}
+
 /// Stores the pointer to the current task in the SP_EL0 register.
 ///
 /// In aarch64 architecture, we use `SP_EL0` as the read cache for
@@ -94,6 +105,8 @@ pub(crate) fn init_primary(cpu_id: usize) {
         IS_BSP.write_current_raw(true);
     }
     crate::arch::cpu_init();
+    #[cfg(feature = "perf")]
+    crate::perf::init();
 }
 
 #[allow(dead_code)]
@@ -0,0 +1,716 @@
+//! Flattened device tree (FDT) parsing.
+//!
+//! On riscv64 and aarch64, the firmware passes the physical address of a
+//! device tree blob (DTB) to the kernel. It is parsed at boot to get the
+//! memory regions, the number of CPUs, the timer frequency, the console UART,
+//! the virtio-mmio devices, the command line and the initial ramdisk (see
+//! [`crate::bootinfo`]), so that one kernel image can boot machines with
+//! different configurations. The static platform configuration
+//! ([`axconfig`]) is only used as a fallback, if there is no device tree or
+//! the information is missing from it.
+
+use lazyinit::LazyInit;
+use memory_addr::PhysAddr;
+
+use crate::bootinfo::BootInfo;
+use crate::mem::{phys_to_virt, RangeList};
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+
+/// Maximum depth of nodes that can be parsed.
+const MAX_DEPTH: usize = 16;
+
+fn be32(data: &[u8], off: usize) -> Option<u32> {
+    let bytes = data.get(off..off + 4)?;
+    Some(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+/// Reads a number of `cells` 32-bit cells.
+fn read_cells(data: &[u8], cells: usize) -> Option<u64> {
+    (0..cells).try_fold(0u64, |val, i| Some(val << 32 | be32(data, i * 4)? as u64))
+}
+
+/// Reads a null-terminated string.
+fn cstr(data: &[u8], off: usize) -> Option<&str> {
+    let bytes = data.get(off..)?;
+    let len = bytes.iter().position(|&c| c == 0)?;
+    core::str::from_utf8(&bytes[..len]).ok()
+}
+
+const fn align4(off: usize) -> usize {
+    (off + 3) & !3
+}
+
+/// A flattened device tree.
+#[derive(Clone, Copy)]
+pub struct Fdt<'a> {
+    structs: &'a [u8],
+    strings: &'a [u8],
+    mem_rsvmap: &'a [u8],
+    total_size: usize,
+}
+
+impl<'a> Fdt<'a> {
+    /// Parses the header of the device tree blob in `data`.
+    pub fn from_bytes(data: &'a [u8]) -> Option<Self> {
+        if be32(data, 0)? != FDT_MAGIC {
+            return None;
+        }
+        let total_size = be32(data, 4)? as usize;
+        let off_structs = be32(data, 8)? as usize;
+        let off_strings = be32(data, 12)? as usize;
+        let off_mem_rsvmap = be32(data, 16)? as usize;
+        let size_strings = be32(data, 32)? as usize;
+        let size_structs = be32(data, 36)? as usize;
+        let data = data.get(..total_size)?;
+        Some(Self {
+            structs: data.get(off_structs..off_structs + size_structs)?,
+            strings: data.get(off_strings..off_strings + size_strings)?,
+            mem_rsvmap: data.get(off_mem_rsvmap..)?,
+            total_size,
+        })
+    }
+
+    /// Parses the device tree blob at `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to a valid device tree blob, which lives as long as
+    /// `'a`.
+    pub unsafe fn from_ptr(ptr: *const u8) -> Option<Self> {
+        let header = core::slice::from_raw_parts(ptr, 8);
+        if be32(header, 0)? != FDT_MAGIC {
+            return None;
+        }
+        let total_size = be32(header, 4)? as usize;
+        Self::from_bytes(core::slice::from_raw_parts(ptr, total_size))
+    }
+
+    /// Returns the size of the device tree blob in bytes.
+    pub const fn total_size(&self) -> usize {
+        self.total_size
+    }
+
+    /// Returns the memory reservation block: an iterator over the address and
+    /// size of each reserved memory range.
+    pub fn memory_reservations(&self) -> impl Iterator<Item = (u64, u64)> + 'a {
+        let rsvmap = self.mem_rsvmap;
+        (0..)
+            .map(move |i| {
+                let entry = rsvmap.get(i * 16..i * 16 + 16)?;
+                Some((read_cells(entry, 2)?, read_cells(&entry[8..], 2)?))
+            })
+            .map_while(|entry| entry.filter(|&(addr, size)| addr != 0 || size != 0))
+    }
+
+    /// Visits all nodes in depth-first order. `f` is called with the node and
+    /// the names of the nodes on its path from the root (the root is named
+    /// `""`).
+    fn walk_with_path(&self, mut f: impl FnMut(&FdtNode<'a>, &[&'a str])) {
+        let data = self.structs;
+        let mut names = [""; MAX_DEPTH];
+        // `#address-cells` and `#size-cells` of the nodes on the path.
+        let mut cells = [(2, 1); MAX_DEPTH];
+        let mut depth = 0;
+        let mut off = 0;
+        while let Some(token) = be32(data, off) {
+            off += 4;
+            match token {
+                FDT_BEGIN_NODE => {
+                    let Some(name) = cstr(data, off) else {
+                        return;
+                    };
+                    off = align4(off + name.len() + 1);
+                    let props_start = off;
+                    while let Some(token @ (FDT_PROP | FDT_NOP)) = be32(data, off) {
+                        off += 4;
+                        if token == FDT_PROP {
+                            let Some(len) = be32(data, off) else {
+                                return;
+                            };
+                            off = align4(off + 8 + len as usize);
+                        }
+                    }
+                    if depth >= MAX_DEPTH {
+                        return;
+                    }
+                    let (addr_cells, size_cells) =
+                        if depth > 0 { cells[depth - 1] } else { (2, 1) };
+                    let node = FdtNode {
+                        fdt: *self,
+                        name,
+                        props: data.get(props_start..off).unwrap_or_default(),
+                        addr_cells,
+                        size_cells,
+                    };
+                    cells[depth] = (
+                        node.prop_u32("#address-cells").map_or(2, |c| c as usize),
+                        node.prop_u32("#size-cells").map_or(1, |c| c as usize),
+                    );
+                    names[depth] = name;
+                    f(&node, &names[..=depth]);
+                    depth += 1;
+                }
+                FDT_END_NODE => depth = depth.saturating_sub(1),
+                FDT_PROP => {
+                    // Properties after child nodes are invalid, skip them.
+                    let Some(len) = be32(data, off) else {
+                        return;
+                    };
+                    off = align4(off + 8 + len as usize);
+                }
+                FDT_NOP => {}
+                _ => return, // FDT_END or invalid
+            }
+        }
+    }
+
+    /// Visits all nodes in depth-first order.
+    pub fn walk(&self, mut f: impl FnMut(&FdtNode<'a>)) {
+        self.walk_with_path(|node, _| f(node));
+    }
+
+    /// Finds the node with the given full path (e.g., `/soc/uart@10000000`),
+    /// or the path of an alias in `/aliases`.
+    ///
+    /// The unit address of a node name can be omitted if it is unambiguous.
+    pub fn find_node(&self, path: &str) -> Option<FdtNode<'a>> {
+        let path = if path.starts_with('/') {
+            path
+        } else {
+            self.find_node("/aliases")?.prop_str(path)?
+        };
+        let mut found = None;
+        self.walk_with_path(|node, names| {
+            if found.is_some() {
+                return;
+            }
+            let mut comps = path.split('/').filter(|c| !c.is_empty());
+            let matched = names[1..].iter().all(|name| {
+                comps
+                    .next()
+                    .is_some_and(|comp| *name == comp || name.split('@').next() == Some(comp))
+            });
+            if matched && comps.next().is_none() {
+                found = Some(*node);
+            }
+        });
+        found
+    }
+
+    /// Returns the node of the console in `/chosen/stdout-path`.
+    pub fn stdout(&self) -> Option<FdtNode<'a>> {
+        let path = self.find_node("/chosen")?.prop_str("stdout-path")?;
+        // Remove the options, e.g. "serial0:115200n8".
+        self.find_node(path.split(':').next()?)
+    }
+}
+
+/// A node in a flattened device tree.
+#[derive(Clone, Copy)]
+pub struct FdtNode<'a> {
+    fdt: Fdt<'a>,
+    name: &'a str,
+    props: &'a [u8],
+    /// `#address-cells` and `#size-cells` of the parent node.
+    addr_cells: usize,
+    size_cells: usize,
+}
+
+impl<'a> FdtNode<'a> {
+    /// Returns the name of the node, with the unit address (e.g.,
+    /// `memory@80000000`).
+    pub const fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// Returns an iterator over the names and values of the properties.
+    pub fn properties(&self) -> impl Iterator<Item = (&'a str, &'a [u8])> + 'a {
+        let (props, strings) = (self.props, self.fdt.strings);
+        let mut off = 0;
+        core::iter::from_fn(move || loop {
+            let token = be32(props, off)?;
+            off += 4;
+            if token != FDT_PROP {
+                continue;
+            }
+            let len = be32(props, off)? as usize;
+            let name = cstr(strings, be32(props, off + 4)? as usize)?;
+            let value = props.get(off + 8..off + 8 + len)?;
+            off = align4(off + 8 + len);
+            return Some((name, value));
+        })
+    }
+
+    /// Returns the value of the property.
+    pub fn property(&self, name: &str) -> Option<&'a [u8]> {
+        self.properties().find(|&(n, _)| n == name).map(|(_, v)| v)
+    }
+
+    /// Returns the value of a string property (the first string of a string
+    /// list).
+    pub fn prop_str(&self, name: &str) -> Option<&'a str> {
+        cstr(self.property(name)?, 0)
+    }
+
+    /// Returns the value of a property with one cell.
+    pub fn prop_u32(&self, name: &str) -> Option<u32> {
+        be32(self.property(name)?, 0)
+    }
+
+    /// Returns the value of a property with one or two cells.
+    pub fn prop_u64(&self, name: &str) -> Option<u64> {
+        let value = self.property(name)?;
+        read_cells(value, value.len().min(8) / 4)
+    }
+
+    /// Whether the node is compatible with `compat`, i.e., it is in the
+    /// `compatible` string list.
+    pub fn is_compatible(&self, compat: &str) -> bool {
+        self.property("compatible")
+            .is_some_and(|list| list.split(|&c| c == 0).any(|s| s == compat.as_bytes()))
+    }
+
+    /// Whether the node is enabled, i.e., its `status` is absent or `okay`.
+    pub fn is_enabled(&self) -> bool {
+        self.prop_str("status")
+            .map_or(true, |s| s == "okay" || s == "ok")
+    }
+
+    /// Returns an iterator over the address and size of each range in the
+    /// `reg` property.
+    pub fn reg(&self) -> impl Iterator<Item = (usize, usize)> + 'a {
+        let reg = self.property("reg").unwrap_or_default();
+        let (addr_cells, size_cells) = (self.addr_cells, self.size_cells);
+        let entry_size = (addr_cells + size_cells) * 4;
+        reg.chunks_exact(entry_size.max(4))
+            .filter_map(move |entry| {
+                let addr = read_cells(entry, addr_cells)?;
+                let size = read_cells(&entry[addr_cells * 4..], size_cells)?;
+                Some((addr as usize, size as usize))
+            })
+    }
+}
+
+/// The GIC (ARM Generic Interrupt Controller) in the device tree.
+#[derive(Clone, Copy)]
+pub(crate) struct DtbGic {
+    /// The GIC version, 2 or 3.
+    pub version: u8,
+    /// The distributor (GICD) registers.
+    pub gicd: (usize, usize),
+    /// The CPU interface (GICC) registers of GICv2, or the redistributor
+    /// (GICR) region of GICv3.
+    pub gicc_or_gicr: (usize, usize),
+    /// The Interrupt Translation Service (ITS) registers of GICv3.
+    pub its: Option<(usize, usize)>,
+}
+
+/// The information collected from the device tree at boot.
+pub(crate) struct DtbInfo {
+    pub paddr: usize,
+    pub size: usize,
+    pub memory: RangeList,
+    pub reserved: RangeList,
+    pub virtio_mmio: RangeList,
+    pub cpu_count: usize,
+    pub timer_freq: Option<u64>,
+    pub uart_paddr: Option<usize>,
+    pub gic: Option<DtbGic>,
+}
+
+static DTB_INFO: LazyInit<DtbInfo> = LazyInit::new();
+
+/// The `compatible` values of GICv2 (and its predecessors).
+const GICV2_COMPATIBLES: [&str; 4] = [
+    "arm,gic-400",
+    "arm,cortex-a15-gic",
+    "arm,cortex-a9-gic",
+    "arm,cortex-a7-gic",
+];
+
+/// Returns the information collected from the device tree, or [`None`] if
+/// there is no device tree.
+pub(crate) fn info() -> Option<&'static DtbInfo> {
+    DTB_INFO.get()
+}
+
+/// Parses the device tree blob at `dtb_paddr`, which is called at the early
+/// stage of the boot on the primary CPU.
+///
+/// The blob must be accessible in the linear mapping of the boot page table.
+/// Memory nodes with a `numa-node-id` are added as NUMA memory ranges (see
+/// [`add_numa_range`](crate::mem::add_numa_range)).
+#[allow(dead_code)]
+pub(crate) fn init(dtb_paddr: usize) {
+    if dtb_paddr == 0 {
+        return;
+    }
+    let ptr = phys_to_virt(PhysAddr::from(dtb_paddr)).as_ptr();
+    // Safety: the firmware passes a valid device tree blob, which is never
+    // freed (it is reserved in the memory regions).
+    let Some(fdt) = (unsafe { Fdt::from_ptr(ptr) }) else {
+        return;
+    };
+
+    let mut info = DtbInfo {
+        paddr: dtb_paddr,
+        size: fdt.total_size(),
+        memory: RangeList::new(),
+        reserved: RangeList::new(),
+        virtio_mmio: RangeList::new(),
+        cpu_count: 0,
+        timer_freq: None,
+        uart_paddr: None,
+        gic: None,
+    };
+    info.reserved.reserve(dtb_paddr, fdt.total_size());
+    for (addr, size) in fdt.memory_reservations() {
+        info.reserved.reserve(addr as usize, size as usize);
+    }
+
+    fdt.walk_with_path(|node, names| {
+        let parent = names.len().checked_sub(2).map_or("", |i| names[i]);
+        if !node.is_enabled() {
+            return;
+        }
+        if node.prop_str("device_type") == Some("memory") {
+            let numa_node = node.prop_u32("numa-node-id");
+            for (addr, size) in node.reg() {
+                info.memory.push(addr, size);
+                if let Some(numa_node) = numa_node {
+                    crate::mem::add_numa_range(addr.into(), size, numa_node as usize);
+                }
+            }
+        } else if node.prop_str("device_type") == Some("cpu") {
+            info.cpu_count += 1;
+            // RISC-V puts the timer frequency in the CPU nodes or their
+            // parent `/cpus` node.
+            if let Some(freq) = node.prop_u64("timebase-frequency") {
+                info.timer_freq.get_or_insert(freq);
+            }
+        } else if node.name() == "cpus" {
+            if let Some(freq) = node.prop_u64("timebase-frequency") {
+                info.timer_freq = Some(freq);
+            }
+        } else if parent == "reserved-memory" {
+            for (addr, size) in node.reg() {
+                info.reserved.reserve(addr, size);
+            }
+        } else if node.is_compatible("virtio,mmio") {
+            for (addr, size) in node.reg() {
+                info.virtio_mmio.push(addr, size);
+            }
+        } else if node.is_compatible("arm,armv8-timer") || node.is_compatible("arm,armv7-timer") {
+            if let Some(freq) = node.prop_u64("clock-frequency") {
+                info.timer_freq = Some(freq);
+            }
+        } else if node.property("interrupt-controller").is_some() && info.gic.is_none() {
+            let version = if node.is_compatible("arm,gic-v3") {
+                3
+            } else if GICV2_COMPATIBLES.iter().any(|c| node.is_compatible(c)) {
+                2
+            } else {
+                return;
+            };
+            let mut reg = node.reg();
+            if let (Some(gicd), Some(gicc_or_gicr)) = (reg.next(), reg.next()) {
+                info.gic = Some(DtbGic {
+                    version,
+                    gicd,
+                    gicc_or_gicr,
+                    its: None,
+                });
+            }
+        } else if node.is_compatible("arm,gic-v3-its") {
+            // The ITS is a child node of the GIC.
+            if let Some(gic) = info.gic.as_mut() {
+                gic.its = gic.its.or(node.reg().next());
+            }
+        }
+    });
+    info.uart_paddr = fdt.stdout().and_then(|node| node.reg().next()).map(|r| r.0);
+
+    // The command line and the initial ramdisk.
+    let mut boot_info = BootInfo::new();
+    if let Some(chosen) = fdt.find_node("/chosen") {
+        if let Some(bootargs) = chosen.prop_str("bootargs") {
+            boot_info.set_cmdline(bootargs.as_bytes());
+        }
+        let start = chosen.prop_u64("linux,initrd-start");
+        let end = chosen.prop_u64("linux,initrd-end");
+        if let (Some(start), Some(end)) = (start, end) {
+            if end > start {
+                let size = (end - start) as usize;
+                info.reserved.reserve(start as usize, size);
+                boot_info.add_module(start as usize, size, b"initrd");
+            }
+        }
+    }
+    crate::bootinfo::init(boot_info);
+
+    DTB_INFO.init_once(info);
+}
+
+/// Returns the physical address and the size of the device tree blob passed
+/// by the firmware, or [`None`] if there is none.
+pub fn dtb_region() -> Option<(PhysAddr, usize)> {
+    info().map(|info| (info.paddr.into(), info.size))
+}
+
+/// Returns the parsed device tree passed by the firmware, or [`None`] if
+/// there is none.
+pub fn fdt() -> Option<Fdt<'static>> {
+    let info = info()?;
+    // Safety: the blob was validated by `init`, and is never freed.
+    unsafe { Fdt::from_ptr(phys_to_virt(info.paddr.into()).as_ptr()) }
+}
+
+/// Returns the number of enabled CPUs in the device tree.
+pub fn cpu_count() -> Option<usize> {
+    info().map(|info| info.cpu_count).filter(|&n| n > 0)
+}
+
+/// Returns the frequency of the timer in the device tree, in Hz.
+pub fn timer_frequency() -> Option<u64> {
+    info()?.timer_freq
+}
+
+/// Returns the physical address of the console UART in the device tree.
+pub fn uart_paddr() -> Option<PhysAddr> {
+    info()?.uart_paddr.map(PhysAddr::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a device tree blob.
+    #[derive(Default)]
+    struct Builder {
+        structs: Vec<u8>,
+        strings: Vec<u8>,
+    }
+
+    impl Builder {
+        fn token(&mut self, token: u32) -> &mut Self {
+            self.structs.extend_from_slice(&token.to_be_bytes());
+            self
+        }
+
+        fn pad(&mut self) {
+            self.structs.resize(align4(self.structs.len()), 0);
+        }
+
+        fn begin(&mut self, name: &str) -> &mut Self {
+            self.token(FDT_BEGIN_NODE);
+            self.structs.extend_from_slice(name.as_bytes());
+            self.structs.push(0);
+            self.pad();
+            self
+        }
+
+        fn end(&mut self) -> &mut Self {
+            self.token(FDT_END_NODE)
+        }
+
+        fn prop(&mut self, name: &str, value: &[u8]) -> &mut Self {
+            let name_off = self.strings.len() as u32;
+            self.strings.extend_from_slice(name.as_bytes());
+            self.strings.push(0);
+            self.token(FDT_PROP)
+                .token(value.len() as u32)
+                .token(name_off);
+            self.structs.extend_from_slice(value);
+            self.pad();
+            self
+        }
+
+        fn prop_str(&mut self, name: &str, value: &str) -> &mut Self {
+            let mut bytes = value.as_bytes().to_vec();
+            bytes.push(0);
+            self.prop(name, &bytes)
+        }
+
+        fn prop_cells(&mut self, name: &str, cells: &[u32]) -> &mut Self {
+            let bytes: Vec<u8> = cells.iter().flat_map(|c| c.to_be_bytes()).collect();
+            self.prop(name, &bytes)
+        }
+
+        /// Returns the blob with the given memory reservations.
+        fn finish(&mut self, reservations: &[(u64, u64)]) -> Vec<u8> {
+            self.token(9); // FDT_END
+            let off_rsvmap = 40;
+            let off_structs = off_rsvmap + (reservations.len() + 1) * 16;
+            let off_strings = off_structs + self.structs.len();
+            let total_size = off_strings + self.strings.len();
+            let header = [
+                FDT_MAGIC,
+                total_size as u32,
+                off_structs as u32,
+                off_strings as u32,
+                off_rsvmap as u32,
+                17, // version
+                16, // last compatible version
+                0,  // boot CPU
+                self.strings.len() as u32,
+                self.structs.len() as u32,
+            ];
+            let mut blob: Vec<u8> = header.iter().flat_map(|w| w.to_be_bytes()).collect();
+            for &(addr, size) in reservations.iter().chain([&(0, 0)]) {
+                blob.extend_from_slice(&addr.to_be_bytes());
+                blob.extend_from_slice(&size.to_be_bytes());
+            }
+            blob.extend_from_slice(&self.structs);
+            blob.extend_from_slice(&self.strings);
+            blob
+        }
+    }
+
+    fn sample_blob() -> Vec<u8> {
+        Builder::default()
+            .begin("")
+            .prop_cells("#address-cells", &[2])
+            .prop_cells("#size-cells", &[2])
+            .begin("aliases")
+            .prop_str("serial0", "/soc/uart@10000000")
+            .end()
+            .begin("chosen")
+            .prop_str("stdout-path", "serial0:115200n8")
+            .prop_str("bootargs", "console=ttyS0")
+            .end()
+            .begin("memory@80000000")
+            .prop_str("device_type", "memory")
+            .prop_cells("reg", &[0, 0x8000_0000, 0, 0x800_0000])
+            .end()
+            .begin("soc")
+            .prop_cells("#address-cells", &[1])
+            .prop_cells("#size-cells", &[1])
+            .begin("uart@10000000")
+            .prop("compatible", b"ns16550a\0snps,dw-apb-uart\0")
+            .prop_cells("reg", &[0x1000_0000, 0x100])
+            .end()
+            .begin("virtio_mmio@10001000")
+            .prop_str("compatible", "virtio,mmio")
+            .prop_str("status", "disabled")
+            .end()
+            .end()
+            .end()
+            .finish(&[(0x8010_0000, 0x1000)])
+    }
+
+    #[test]
+    fn test_header() {
+        let blob = sample_blob();
+        let fdt = Fdt::from_bytes(&blob).unwrap();
+        assert_eq!(fdt.total_size(), blob.len());
+        assert!(Fdt::from_bytes(&blob[..blob.len() - 1]).is_none());
+        let mut bad_magic = blob.clone();
+        bad_magic[0] = 0;
+        assert!(Fdt::from_bytes(&bad_magic).is_none());
+        assert!(Fdt::from_bytes(&[]).is_none());
+    }
+
+    #[test]
+    fn test_memory_reservations() {
+        let blob = sample_blob();
+        let fdt = Fdt::from_bytes(&blob).unwrap();
+        let rsv: Vec<_> = fdt.memory_reservations().collect();
+        assert_eq!(rsv, [(0x8010_0000, 0x1000)]);
+    }
+
+    #[test]
+    fn test_walk() {
+        let blob = sample_blob();
+        let fdt = Fdt::from_bytes(&blob).unwrap();
+        let mut names = Vec::new();
+        fdt.walk(|node| names.push(node.name()));
+        assert_eq!(
+            names,
+            [
+                "",
+                "aliases",
+                "chosen",
+                "memory@80000000",
+                "soc",
+                "uart@10000000",
+                "virtio_mmio@10001000",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_node() {
+        let blob = sample_blob();
+        let fdt = Fdt::from_bytes(&blob).unwrap();
+        assert_eq!(fdt.find_node("/").unwrap().name(), "");
+        assert_eq!(
+            fdt.find_node("/soc/uart@10000000").unwrap().name(),
+            "uart@10000000"
+        );
+        // The unit address can be omitted.
+        assert_eq!(fdt.find_node("/soc/uart").unwrap().name(), "uart@10000000");
+        // Aliases.
+        assert_eq!(fdt.find_node("serial0").unwrap().name(), "uart@10000000");
+        assert!(fdt.find_node("/soc/uart@20000000").is_none());
+        assert!(fdt.find_node("/soc/uart@10000000/child").is_none());
+        assert!(fdt.find_node("serial1").is_none());
+        assert_eq!(fdt.stdout().unwrap().name(), "uart@10000000");
+    }
+
+    #[test]
+    fn test_properties() {
+        let blob = sample_blob();
+        let fdt = Fdt::from_bytes(&blob).unwrap();
+        let chosen = fdt.find_node("/chosen").unwrap();
+        assert_eq!(chosen.prop_str("bootargs"), Some("console=ttyS0"));
+        assert!(chosen.property("missing").is_none());
+
+        let uart = fdt.find_node("/soc/uart").unwrap();
+        assert!(uart.is_compatible("ns16550a"));
+        assert!(uart.is_compatible("snps,dw-apb-uart"));
+        assert!(!uart.is_compatible("ns16550"));
+        assert!(uart.is_enabled());
+        assert_eq!(uart.prop_u32("reg"), Some(0x1000_0000));
+        assert_eq!(uart.prop_u64("reg"), Some(0x1000_0000_0000_0100));
+
+        let virtio = fdt.find_node("/soc/virtio_mmio").unwrap();
+        assert!(!virtio.is_enabled());
+    }
+
+    #[test]
+    fn test_reg_cells() {
+        let blob = sample_blob();
+        let fdt = Fdt::from_bytes(&blob).unwrap();
+        // Two address and size cells from the root node.
+        let memory = fdt.find_node("/memory").unwrap();
+        assert_eq!(
+            memory.reg().collect::<Vec<_>>(),
+            [(0x8000_0000, 0x800_0000)]
+        );
+        // One address and size cell from the `soc` node.
+        let uart = fdt.find_node("/soc/uart").unwrap();
+        assert_eq!(uart.reg().collect::<Vec<_>>(), [(0x1000_0000, 0x100)]);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut builder = Builder::default();
+        builder.begin("").begin("child").prop("reg", &[0; 8]);
+        // A property whose length runs past the end of the structure block.
+        builder.token(FDT_PROP).token(0x1000).token(0);
+        let blob = builder.finish(&[]);
+        let fdt = Fdt::from_bytes(&blob).unwrap();
+        // The walk stops at the end of the block, and the properties of the
+        // truncated node are dropped.
+        let mut count = 0;
+        fdt.walk(|_| count += 1);
+        assert_eq!(count, 2);
+        let child = fdt.find_node("/child").unwrap();
+        assert!(child.property("reg").is_none());
+    }
+}
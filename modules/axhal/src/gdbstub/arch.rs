@@ -0,0 +1,283 @@
+//! Architecture-specific parts of the GDB stub: the register layout of the
+//! `g` packet, breakpoint instructions and single-stepping.
+
+use crate::arch::TrapFrame;
+
+cfg_if::cfg_if! {
+    if #[cfg(target_arch = "x86_64")] {
+        const TF: u64 = 1 << 8; // Trap Flag in RFLAGS
+
+        /// Sizes of the registers in the `g` packet: RAX..R15, RIP, EFLAGS,
+        /// CS, SS, DS, ES, FS and GS.
+        pub const REG_SIZES: [usize; 24] = {
+            let mut sizes = [8; 24];
+            let mut i = 17;
+            while i < 24 {
+                sizes[i] = 4;
+                i += 1;
+            }
+            sizes
+        };
+
+        fn reg_mut(tf: &mut TrapFrame, i: usize) -> Option<&mut u64> {
+            Some(match i {
+                0 => &mut tf.rax,
+                1 => &mut tf.rbx,
+                2 => &mut tf.rcx,
+                3 => &mut tf.rdx,
+                4 => &mut tf.rsi,
+                5 => &mut tf.rdi,
+                6 => &mut tf.rbp,
+                7 => &mut tf.rsp,
+                8 => &mut tf.r8,
+                9 => &mut tf.r9,
+                10 => &mut tf.r10,
+                11 => &mut tf.r11,
+                12 => &mut tf.r12,
+                13 => &mut tf.r13,
+                14 => &mut tf.r14,
+                15 => &mut tf.r15,
+                16 => &mut tf.rip,
+                17 => &mut tf.rflags,
+                _ => return None,
+            })
+        }
+
+        pub fn read_reg(tf: &mut TrapFrame, i: usize) -> u64 {
+            match i {
+                18 => tf.cs,
+                19 => tf.ss,
+                _ => reg_mut(tf, i).map_or(0, |r| *r),
+            }
+        }
+
+        /// Writes a register. Segment registers are read-only.
+        pub fn write_reg(tf: &mut TrapFrame, i: usize, val: u64) {
+            if let Some(r) = reg_mut(tf, i) {
+                *r = val;
+            }
+        }
+
+        pub const fn breakpoint_insn(kind: usize) -> Option<&'static [u8]> {
+            match kind {
+                1 => Some(&[0xcc]), // int3
+                _ => None,
+            }
+        }
+
+        /// Returns the address of the breakpoint instruction that trapped.
+        pub fn breakpoint_addr(tf: &TrapFrame) -> usize {
+            // `int3` traps after the instruction.
+            tf.rip as usize - 1
+        }
+
+        pub fn set_pc(tf: &mut TrapFrame, pc: usize) {
+            tf.rip = pc as u64;
+        }
+
+        /// Skips the breakpoint instruction that trapped.
+        pub fn skip_breakpoint(_tf: &mut TrapFrame) {}
+
+        /// Enables single-stepping when returning to `tf`, returns `false` if
+        /// it is not supported.
+        pub fn start_step(tf: &mut TrapFrame) -> bool {
+            tf.rflags |= TF;
+            true
+        }
+
+        pub fn end_step(tf: &mut TrapFrame) {
+            tf.rflags &= !TF;
+        }
+
+        /// Makes the modified code visible to instruction fetches.
+        pub fn sync_icache(_addr: usize, _len: usize) {
+            sync_icache_local();
+        }
+
+        /// Discards the stale instructions fetched by the current CPU.
+        pub fn sync_icache_local() {}
+
+        #[inline(always)]
+        pub fn breakpoint() {
+            unsafe { core::arch::asm!("int3") };
+        }
+    } else if #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))] {
+        const XLEN: usize = core::mem::size_of::<usize>();
+
+        /// Sizes of the registers in the `g` packet: X0..X31 and PC.
+        pub const REG_SIZES: [usize; 33] = [XLEN; 33];
+
+        /// Returns X1..X31, which are laid out in order in the trap frame.
+        fn gprs(tf: &mut TrapFrame) -> &mut [usize; 31] {
+            const _: () =
+                assert!(core::mem::size_of::<crate::arch::GeneralRegisters>() == 31 * XLEN);
+            // Safety: `GeneralRegisters` is `repr(C)` with 31 `usize` fields.
+            unsafe { &mut *(&mut tf.regs as *mut _ as *mut [usize; 31]) }
+        }
+
+        pub fn read_reg(tf: &mut TrapFrame, i: usize) -> u64 {
+            match i {
+                0 => 0,
+                1..=31 => gprs(tf)[i - 1] as u64,
+                _ => tf.sepc as u64,
+            }
+        }
+
+        pub fn write_reg(tf: &mut TrapFrame, i: usize, val: u64) {
+            match i {
+                0 => {}
+                1..=31 => gprs(tf)[i - 1] = val as usize,
+                _ => tf.sepc = val as usize,
+            }
+        }
+
+        pub const fn breakpoint_insn(kind: usize) -> Option<&'static [u8]> {
+            match kind {
+                2 => Some(&[0x02, 0x90]),             // c.ebreak
+                4 => Some(&[0x73, 0x00, 0x10, 0x00]), // ebreak
+                _ => None,
+            }
+        }
+
+        pub fn breakpoint_addr(tf: &TrapFrame) -> usize {
+            tf.sepc
+        }
+
+        pub fn set_pc(tf: &mut TrapFrame, pc: usize) {
+            tf.sepc = pc;
+        }
+
+        pub fn skip_breakpoint(tf: &mut TrapFrame) {
+            // Safety: `sepc` points to the instruction that trapped.
+            let insn = unsafe { (tf.sepc as *const u16).read() };
+            tf.sepc += if insn & 0b11 == 0b11 { 4 } else { 2 };
+        }
+
+        /// There is no single-stepping in S-mode. GDB steps by inserting
+        /// breakpoints on RISC-V instead.
+        pub fn start_step(_tf: &mut TrapFrame) -> bool {
+            false
+        }
+
+        pub fn end_step(_tf: &mut TrapFrame) {}
+
+        pub fn sync_icache(_addr: usize, _len: usize) {
+            sync_icache_local();
+        }
+
+        pub fn sync_icache_local() {
+            unsafe { core::arch::asm!("fence.i") };
+        }
+
+        #[inline(always)]
+        pub fn breakpoint() {
+            unsafe { core::arch::asm!("ebreak") };
+        }
+    } else if #[cfg(target_arch = "aarch64")] {
+        use core::arch::asm;
+        use core::sync::atomic::{AtomicU64, Ordering};
+
+        const MDSCR_SS: u64 = 1 << 0; // Software step enable
+        const MDSCR_KDE: u64 = 1 << 13; // Local (kernel) debug enable
+        const SPSR_SS: u64 = 1 << 21;
+        const SPSR_D: u64 = 1 << 9;
+        const SPSR_I: u64 = 1 << 7;
+
+        /// The `D` and `I` bits of SPSR before single-stepping.
+        static STEP_SAVED_DAIF: AtomicU64 = AtomicU64::new(0);
+
+        /// Sizes of the registers in the `g` packet: X0..X30, SP, PC and
+        /// CPSR.
+        pub const REG_SIZES: [usize; 34] = {
+            let mut sizes = [8; 34];
+            sizes[33] = 4;
+            sizes
+        };
+
+        pub fn read_reg(tf: &mut TrapFrame, i: usize) -> u64 {
+            match i {
+                0..=30 => tf.r[i],
+                // The trap frame is pushed on the interrupted kernel stack.
+                31 => tf as *const _ as u64 + core::mem::size_of::<TrapFrame>() as u64,
+                32 => tf.elr,
+                _ => tf.spsr,
+            }
+        }
+
+        /// Writes a register. SP is read-only.
+        pub fn write_reg(tf: &mut TrapFrame, i: usize, val: u64) {
+            match i {
+                0..=30 => tf.r[i] = val,
+                31 => {}
+                32 => tf.elr = val,
+                _ => tf.spsr = val,
+            }
+        }
+
+        pub const fn breakpoint_insn(kind: usize) -> Option<&'static [u8]> {
+            match kind {
+                4 => Some(&[0x00, 0x00, 0x20, 0xd4]), // brk #0
+                _ => None,
+            }
+        }
+
+        pub fn breakpoint_addr(tf: &TrapFrame) -> usize {
+            tf.elr as usize
+        }
+
+        pub fn set_pc(tf: &mut TrapFrame, pc: usize) {
+            tf.elr = pc as u64;
+        }
+
+        pub fn skip_breakpoint(tf: &mut TrapFrame) {
+            tf.elr += 4;
+        }
+
+        fn read_mdscr() -> u64 {
+            let mdscr: u64;
+            unsafe { asm!("mrs {}, mdscr_el1", out(reg) mdscr) };
+            mdscr
+        }
+
+        fn write_mdscr(mdscr: u64) {
+            unsafe { asm!("msr mdscr_el1, {}; isb", in(reg) mdscr) };
+        }
+
+        pub fn start_step(tf: &mut TrapFrame) -> bool {
+            // Unlock the OS lock, which blocks debug exceptions.
+            unsafe { asm!("msr oslar_el1, xzr; isb") };
+            write_mdscr(read_mdscr() | MDSCR_SS | MDSCR_KDE);
+            // Debug exceptions must be unmasked to step at EL1, and IRQs are
+            // masked so that the step does not enter the IRQ handler.
+            STEP_SAVED_DAIF.store(tf.spsr & (SPSR_D | SPSR_I), Ordering::Relaxed);
+            tf.spsr = (tf.spsr & !SPSR_D) | SPSR_I | SPSR_SS;
+            true
+        }
+
+        pub fn end_step(tf: &mut TrapFrame) {
+            write_mdscr(read_mdscr() & !(MDSCR_SS | MDSCR_KDE));
+            let daif = STEP_SAVED_DAIF.load(Ordering::Relaxed);
+            tf.spsr = (tf.spsr & !(SPSR_D | SPSR_I | SPSR_SS)) | daif;
+        }
+
+        pub fn sync_icache(addr: usize, len: usize) {
+            const CACHE_LINE_SIZE: usize = 64;
+            let mut line = addr & !(CACHE_LINE_SIZE - 1);
+            while line < addr + len {
+                unsafe { asm!("dc cvau, {}", in(reg) line) };
+                line += CACHE_LINE_SIZE;
+            }
+            unsafe { asm!("dsb ish; ic ialluis; dsb ish") };
+            sync_icache_local();
+        }
+
+        pub fn sync_icache_local() {
+            unsafe { asm!("isb") };
+        }
+
+        #[inline(always)]
+        pub fn breakpoint() {
+            unsafe { asm!("brk #0") };
+        }
+    }
+}
@@ -0,0 +1,512 @@
+//! A GDB stub, which implements the [GDB Remote Serial Protocol] over the
+//! console UART.
+//!
+//! When the kernel hits a breakpoint (including [`breakpoint`]) or finishes a
+//! single-step, the current CPU stops and serves the requests from GDB on the
+//! console until it is told to continue. Other CPUs are stopped by IPIs if
+//! the `irq` feature is enabled.
+//!
+//! The supported requests are: reading and writing registers of the trap
+//! frame (`g`, `G`), reading and writing memory (`m`, `M`), software
+//! breakpoints (`Z0`, `z0`), continuing (`c`), single-stepping (`s`, not
+//! available on RISC-V, where GDB steps with breakpoints) and detaching
+//! (`D`, `k`). As the console is polled only when the kernel is stopped,
+//! GDB can not interrupt a running kernel with Ctrl-C.
+//!
+//! With QEMU, connect the serial port to a TCP socket, e.g., `-serial
+//! tcp::4321,server=on,wait=off`, and run `target remote localhost:4321` in
+//! GDB.
+//!
+//! [GDB Remote Serial Protocol]: https://sourceware.org/gdb/current/onlinedocs/gdb.html/Remote-Protocol.html
+
+mod arch;
+mod packet;
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use kspin::SpinNoIrq;
+use memory_addr::{align_down_4k, align_up_4k, PAGE_SIZE_4K};
+use page_table_entry::MappingFlags;
+
+use self::packet::{hex_value, recv_packet, send_packet, Reply, PACKET_SIZE};
+use crate::arch::TrapFrame;
+use crate::mem::{memory_regions, phys_to_virt, MemRegionFlags};
+
+/// Maximum number of software breakpoints.
+const MAX_BREAKPOINTS: usize = 32;
+
+/// A function to change the mapping flags of kernel pages, see
+/// [`set_protect_hook`].
+pub type ProtectFn = fn(usize, usize, MappingFlags) -> bool;
+
+static PROTECT_HOOK: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether the other CPUs are stopped by the CPU in the debugger.
+static STOPPED: AtomicBool = AtomicBool::new(false);
+
+static STUB: SpinNoIrq<Stub> = SpinNoIrq::new(Stub::new());
+
+/// Why the CPU stops.
+pub(crate) enum StopReason {
+    /// A breakpoint instruction is executed.
+    Breakpoint,
+    /// A single-step is finished.
+    Step,
+}
+
+#[derive(Clone, Copy)]
+struct Breakpoint {
+    addr: usize,
+    len: usize,
+    /// The original bytes replaced by the breakpoint instruction.
+    orig: [u8; 4],
+}
+
+/// The software breakpoints inserted by GDB.
+struct Breakpoints([Option<Breakpoint>; MAX_BREAKPOINTS]);
+
+struct Stub {
+    rx: [u8; PACKET_SIZE],
+    tx: Reply,
+    breakpoints: Breakpoints,
+}
+
+/// Sets the function to change the mapping flags of kernel pages, which is
+/// called with the start address, the number of pages and the new flags.
+///
+/// It is used to write breakpoints into the kernel code, which is read-only
+/// if the kernel page table is remapped by the `paging` feature. Without the
+/// hook, the code is assumed to be writable.
+pub fn set_protect_hook(f: ProtectFn) {
+    PROTECT_HOOK.store(f as usize, Ordering::Release);
+}
+
+/// Stops the current CPU and waits for GDB, as if a breakpoint is hit.
+#[inline(always)]
+pub fn breakpoint() {
+    arch::breakpoint();
+}
+
+/// Handles a breakpoint or single-step trap from the kernel.
+pub(crate) fn handle_trap(tf: &mut TrapFrame, reason: StopReason) {
+    let mut stub = STUB.lock();
+    let swbreak = match reason {
+        StopReason::Breakpoint => {
+            let addr = arch::breakpoint_addr(tf);
+            if stub.breakpoints.find(addr).is_some() {
+                arch::set_pc(tf, addr);
+                true
+            } else {
+                // Not inserted by GDB, e.g., `breakpoint()`. Resume after it.
+                arch::skip_breakpoint(tf);
+                false
+            }
+        }
+        StopReason::Step => {
+            arch::end_step(tf);
+            false
+        }
+    };
+    stop_other_cpus();
+    stub.serve(tf, swbreak);
+    STOPPED.store(false, Ordering::Release);
+}
+
+fn stop_other_cpus() {
+    STOPPED.store(true, Ordering::Release);
+    #[cfg(all(feature = "smp", feature = "irq"))]
+    {
+        let this_cpu = crate::cpu::this_cpu_id();
+        let ready = crate::irq::ipi_ready_cpus();
+        crate::irq::run_on_cpus(
+            (0..axconfig::SMP).filter(|&cpu| cpu != this_cpu && ready & (1 << cpu) != 0),
+            park_cpu,
+            0,
+            false,
+        );
+    }
+}
+
+#[cfg(all(feature = "smp", feature = "irq"))]
+fn park_cpu(_: usize) {
+    while STOPPED.load(Ordering::Acquire) {
+        // Serve the TLB shootdowns of the debugger writing to the code.
+        crate::irq::handle_calls();
+        core::hint::spin_loop();
+    }
+    // The code may have been modified while stopped.
+    arch::sync_icache_local();
+}
+
+impl Stub {
+    const fn new() -> Self {
+        Self {
+            rx: [0; PACKET_SIZE],
+            tx: Reply::new(),
+            breakpoints: Breakpoints([None; MAX_BREAKPOINTS]),
+        }
+    }
+
+    /// Serves the requests from GDB until it resumes the execution.
+    fn serve(&mut self, tf: &mut TrapFrame, swbreak: bool) {
+        let Self {
+            rx,
+            tx,
+            breakpoints,
+        } = self;
+        let stop_reply: &[u8] = if swbreak { b"T05swbreak:;" } else { b"S05" };
+        send_packet(stop_reply);
+        loop {
+            let pkt = recv_packet(rx);
+            tx.clear();
+            let resume = handle_packet(tf, pkt, tx, breakpoints, stop_reply);
+            // `c`, `s` and `k` are not replied, the stop reply is sent on the
+            // next stop instead.
+            if matches!(resume, Resume::No) || !tx.as_bytes().is_empty() {
+                send_packet(tx.as_bytes());
+            }
+            match resume {
+                Resume::No => {}
+                Resume::Continue => return,
+                Resume::Kill => crate::misc::terminate(),
+            }
+        }
+    }
+}
+
+impl Breakpoints {
+    fn find(&self, addr: usize) -> Option<usize> {
+        self.0
+            .iter()
+            .position(|bp| bp.is_some_and(|bp| bp.addr == addr))
+    }
+
+    fn insert(&mut self, addr: usize, kind: usize) -> bool {
+        if self.find(addr).is_some() {
+            return true;
+        }
+        let Some(insn) = arch::breakpoint_insn(kind) else {
+            return false;
+        };
+        let Some(slot) = self.0.iter().position(|bp| bp.is_none()) else {
+            return false;
+        };
+        let mut orig = [0; 4];
+        if !read_memory(addr, &mut orig[..insn.len()]) || !write_memory(addr, insn) {
+            return false;
+        }
+        self.0[slot] = Some(Breakpoint {
+            addr,
+            len: insn.len(),
+            orig,
+        });
+        true
+    }
+
+    fn remove(&mut self, addr: usize) -> bool {
+        let Some(slot) = self.find(addr) else {
+            return false;
+        };
+        let bp = self.0[slot].take().unwrap();
+        write_memory(bp.addr, &bp.orig[..bp.len])
+    }
+
+    fn remove_all(&mut self) {
+        for bp in self.0.iter_mut() {
+            if let Some(bp) = bp.take() {
+                write_memory(bp.addr, &bp.orig[..bp.len]);
+            }
+        }
+    }
+
+    /// Reads memory as if no breakpoints are inserted.
+    fn read_memory(&self, addr: usize, buf: &mut [u8]) -> bool {
+        if !read_memory(addr, buf) {
+            return false;
+        }
+        for bp in self.0.iter().flatten() {
+            for i in 0..bp.len {
+                if let Some(b) = (bp.addr + i).checked_sub(addr).and_then(|j| buf.get_mut(j)) {
+                    *b = bp.orig[i];
+                }
+            }
+        }
+        true
+    }
+}
+
+/// Handles a request from GDB, and puts the reply in `tx`.
+fn handle_packet(
+    tf: &mut TrapFrame,
+    pkt: &[u8],
+    tx: &mut Reply,
+    breakpoints: &mut Breakpoints,
+    stop_reply: &[u8],
+) -> Resume {
+    let Some((&cmd, args)) = pkt.split_first() else {
+        return Resume::No;
+    };
+    match cmd {
+        b'?' => tx.push(stop_reply),
+        b'g' => {
+            for (i, &size) in arch::REG_SIZES.iter().enumerate() {
+                tx.push_hex(&arch::read_reg(tf, i).to_le_bytes()[..size]);
+            }
+        }
+        b'G' => {
+            let mut pos = 0;
+            for (i, &size) in arch::REG_SIZES.iter().enumerate() {
+                let Some(val) = args.get(pos..pos + size * 2).and_then(parse_le_hex) else {
+                    break;
+                };
+                arch::write_reg(tf, i, val);
+                pos += size * 2;
+            }
+            tx.push(b"OK");
+        }
+        b'm' => {
+            let Some((addr, len)) = parse_addr_len(args) else {
+                tx.push(b"E01");
+                return Resume::No;
+            };
+            let mut buf = [0; PACKET_SIZE / 2];
+            let buf = &mut buf[..len.min(PACKET_SIZE / 2)];
+            if breakpoints.read_memory(addr, buf) {
+                tx.push_hex(buf);
+            } else {
+                tx.push(b"E14");
+            }
+        }
+        b'M' => {
+            let mut parts = args.splitn(2, |&c| c == b':');
+            let (Some((addr, len)), Some(data)) =
+                (parts.next().and_then(parse_addr_len), parts.next())
+            else {
+                tx.push(b"E01");
+                return Resume::No;
+            };
+            let mut buf = [0; PACKET_SIZE / 2];
+            let Some(buf) = buf.get_mut(..len) else {
+                tx.push(b"E01");
+                return Resume::No;
+            };
+            if !decode_hex(data, buf) {
+                tx.push(b"E01");
+            } else if write_memory(addr, buf) {
+                tx.push(b"OK");
+            } else {
+                tx.push(b"E14");
+            }
+        }
+        b'c' | b's' => {
+            if let Some(addr) = parse_hex(args) {
+                arch::set_pc(tf, addr);
+            }
+            if cmd == b's' && !arch::start_step(tf) {
+                tx.push(b"E01");
+                return Resume::No;
+            }
+            return Resume::Continue;
+        }
+        b'Z' | b'z' => {
+            // Only software breakpoints: "Z0,addr,kind".
+            let Some(args) = args.strip_prefix(b"0,") else {
+                return Resume::No;
+            };
+            let Some((addr, kind)) = parse_addr_len(args) else {
+                tx.push(b"E01");
+                return Resume::No;
+            };
+            let ok = if cmd == b'Z' {
+                breakpoints.insert(addr, kind)
+            } else {
+                breakpoints.remove(addr)
+            };
+            if ok {
+                tx.push(b"OK");
+            } else {
+                tx.push(b"E01");
+            }
+        }
+        b'D' => {
+            breakpoints.remove_all();
+            tx.push(b"OK");
+            return Resume::Continue;
+        }
+        b'k' => {
+            breakpoints.remove_all();
+            return Resume::Kill;
+        }
+        b'H' => tx.push(b"OK"),
+        b'q' => {
+            if args.starts_with(b"Supported") {
+                tx.push(b"PacketSize=1000;swbreak+");
+            } else if args == b"Attached" {
+                tx.push(b"1");
+            }
+        }
+        // Unsupported requests are replied with an empty packet.
+        _ => {}
+    }
+    Resume::No
+}
+
+/// What to do after a request.
+enum Resume {
+    /// Wait for the next request.
+    No,
+    /// Resume the execution.
+    Continue,
+    /// Terminate the system.
+    Kill,
+}
+
+fn parse_hex(s: &[u8]) -> Option<usize> {
+    if s.is_empty() || s.len() > core::mem::size_of::<usize>() * 2 {
+        return None;
+    }
+    s.iter()
+        .try_fold(0, |val, &c| Some(val << 4 | hex_value(c)? as usize))
+}
+
+/// Parses a little-endian value in hex, as in the `G` packet.
+fn parse_le_hex(s: &[u8]) -> Option<u64> {
+    let mut bytes = [0; 8];
+    if s.len() > bytes.len() * 2 || !decode_hex(s, &mut bytes[..s.len() / 2]) {
+        return None;
+    }
+    Some(u64::from_le_bytes(bytes))
+}
+
+/// Parses "addr,len".
+fn parse_addr_len(s: &[u8]) -> Option<(usize, usize)> {
+    let mut parts = s.splitn(2, |&c| c == b',');
+    Some((parse_hex(parts.next()?)?, parse_hex(parts.next()?)?))
+}
+
+/// Decodes hex digits in `s` to `buf`, which must be exactly half as long.
+fn decode_hex(s: &[u8], buf: &mut [u8]) -> bool {
+    if s.len() != buf.len() * 2 {
+        return false;
+    }
+    for (b, pair) in buf.iter_mut().zip(s.chunks_exact(2)) {
+        let (Some(hi), Some(lo)) = (hex_value(pair[0]), hex_value(pair[1])) else {
+            return false;
+        };
+        *b = hi << 4 | lo;
+    }
+    true
+}
+
+/// Whether `[addr, addr + len)` is in the mapped memory (excluding MMIO
+/// regions, as reading them may have side effects).
+fn is_accessible(addr: usize, len: usize) -> bool {
+    let Some(end) = addr.checked_add(len) else {
+        return false;
+    };
+    memory_regions().any(|r| {
+        let start = phys_to_virt(r.paddr).as_usize();
+        !r.flags.contains(MemRegionFlags::DEVICE) && start <= addr && end <= start + r.size
+    })
+}
+
+fn is_kernel_text(addr: usize, len: usize) -> bool {
+    extern "C" {
+        fn _stext();
+        fn _etext();
+    }
+    addr < _etext as usize && addr + len > _stext as usize
+}
+
+fn read_memory(addr: usize, buf: &mut [u8]) -> bool {
+    if !is_accessible(addr, buf.len()) {
+        return false;
+    }
+    for (i, b) in buf.iter_mut().enumerate() {
+        *b = unsafe { ((addr + i) as *const u8).read_volatile() };
+    }
+    true
+}
+
+fn write_memory(addr: usize, data: &[u8]) -> bool {
+    if !is_accessible(addr, data.len()) {
+        return false;
+    }
+    let is_text = is_kernel_text(addr, data.len());
+    if is_text && !set_text_writable(addr, data.len(), true) {
+        return false;
+    }
+    for (i, &b) in data.iter().enumerate() {
+        unsafe { ((addr + i) as *mut u8).write_volatile(b) };
+    }
+    if is_text {
+        set_text_writable(addr, data.len(), false);
+        arch::sync_icache(addr, data.len());
+    }
+    true
+}
+
+fn set_text_writable(addr: usize, len: usize, writable: bool) -> bool {
+    let hook = PROTECT_HOOK.load(Ordering::Acquire);
+    if hook == 0 {
+        return true;
+    }
+    let hook: ProtectFn = unsafe { core::mem::transmute(hook) };
+    let start = align_down_4k(addr);
+    let num_pages = (align_up_4k(addr + len) - start) / PAGE_SIZE_4K;
+    let mut flags = MappingFlags::READ | MappingFlags::EXECUTE;
+    if writable {
+        flags |= MappingFlags::WRITE;
+    }
+    hook(start, num_pages, flags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hex() {
+        assert_eq!(parse_hex(b"0"), Some(0));
+        assert_eq!(parse_hex(b"ffff8000"), Some(0xffff_8000));
+        assert_eq!(parse_hex(b"DeadBeef"), Some(0xdead_beef));
+        assert_eq!(parse_hex(b""), None);
+        assert_eq!(parse_hex(b"12x"), None);
+        // Longer than a `usize`.
+        assert_eq!(
+            parse_hex(&[b'1'; core::mem::size_of::<usize>() * 2 + 1]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_le_hex() {
+        assert_eq!(parse_le_hex(b"78563412"), Some(0x1234_5678));
+        assert_eq!(
+            parse_le_hex(b"0100000000000080"),
+            Some(0x8000_0000_0000_0001)
+        );
+        assert_eq!(parse_le_hex(b""), Some(0));
+        assert_eq!(parse_le_hex(b"123"), None);
+        assert_eq!(parse_le_hex(b"zz"), None);
+        assert_eq!(parse_le_hex(b"000000000000000000"), None);
+    }
+
+    #[test]
+    fn test_parse_addr_len() {
+        assert_eq!(parse_addr_len(b"1000,4"), Some((0x1000, 4)));
+        assert_eq!(parse_addr_len(b"1000"), None);
+        assert_eq!(parse_addr_len(b",4"), None);
+        assert_eq!(parse_addr_len(b"1000,"), None);
+        assert_eq!(parse_addr_len(b"1000,4,1"), None);
+    }
+
+    #[test]
+    fn test_decode_hex() {
+        let mut buf = [0; 3];
+        assert!(decode_hex(b"00fF7a", &mut buf));
+        assert_eq!(buf, [0x00, 0xff, 0x7a]);
+        assert!(!decode_hex(b"00ff", &mut buf));
+        assert!(!decode_hex(b"00ff7g", &mut buf));
+    }
+}
@@ -0,0 +1,222 @@
+//! Packet framing of the GDB Remote Serial Protocol.
+//!
+//! A packet is `$<data>#<checksum>`, where the checksum is the sum of the
+//! data bytes modulo 256 in two hex digits. Each packet is acknowledged by
+//! `+`, or `-` to request a retransmission.
+
+use crate::console::{getchar, putchar};
+
+/// Maximum size of the data of a packet, advertised to GDB as `PacketSize`.
+pub const PACKET_SIZE: usize = 0x1000;
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+fn read_byte() -> u8 {
+    loop {
+        if let Some(c) = getchar() {
+            return c;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Converts a hex digit to its value.
+pub fn hex_value(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Receives a packet into `buf` and returns its data.
+///
+/// Anything before the start of a packet (e.g., acknowledgements and
+/// interrupt requests) is ignored. Packets that are corrupted or too long are
+/// dropped and a retransmission is requested.
+pub fn recv_packet(buf: &mut [u8; PACKET_SIZE]) -> &[u8] {
+    recv_packet_with(buf, read_byte, putchar)
+}
+
+/// Sends a packet with `data`, and retransmits it until it is acknowledged.
+pub fn send_packet(data: &[u8]) {
+    send_packet_with(data, read_byte, putchar)
+}
+
+/// Implements [`recv_packet`] with the given functions to read a byte and to
+/// write an acknowledgement.
+fn recv_packet_with(
+    buf: &mut [u8; PACKET_SIZE],
+    mut read: impl FnMut() -> u8,
+    mut write: impl FnMut(u8),
+) -> &[u8] {
+    loop {
+        while read() != b'$' {}
+        let mut len = 0;
+        let mut sum = 0u8;
+        let mut overflow = false;
+        loop {
+            let c = read();
+            if c == b'#' {
+                break;
+            }
+            sum = sum.wrapping_add(c);
+            if len < PACKET_SIZE {
+                buf[len] = c;
+                len += 1;
+            } else {
+                overflow = true;
+            }
+        }
+        let hi = hex_value(read());
+        let lo = hex_value(read());
+        match (hi, lo) {
+            (Some(hi), Some(lo)) if !overflow && (hi << 4 | lo) == sum => {
+                write(b'+');
+                return &buf[..len];
+            }
+            _ => write(b'-'),
+        }
+    }
+}
+
+/// Implements [`send_packet`] with the given functions to read an
+/// acknowledgement and to write a byte.
+fn send_packet_with(data: &[u8], mut read: impl FnMut() -> u8, mut write: impl FnMut(u8)) {
+    loop {
+        write(b'$');
+        let mut sum = 0u8;
+        for &c in data {
+            write(c);
+            sum = sum.wrapping_add(c);
+        }
+        write(b'#');
+        write(HEX_DIGITS[(sum >> 4) as usize]);
+        write(HEX_DIGITS[(sum & 0xf) as usize]);
+        loop {
+            match read() {
+                b'+' => return,
+                b'-' => break,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// A buffer to build the data of a reply packet.
+pub struct Reply {
+    buf: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl Reply {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; PACKET_SIZE],
+            len: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Appends raw bytes. Bytes that do not fit are dropped.
+    pub fn push(&mut self, data: &[u8]) {
+        let n = data.len().min(PACKET_SIZE - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&data[..n]);
+        self.len += n;
+    }
+
+    /// Appends bytes encoded as pairs of hex digits.
+    pub fn push_hex(&mut self, data: &[u8]) {
+        for &b in data {
+            self.push(&[
+                HEX_DIGITS[(b >> 4) as usize],
+                HEX_DIGITS[(b & 0xf) as usize],
+            ]);
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    /// Feeds `input` to `recv_packet_with`, returns the packet data and the
+    /// acknowledgements sent.
+    fn recv(input: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let mut buf = [0; PACKET_SIZE];
+        let mut input = input.iter().copied();
+        let mut acks = Vec::new();
+        let data = recv_packet_with(&mut buf, || input.next().unwrap(), |c| acks.push(c));
+        (data.to_vec(), acks)
+    }
+
+    #[test]
+    fn test_hex_value() {
+        assert_eq!(hex_value(b'0'), Some(0));
+        assert_eq!(hex_value(b'9'), Some(9));
+        assert_eq!(hex_value(b'a'), Some(10));
+        assert_eq!(hex_value(b'F'), Some(15));
+        assert_eq!(hex_value(b'g'), None);
+        assert_eq!(hex_value(b'#'), None);
+    }
+
+    #[test]
+    fn test_recv_packet() {
+        // "g" = 0x67.
+        assert_eq!(recv(b"$g#67"), (b"g".to_vec(), b"+".to_vec()));
+        assert_eq!(recv(b"$#00"), (b"".to_vec(), b"+".to_vec()));
+        // Acknowledgements and Ctrl-C before the packet are skipped, and the
+        // checksum digits may be uppercase.
+        assert_eq!(recv(b"+\x03$m0,4#FD"), (b"m0,4".to_vec(), b"+".to_vec()));
+    }
+
+    #[test]
+    fn test_recv_packet_retransmit() {
+        // A bad checksum, then a bad hex digit, then the right one.
+        let (data, acks) = recv(b"$g#66$g#6x$g#67");
+        assert_eq!(data, b"g");
+        assert_eq!(acks, b"--+");
+    }
+
+    #[test]
+    fn test_recv_packet_overflow() {
+        let mut input = Vec::from(*b"$");
+        input.resize(PACKET_SIZE + 2, b'0');
+        // 0x30 * (PACKET_SIZE + 1) = 0x30 mod 256.
+        input.extend_from_slice(b"#30$?#3f");
+        let (data, acks) = recv(&input);
+        assert_eq!(data, b"?");
+        assert_eq!(acks, b"-+");
+    }
+
+    #[test]
+    fn test_send_packet() {
+        let mut out = Vec::new();
+        let mut acks = b"x-+".iter().copied();
+        send_packet_with(b"OK", || acks.next().unwrap(), |c| out.push(c));
+        // Retransmitted once after the `-`.
+        assert_eq!(out, b"$OK#9a$OK#9a");
+    }
+
+    #[test]
+    fn test_reply() {
+        let mut reply = Reply::new();
+        reply.push(b"T05");
+        reply.push_hex(&[0x01, 0xab]);
+        assert_eq!(reply.as_bytes(), b"T0501ab");
+        reply.clear();
+        assert!(reply.as_bytes().is_empty());
+        // Bytes that do not fit are dropped.
+        reply.push(&[b'x'; PACKET_SIZE + 1]);
+        assert_eq!(reply.as_bytes().len(), PACKET_SIZE);
+    }
+}
@@ -1,21 +1,77 @@
 //! Interrupt management.
 
+use core::sync::atomic::{AtomicUsize, Ordering};
+
 use handler_table::HandlerTable;
 
 use crate::platform::irq::{dispatch_irq, MAX_IRQ_COUNT};
 use crate::trap::{register_trap_handler, IRQ};
 
-pub use crate::platform::irq::{register_handler, set_enable};
+pub use crate::platform::irq::{
+    alloc_msi_irq, free_msi_irq, register_handler, send_ipi, send_ipi_all_others, set_enable,
+    IPI_IRQ_NUM,
+};
+
+mod ipi;
+
+#[allow(unused_imports)]
+pub(crate) use self::ipi::handle_calls;
+pub use self::ipi::{
+    init_ipi, init_ipi_secondary, ipi_ready_cpus, run_on_cpu, run_on_cpus, run_on_other_cpus,
+    CallFn,
+};
 
 /// The type if an IRQ handler.
 pub type IrqHandler = handler_table::Handler;
 
+/// A message signaled interrupt (MSI) message: a device raises the interrupt
+/// by writing `data` to the physical address `address`.
+#[derive(Debug, Clone, Copy)]
+pub struct MsiMessage {
+    /// The physical address to write.
+    pub address: u64,
+    /// The data to write.
+    pub data: u32,
+}
+
 static IRQ_HANDLER_TABLE: HandlerTable<MAX_IRQ_COUNT> = HandlerTable::new();
 
+/// The number of times each IRQ has been dispatched, on all CPUs.
+#[allow(clippy::declare_interior_mutable_const)]
+static IRQ_COUNTS: [AtomicUsize; MAX_IRQ_COUNT] = {
+    const ZERO: AtomicUsize = AtomicUsize::new(0);
+    [ZERO; MAX_IRQ_COUNT]
+};
+
+/// Returns the number of times the IRQ has been dispatched, on all CPUs.
+///
+/// Only the IRQs dispatched by the IRQ handler table are counted, so the
+/// timer and IPI interrupts handled directly by the platform may be missed.
+pub fn irq_count(irq_num: usize) -> usize {
+    IRQ_COUNTS
+        .get(irq_num)
+        .map_or(0, |count| count.load(Ordering::Relaxed))
+}
+
+/// Returns the IRQ numbers and counts of all IRQs that have been dispatched,
+/// see [`irq_count`].
+pub fn irq_counts() -> impl Iterator<Item = (usize, usize)> {
+    IRQ_COUNTS
+        .iter()
+        .map(|count| count.load(Ordering::Relaxed))
+        .enumerate()
+        .filter(|&(_, count)| count > 0)
+}
+
 /// Platform-independent IRQ dispatching.
 #[allow(dead_code)]
 pub(crate) fn dispatch_irq_common(irq_num: usize) {
     trace!("IRQ {}", irq_num);
+    // The arrival time of interrupts is a source of entropy.
+    crate::random::add_entropy(crate::time::current_ticks() ^ irq_num as u64);
+    if let Some(count) = IRQ_COUNTS.get(irq_num) {
+        count.fetch_add(1, Ordering::Relaxed);
+    }
     if !IRQ_HANDLER_TABLE.handle(irq_num) {
         warn!("Unhandled IRQ {}", irq_num);
     }
@@ -38,6 +94,9 @@ pub(crate) fn register_handler_common(irq_num: usize, handler: IrqHandler) -> bo
 #[register_trap_handler(IRQ)]
 fn handler_irq(irq_num: usize) -> bool {
     let guard = kernel_guard::NoPreempt::new();
+    #[cfg(feature = "alloc-stats")]
+    axalloc::stats::with_irq_tag(|| dispatch_irq(irq_num));
+    #[cfg(not(feature = "alloc-stats"))]
     dispatch_irq(irq_num);
     drop(guard); // rescheduling may occur when preemption is re-enabled.
     true
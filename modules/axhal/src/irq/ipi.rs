@@ -0,0 +1,218 @@
+//! Inter-processor interrupts (IPIs) and cross-CPU function calls.
+//!
+//! Each CPU has a queue of function calls requested by other CPUs. A request
+//! is pushed to the queue of the target CPU, followed by an IPI, and the IPI
+//! handler runs the requested functions on the target CPU.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use kspin::SpinNoIrq;
+
+use crate::cpu::this_cpu_id;
+use crate::platform::irq::{register_handler, send_ipi, IPI_IRQ_NUM};
+
+/// Maximum number of pending function calls per CPU.
+const CALL_QUEUE_LEN: usize = 16;
+
+/// A function that can be called on other CPUs, with an argument.
+pub type CallFn = fn(usize);
+
+#[derive(Clone, Copy)]
+struct CallRequest {
+    func: CallFn,
+    arg: usize,
+    /// Address of the counter of pending calls to decrease on completion,
+    /// or 0 if the caller does not wait.
+    pending: usize,
+}
+
+struct CallQueue {
+    reqs: [Option<CallRequest>; CALL_QUEUE_LEN],
+    head: usize,
+    len: usize,
+}
+
+impl CallQueue {
+    const fn new() -> Self {
+        Self {
+            reqs: [None; CALL_QUEUE_LEN],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, req: CallRequest) -> bool {
+        if self.len == CALL_QUEUE_LEN {
+            return false;
+        }
+        self.reqs[(self.head + self.len) % CALL_QUEUE_LEN] = Some(req);
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<CallRequest> {
+        if self.len == 0 {
+            return None;
+        }
+        let req = self.reqs[self.head].take();
+        self.head = (self.head + 1) % CALL_QUEUE_LEN;
+        self.len -= 1;
+        req
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+static CALL_QUEUES: [SpinNoIrq<CallQueue>; axconfig::SMP] = {
+    const QUEUE: SpinNoIrq<CallQueue> = SpinNoIrq::new(CallQueue::new());
+    [QUEUE; axconfig::SMP]
+};
+
+/// The set of CPUs (as a bitmap) that are ready to receive IPIs.
+static IPI_READY_CPUS: AtomicUsize = AtomicUsize::new(0);
+
+/// Runs the function calls requested by other CPUs on the current CPU.
+pub(crate) fn handle_calls() {
+    let queue = &CALL_QUEUES[this_cpu_id()];
+    loop {
+        // Do not hold the lock while calling the function.
+        let Some(req) = queue.lock().pop() else {
+            break;
+        };
+        (req.func)(req.arg);
+        if req.pending != 0 {
+            // Safety: the caller waits until the counter drops to zero.
+            let pending = unsafe { &*(req.pending as *const AtomicUsize) };
+            pending.fetch_sub(1, Ordering::Release);
+        }
+    }
+}
+
+/// Pushes a function call to the queue of `cpu_id` and sends an IPI to it.
+fn request_call(cpu_id: usize, req: CallRequest) {
+    // Process our own queue while the target queue is full, in case the
+    // target CPU is waiting for us.
+    while !CALL_QUEUES[cpu_id].lock().push(req) {
+        handle_calls();
+        core::hint::spin_loop();
+    }
+    send_ipi(cpu_id);
+}
+
+/// Registers the IPI handler, which runs the cross-CPU function calls, and
+/// marks the primary CPU as ready to receive IPIs.
+///
+/// It must be called on the primary CPU before any call to [`run_on_cpu`].
+pub fn init_ipi() {
+    register_handler(IPI_IRQ_NUM, handle_calls);
+    mark_ipi_ready();
+}
+
+/// Marks a secondary CPU as ready to receive IPIs.
+pub fn init_ipi_secondary() {
+    mark_ipi_ready();
+}
+
+fn mark_ipi_ready() {
+    IPI_READY_CPUS.fetch_or(1 << this_cpu_id(), Ordering::SeqCst);
+    // TLB shootdowns skip CPUs that are not ready, so drop the entries that
+    // may have become stale before.
+    #[cfg(feature = "paging")]
+    crate::arch::flush_tlb(None);
+}
+
+/// Returns the set of CPUs (as a bitmap) that are ready to receive IPIs,
+/// i.e., that have called [`init_ipi`] or [`init_ipi_secondary`].
+///
+/// Requests to other CPUs are not handled until they are ready.
+pub fn ipi_ready_cpus() -> usize {
+    IPI_READY_CPUS.load(Ordering::SeqCst)
+}
+
+/// Runs `func(arg)` on the given CPU.
+///
+/// If `cpu_id` is the current CPU, the function is called directly with
+/// IRQs disabled. Otherwise, it is called in the IPI handler of the target
+/// CPU. If `wait` is `true`, it returns after the function has completed.
+///
+/// The function runs in the interrupt context, so it must not block.
+///
+/// Returns `false` without calling the function if the target CPU is not
+/// ready to receive IPIs (see [`ipi_ready_cpus`]), as the request would not
+/// be handled until then.
+pub fn run_on_cpu(cpu_id: usize, func: CallFn, arg: usize, wait: bool) -> bool {
+    run_on_cpus(core::iter::once(cpu_id), func, arg, wait) != 0
+}
+
+/// Runs `func(arg)` on each of the given CPUs.
+///
+/// See [`run_on_cpu`] for details. If `wait` is `true`, it returns after the
+/// function has completed on all of the CPUs.
+///
+/// The CPUs that are not ready to receive IPIs are skipped. Returns the set
+/// of CPUs (as a bitmap) that the function is called on.
+pub fn run_on_cpus(
+    cpus: impl IntoIterator<Item = usize>,
+    func: CallFn,
+    arg: usize,
+    wait: bool,
+) -> usize {
+    let _guard = kernel_guard::NoPreempt::new();
+    let this_cpu = this_cpu_id();
+    let ready = ipi_ready_cpus();
+    let pending = AtomicUsize::new(0);
+    let mut run_here = false;
+    let mut called = 0;
+    for cpu_id in cpus {
+        assert!(cpu_id < axconfig::SMP, "invalid CPU ID {}", cpu_id);
+        if cpu_id == this_cpu {
+            run_here = true;
+            called |= 1 << cpu_id;
+            continue;
+        }
+        if ready & (1 << cpu_id) == 0 {
+            warn!("CPU {} is not ready to receive IPIs, skipped", cpu_id);
+            continue;
+        }
+        called |= 1 << cpu_id;
+        if wait {
+            pending.fetch_add(1, Ordering::Relaxed);
+        }
+        let req = CallRequest {
+            func,
+            arg,
+            pending: if wait {
+                &pending as *const _ as usize
+            } else {
+                0
+            },
+        };
+        request_call(cpu_id, req);
+    }
+    if run_here {
+        let _guard = kernel_guard::IrqSave::new();
+        func(arg);
+    }
+    // Keep handling our own requests while waiting, to avoid deadlocks when
+    // other CPUs are waiting for us at the same time.
+    while pending.load(Ordering::Acquire) != 0 {
+        handle_calls();
+        core::hint::spin_loop();
+    }
+    called
+}
+
+/// Runs `func(arg)` on all CPUs except the current one.
+///
+/// See [`run_on_cpus`] for details. The CPUs that are not ready to receive
+/// IPIs yet are skipped silently.
+pub fn run_on_other_cpus(func: CallFn, arg: usize, wait: bool) {
+    let _guard = kernel_guard::NoPreempt::new();
+    let this_cpu = this_cpu_id();
+    let others = ipi_ready_cpus() & !(1 << this_cpu);
+    run_on_cpus(
+        (0..axconfig::SMP).filter(|&cpu_id| others & (1 << cpu_id) != 0),
+        func,
+        arg,
+        wait,
+    );
+}
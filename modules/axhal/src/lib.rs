@@ -20,11 +20,14 @@
 //! - `fp_simd`: Enable floating-point and SIMD support.
 //! - `paging`: Enable page table manipulation.
 //! - `irq`: Enable interrupt handling support.
+//! - `backtrace`: Enable stack backtraces with symbol names.
+//! - `gdbstub`: Enable the GDB stub on the console.
+//! - `perf`: Enable the hardware performance counters.
 //!
 //! [ArceOS]: https://github.com/arceos-org/arceos
 //! [cargo test]: https://doc.rust-lang.org/cargo/guide/tests.html
 
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![feature(asm_const)]
 #![feature(naked_functions)]
 #![feature(const_option)]
@@ -44,9 +47,15 @@ mod platform;
 #[macro_use]
 pub mod trap;
 
+pub mod acpi;
 pub mod arch;
+pub mod backtrace;
+pub mod bootinfo;
+pub mod console;
 pub mod cpu;
+pub mod dtb;
 pub mod mem;
+pub mod random;
 pub mod time;
 
 #[cfg(feature = "tls")]
@@ -58,19 +67,13 @@ pub mod irq;
 #[cfg(feature = "paging")]
 pub mod paging;
 
-/// Console input and output.
-pub mod console {
-    pub use super::platform::console::*;
+#[cfg(feature = "gdbstub")]
+pub mod gdbstub;
 
-    /// Write a slice of bytes to the console.
-    pub fn write_bytes(bytes: &[u8]) {
-        for c in bytes {
-            putchar(*c);
-        }
-    }
-}
+#[cfg(feature = "perf")]
+pub mod perf;
 
-/// Miscellaneous operation, e.g. terminate the system.
+/// Miscellaneous operation, e.g. terminate, power off or reboot the system.
 pub mod misc {
     pub use super::platform::misc::*;
 }
@@ -1,6 +1,9 @@
 //! Physical memory management.
 
 use core::fmt;
+use core::ops::RangeInclusive;
+
+use kspin::SpinNoIrq;
 
 #[doc(no_inline)]
 pub use memory_addr::{MemoryAddr, PhysAddr, VirtAddr, PAGE_SIZE_4K};
@@ -75,6 +78,16 @@ pub fn memory_regions() -> impl Iterator<Item = MemRegion> {
     kernel_image_regions().chain(crate::platform::mem::platform_regions())
 }
 
+/// Returns the total size in bytes of the RAM known to the kernel, i.e., all
+/// the memory regions except device memory and framebuffers.
+pub fn total_ram_size() -> usize {
+    let not_ram = MemRegionFlags::DEVICE | MemRegionFlags::UNCACHED;
+    memory_regions()
+        .filter(|r| !r.flags.intersects(not_ram))
+        .map(|r| r.size)
+        .sum()
+}
+
 /// Returns the memory regions of the kernel image (code and data sections).
 fn kernel_image_regions() -> impl Iterator<Item = MemRegion> {
     [
@@ -118,6 +131,99 @@ fn kernel_image_regions() -> impl Iterator<Item = MemRegion> {
     .into_iter()
 }
 
+/// Maximum number of ranges in a [`RangeList`].
+const MAX_RANGES: usize = 32;
+
+/// A fixed-capacity list of physical memory ranges (address and size).
+#[derive(Clone, Copy)]
+pub(crate) struct RangeList {
+    ranges: [(usize, usize); MAX_RANGES],
+    len: usize,
+}
+
+impl RangeList {
+    pub const fn new() -> Self {
+        Self {
+            ranges: [(0, 0); MAX_RANGES],
+            len: 0,
+        }
+    }
+
+    /// Appends a range, returns `false` (and warns) if the list is full.
+    ///
+    /// Use [`RangeList::reserve`] for ranges that must not be lost.
+    pub fn push(&mut self, paddr: usize, size: usize) -> bool {
+        if self.len == MAX_RANGES {
+            warn!(
+                "too many physical memory ranges, [{:#x}, {:#x}) is ignored",
+                paddr,
+                paddr + size
+            );
+            return false;
+        }
+        self.ranges[self.len] = (paddr, size);
+        self.len += 1;
+        true
+    }
+
+    /// Appends a reserved range. If the list is full, the range is merged
+    /// with the nearest one, which may reserve more memory than needed but
+    /// never loses a reservation.
+    pub fn reserve(&mut self, paddr: usize, size: usize) {
+        if self.len < MAX_RANGES {
+            self.push(paddr, size);
+            return;
+        }
+        let end = paddr + size;
+        let gap = |&(start, len): &(usize, usize)| {
+            if start + len < paddr {
+                paddr - (start + len)
+            } else {
+                start.saturating_sub(end)
+            }
+        };
+        let nearest = self
+            .as_mut_slice()
+            .iter_mut()
+            .min_by_key(|r| gap(r))
+            .unwrap();
+        let start = nearest.0.min(paddr);
+        nearest.1 = (nearest.0 + nearest.1).max(end) - start;
+        nearest.0 = start;
+        warn!(
+            "too many reserved memory ranges, [{:#x}, {:#x}) is merged into [{:#x}, {:#x})",
+            paddr,
+            end,
+            nearest.0,
+            nearest.0 + nearest.1
+        );
+    }
+
+    pub fn as_slice(&self) -> &[(usize, usize)] {
+        &self.ranges[..self.len]
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [(usize, usize)] {
+        &mut self.ranges[..self.len]
+    }
+
+    /// Whether `[paddr, paddr + size)` is in one of the ranges.
+    pub fn contains(&self, paddr: usize, size: usize) -> bool {
+        self.as_slice()
+            .iter()
+            .any(|&(start, len)| paddr >= start && paddr + size <= start + len)
+    }
+}
+
+impl IntoIterator for RangeList {
+    type Item = (usize, usize);
+    type IntoIter = core::iter::Take<core::array::IntoIter<(usize, usize), MAX_RANGES>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.ranges.into_iter().take(self.len)
+    }
+}
+
 /// Returns the default MMIO memory regions (from [`axconfig::MMIO_REGIONS`]).
 #[allow(dead_code)]
 pub(crate) fn default_mmio_regions() -> impl Iterator<Item = MemRegion> {
@@ -145,6 +251,273 @@ pub(crate) fn default_free_regions() -> impl Iterator<Item = MemRegion> {
     })
 }
 
+/// Returns the free memory regions in `memory` (the RAM reported by the
+/// firmware or the bootloader) after the kernel image, excluding the
+/// `reserved` ranges.
+fn free_regions_in(memory: &RangeList, mut reserved: RangeList) -> impl Iterator<Item = MemRegion> {
+    let mut free = RangeList::new();
+    let kernel_end = virt_to_phys((_ekernel as usize).into()).as_usize();
+    reserved.as_mut_slice().sort_unstable();
+    for &(start, size) in memory.as_slice() {
+        let end = start + size;
+        let mut cur = start.max(kernel_end);
+        for &(rsv_start, rsv_size) in reserved.as_slice() {
+            let rsv_end = rsv_start + rsv_size;
+            if rsv_end <= cur || rsv_start >= end {
+                continue;
+            }
+            if rsv_start > cur {
+                free.push(cur, rsv_start - cur);
+            }
+            cur = cur.max(rsv_end);
+        }
+        if cur < end {
+            free.push(cur, end - cur);
+        }
+    }
+    free.into_iter().filter_map(|(paddr, size)| {
+        let start = pa!(paddr).align_up_4k();
+        let end = pa!(paddr + size).align_down_4k();
+        (start < end).then(|| MemRegion {
+            paddr: start,
+            size: end.as_usize() - start.as_usize(),
+            flags: MemRegionFlags::FREE | MemRegionFlags::READ | MemRegionFlags::WRITE,
+            name: "free memory",
+        })
+    })
+}
+
+/// Returns the free memory regions from the device tree: the memory nodes
+/// after the kernel image, excluding the reserved memory.
+///
+/// Falls back to [`default_free_regions`] if there is no device tree.
+#[allow(dead_code)]
+pub(crate) fn dtb_free_regions() -> impl Iterator<Item = MemRegion> {
+    let info = crate::dtb::info().filter(|info| !info.memory.as_slice().is_empty());
+    let from_dtb = info.map(|info| free_regions_in(&info.memory, info.reserved));
+    let default = from_dtb.is_none().then(default_free_regions);
+    from_dtb
+        .into_iter()
+        .flatten()
+        .chain(default.into_iter().flatten())
+}
+
+/// Returns the free memory regions from the memory map passed by the
+/// bootloader (see [`crate::bootinfo`]): the usable RAM after the kernel
+/// image, excluding the reserved ranges and the boot modules.
+///
+/// Falls back to [`default_free_regions`] if there is no memory map.
+#[allow(dead_code)]
+pub(crate) fn bootinfo_free_regions() -> impl Iterator<Item = MemRegion> {
+    let info = crate::bootinfo::info().filter(|info| !info.memory.as_slice().is_empty());
+    let from_bootinfo = info.map(|info| {
+        let mut reserved = info.reserved;
+        for m in crate::bootinfo::modules() {
+            reserved.reserve(m.paddr.as_usize(), m.size);
+        }
+        free_regions_in(&info.memory, reserved)
+    });
+    let default = from_bootinfo.is_none().then(default_free_regions);
+    from_bootinfo
+        .into_iter()
+        .flatten()
+        .chain(default.into_iter().flatten())
+}
+
+/// Returns the memory regions of the modules loaded by the bootloader (e.g.,
+/// the initial ramdisk), see [`crate::bootinfo::modules`].
+#[allow(dead_code)]
+pub(crate) fn boot_module_regions() -> impl Iterator<Item = MemRegion> {
+    crate::bootinfo::modules().map(|m| {
+        let start = m.paddr.align_down_4k();
+        let end = (m.paddr + m.size).align_up_4k();
+        MemRegion {
+            paddr: start,
+            size: end.as_usize() - start.as_usize(),
+            flags: MemRegionFlags::RESERVED | MemRegionFlags::READ | MemRegionFlags::WRITE,
+            name: "boot module",
+        }
+    })
+}
+
+/// Returns the memory region of the framebuffer set up by the bootloader (see
+/// [`crate::bootinfo::framebuffer`]), if it is not covered by
+/// [`axconfig::MMIO_REGIONS`].
+#[allow(dead_code)]
+pub(crate) fn boot_framebuffer_regions() -> impl Iterator<Item = MemRegion> {
+    crate::bootinfo::framebuffer()
+        .map(|fb| {
+            let start = fb.paddr.align_down_4k();
+            let end = (fb.paddr + fb.size()).align_up_4k();
+            (start, end)
+        })
+        .filter(|&(start, end)| {
+            !axconfig::MMIO_REGIONS
+                .iter()
+                .any(|&(paddr, size)| start.as_usize() < paddr + size && paddr < end.as_usize())
+        })
+        .map(|(start, end)| MemRegion {
+            paddr: start,
+            size: end.as_usize() - start.as_usize(),
+            flags: MemRegionFlags::RESERVED
+                | MemRegionFlags::UNCACHED
+                | MemRegionFlags::READ
+                | MemRegionFlags::WRITE,
+            name: "framebuffer",
+        })
+        .into_iter()
+}
+
+/// Returns the default MMIO memory regions (from [`axconfig::MMIO_REGIONS`]),
+/// and the `devices` (physical address and size) that are not covered by
+/// them.
+fn mmio_regions_with(
+    devices: impl Iterator<Item = (usize, usize)>,
+) -> impl Iterator<Item = MemRegion> {
+    let mut covered = RangeList::new();
+    for &(paddr, size) in axconfig::MMIO_REGIONS {
+        covered.push(paddr, size);
+    }
+    let mut extra = RangeList::new();
+    for (paddr, size) in devices {
+        let start = paddr & !(PAGE_SIZE_4K - 1);
+        let end = (paddr + size + PAGE_SIZE_4K - 1) & !(PAGE_SIZE_4K - 1);
+        if !covered.contains(start, end - start) {
+            covered.push(start, end - start);
+            extra.push(start, end - start);
+        }
+    }
+    default_mmio_regions().chain(extra.into_iter().map(|(paddr, size)| MemRegion {
+        paddr: paddr.into(),
+        size,
+        flags: MemRegionFlags::RESERVED
+            | MemRegionFlags::DEVICE
+            | MemRegionFlags::READ
+            | MemRegionFlags::WRITE,
+        name: "mmio",
+    }))
+}
+
+/// Returns the MMIO memory regions: the default ones (from
+/// [`axconfig::MMIO_REGIONS`]), and the console UART, the interrupt controller
+/// and virtio-mmio devices in the device tree that are not covered by them.
+#[allow(dead_code)]
+pub(crate) fn dtb_mmio_regions() -> impl Iterator<Item = MemRegion> {
+    let devices = crate::dtb::info().map(|info| {
+        let uart = info.uart_paddr.map(|paddr| (paddr, 0x1000));
+        let gic = info
+            .gic
+            .map(|gic| [Some(gic.gicd), Some(gic.gicc_or_gicr), gic.its]);
+        uart.into_iter()
+            .chain(gic.into_iter().flatten().flatten())
+            .chain(info.virtio_mmio)
+    });
+    mmio_regions_with(devices.into_iter().flatten())
+}
+
+/// Returns the MMIO memory regions: the default ones (from
+/// [`axconfig::MMIO_REGIONS`]), and the IO APICs, the HPET and the PCIe ECAM
+/// window in the ACPI tables that are not covered by them.
+#[allow(dead_code)]
+pub(crate) fn acpi_mmio_regions() -> impl Iterator<Item = MemRegion> {
+    let io_apics = crate::acpi::io_apics().map(|io_apic| (io_apic.paddr.as_usize(), 0x1000));
+    let hpet = crate::acpi::hpet_paddr().map(|paddr| (paddr.as_usize(), 0x1000));
+    let ecam = crate::acpi::pci_ecam().map(|ecam| {
+        let (paddr, size) = ecam.region();
+        (paddr.as_usize(), size)
+    });
+    mmio_regions_with(io_apics.chain(hpet).chain(ecam))
+}
+
+/// Returns the physical address of the PCIe ECAM space (the configuration
+/// space of bus 0) and the range of bus numbers to probe.
+///
+/// They are from the ACPI MCFG table if any, otherwise from
+/// [`axconfig::PCI_ECAM_BASE`] and [`axconfig::PCI_BUS_END`].
+pub fn pci_ecam() -> (PhysAddr, RangeInclusive<u8>) {
+    match crate::acpi::pci_ecam() {
+        Some(ecam) => (ecam.paddr, ecam.bus_start..=ecam.bus_end),
+        None => (
+            pa!(axconfig::PCI_ECAM_BASE),
+            0..=axconfig::PCI_BUS_END as u8,
+        ),
+    }
+}
+
+/// Returns the physical address and size of each virtio-mmio device slot.
+///
+/// They are from the device tree if any, otherwise from
+/// [`axconfig::VIRTIO_MMIO_REGIONS`].
+pub fn virtio_mmio_regions() -> impl Iterator<Item = (usize, usize)> {
+    let from_dtb = crate::dtb::info().map(|info| info.virtio_mmio);
+    let default = from_dtb
+        .is_none()
+        .then(|| axconfig::VIRTIO_MMIO_REGIONS.iter().copied());
+    from_dtb
+        .into_iter()
+        .flatten()
+        .chain(default.into_iter().flatten())
+}
+
+/// Maximum number of NUMA memory ranges.
+pub const MAX_NUMA_RANGES: usize = 16;
+
+/// A physical memory range that belongs to a NUMA node.
+#[derive(Debug, Clone, Copy)]
+pub struct NumaMemRange {
+    /// The start physical address of the range.
+    pub paddr: PhysAddr,
+    /// The size in bytes of the range.
+    pub size: usize,
+    /// The NUMA node (proximity domain) of the range.
+    pub node: usize,
+}
+
+const EMPTY_NUMA_RANGE: NumaMemRange = NumaMemRange {
+    paddr: pa!(0),
+    size: 0,
+    node: 0,
+};
+
+static NUMA_RANGES: SpinNoIrq<([NumaMemRange; MAX_NUMA_RANGES], usize)> =
+    SpinNoIrq::new(([EMPTY_NUMA_RANGE; MAX_NUMA_RANGES], 0));
+
+/// Records that the physical memory range belongs to the given NUMA node.
+///
+/// It is called by the platform code when parsing the firmware tables (e.g.,
+/// the device tree or the ACPI SRAT). Returns `false` if there are too many
+/// ranges.
+pub fn add_numa_range(paddr: PhysAddr, size: usize, node: usize) -> bool {
+    let mut ranges = NUMA_RANGES.lock();
+    let (ranges, len) = &mut *ranges;
+    if *len == MAX_NUMA_RANGES {
+        return false;
+    }
+    ranges[*len] = NumaMemRange { paddr, size, node };
+    *len += 1;
+    true
+}
+
+/// Returns an iterator over all NUMA memory ranges.
+///
+/// It is empty if the platform has not reported any NUMA information.
+pub fn numa_ranges() -> impl Iterator<Item = NumaMemRange> {
+    let (ranges, len) = *NUMA_RANGES.lock();
+    ranges.into_iter().take(len)
+}
+
+/// Returns the NUMA node of the physical address, or 0 if it is unknown.
+pub fn numa_node_of(paddr: PhysAddr) -> usize {
+    numa_ranges()
+        .find(|r| paddr >= r.paddr && paddr.as_usize() - r.paddr.as_usize() < r.size)
+        .map_or(0, |r| r.node)
+}
+
+/// Returns the number of NUMA nodes, which is at least 1.
+pub fn num_numa_nodes() -> usize {
+    numa_ranges().map(|r| r.node + 1).max().unwrap_or(1)
+}
+
 /// Fills the `.bss` section with zeros.
 #[allow(dead_code)]
 pub(crate) fn clear_bss() {
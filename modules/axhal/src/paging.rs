@@ -1,5 +1,7 @@
 //! Page table manipulation.
 
+use core::sync::atomic::{AtomicUsize, Ordering};
+
 use axalloc::global_allocator;
 use lazyinit::LazyInit;
 use page_table_multiarch::PagingHandler;
@@ -68,6 +70,16 @@ cfg_if::cfg_if! {
 
 static KERNEL_PAGE_TABLE_ROOT: LazyInit<PhysAddr> = LazyInit::new();
 
+// CPU sets are bitmaps in a `usize`.
+static_assertions::const_assert!(axconfig::SMP <= usize::BITS as usize);
+
+/// The root of the (user) page table active on each CPU.
+#[allow(clippy::declare_interior_mutable_const)]
+static ACTIVE_ROOTS: [AtomicUsize; axconfig::SMP] = {
+    const EMPTY: AtomicUsize = AtomicUsize::new(0);
+    [EMPTY; axconfig::SMP]
+};
+
 /// Saves the root physical address of the kernel page table, which may be used
 /// on context switch.
 pub fn set_kernel_page_table_root(root_paddr: PhysAddr) {
@@ -85,3 +97,75 @@ pub fn kernel_page_table_root() -> PhysAddr {
         .get()
         .expect("kernel page table not initialized")
 }
+
+/// Records the page table that becomes active on the current CPU.
+///
+/// It is called right before the (user) page table root register is written.
+pub(crate) fn set_active_page_table_root(root_paddr: PhysAddr) {
+    ACTIVE_ROOTS[crate::cpu::this_cpu_id()].store(root_paddr.as_usize(), Ordering::SeqCst);
+}
+
+/// Returns the set of CPUs (as a bitmap) on which the page table with the
+/// given root is active.
+///
+/// A CPU may switch to or away from the page table right after this
+/// function returns. Switching to a page table flushes stale TLB entries, so
+/// the page table entries must be updated before calling this function.
+pub fn cpus_using_page_table(root_paddr: PhysAddr) -> usize {
+    core::sync::atomic::fence(Ordering::SeqCst);
+    ACTIVE_ROOTS
+        .iter()
+        .enumerate()
+        .filter(|(_, root)| root.load(Ordering::SeqCst) == root_paddr.as_usize())
+        .fold(0, |cpus, (cpu_id, _)| cpus | (1 << cpu_id))
+}
+
+/// Flushes the TLB entries of `[start, start + size)` on the given CPUs
+/// (as a bitmap) except the current one.
+///
+/// If `wait` is `true`, it returns after the entries have been flushed on
+/// all of the CPUs.
+///
+/// Only CPUs that can receive IPIs are involved (see
+/// `axhal::irq::ipi_ready_cpus`). Without the `smp` and `irq` features, it
+/// does nothing.
+#[cfg_attr(not(all(feature = "smp", feature = "irq")), allow(unused_variables))]
+pub fn flush_tlb_remote(cpus: usize, start: VirtAddr, size: usize, wait: bool) {
+    #[cfg(all(feature = "smp", feature = "irq"))]
+    {
+        use crate::mem::MemoryAddr;
+
+        /// Flush the whole TLB instead of page by page if more pages than
+        /// this are involved. It must fit in the page offset bits.
+        const FLUSH_ALL_THRESHOLD: usize = 64;
+
+        // The argument is the page-aligned start address, with the number
+        // of pages in the lower bits.
+        fn flush(arg: usize) {
+            let start = VirtAddr::from(arg).align_down_4k();
+            let num_pages = arg - start.as_usize();
+            if num_pages > FLUSH_ALL_THRESHOLD {
+                crate::arch::flush_tlb(None);
+            } else {
+                for i in 0..num_pages {
+                    crate::arch::flush_tlb(Some(start + i * PAGE_SIZE_4K));
+                }
+            }
+        }
+
+        let _guard = kernel_guard::NoPreempt::new();
+        let cpus = cpus & crate::irq::ipi_ready_cpus() & !(1 << crate::cpu::this_cpu_id());
+        if cpus == 0 {
+            return;
+        }
+        let num_pages = (size + start.align_offset_4k())
+            .div_ceil(PAGE_SIZE_4K)
+            .min(FLUSH_ALL_THRESHOLD + 1);
+        crate::irq::run_on_cpus(
+            (0..axconfig::SMP).filter(|&cpu_id| cpus & (1 << cpu_id) != 0),
+            flush,
+            start.align_down_4k().as_usize() | num_pages,
+            wait,
+        );
+    }
+}
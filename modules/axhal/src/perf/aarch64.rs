@@ -0,0 +1,174 @@
+//! Arm PMUv3.
+
+use core::arch::asm;
+
+use super::{CounterConfig, PerfError, PerfEvent, PerfResult};
+
+const PMCR_E: u64 = 1 << 0; // Enable all counters
+const PMCR_LC: u64 = 1 << 6; // 64-bit cycle counter overflow
+const EVTYPER_P: u64 = 1 << 31; // Do not count at EL1
+const EVTYPER_U: u64 = 1 << 30; // Do not count at EL0
+
+/// The bit of the cycle counter in the enable and overflow registers.
+const CYCLE_BIT: u32 = 31;
+
+macro_rules! read_sysreg {
+    ($reg:literal) => {{
+        let val: u64;
+        unsafe { asm!(concat!("mrs {}, ", $reg), out(reg) val) };
+        val
+    }};
+}
+
+macro_rules! write_sysreg {
+    ($reg:literal, $val:expr) => {
+        unsafe { asm!(concat!("msr ", $reg, ", {}; isb"), in(reg) $val as u64) }
+    };
+}
+
+pub fn init() {}
+
+fn has_pmuv3() -> bool {
+    // ID_AA64DFR0_EL1.PMUVer: 0 is not implemented, 0xf is IMPLEMENTATION
+    // DEFINED.
+    let pmuver = (read_sysreg!("id_aa64dfr0_el1") >> 8) & 0xf;
+    pmuver != 0 && pmuver != 0xf
+}
+
+/// Number of event counters.
+fn num_event_counters() -> usize {
+    ((read_sysreg!("pmcr_el0") >> 11) & 0x1f) as usize
+}
+
+/// The bit of the counter in the enable and overflow registers.
+fn counter_bit(counter: usize) -> u32 {
+    if counter == num_event_counters() {
+        CYCLE_BIT
+    } else {
+        counter as u32
+    }
+}
+
+fn event_number(event: PerfEvent) -> u64 {
+    match event {
+        PerfEvent::CpuCycles => 0x11,
+        PerfEvent::Instructions => 0x08,
+        PerfEvent::CacheReferences => 0x04,
+        PerfEvent::CacheMisses => 0x03,
+        PerfEvent::BranchInstructions => 0x21,
+        PerfEvent::BranchMisses => 0x22,
+        PerfEvent::Raw(num) => num & 0xffff,
+    }
+}
+
+/// Checks the common event bitmap in PMCEID0/1_EL0.
+fn is_event_supported(num: u64) -> bool {
+    match num {
+        0..=31 => read_sysreg!("pmceid0_el0") & (1 << num) != 0,
+        32..=63 => read_sysreg!("pmceid1_el0") & (1 << (num - 32)) != 0,
+        // Implementation defined or extended events.
+        _ => true,
+    }
+}
+
+fn select(counter: usize) {
+    write_sysreg!("pmselr_el0", counter);
+}
+
+pub fn num_counters() -> usize {
+    if has_pmuv3() {
+        // The event counters and the cycle counter.
+        num_event_counters() + 1
+    } else {
+        0
+    }
+}
+
+pub fn counter_bits(counter: usize) -> u32 {
+    if counter == num_event_counters() {
+        64
+    } else {
+        32
+    }
+}
+
+pub fn max_period(counter: usize) -> u64 {
+    1 << (counter_bits(counter) - 1)
+}
+
+pub fn configure(counter: usize, config: &CounterConfig) -> PerfResult {
+    let mut filter = 0;
+    if !config.count_kernel {
+        filter |= EVTYPER_P;
+    }
+    if !config.count_user {
+        filter |= EVTYPER_U;
+    }
+    if counter == num_event_counters() {
+        if config.event != PerfEvent::CpuCycles {
+            return Err(PerfError::UnsupportedEvent);
+        }
+        write_sysreg!("pmccfiltr_el0", filter);
+    } else {
+        let num = event_number(config.event);
+        if !is_event_supported(num) {
+            return Err(PerfError::UnsupportedEvent);
+        }
+        select(counter);
+        write_sysreg!("pmxevtyper_el0", filter | num);
+    }
+
+    let bit = 1u64 << counter_bit(counter);
+    if config.sample_period != 0 {
+        write_sysreg!("pmintenset_el1", bit);
+    } else {
+        write_sysreg!("pmintenclr_el1", bit);
+    }
+    write_sysreg!("pmovsclr_el0", bit);
+    write_sysreg!("pmcr_el0", read_sysreg!("pmcr_el0") | PMCR_E | PMCR_LC);
+    write(counter, 0);
+    Ok(())
+}
+
+fn write(counter: usize, value: u64) {
+    if counter == num_event_counters() {
+        write_sysreg!("pmccntr_el0", value);
+    } else {
+        select(counter);
+        write_sysreg!("pmxevcntr_el0", value);
+    }
+}
+
+pub fn read(counter: usize) -> u64 {
+    if counter == num_event_counters() {
+        read_sysreg!("pmccntr_el0")
+    } else {
+        select(counter);
+        read_sysreg!("pmxevcntr_el0")
+    }
+}
+
+pub fn start(counter: usize, init: Option<u64>) {
+    if let Some(init) = init {
+        write(counter, init);
+    }
+    write_sysreg!("pmcntenset_el0", 1u64 << counter_bit(counter));
+}
+
+pub fn stop(counter: usize) {
+    write_sysreg!("pmcntenclr_el0", 1u64 << counter_bit(counter));
+}
+
+/// Returns and clears the overflowed counters, as a bitmask of counter
+/// numbers.
+#[cfg(feature = "irq")]
+pub fn take_overflow() -> u64 {
+    let status = read_sysreg!("pmovsclr_el0") & 0xffff_ffff;
+    write_sysreg!("pmovsclr_el0", status);
+    let events = status & !(1 << CYCLE_BIT);
+    if status & (1 << CYCLE_BIT) != 0 {
+        events | 1 << num_event_counters()
+    } else {
+        events
+    }
+}
@@ -0,0 +1,254 @@
+//! Hardware performance counters.
+//!
+//! The performance monitoring unit (PMU) of each CPU has a number of
+//! counters, which count the occurrences of a hardware [`PerfEvent`]. A
+//! counter can also be set to sample the event: it raises an overflow
+//! interrupt every `sample_period` events, and the handler set by
+//! [`set_overflow_handler`] is called with the interrupted PC.
+//!
+//! Counters are per-CPU, and all the functions operate on the counters of
+//! the current CPU, so they should be called with preemption disabled (or
+//! on every CPU with [`run_on_cpus`]).
+//!
+//! The counters are numbered from 0 to [`num_counters`] - 1:
+//!
+//! - x86_64: the general-purpose counters, followed by the fixed-function
+//!   counters (instructions, core cycles and reference cycles, in order).
+//!   Architectural performance monitoring version 2 or later is required.
+//! - AArch64: the PMUv3 event counters, followed by the cycle counter.
+//! - RISC-V: the counters of the SBI PMU extension. Only the programmable
+//!   counters (from 3) can raise overflow interrupts, which requires the
+//!   Sscofpmf extension.
+//!
+//! [`run_on_cpus`]: crate::irq::run_on_cpus
+
+#[cfg_attr(target_arch = "x86_64", path = "x86_64.rs")]
+#[cfg_attr(target_arch = "aarch64", path = "aarch64.rs")]
+#[cfg_attr(
+    any(target_arch = "riscv32", target_arch = "riscv64"),
+    path = "riscv.rs"
+)]
+mod arch;
+
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use crate::arch::TrapFrame;
+use crate::cpu::this_cpu_id;
+
+/// Maximum number of counters per CPU.
+pub const MAX_COUNTERS: usize = 32;
+
+/// A hardware event to count.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PerfEvent {
+    /// CPU cycles.
+    CpuCycles,
+    /// Retired instructions.
+    Instructions,
+    /// Cache accesses (the last level cache on x86_64, the L1 data cache on
+    /// AArch64).
+    CacheReferences,
+    /// Cache misses, of the same cache as [`PerfEvent::CacheReferences`].
+    CacheMisses,
+    /// Retired branch instructions.
+    BranchInstructions,
+    /// Mispredicted branch instructions.
+    BranchMisses,
+    /// An architecture-specific event number: the event select and unit
+    /// mask on x86_64 (`umask << 8 | event`), the PMUv3 event number on
+    /// AArch64, or the raw event data of the SBI PMU on RISC-V.
+    Raw(u64),
+}
+
+/// The configuration of a counter.
+#[derive(Debug, Clone, Copy)]
+pub struct CounterConfig {
+    /// The event to count.
+    pub event: PerfEvent,
+    /// Whether to count events in user mode.
+    pub count_user: bool,
+    /// Whether to count events in kernel mode.
+    pub count_kernel: bool,
+    /// Raise an overflow interrupt every `sample_period` events, or 0 to only
+    /// count.
+    pub sample_period: u64,
+}
+
+impl CounterConfig {
+    /// Counts `event` in both user and kernel mode, without sampling.
+    pub const fn new(event: PerfEvent) -> Self {
+        Self {
+            event,
+            count_user: true,
+            count_kernel: true,
+            sample_period: 0,
+        }
+    }
+}
+
+/// Performance counter errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PerfError {
+    /// There is no PMU, or it is not supported.
+    Unsupported,
+    /// The counter does not exist.
+    InvalidCounter,
+    /// The counter can not count the event.
+    UnsupportedEvent,
+    /// The counter can not sample with the period, or can not raise overflow
+    /// interrupts at all.
+    InvalidPeriod,
+}
+
+/// Result type of performance counter operations.
+pub type PerfResult<T = ()> = Result<T, PerfError>;
+
+/// An overflow handler, called in the interrupt context with the counter
+/// number, the interrupted PC and the trap frame of the interrupted code.
+pub type OverflowFn = fn(usize, usize, &TrapFrame);
+
+static OVERFLOW_HANDLER: AtomicUsize = AtomicUsize::new(0);
+
+/// The sample period of each counter on each CPU, 0 if not sampling.
+#[allow(clippy::declare_interior_mutable_const)]
+static SAMPLE_PERIODS: [[AtomicU64; MAX_COUNTERS]; axconfig::SMP] = {
+    const PERIOD: AtomicU64 = AtomicU64::new(0);
+    const PERIODS: [AtomicU64; MAX_COUNTERS] = [PERIOD; MAX_COUNTERS];
+    [PERIODS; axconfig::SMP]
+};
+
+fn sample_period(counter: usize) -> &'static AtomicU64 {
+    &SAMPLE_PERIODS[this_cpu_id()][counter]
+}
+
+/// Queries the PMU of the boot CPU. The other CPUs are assumed to have the
+/// same counters.
+pub(crate) fn init() {
+    arch::init();
+}
+
+/// Returns the number of counters of the current CPU, 0 if there is no
+/// supported PMU.
+pub fn num_counters() -> usize {
+    arch::num_counters().min(MAX_COUNTERS)
+}
+
+/// Returns the width in bits of the counter.
+pub fn counter_bits(counter: usize) -> PerfResult<u32> {
+    check_counter(counter)?;
+    Ok(arch::counter_bits(counter))
+}
+
+fn check_counter(counter: usize) -> PerfResult {
+    if counter < num_counters() {
+        Ok(())
+    } else if num_counters() == 0 {
+        Err(PerfError::Unsupported)
+    } else {
+        Err(PerfError::InvalidCounter)
+    }
+}
+
+/// The value to load into the counter, so that it overflows after `period`
+/// events.
+fn initial_value(counter: usize, period: u64) -> u64 {
+    let bits = arch::counter_bits(counter);
+    let mask = u64::MAX >> (64 - bits);
+    period.wrapping_neg() & mask
+}
+
+/// The initial value to start the counter with, if it is sampling.
+fn sample_init(counter: usize) -> Option<u64> {
+    match sample_period(counter).load(Ordering::Relaxed) {
+        0 => None,
+        period => Some(initial_value(counter, period)),
+    }
+}
+
+/// Configures a counter. The counter is stopped and reset to 0.
+///
+/// If `config.sample_period` is not 0, the counter raises overflow
+/// interrupts, which requires the `irq` feature.
+pub fn configure(counter: usize, config: &CounterConfig) -> PerfResult {
+    check_counter(counter)?;
+    arch::stop(counter);
+    let period = config.sample_period;
+    if period != 0 && (!cfg!(feature = "irq") || period > arch::max_period(counter)) {
+        return Err(PerfError::InvalidPeriod);
+    }
+    arch::configure(counter, config)?;
+    sample_period(counter).store(period, Ordering::Relaxed);
+    #[cfg(feature = "irq")]
+    if period != 0 {
+        init_overflow_irq();
+    }
+    Ok(())
+}
+
+/// Starts counting.
+///
+/// A sampling counter starts a new sample period, so that it overflows after
+/// `sample_period` events.
+pub fn start(counter: usize) -> PerfResult {
+    check_counter(counter)?;
+    arch::start(counter, sample_init(counter));
+    Ok(())
+}
+
+/// Stops counting.
+pub fn stop(counter: usize) -> PerfResult {
+    check_counter(counter)?;
+    arch::stop(counter);
+    Ok(())
+}
+
+/// Reads the current value of a counter.
+///
+/// For sampling counters, it is the number of events since the last
+/// overflow, plus the initial offset to raise the next overflow.
+pub fn read(counter: usize) -> PerfResult<u64> {
+    check_counter(counter)?;
+    Ok(arch::read(counter))
+}
+
+/// Sets the function to be called on counter overflows.
+pub fn set_overflow_handler(f: OverflowFn) {
+    OVERFLOW_HANDLER.store(f as usize, Ordering::Release);
+}
+
+/// Registers the overflow IRQ handler (once), and enables the IRQ on the
+/// current CPU.
+#[cfg(feature = "irq")]
+fn init_overflow_irq() {
+    use core::sync::atomic::AtomicBool;
+    static REGISTERED: AtomicBool = AtomicBool::new(false);
+
+    if !REGISTERED.swap(true, Ordering::AcqRel) {
+        crate::irq::register_handler(crate::platform::irq::PMU_IRQ_NUM, handle_overflow_irq);
+    }
+    crate::platform::irq::enable_pmu_irq();
+}
+
+#[cfg(feature = "irq")]
+fn handle_overflow_irq() {
+    let overflowed = arch::take_overflow();
+    let handler = OVERFLOW_HANDLER.load(Ordering::Acquire);
+    for counter in 0..num_counters() {
+        if overflowed & (1 << counter) == 0 {
+            continue;
+        }
+        let Some(init) = sample_init(counter) else {
+            continue;
+        };
+        // Restart the counter for the next sample.
+        arch::stop(counter);
+        arch::start(counter, Some(init));
+        if handler != 0 {
+            let handler: OverflowFn = unsafe { core::mem::transmute(handler) };
+            crate::trap::with_irq_trap_frame(|tf| {
+                handler(counter, crate::backtrace::trap_pc(tf), tf)
+            });
+        }
+    }
+    crate::platform::irq::enable_pmu_irq();
+}
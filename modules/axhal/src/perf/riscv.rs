@@ -0,0 +1,205 @@
+//! The SBI performance monitoring unit extension.
+
+use core::arch::asm;
+
+use lazyinit::LazyInit;
+
+use super::{CounterConfig, PerfError, PerfEvent, PerfResult, MAX_COUNTERS};
+
+const EID_PMU: usize = 0x504d55;
+const FID_NUM_COUNTERS: usize = 0;
+const FID_COUNTER_GET_INFO: usize = 1;
+const FID_COUNTER_CONFIG_MATCHING: usize = 2;
+const FID_COUNTER_START: usize = 3;
+const FID_COUNTER_STOP: usize = 4;
+const FID_COUNTER_FW_READ: usize = 5;
+
+const CFG_FLAG_CLEAR_VALUE: usize = 1 << 1;
+const CFG_FLAG_SET_UINH: usize = 1 << 5;
+const CFG_FLAG_SET_SINH: usize = 1 << 6;
+const START_FLAG_SET_INIT_VALUE: usize = 1 << 0;
+
+/// Event index of raw hardware events, whose code is the event data.
+const EVENT_IDX_RAW: usize = 0x20000;
+
+/// The first counter that can raise overflow interrupts. Counters 0..3 are
+/// `cycle`, `time` and `instret`.
+const FIRST_PROGRAMMABLE: usize = 3;
+
+const CSR_CYCLE: usize = 0xc00;
+const CSR_SCOUNTOVF: usize = 0xda0;
+
+fn sbi_pmu_call(fid: usize, args: [usize; 5]) -> Result<usize, isize> {
+    let (error, value): (isize, usize);
+    unsafe {
+        asm!(
+            "ecall",
+            inlateout("a0") args[0] => error,
+            inlateout("a1") args[1] => value,
+            in("a2") args[2],
+            in("a3") args[3],
+            in("a4") args[4],
+            in("a6") fid,
+            in("a7") EID_PMU,
+        );
+    }
+    if error == 0 {
+        Ok(value)
+    } else {
+        Err(error)
+    }
+}
+
+/// The counter info of `sbi_pmu_counter_get_info`.
+#[derive(Clone, Copy)]
+struct CounterInfo {
+    csr: usize,
+    bits: u32,
+    firmware: bool,
+}
+
+/// The number of counters and the info of each counter, queried once at
+/// boot, so that the overflow handler makes no SBI calls to find them.
+struct PmuInfo {
+    num_counters: usize,
+    counters: [Option<CounterInfo>; MAX_COUNTERS],
+}
+
+static PMU_INFO: LazyInit<PmuInfo> = LazyInit::new();
+
+fn read_counter_info(counter: usize) -> Option<CounterInfo> {
+    let info = sbi_pmu_call(FID_COUNTER_GET_INFO, [counter, 0, 0, 0, 0]).ok()?;
+    let firmware = info >> (usize::BITS - 1) != 0;
+    Some(CounterInfo {
+        csr: info & 0xfff,
+        bits: if firmware {
+            64
+        } else {
+            ((info >> 12) & 0x3f) as u32 + 1
+        },
+        firmware,
+    })
+}
+
+pub fn init() {
+    let num_counters = sbi_pmu_call(FID_NUM_COUNTERS, [0; 5]).unwrap_or(0);
+    let mut counters = [None; MAX_COUNTERS];
+    for (counter, info) in counters.iter_mut().enumerate().take(num_counters) {
+        *info = read_counter_info(counter);
+    }
+    PMU_INFO.init_once(PmuInfo {
+        num_counters,
+        counters,
+    });
+}
+
+fn counter_info(counter: usize) -> Option<CounterInfo> {
+    *PMU_INFO.counters.get(counter)?
+}
+
+fn event_idx_data(event: PerfEvent) -> (usize, u64) {
+    match event {
+        PerfEvent::CpuCycles => (1, 0),
+        PerfEvent::Instructions => (2, 0),
+        PerfEvent::CacheReferences => (3, 0),
+        PerfEvent::CacheMisses => (4, 0),
+        PerfEvent::BranchInstructions => (5, 0),
+        PerfEvent::BranchMisses => (6, 0),
+        PerfEvent::Raw(data) => (EVENT_IDX_RAW, data),
+    }
+}
+
+#[inline(always)]
+fn read_csr<const CSR: usize>() -> u64 {
+    let val: usize;
+    unsafe { asm!("csrr {}, {csr}", out(reg) val, csr = const CSR) };
+    val as u64
+}
+
+macro_rules! read_counter_csr {
+    ($csr:expr, $($i:literal)*) => {
+        match $csr {
+            $(csr if csr == CSR_CYCLE + $i => read_csr::<{ CSR_CYCLE + $i }>(),)*
+            _ => 0,
+        }
+    };
+}
+
+pub fn num_counters() -> usize {
+    PMU_INFO.num_counters
+}
+
+pub fn counter_bits(counter: usize) -> u32 {
+    counter_info(counter).map_or(64, |info| info.bits)
+}
+
+pub fn max_period(counter: usize) -> u64 {
+    match counter_info(counter) {
+        Some(info) if counter >= FIRST_PROGRAMMABLE && !info.firmware => 1 << (info.bits - 1),
+        _ => 0,
+    }
+}
+
+pub fn configure(counter: usize, config: &CounterConfig) -> PerfResult {
+    let mut flags = CFG_FLAG_CLEAR_VALUE;
+    if !config.count_user {
+        flags |= CFG_FLAG_SET_UINH;
+    }
+    if !config.count_kernel {
+        flags |= CFG_FLAG_SET_SINH;
+    }
+    let (idx, data) = event_idx_data(config.event);
+    // Only the given counter can match.
+    match sbi_pmu_call(
+        FID_COUNTER_CONFIG_MATCHING,
+        [counter, 1, flags, idx, data as usize],
+    ) {
+        Ok(matched) if matched == counter => Ok(()),
+        Ok(_) => Err(PerfError::InvalidCounter),
+        Err(_) => Err(PerfError::UnsupportedEvent),
+    }
+}
+
+pub fn read(counter: usize) -> u64 {
+    match counter_info(counter) {
+        Some(info) if info.firmware => {
+            sbi_pmu_call(FID_COUNTER_FW_READ, [counter, 0, 0, 0, 0]).unwrap_or(0) as u64
+        }
+        Some(info) => read_counter_csr!(
+            info.csr,
+            0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15
+            16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 31
+        ),
+        None => 0,
+    }
+}
+
+pub fn start(counter: usize, init: Option<u64>) {
+    let (flags, init) = match init {
+        Some(init) => (START_FLAG_SET_INIT_VALUE, init as usize),
+        None => (0, 0),
+    };
+    let _ = sbi_pmu_call(FID_COUNTER_START, [counter, 1, flags, init, 0]);
+}
+
+pub fn stop(counter: usize) {
+    let _ = sbi_pmu_call(FID_COUNTER_STOP, [counter, 1, 0, 0, 0]);
+}
+
+/// Returns the overflowed counters, as a bitmask of counter numbers.
+///
+/// The overflow flags are cleared when the counters are restarted.
+#[cfg(feature = "irq")]
+pub fn take_overflow() -> u64 {
+    let status = read_csr::<CSR_SCOUNTOVF>();
+    let mut overflowed = 0;
+    for counter in FIRST_PROGRAMMABLE..num_counters().min(MAX_COUNTERS) {
+        if let Some(info) = counter_info(counter) {
+            let bit = info.csr.wrapping_sub(CSR_CYCLE);
+            if !info.firmware && bit < 32 && status & (1 << bit) != 0 {
+                overflowed |= 1 << counter;
+            }
+        }
+    }
+    overflowed
+}
@@ -0,0 +1,205 @@
+//! Intel architectural performance monitoring (version 2 or later).
+
+use core::arch::x86_64::__cpuid;
+
+use lazyinit::LazyInit;
+use x86::msr::{rdmsr, wrmsr};
+
+use super::{CounterConfig, PerfError, PerfEvent, PerfResult};
+
+const IA32_PMC0: u32 = 0xc1;
+const IA32_PERFEVTSEL0: u32 = 0x186;
+const IA32_FIXED_CTR0: u32 = 0x309;
+const IA32_FIXED_CTR_CTRL: u32 = 0x38d;
+const IA32_PERF_GLOBAL_STATUS: u32 = 0x38e;
+const IA32_PERF_GLOBAL_CTRL: u32 = 0x38f;
+const IA32_PERF_GLOBAL_OVF_CTRL: u32 = 0x390;
+
+const EVTSEL_USR: u64 = 1 << 16;
+const EVTSEL_OS: u64 = 1 << 17;
+const EVTSEL_INT: u64 = 1 << 20;
+const EVTSEL_EN: u64 = 1 << 22;
+
+const FIXED_OS: u64 = 1 << 0;
+const FIXED_USR: u64 = 1 << 1;
+const FIXED_PMI: u64 = 1 << 3;
+
+/// Events counted by the fixed-function counters, in order.
+const FIXED_EVENTS: [PerfEvent; 3] = [
+    PerfEvent::Instructions,
+    PerfEvent::CpuCycles,
+    PerfEvent::Raw(0x013c), // Reference cycles
+];
+
+/// The version, the number and width of general-purpose counters, and the
+/// number and width of fixed-function counters.
+struct PmuInfo {
+    version: u32,
+    num_gp: usize,
+    gp_bits: u32,
+    num_fixed: usize,
+    fixed_bits: u32,
+}
+
+/// The PMU of the boot CPU, assumed to be the same on all CPUs.
+static PMU_INFO: LazyInit<PmuInfo> = LazyInit::new();
+
+fn read_pmu_info() -> PmuInfo {
+    // Safety: CPUID is always available on x86_64.
+    let max_leaf = unsafe { __cpuid(0) }.eax;
+    if max_leaf < 0xa {
+        return PmuInfo {
+            version: 0,
+            num_gp: 0,
+            gp_bits: 0,
+            num_fixed: 0,
+            fixed_bits: 0,
+        };
+    }
+    let leaf = unsafe { __cpuid(0xa) };
+    PmuInfo {
+        version: leaf.eax & 0xff,
+        num_gp: ((leaf.eax >> 8) & 0xff) as usize,
+        gp_bits: (leaf.eax >> 16) & 0xff,
+        num_fixed: (leaf.edx & 0x1f) as usize,
+        fixed_bits: (leaf.edx >> 5) & 0xff,
+    }
+}
+
+pub fn init() {
+    PMU_INFO.init_once(read_pmu_info());
+}
+
+fn pmu_info() -> &'static PmuInfo {
+    &PMU_INFO
+}
+
+/// Returns the index of the fixed-function counter, if it is one.
+fn fixed_index(counter: usize) -> Option<usize> {
+    counter.checked_sub(pmu_info().num_gp)
+}
+
+fn global_bit(counter: usize) -> u64 {
+    match fixed_index(counter) {
+        Some(f) => 1 << (32 + f),
+        None => 1 << counter,
+    }
+}
+
+fn event_code(event: PerfEvent) -> u64 {
+    match event {
+        PerfEvent::CpuCycles => 0x003c,
+        PerfEvent::Instructions => 0x00c0,
+        PerfEvent::CacheReferences => 0x4f2e,
+        PerfEvent::CacheMisses => 0x412e,
+        PerfEvent::BranchInstructions => 0x00c4,
+        PerfEvent::BranchMisses => 0x00c5,
+        PerfEvent::Raw(code) => code & 0xffff,
+    }
+}
+
+pub fn num_counters() -> usize {
+    let info = pmu_info();
+    if info.version < 2 {
+        return 0;
+    }
+    info.num_gp + info.num_fixed.min(FIXED_EVENTS.len())
+}
+
+pub fn counter_bits(counter: usize) -> u32 {
+    let info = pmu_info();
+    if counter < info.num_gp {
+        info.gp_bits
+    } else {
+        info.fixed_bits
+    }
+}
+
+pub fn max_period(counter: usize) -> u64 {
+    if fixed_index(counter).is_some() {
+        1 << (counter_bits(counter) - 1)
+    } else {
+        // Writes to the general-purpose counters are sign-extended from bit 31.
+        i32::MAX as u64
+    }
+}
+
+pub fn configure(counter: usize, config: &CounterConfig) -> PerfResult {
+    let irq = config.sample_period != 0;
+    unsafe {
+        match fixed_index(counter) {
+            Some(f) => {
+                if FIXED_EVENTS[f] != config.event {
+                    return Err(PerfError::UnsupportedEvent);
+                }
+                let mut bits = 0;
+                if config.count_kernel {
+                    bits |= FIXED_OS;
+                }
+                if config.count_user {
+                    bits |= FIXED_USR;
+                }
+                if irq {
+                    bits |= FIXED_PMI;
+                }
+                let ctrl = rdmsr(IA32_FIXED_CTR_CTRL) & !(0xf << (4 * f));
+                wrmsr(IA32_FIXED_CTR_CTRL, ctrl | bits << (4 * f));
+            }
+            None => {
+                let mut evtsel = event_code(config.event) | EVTSEL_EN;
+                if config.count_kernel {
+                    evtsel |= EVTSEL_OS;
+                }
+                if config.count_user {
+                    evtsel |= EVTSEL_USR;
+                }
+                if irq {
+                    evtsel |= EVTSEL_INT;
+                }
+                wrmsr(IA32_PERFEVTSEL0 + counter as u32, evtsel);
+            }
+        }
+        wrmsr(counter_msr(counter), 0);
+    }
+    Ok(())
+}
+
+fn counter_msr(counter: usize) -> u32 {
+    match fixed_index(counter) {
+        Some(f) => IA32_FIXED_CTR0 + f as u32,
+        None => IA32_PMC0 + counter as u32,
+    }
+}
+
+pub fn read(counter: usize) -> u64 {
+    unsafe { rdmsr(counter_msr(counter)) }
+}
+
+pub fn start(counter: usize, init: Option<u64>) {
+    unsafe {
+        if let Some(init) = init {
+            wrmsr(counter_msr(counter), init);
+        }
+        let ctrl = rdmsr(IA32_PERF_GLOBAL_CTRL);
+        wrmsr(IA32_PERF_GLOBAL_CTRL, ctrl | global_bit(counter));
+    }
+}
+
+pub fn stop(counter: usize) {
+    unsafe {
+        let ctrl = rdmsr(IA32_PERF_GLOBAL_CTRL);
+        wrmsr(IA32_PERF_GLOBAL_CTRL, ctrl & !global_bit(counter));
+    }
+}
+
+/// Returns and clears the overflowed counters, as a bitmask of counter
+/// numbers.
+#[cfg(feature = "irq")]
+pub fn take_overflow() -> u64 {
+    let num_gp = pmu_info().num_gp;
+    let status = unsafe { rdmsr(IA32_PERF_GLOBAL_STATUS) };
+    unsafe { wrmsr(IA32_PERF_GLOBAL_OVF_CTRL, status) };
+    let gp = status & ((1 << num_gp) - 1);
+    let fixed = (status >> 32) & 0b111;
+    gp | fixed << num_gp
+}
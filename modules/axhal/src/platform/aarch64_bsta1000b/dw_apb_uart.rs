@@ -35,10 +35,12 @@ pub fn init_early() {
 #[cfg(feature = "irq")]
 pub fn init_irq() {
     UART.lock().set_ier(true);
-    crate::irq::register_handler(crate::platform::irq::UART_IRQ_NUM, handle);
+    crate::console::register_input_irq(crate::platform::irq::UART_IRQ_NUM, handle);
 }
 
 /// UART IRQ Handler
-pub fn handle() {
+#[cfg(feature = "irq")]
+fn handle() {
     trace!("Uart IRQ Handler");
+    crate::console::handle_input_irq();
 }
@@ -37,6 +37,18 @@ unsafe fn switch_to_el1() {
         // Disable EL1 timer traps and the timer offset.
         CNTHCTL_EL2.modify(CNTHCTL_EL2::EL1PCEN::SET + CNTHCTL_EL2::EL1PCTEN::SET);
         CNTVOFF_EL2.set(0);
+        // Allow EL1 to access the system registers of the GICv3 CPU
+        // interface, if supported.
+        if (ID_AA64PFR0_EL1.get() >> 24) & 0xf != 0 {
+            core::arch::asm!(
+                "
+                mrs     x8, S3_4_C12_C9_5       // ICC_SRE_EL2
+                orr     x8, x8, #0x9            // Enable | SRE
+                msr     S3_4_C12_C9_5, x8
+                isb",
+                out("x8") _,
+            );
+        }
         // Set EL1 to 64bit.
         HCR_EL2.write(HCR_EL2::RW::EL1IsAarch64);
         // Set the return address and exception level.
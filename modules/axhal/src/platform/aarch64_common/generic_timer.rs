@@ -49,8 +49,11 @@ pub fn set_oneshot_timer(deadline_ns: u64) {
 }
 
 /// Early stage initialization: stores the timer frequency.
+///
+/// The `clock-frequency` of the timer in the device tree takes precedence
+/// over `CNTFRQ_EL0`, which is not set correctly by some firmware.
 pub(crate) fn init_early() {
-    let freq = CNTFRQ_EL0.get();
+    let freq = crate::dtb::timer_frequency().unwrap_or_else(|| CNTFRQ_EL0.get());
     unsafe {
         CNTPCT_TO_NANOS_RATIO = Ratio::new(crate::time::NANOS_PER_SEC as u32, freq as u32);
         NANOS_TO_CNTPCT_RATIO = CNTPCT_TO_NANOS_RATIO.inverse();
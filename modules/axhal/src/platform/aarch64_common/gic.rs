@@ -1,30 +1,100 @@
-use crate::{irq::IrqHandler, mem::phys_to_virt};
+//! ARM Generic Interrupt Controller (GIC).
+//!
+//! Both GICv2 and GICv3 are supported. The version and the register addresses
+//! are from the device tree if any, otherwise from the platform configuration
+//! (`gic-version`, `gicd-paddr`, `gicc-paddr`, `gicr-paddr` and `gits-paddr`).
+//!
+//! MSIs are supported with the GICv3 ITS, as LPIs.
+
+use crate::irq::{IrqHandler, MsiMessage};
+use crate::mem::phys_to_virt;
 use arm_gicv2::{translate_irq, GicCpuInterface, GicDistributor, InterruptType};
 use kspin::SpinNoIrq;
-use memory_addr::PhysAddr;
+use lazyinit::LazyInit;
+
+use super::{gicv3, its};
 
-/// The maximum number of IRQs.
-pub const MAX_IRQ_COUNT: usize = 1024;
+/// The maximum number of IRQs, including the LPIs allocated for MSIs.
+pub const MAX_IRQ_COUNT: usize = its::LPI_BASE + its::MAX_LPIS;
 
 /// The timer IRQ number.
 pub const TIMER_IRQ_NUM: usize = translate_irq(14, InterruptType::PPI).unwrap();
 
+/// The inter-processor interrupt (IPI) number, using SGI 1.
+pub const IPI_IRQ_NUM: usize = translate_irq(1, InterruptType::SGI).unwrap();
+
+/// The performance counter overflow IRQ number, using PPI 7 as recommended
+/// by the Arm Base System Architecture.
+pub const PMU_IRQ_NUM: usize = translate_irq(7, InterruptType::PPI).unwrap();
+
 /// The UART IRQ number.
 pub const UART_IRQ_NUM: usize = translate_irq(axconfig::UART_IRQ, InterruptType::SPI).unwrap();
 
-const GICD_BASE: PhysAddr = pa!(axconfig::GICD_PADDR);
-const GICC_BASE: PhysAddr = pa!(axconfig::GICC_PADDR);
+/// The size of the GICv2 CPU interface registers.
+const GICC_SIZE: usize = 0x2000;
+/// The maximum size of the redistributor registers of a CPU.
+const GICR_SIZE_PER_CPU: usize = 0x4_0000;
+
+enum Gic {
+    V2 {
+        gicd: SpinNoIrq<GicDistributor>,
+        // per-CPU, no lock
+        gicc: GicCpuInterface,
+    },
+    V3 {
+        gicd: SpinNoIrq<gicv3::GicDistributor>,
+        /// The base virtual address and the size of the redistributor region.
+        gicr_region: (usize, usize),
+        its: Option<SpinNoIrq<its::Its>>,
+    },
+}
 
-static GICD: SpinNoIrq<GicDistributor> =
-    SpinNoIrq::new(GicDistributor::new(phys_to_virt(GICD_BASE).as_mut_ptr()));
+static GIC: LazyInit<Gic> = LazyInit::new();
 
-// per-CPU, no lock
-static GICC: GicCpuInterface = GicCpuInterface::new(phys_to_virt(GICC_BASE).as_mut_ptr());
+/// The base virtual address of the redistributor of the current CPU (GICv3
+/// only).
+#[percpu::def_percpu]
+static GICR_BASE: usize = 0;
+
+fn current_gicr() -> gicv3::GicRedistributor {
+    gicv3::GicRedistributor::new(GICR_BASE.read_current())
+}
 
 /// Enables or disables the given IRQ.
 pub fn set_enable(irq_num: usize, enabled: bool) {
     trace!("GICD set enable: {} {}", irq_num, enabled);
-    GICD.lock().set_enable(irq_num as _, enabled);
+    match &*GIC {
+        Gic::V2 { gicd, .. } => gicd.lock().set_enable(irq_num as _, enabled),
+        // SGIs and PPIs are handled by the redistributor of each CPU.
+        Gic::V3 { .. } if irq_num < 32 => current_gicr().set_enable(irq_num, enabled),
+        Gic::V3 { its: Some(its), .. } if irq_num >= its::LPI_BASE => {
+            its.lock().set_enable(irq_num, enabled)
+        }
+        Gic::V3 { gicd, .. } => gicd.lock().set_enable(irq_num, enabled),
+    }
+}
+
+/// Allocates an IRQ for an MSI of the PCI device with the given requester ID
+/// (bus, device and function numbers), returns the IRQ number and the message
+/// that the device should write.
+///
+/// It allocates an LPI through the GICv3 ITS, and returns [`None`] if there
+/// is no ITS or no free LPI.
+pub fn alloc_msi_irq(device_id: u32) -> Option<(usize, MsiMessage)> {
+    match &*GIC {
+        Gic::V3 { its: Some(its), .. } => its.lock().alloc_msi(device_id),
+        _ => None,
+    }
+}
+
+/// Frees an IRQ allocated by [`alloc_msi_irq`], whose handler must not be
+/// registered.
+pub fn free_msi_irq(irq_num: usize) {
+    if let Gic::V3 { its: Some(its), .. } = &*GIC {
+        if irq_num >= its::LPI_BASE {
+            its.lock().free_msi(irq_num);
+        }
+    }
 }
 
 /// Registers an IRQ handler for the given IRQ.
@@ -36,24 +106,119 @@ pub fn register_handler(irq_num: usize, handler: IrqHandler) -> bool {
     crate::irq::register_handler_common(irq_num, handler)
 }
 
+/// Enables the performance counter overflow interrupt on the current CPU.
+pub fn enable_pmu_irq() {
+    // PPIs are banked per CPU.
+    set_enable(PMU_IRQ_NUM, true);
+}
+
+/// Sends an inter-processor interrupt to the given CPU.
+pub fn send_ipi(cpu_id: usize) {
+    match &*GIC {
+        Gic::V2 { gicd, .. } => gicd.lock().send_sgi(cpu_id, IPI_IRQ_NUM),
+        // The CPU ID is the affinity fields of its MPIDR.
+        Gic::V3 { .. } => gicv3::send_sgi(cpu_id as u64, IPI_IRQ_NUM),
+    }
+}
+
+/// Sends an inter-processor interrupt to all CPUs except the current one.
+pub fn send_ipi_all_others() {
+    match &*GIC {
+        Gic::V2 { gicd, .. } => gicd.lock().send_sgi_all_except_self(IPI_IRQ_NUM),
+        Gic::V3 { .. } => gicv3::send_sgi_all_except_self(IPI_IRQ_NUM),
+    }
+}
+
 /// Dispatches the IRQ.
 ///
 /// This function is called by the common interrupt handler. It looks
 /// up in the IRQ handler table and calls the corresponding handler. If
 /// necessary, it also acknowledges the interrupt controller after handling.
 pub fn dispatch_irq(_unused: usize) {
-    GICC.handle_irq(|irq_num| crate::irq::dispatch_irq_common(irq_num as _));
+    match &*GIC {
+        Gic::V2 { gicc, .. } => {
+            gicc.handle_irq(|irq_num| crate::irq::dispatch_irq_common(irq_num as _))
+        }
+        Gic::V3 { .. } => {
+            gicv3::handle_irq(|irq_num| crate::irq::dispatch_irq_common(irq_num as _))
+        }
+    }
+}
+
+/// Initializes the CPU interface (GICv2), or the redistributor and the CPU
+/// interface (GICv3) of the current CPU.
+fn init_percpu() {
+    match &*GIC {
+        Gic::V2 { gicc, .. } => gicc.init(),
+        Gic::V3 {
+            gicr_region, its, ..
+        } => {
+            let gicr = gicv3::GicRedistributor::find_current(gicr_region.0, gicr_region.1)
+                .expect("No GICv3 redistributor for the current CPU");
+            gicr.init();
+            if its.is_some() {
+                its::enable_lpis(&gicr);
+            }
+            unsafe { GICR_BASE.write_current_raw(gicr.base()) };
+            gicv3::init_cpu_interface();
+        }
+    }
 }
 
-/// Initializes GICD, GICC on the primary CPU.
+/// Initializes the GIC on the primary CPU.
 pub(crate) fn init_primary() {
-    info!("Initialize GICv2...");
-    GICD.lock().init();
-    GICC.init();
+    let dtb_gic = crate::dtb::info().and_then(|info| info.gic);
+    let (version, gicd_paddr, gicc_or_gicr) = match dtb_gic {
+        Some(gic) => (gic.version, gic.gicd.0, gic.gicc_or_gicr),
+        None if axconfig::GIC_VERSION >= 3 => (
+            3,
+            axconfig::GICD_PADDR,
+            (axconfig::GICR_PADDR, axconfig::SMP * GICR_SIZE_PER_CPU),
+        ),
+        None => (2, axconfig::GICD_PADDR, (axconfig::GICC_PADDR, GICC_SIZE)),
+    };
+    let its_paddr = match dtb_gic {
+        Some(gic) => gic.its.map(|its| its.0),
+        None => (axconfig::GITS_PADDR != 0).then_some(axconfig::GITS_PADDR),
+    };
+    let gicd_base = phys_to_virt(pa!(gicd_paddr)).as_mut_ptr();
+    let gic = if version >= 3 {
+        info!("Initialize GICv3...");
+        let mut gicd = gicv3::GicDistributor::new(gicd_base);
+        gicd.init();
+        let gicr_base = phys_to_virt(pa!(gicc_or_gicr.0)).as_usize();
+        let gicr = gicv3::GicRedistributor::find_current(gicr_base, gicc_or_gicr.1);
+        let its = its_paddr
+            .zip(gicr)
+            .filter(|_| gicd.id_bits() >= its::LPI_ID_BITS)
+            .and_then(|(paddr, gicr)| {
+                info!("Initialize GICv3 ITS at {:#x}...", paddr);
+                let base = phys_to_virt(pa!(paddr)).as_usize();
+                its::Its::new(base, paddr, &gicr)
+            });
+        Gic::V3 {
+            gicd: SpinNoIrq::new(gicd),
+            gicr_region: (gicr_base, gicc_or_gicr.1),
+            its: its.map(SpinNoIrq::new),
+        }
+    } else {
+        info!("Initialize GICv2...");
+        let mut gicd = GicDistributor::new(gicd_base);
+        gicd.init();
+        let gicc_base = phys_to_virt(pa!(gicc_or_gicr.0)).as_mut_ptr();
+        Gic::V2 {
+            gicd: SpinNoIrq::new(gicd),
+            gicc: GicCpuInterface::new(gicc_base),
+        }
+    };
+    GIC.init_once(gic);
+    init_percpu();
 }
 
-/// Initializes GICC on secondary CPUs.
+/// Initializes the GIC on secondary CPUs.
 #[cfg(feature = "smp")]
 pub(crate) fn init_secondary() {
-    GICC.init();
+    init_percpu();
+    // SGIs are banked per CPU, so enable the IPI on each CPU.
+    set_enable(IPI_IRQ_NUM, true);
 }
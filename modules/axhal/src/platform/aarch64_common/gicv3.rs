@@ -0,0 +1,405 @@
+//! ARM Generic Interrupt Controller version 3 (GICv3).
+//!
+//! It consists of the distributor (GICD) for the shared peripheral interrupts
+//! (SPIs), a redistributor (GICR) for each CPU for its private interrupts
+//! (SGIs and PPIs), and the CPU interface, which is accessed with the system
+//! registers. SPIs are routed to CPUs by affinity (`GICD_IROUTER`), and all
+//! interrupts are in the non-secure group 1.
+//!
+//! See the Arm GIC Architecture Specification (IHI 0069).
+
+use core::arch::asm;
+
+/// The first SPI number.
+const SPI_BASE: usize = 32;
+/// The maximum number of SPIs plus 32 (interrupt IDs 1020..1023 are special).
+const MAX_IRQS: usize = 1020;
+/// The default priority of all interrupts.
+const DEFAULT_PRIORITY: u8 = 0xa0;
+
+const GICD_CTLR: usize = 0x0000;
+const GICD_TYPER: usize = 0x0004;
+const GICD_IGROUPR: usize = 0x0080;
+const GICD_ISENABLER: usize = 0x0100;
+const GICD_ICENABLER: usize = 0x0180;
+const GICD_ICPENDR: usize = 0x0280;
+const GICD_IPRIORITYR: usize = 0x0400;
+const GICD_ICFGR: usize = 0x0c00;
+const GICD_IROUTER: usize = 0x6000;
+
+const GICD_CTLR_ENABLE_G1: u32 = 1 << 0;
+const GICD_CTLR_ENABLE_G1A: u32 = 1 << 1;
+/// Affinity routing enable.
+const GICD_CTLR_ARE: u32 = 1 << 4;
+/// Register write pending.
+const GICD_CTLR_RWP: u32 = 1 << 31;
+
+const GICR_CTLR: usize = 0x0000;
+const GICR_TYPER: usize = 0x0008;
+const GICR_WAKER: usize = 0x0014;
+const GICR_PROPBASER: usize = 0x0070;
+const GICR_PENDBASER: usize = 0x0078;
+/// The offset of the SGI and PPI frame from the redistributor base.
+const GICR_SGI_BASE: usize = 0x1_0000;
+/// The size of the frames of a redistributor (`RD_base` and `SGI_base`).
+const GICR_STRIDE: usize = 0x2_0000;
+/// The size of the frames of a redistributor with the virtual LPI frames
+/// (GICv4).
+const GICR_STRIDE_VLPIS: usize = 0x4_0000;
+
+const GICR_CTLR_ENABLE_LPIS: u32 = 1 << 0;
+const GICR_CTLR_RWP: u32 = 1 << 3;
+const GICR_TYPER_PLPIS: u64 = 1 << 0;
+const GICR_TYPER_VLPIS: u64 = 1 << 1;
+const GICR_TYPER_LAST: u64 = 1 << 4;
+const GICR_WAKER_PROCESSOR_SLEEP: u32 = 1 << 1;
+const GICR_WAKER_CHILDREN_ASLEEP: u32 = 1 << 2;
+
+/// Inner-shareable, normal inner write-back memory attributes of the LPI
+/// tables in `GICR_PROPBASER` and `GICR_PENDBASER`.
+const GICR_BASER_ATTRS: u64 = (0b01 << 10) | (0b111 << 7);
+
+/// The interrupt routing mode bit of `ICC_SGI1R_EL1`: to all CPUs except self.
+const ICC_SGI1R_IRM: u64 = 1 << 40;
+
+unsafe fn read32(addr: usize) -> u32 {
+    (addr as *const u32).read_volatile()
+}
+
+unsafe fn write32(addr: usize, value: u32) {
+    (addr as *mut u32).write_volatile(value)
+}
+
+unsafe fn read64(addr: usize) -> u64 {
+    (addr as *const u64).read_volatile()
+}
+
+unsafe fn write64(addr: usize, value: u64) {
+    (addr as *mut u64).write_volatile(value)
+}
+
+/// Returns the affinity fields (Aff3.Aff2.Aff1.Aff0) of `MPIDR_EL1`, which
+/// has Aff3 at bits 39:32.
+const fn mpidr_affinity(mpidr: u64) -> u64 {
+    (mpidr & 0xff_ffff) | ((mpidr >> 8) & 0xff00_0000)
+}
+
+/// Returns the affinity fields (Aff3.Aff2.Aff1.Aff0) of the current CPU.
+fn current_affinity() -> u64 {
+    let mpidr: u64;
+    unsafe { asm!("mrs {}, mpidr_el1", out(reg) mpidr) };
+    mpidr_affinity(mpidr)
+}
+
+/// Returns the `GICD_IROUTER` value to route an SPI to the CPU with the given
+/// affinity (Aff3.Aff2.Aff1.Aff0).
+const fn irouter(affinity: u64) -> u64 {
+    (affinity & 0xff_ffff) | ((affinity & 0xff00_0000) << 8)
+}
+
+/// Returns the `ICC_SGI1R_EL1` value to send the SGI to the CPU with the
+/// given affinity (Aff3.Aff2.Aff1.Aff0).
+const fn sgi1r(affinity: u64, sgi: usize) -> u64 {
+    let aff0 = affinity & 0xff;
+    let aff1 = (affinity >> 8) & 0xff;
+    let aff2 = (affinity >> 16) & 0xff;
+    let aff3 = (affinity >> 24) & 0xff;
+    // The target list covers Aff0 values 16 * RS .. 16 * RS + 15.
+    let range_selector = aff0 >> 4;
+    (aff3 << 48)
+        | (range_selector << 44)
+        | (aff2 << 32)
+        | ((sgi as u64 & 0xf) << 24)
+        | (aff1 << 16)
+        | (1 << (aff0 & 0xf))
+}
+
+/// Returns the size of the frames of a redistributor with the given
+/// `GICR_TYPER`.
+const fn redistributor_stride(typer: u64) -> usize {
+    if typer & GICR_TYPER_VLPIS != 0 {
+        GICR_STRIDE_VLPIS
+    } else {
+        GICR_STRIDE
+    }
+}
+
+/// The GICv3 distributor, which handles the SPIs.
+pub struct GicDistributor {
+    base: usize,
+    max_irqs: usize,
+}
+
+impl GicDistributor {
+    /// Constructs the distributor from its base virtual address.
+    pub const fn new(base: *mut u8) -> Self {
+        Self {
+            base: base as usize,
+            max_irqs: MAX_IRQS,
+        }
+    }
+
+    fn wait_for_rwp(&self) {
+        while unsafe { read32(self.base + GICD_CTLR) } & GICD_CTLR_RWP != 0 {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Initializes the distributor: all SPIs are disabled, level-sensitive,
+    /// in group 1 and routed to the current CPU.
+    pub fn init(&mut self) {
+        let base = self.base;
+        unsafe {
+            write32(base + GICD_CTLR, 0);
+            self.wait_for_rwp();
+
+            let lines = (read32(base + GICD_TYPER) & 0x1f) as usize + 1;
+            self.max_irqs = (lines * 32).min(MAX_IRQS);
+            for i in (SPI_BASE..self.max_irqs).step_by(32) {
+                write32(base + GICD_IGROUPR + i / 8, u32::MAX);
+                write32(base + GICD_ICENABLER + i / 8, u32::MAX);
+                write32(base + GICD_ICPENDR + i / 8, u32::MAX);
+            }
+            for i in (SPI_BASE..self.max_irqs).step_by(16) {
+                write32(base + GICD_ICFGR + i / 4, 0);
+            }
+            for i in SPI_BASE..self.max_irqs {
+                ((base + GICD_IPRIORITYR + i) as *mut u8).write_volatile(DEFAULT_PRIORITY);
+                write64(base + GICD_IROUTER + i * 8, irouter(current_affinity()));
+            }
+            self.wait_for_rwp();
+
+            write32(
+                base + GICD_CTLR,
+                GICD_CTLR_ARE | GICD_CTLR_ENABLE_G1A | GICD_CTLR_ENABLE_G1,
+            );
+            self.wait_for_rwp();
+        }
+    }
+
+    /// Returns the number of interrupt ID bits supported (`GICD_TYPER.IDbits`).
+    pub fn id_bits(&self) -> u32 {
+        ((unsafe { read32(self.base + GICD_TYPER) } >> 19) & 0x1f) + 1
+    }
+
+    /// Enables or disables the given SPI.
+    pub fn set_enable(&mut self, irq: usize, enabled: bool) {
+        if !(SPI_BASE..self.max_irqs).contains(&irq) {
+            return;
+        }
+        let reg = if enabled {
+            GICD_ISENABLER
+        } else {
+            GICD_ICENABLER
+        };
+        unsafe { write32(self.base + reg + irq / 32 * 4, 1 << (irq % 32)) };
+        if !enabled {
+            self.wait_for_rwp();
+        }
+    }
+}
+
+/// The GICv3 redistributor of a CPU, which handles its SGIs and PPIs.
+#[derive(Clone, Copy)]
+pub struct GicRedistributor {
+    base: usize,
+}
+
+impl GicRedistributor {
+    /// Constructs the redistributor from its base virtual address.
+    pub const fn new(base: usize) -> Self {
+        Self { base }
+    }
+
+    /// Returns the base virtual address of the redistributor.
+    pub const fn base(&self) -> usize {
+        self.base
+    }
+
+    /// Finds the redistributor of the current CPU in the redistributor region
+    /// at `base` (virtual address) with `size` bytes.
+    pub fn find_current(base: usize, size: usize) -> Option<Self> {
+        let affinity = current_affinity();
+        let mut rd = base;
+        while rd < base + size {
+            let typer = unsafe { read64(rd + GICR_TYPER) };
+            if typer >> 32 == affinity {
+                return Some(Self { base: rd });
+            }
+            if typer & GICR_TYPER_LAST != 0 {
+                break;
+            }
+            rd += redistributor_stride(typer);
+        }
+        None
+    }
+
+    fn wait_for_rwp(&self) {
+        while unsafe { read32(self.base + GICR_CTLR) } & GICR_CTLR_RWP != 0 {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Wakes up the redistributor, and initializes the SGIs and PPIs: all are
+    /// disabled and in group 1.
+    pub fn init(&self) {
+        let sgi = self.base + GICR_SGI_BASE;
+        unsafe {
+            let waker = read32(self.base + GICR_WAKER);
+            write32(self.base + GICR_WAKER, waker & !GICR_WAKER_PROCESSOR_SLEEP);
+            while read32(self.base + GICR_WAKER) & GICR_WAKER_CHILDREN_ASLEEP != 0 {
+                core::hint::spin_loop();
+            }
+
+            write32(sgi + GICD_IGROUPR, u32::MAX);
+            write32(sgi + GICD_ICENABLER, u32::MAX);
+            self.wait_for_rwp();
+            for i in 0..SPI_BASE {
+                ((sgi + GICD_IPRIORITYR + i) as *mut u8).write_volatile(DEFAULT_PRIORITY);
+            }
+        }
+    }
+
+    /// Returns whether the redistributor supports LPIs.
+    pub fn supports_lpis(&self) -> bool {
+        unsafe { read64(self.base + GICR_TYPER) & GICR_TYPER_PLPIS != 0 }
+    }
+
+    /// Returns the processor number of the redistributor (`GICR_TYPER`),
+    /// which identifies it in ITS commands if `GITS_TYPER.PTA` is 0.
+    pub fn processor_number(&self) -> u64 {
+        (unsafe { read64(self.base + GICR_TYPER) } >> 8) & 0xffff
+    }
+
+    /// Enables LPIs with the LPI configuration table at `prop_paddr` (shared
+    /// by all redistributors), which covers interrupt IDs with `id_bits`
+    /// bits, and the LPI pending table of this redistributor at `pend_paddr`.
+    pub fn enable_lpis(&self, prop_paddr: usize, id_bits: u32, pend_paddr: usize) {
+        unsafe {
+            write64(
+                self.base + GICR_PROPBASER,
+                prop_paddr as u64 | GICR_BASER_ATTRS | (id_bits - 1) as u64,
+            );
+            write64(
+                self.base + GICR_PENDBASER,
+                pend_paddr as u64 | GICR_BASER_ATTRS,
+            );
+            let ctlr = read32(self.base + GICR_CTLR);
+            write32(self.base + GICR_CTLR, ctlr | GICR_CTLR_ENABLE_LPIS);
+        }
+    }
+
+    /// Enables or disables the given SGI or PPI.
+    pub fn set_enable(&self, irq: usize, enabled: bool) {
+        if irq >= SPI_BASE {
+            return;
+        }
+        let sgi = self.base + GICR_SGI_BASE;
+        let reg = if enabled {
+            GICD_ISENABLER
+        } else {
+            GICD_ICENABLER
+        };
+        unsafe { write32(sgi + reg, 1 << irq) };
+        if !enabled {
+            self.wait_for_rwp();
+        }
+    }
+}
+
+/// Enables the system register interface of the CPU interface, and accepts
+/// the group 1 interrupts of all priorities.
+pub fn init_cpu_interface() {
+    unsafe {
+        // ICC_SRE_EL1: SRE, DFB, DIB
+        asm!("msr S3_0_C12_C12_5, {}", "isb", in(reg) 0x7u64);
+        // ICC_PMR_EL1: the lowest priority mask
+        asm!("msr S3_0_C4_C6_0, {}", in(reg) 0xffu64);
+        // ICC_BPR1_EL1: no preemption grouping
+        asm!("msr S3_0_C12_C12_3, {}", in(reg) 0u64);
+        // ICC_CTLR_EL1: EOI both drops the priority and deactivates
+        asm!("msr S3_0_C12_C12_4, {}", in(reg) 0u64);
+        // ICC_IGRPEN1_EL1: enable group 1 interrupts
+        asm!("msr S3_0_C12_C12_7, {}", "isb", in(reg) 1u64);
+    }
+}
+
+/// Acknowledges the pending interrupt, calls `f` with the interrupt ID, and
+/// ends the interrupt. Does nothing if the interrupt is spurious.
+pub fn handle_irq<F: FnOnce(u32)>(f: F) {
+    let iar: u64;
+    // ICC_IAR1_EL1
+    unsafe { asm!("mrs {}, S3_0_C12_C12_0", out(reg) iar) };
+    let irq = (iar & 0xff_ffff) as u32;
+    if (1020..1024).contains(&irq) {
+        return;
+    }
+    f(irq);
+    // ICC_EOIR1_EL1
+    unsafe { asm!("msr S3_0_C12_C12_1, {}", in(reg) iar) };
+}
+
+fn write_sgi1r(value: u64) {
+    // ICC_SGI1R_EL1
+    unsafe { asm!("msr S3_0_C12_C11_5, {}", "isb", in(reg) value) };
+}
+
+/// Sends the SGI to the CPU with the given affinity (Aff3.Aff2.Aff1.Aff0, as
+/// in `MPIDR_EL1`).
+pub fn send_sgi(affinity: u64, sgi: usize) {
+    write_sgi1r(sgi1r(affinity, sgi));
+}
+
+/// Sends the SGI to all CPUs except the current one.
+pub fn send_sgi_all_except_self(sgi: usize) {
+    write_sgi1r(ICC_SGI1R_IRM | ((sgi as u64 & 0xf) << 24));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mpidr_affinity() {
+        // Aff3 is moved from bits 39:32, and the MT and U bits are dropped.
+        assert_eq!(mpidr_affinity(0x8000_0000), 0);
+        assert_eq!(mpidr_affinity(0x0000_0001_0102_0304), 0x0102_0304);
+        assert_eq!(mpidr_affinity(0x0000_00ab_c1ff_ffff), 0xab_ff_ff_ff);
+    }
+
+    #[test]
+    fn test_irouter() {
+        // Aff3 goes to bits 39:32, the routing mode (bit 31) is 0.
+        assert_eq!(irouter(0), 0);
+        assert_eq!(irouter(0x0001_0203), 0x0001_0203);
+        assert_eq!(irouter(0x0401_0203), 0x04_0001_0203);
+        let mpidr = 0x0000_0004_8001_0203;
+        assert_eq!(irouter(mpidr_affinity(mpidr)), mpidr & 0xff_00ff_ffff);
+    }
+
+    #[test]
+    fn test_sgi1r() {
+        assert_eq!(sgi1r(0, 0), 1);
+        assert_eq!(sgi1r(0, 5), 5 << 24 | 1);
+        // Only the low 4 bits of the SGI number are used.
+        assert_eq!(sgi1r(0, 0x13), 3 << 24 | 1);
+        // Aff0 selects the range selector and the bit in the target list.
+        assert_eq!(sgi1r(0x13, 0), 1 << 44 | 1 << 3);
+        assert_eq!(sgi1r(0xff, 0), 0xf << 44 | 1 << 15);
+        // Aff1, Aff2 and Aff3.
+        assert_eq!(
+            sgi1r(0x0403_0201, 1),
+            4 << 48 | 3 << 32 | 1 << 24 | 2 << 16 | 1 << 1
+        );
+    }
+
+    #[test]
+    fn test_redistributor_stride() {
+        assert_eq!(redistributor_stride(0), GICR_STRIDE);
+        assert_eq!(
+            redistributor_stride(GICR_TYPER_LAST | GICR_TYPER_PLPIS),
+            GICR_STRIDE
+        );
+        assert_eq!(redistributor_stride(GICR_TYPER_VLPIS), GICR_STRIDE_VLPIS);
+    }
+}
@@ -0,0 +1,350 @@
+//! ARM GICv3 Interrupt Translation Service (ITS).
+//!
+//! The ITS translates the message signaled interrupts (MSIs) written by
+//! devices to `GITS_TRANSLATER` into LPIs, by the device ID (the PCI
+//! requester ID) and the event ID (the written data). All LPIs are in a
+//! single collection, which is mapped to the redistributor of the primary CPU.
+//!
+//! The ITS tables and the LPI tables are statically allocated, so only a few
+//! devices and LPIs are supported.
+//!
+//! See the Arm GIC Architecture Specification (IHI 0069).
+
+use core::arch::asm;
+use core::ptr::{addr_of, addr_of_mut};
+
+use super::gicv3::GicRedistributor;
+use crate::irq::MsiMessage;
+use crate::mem::{virt_to_phys, VirtAddr};
+
+/// The first LPI number.
+pub const LPI_BASE: usize = 8192;
+/// The number of LPIs that can be allocated.
+pub const MAX_LPIS: usize = 64;
+/// The number of interrupt ID bits covered by the LPI tables, which is the
+/// minimum for LPIs (interrupt IDs 8192..16383).
+pub const LPI_ID_BITS: u32 = 14;
+
+/// The priority of all LPIs, the same as the other interrupts.
+const LPI_PRIORITY: u8 = 0xa0;
+/// The enable bit of the LPI configuration table entries.
+const LPI_ENABLE: u8 = 1 << 0;
+
+const PROP_TABLE_SIZE: usize = (1 << LPI_ID_BITS) - LPI_BASE;
+const PEND_TABLE_SIZE: usize = (1 << LPI_ID_BITS) / 8;
+
+/// The maximum number of devices with MSIs.
+const MAX_DEVICES: usize = 16;
+/// The number of event ID bits of each device.
+const EVENT_ID_BITS: u32 = 5;
+const EVENTS_PER_DEVICE: usize = 1 << EVENT_ID_BITS;
+/// The size of the interrupt translation table (ITT) of each device, with the
+/// maximum ITT entry size of 16 bytes.
+const ITT_SIZE: usize = EVENTS_PER_DEVICE * 16;
+
+const DEVICE_TABLE_SIZE: usize = 0x1_0000;
+const COLLECTION_TABLE_SIZE: usize = 0x1000;
+const CMD_QUEUE_SIZE: usize = 0x1000;
+const CMD_SIZE: usize = 32;
+
+const GITS_CTLR: usize = 0x0000;
+const GITS_TYPER: usize = 0x0008;
+const GITS_CBASER: usize = 0x0080;
+const GITS_CWRITER: usize = 0x0088;
+const GITS_CREADR: usize = 0x0090;
+const GITS_BASER: usize = 0x0100;
+const GITS_TRANSLATER: usize = 0x1_0040;
+
+const GITS_CTLR_ENABLED: u32 = 1 << 0;
+const GITS_CTLR_QUIESCENT: u32 = 1 << 31;
+const GITS_TYPER_PHYSICAL: u64 = 1 << 0;
+/// Target addresses in commands are physical addresses of redistributors,
+/// instead of processor numbers.
+const GITS_TYPER_PTA: u64 = 1 << 19;
+const GITS_BASER_VALID: u64 = 1 << 63;
+const GITS_BASER_TYPE_DEVICE: u64 = 1;
+const GITS_BASER_TYPE_COLLECTION: u64 = 4;
+/// Inner-shareable, normal inner write-back memory attributes of the ITS
+/// tables in `GITS_CBASER` and `GITS_BASER<n>`.
+const GITS_BASER_ATTRS: u64 = (0b111 << 59) | (0b01 << 10);
+
+const CMD_SYNC: u64 = 0x05;
+const CMD_MAPD: u64 = 0x08;
+const CMD_MAPC: u64 = 0x09;
+const CMD_MAPTI: u64 = 0x0a;
+const CMD_INV: u64 = 0x0c;
+const CMD_DISCARD: u64 = 0x0f;
+const CMD_VALID: u64 = 1 << 63;
+
+/// The ID of the only collection.
+const ICID: u64 = 0;
+
+#[repr(C, align(65536))]
+struct Align64K<T>(T);
+
+#[repr(C, align(256))]
+struct Align256<T>(T);
+
+const ZERO_PEND_TABLE: Align64K<[u8; PEND_TABLE_SIZE]> = Align64K([0; PEND_TABLE_SIZE]);
+const ZERO_ITT: Align256<[u8; ITT_SIZE]> = Align256([0; ITT_SIZE]);
+
+static mut PROP_TABLE: Align64K<[u8; PROP_TABLE_SIZE]> = Align64K([0; PROP_TABLE_SIZE]);
+static mut PEND_TABLES: [Align64K<[u8; PEND_TABLE_SIZE]>; axconfig::SMP] =
+    [ZERO_PEND_TABLE; axconfig::SMP];
+static mut DEVICE_TABLE: Align64K<[u8; DEVICE_TABLE_SIZE]> = Align64K([0; DEVICE_TABLE_SIZE]);
+static mut COLLECTION_TABLE: Align64K<[u8; COLLECTION_TABLE_SIZE]> =
+    Align64K([0; COLLECTION_TABLE_SIZE]);
+static mut CMD_QUEUE: Align64K<[u64; CMD_QUEUE_SIZE / 8]> = Align64K([0; CMD_QUEUE_SIZE / 8]);
+static mut ITTS: [Align256<[u8; ITT_SIZE]>; MAX_DEVICES] = [ZERO_ITT; MAX_DEVICES];
+
+unsafe fn read32(addr: usize) -> u32 {
+    (addr as *const u32).read_volatile()
+}
+
+unsafe fn write32(addr: usize, value: u32) {
+    (addr as *mut u32).write_volatile(value)
+}
+
+unsafe fn read64(addr: usize) -> u64 {
+    (addr as *const u64).read_volatile()
+}
+
+unsafe fn write64(addr: usize, value: u64) {
+    (addr as *mut u64).write_volatile(value)
+}
+
+fn paddr_of<T>(ptr: *const T) -> u64 {
+    virt_to_phys(VirtAddr::from(ptr as usize)).as_usize() as u64
+}
+
+/// Makes the writes to the tables in memory visible to the GIC.
+fn sync_tables() {
+    unsafe { asm!("dsb ishst") };
+}
+
+/// Enables LPIs on the redistributor of the current CPU, with the shared LPI
+/// configuration table and a pending table of the CPU.
+pub fn enable_lpis(gicr: &GicRedistributor) {
+    let pend_table = unsafe { addr_of!(PEND_TABLES[crate::cpu::this_cpu_id()]) };
+    gicr.enable_lpis(
+        paddr_of(unsafe { addr_of!(PROP_TABLE) }) as usize,
+        LPI_ID_BITS,
+        paddr_of(pend_table) as usize,
+    );
+}
+
+/// A device with MSIs, and the number of its allocated events.
+#[derive(Clone, Copy)]
+struct ItsDevice {
+    device_id: u32,
+    num_events: usize,
+}
+
+/// The ITS.
+pub struct Its {
+    base: usize,
+    paddr: usize,
+    /// The target redistributor (`RDbase`) in commands.
+    target: u64,
+    /// The offset in the command queue to write the next command.
+    cmd_write: usize,
+    max_device_id: u32,
+    devices: [Option<ItsDevice>; MAX_DEVICES],
+    /// The device ID and the event ID of each allocated LPI.
+    lpis: [Option<(u32, u32)>; MAX_LPIS],
+}
+
+impl Its {
+    /// Initializes the ITS with registers at `base` (virtual address) and
+    /// `paddr`, and maps the collection to the redistributor `gicr` (of the
+    /// current CPU).
+    ///
+    /// Returns [`None`] if the ITS does not support physical LPIs.
+    pub fn new(base: usize, paddr: usize, gicr: &GicRedistributor) -> Option<Self> {
+        unsafe {
+            write32(base + GITS_CTLR, 0);
+            while read32(base + GITS_CTLR) & GITS_CTLR_QUIESCENT == 0 {
+                core::hint::spin_loop();
+            }
+        }
+        let typer = unsafe { read64(base + GITS_TYPER) };
+        if typer & GITS_TYPER_PHYSICAL == 0 || !gicr.supports_lpis() {
+            return None;
+        }
+        let dev_bits = ((typer >> 13) & 0x1f) + 1;
+        let target = if typer & GITS_TYPER_PTA != 0 {
+            paddr_of(gicr.base() as *const u8)
+        } else {
+            gicr.processor_number() << 16
+        };
+
+        let mut max_devices: u64 = 1 << dev_bits;
+        unsafe {
+            write64(
+                base + GITS_CBASER,
+                GITS_BASER_VALID
+                    | GITS_BASER_ATTRS
+                    | paddr_of(addr_of!(CMD_QUEUE))
+                    | (CMD_QUEUE_SIZE / 0x1000 - 1) as u64,
+            );
+            write64(base + GITS_CWRITER, 0);
+
+            for n in 0..8 {
+                let reg = base + GITS_BASER + n * 8;
+                let baser = read64(reg);
+                let entry_size = ((baser >> 48) & 0x1f) as usize + 1;
+                let (table, size) = match (baser >> 56) & 0x7 {
+                    GITS_BASER_TYPE_DEVICE => {
+                        max_devices = max_devices.min((DEVICE_TABLE_SIZE / entry_size) as u64);
+                        (paddr_of(addr_of!(DEVICE_TABLE)), DEVICE_TABLE_SIZE)
+                    }
+                    GITS_BASER_TYPE_COLLECTION => {
+                        (paddr_of(addr_of!(COLLECTION_TABLE)), COLLECTION_TABLE_SIZE)
+                    }
+                    _ => continue,
+                };
+                // Keep the read-only type and entry size, with 4 KiB pages
+                // and a flat table.
+                write64(
+                    reg,
+                    GITS_BASER_VALID
+                        | GITS_BASER_ATTRS
+                        | (baser & ((0x7 << 56) | (0x1f << 48)))
+                        | table
+                        | (size / 0x1000 - 1) as u64,
+                );
+            }
+            sync_tables();
+            write32(base + GITS_CTLR, GITS_CTLR_ENABLED);
+        }
+
+        let mut its = Self {
+            base,
+            paddr,
+            target,
+            cmd_write: 0,
+            max_device_id: (max_devices - 1) as u32,
+            devices: [None; MAX_DEVICES],
+            lpis: [None; MAX_LPIS],
+        };
+        its.send([CMD_MAPC, 0, CMD_VALID | target | ICID, 0]);
+        its.sync();
+        Some(its)
+    }
+
+    /// Writes a command to the command queue, and waits until the ITS has
+    /// consumed it.
+    fn send(&mut self, cmd: [u64; 4]) {
+        let queue = unsafe { addr_of_mut!(CMD_QUEUE.0) } as *mut [u64; 4];
+        unsafe { queue.add(self.cmd_write / CMD_SIZE).write_volatile(cmd) };
+        self.cmd_write = (self.cmd_write + CMD_SIZE) % CMD_QUEUE_SIZE;
+        sync_tables();
+        unsafe {
+            write64(self.base + GITS_CWRITER, self.cmd_write as u64);
+            while read64(self.base + GITS_CREADR) as usize & 0xf_ffe0 != self.cmd_write {
+                core::hint::spin_loop();
+            }
+        }
+    }
+
+    fn sync(&mut self) {
+        self.send([CMD_SYNC, 0, self.target, 0]);
+    }
+
+    /// Returns the index of the device in `self.devices`, and maps the device
+    /// to a new ITT if it is not mapped yet.
+    fn device_index(&mut self, device_id: u32) -> Option<usize> {
+        if let Some(idx) = self
+            .devices
+            .iter()
+            .position(|dev| dev.is_some_and(|dev| dev.device_id == device_id))
+        {
+            return Some(idx);
+        }
+        let idx = self.devices.iter().position(Option::is_none)?;
+        let itt = paddr_of(unsafe { addr_of!(ITTS[idx]) });
+        self.send([
+            CMD_MAPD | ((device_id as u64) << 32),
+            (EVENT_ID_BITS - 1) as u64,
+            CMD_VALID | itt,
+            0,
+        ]);
+        self.devices[idx] = Some(ItsDevice {
+            device_id,
+            num_events: 0,
+        });
+        Some(idx)
+    }
+
+    /// Allocates an LPI for a new event of the device, returns the LPI number
+    /// and the MSI message of the event. The LPI is disabled.
+    ///
+    /// The event IDs of a device are not reused after they are freed.
+    pub fn alloc_msi(&mut self, device_id: u32) -> Option<(usize, MsiMessage)> {
+        let slot = self.lpis.iter().position(Option::is_none)?;
+        if device_id > self.max_device_id {
+            warn!("ITS: device ID {:#x} is out of range", device_id);
+            return None;
+        }
+        let idx = self.device_index(device_id)?;
+        let dev = self.devices[idx].as_mut().unwrap();
+        if dev.num_events >= EVENTS_PER_DEVICE {
+            return None;
+        }
+        let event_id = dev.num_events as u32;
+        dev.num_events += 1;
+
+        let lpi = LPI_BASE + slot;
+        self.lpis[slot] = Some((device_id, event_id));
+        unsafe { addr_of_mut!(PROP_TABLE.0[lpi - LPI_BASE]).write_volatile(LPI_PRIORITY) };
+        sync_tables();
+        self.send([
+            CMD_MAPTI | ((device_id as u64) << 32),
+            event_id as u64 | ((lpi as u64) << 32),
+            ICID,
+            0,
+        ]);
+        self.sync();
+
+        let msg = MsiMessage {
+            address: (self.paddr + GITS_TRANSLATER) as u64,
+            data: event_id,
+        };
+        Some((lpi, msg))
+    }
+
+    /// Enables or disables the given LPI, which must be allocated.
+    pub fn set_enable(&mut self, lpi: usize, enabled: bool) {
+        let idx = lpi - LPI_BASE;
+        let Some(&Some((device_id, event_id))) = self.lpis.get(idx) else {
+            return;
+        };
+        let config = if enabled {
+            LPI_PRIORITY | LPI_ENABLE
+        } else {
+            LPI_PRIORITY
+        };
+        unsafe { addr_of_mut!(PROP_TABLE.0[idx]).write_volatile(config) };
+        sync_tables();
+        self.send([CMD_INV | ((device_id as u64) << 32), event_id as u64, 0, 0]);
+        self.sync();
+    }
+
+    /// Frees an LPI allocated by [`Its::alloc_msi`]: the mapping of its event
+    /// is discarded, and the LPI is disabled.
+    pub fn free_msi(&mut self, lpi: usize) {
+        let idx = lpi - LPI_BASE;
+        let Some(Some((device_id, event_id))) = self.lpis.get_mut(idx).map(Option::take) else {
+            return;
+        };
+        self.send([
+            CMD_DISCARD | ((device_id as u64) << 32),
+            event_id as u64,
+            0,
+            0,
+        ]);
+        self.sync();
+        unsafe { addr_of_mut!(PROP_TABLE.0[idx]).write_volatile(LPI_PRIORITY) };
+        sync_tables();
+    }
+}
@@ -6,6 +6,10 @@ pub mod psci;
 
 #[cfg(feature = "irq")]
 pub mod gic;
+#[cfg(feature = "irq")]
+mod gicv3;
+#[cfg(feature = "irq")]
+mod its;
 
 #[cfg(not(platform_family = "aarch64-bsta1000b"))]
 pub mod pl011;
@@ -6,6 +6,7 @@ use memory_addr::PhysAddr;
 
 use crate::mem::phys_to_virt;
 
+/// The default UART base address, if it is not found in the device tree.
 const UART_BASE: PhysAddr = pa!(axconfig::UART_PADDR);
 
 static UART: SpinNoIrq<Pl011Uart> =
@@ -30,22 +31,30 @@ pub fn getchar() -> Option<u8> {
 
 /// Initialize the UART
 pub fn init_early() {
-    UART.lock().init();
+    let mut uart = UART.lock();
+    if let Some(base) = crate::dtb::uart_paddr() {
+        *uart = Pl011Uart::new(phys_to_virt(base).as_mut_ptr());
+    }
+    uart.init();
 }
 
-/// Set UART IRQ Enable
+/// Registers the UART IRQ handler, for the receive interrupt enabled by
+/// [`init_early`].
 pub fn init() {
     #[cfg(feature = "irq")]
-    crate::irq::set_enable(crate::platform::irq::UART_IRQ_NUM, true);
+    crate::console::register_input_irq(crate::platform::irq::UART_IRQ_NUM, handle);
 }
 
 /// UART IRQ Handler
-pub fn handle() {
-    let is_receive_interrupt = UART.lock().is_receive_interrupt();
-    UART.lock().ack_interrupts();
+#[cfg(feature = "irq")]
+fn handle() {
+    let is_receive_interrupt = {
+        let mut uart = UART.lock();
+        let is_receive_interrupt = uart.is_receive_interrupt();
+        uart.ack_interrupts();
+        is_receive_interrupt
+    };
     if is_receive_interrupt {
-        while let Some(c) = getchar() {
-            putchar(c);
-        }
+        crate::console::handle_input_irq();
     }
 }
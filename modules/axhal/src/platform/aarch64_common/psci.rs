@@ -101,6 +101,16 @@ pub fn system_off() -> ! {
     }
 }
 
+/// Reboots the whole system.
+pub fn system_reset() -> ! {
+    info!("Rebooting...");
+    psci_call(PSCI_0_2_FN_SYSTEM_RESET, 0, 0, 0).ok();
+    warn!("It should reboot!");
+    loop {
+        crate::arch::halt();
+    }
+}
+
 /// Power up a core. This call is used to power up cores that either:
 ///
 /// * Have not yet been booted into the calling supervisory software.
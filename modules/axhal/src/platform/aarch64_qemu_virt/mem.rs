@@ -1,9 +1,11 @@
 use crate::mem::MemRegion;
 use page_table_entry::{aarch64::A64PTE, GenericPTE, MappingFlags};
 
-/// Returns platform-specific memory regions.
+/// Returns platform-specific memory regions, from the device tree if any.
 pub(crate) fn platform_regions() -> impl Iterator<Item = MemRegion> {
-    crate::mem::default_free_regions().chain(crate::mem::default_mmio_regions())
+    crate::mem::dtb_free_regions()
+        .chain(crate::mem::boot_module_regions())
+        .chain(crate::mem::dtb_mmio_regions())
 }
 
 pub(crate) unsafe fn init_boot_page_table(
@@ -20,10 +22,13 @@ pub(crate) unsafe fn init_boot_page_table(
         MappingFlags::READ | MappingFlags::WRITE | MappingFlags::DEVICE,
         true,
     );
-    // 0x0000_4000_0000..0x0000_8000_0000, 1G block, normal memory
-    boot_pt_l1[1] = A64PTE::new_page(
-        pa!(0x4000_0000),
-        MappingFlags::READ | MappingFlags::WRITE | MappingFlags::EXECUTE,
-        true,
-    );
+    // 0x0000_4000_0000..0x0040_0000_0000, 1G blocks, normal memory (the RAM
+    // below the high PCIe region, all accessible before paging is initialized)
+    for (i, pte) in boot_pt_l1.iter_mut().enumerate().take(0x100).skip(1) {
+        *pte = A64PTE::new_page(
+            pa!(i << 30),
+            MappingFlags::READ | MappingFlags::WRITE | MappingFlags::EXECUTE,
+            true,
+        );
+    }
 }
@@ -17,7 +17,9 @@ pub mod time {
 }
 
 pub mod misc {
-    pub use crate::platform::aarch64_common::psci::system_off as terminate;
+    pub use crate::platform::aarch64_common::psci::{
+        system_off as poweroff, system_off as terminate, system_reset as reboot,
+    };
 }
 
 extern "C" {
@@ -29,6 +31,7 @@ extern "C" {
 pub(crate) unsafe extern "C" fn rust_entry(cpu_id: usize, dtb: usize) {
     crate::mem::clear_bss();
     crate::cpu::init_primary(cpu_id);
+    crate::dtb::init(dtb);
     super::aarch64_common::pl011::init_early();
     super::aarch64_common::generic_timer::init_early();
     rust_main(cpu_id, dtb);
@@ -17,12 +17,40 @@ pub mod time {
 }
 
 pub mod misc {
-    pub fn terminate() -> ! {
+    use crate::mem::phys_to_virt;
+
+    const PM_RSTC: usize = 0x1c;
+    const PM_WDOG: usize = 0x24;
+    const PM_PASSWORD: u32 = 0x5a00_0000;
+    const PM_RSTC_WRCFG_FULL_RESET: u32 = 0x20;
+    /// The watchdog timeout in ticks (about 16us each).
+    const PM_WDOG_TIMEOUT: u32 = 10;
+
+    /// Halts all CPUs, as the board cannot be powered off by software.
+    pub fn poweroff() -> ! {
         info!("Shutting down...");
         loop {
             crate::arch::halt();
         }
     }
+
+    /// Reboots the board, with the watchdog of the power management block.
+    pub fn reboot() -> ! {
+        info!("Rebooting...");
+        let base = phys_to_virt(pa!(axconfig::PM_PADDR)).as_usize();
+        unsafe {
+            ((base + PM_WDOG) as *mut u32).write_volatile(PM_PASSWORD | PM_WDOG_TIMEOUT);
+            ((base + PM_RSTC) as *mut u32).write_volatile(PM_PASSWORD | PM_RSTC_WRCFG_FULL_RESET);
+        }
+        loop {
+            crate::arch::halt();
+        }
+    }
+
+    /// Shutdown the whole system, including all CPUs.
+    pub fn terminate() -> ! {
+        poweroff()
+    }
 }
 
 extern "C" {
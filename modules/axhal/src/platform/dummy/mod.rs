@@ -18,6 +18,16 @@ pub mod misc {
     pub fn terminate() -> ! {
         unimplemented!()
     }
+
+    /// Powers off the whole system, including all CPUs.
+    pub fn poweroff() -> ! {
+        unimplemented!()
+    }
+
+    /// Reboots the whole system.
+    pub fn reboot() -> ! {
+        unimplemented!()
+    }
 }
 
 #[cfg(feature = "smp")]
@@ -68,6 +78,12 @@ pub mod irq {
     /// The timer IRQ number.
     pub const TIMER_IRQ_NUM: usize = 0;
 
+    /// The inter-processor interrupt (IPI) number.
+    pub const IPI_IRQ_NUM: usize = 1;
+
+    /// The performance counter overflow IRQ number.
+    pub const PMU_IRQ_NUM: usize = 2;
+
     /// Enables or disables the given IRQ.
     pub fn set_enable(irq_num: usize, enabled: bool) {}
 
@@ -76,12 +92,32 @@ pub mod irq {
         false
     }
 
+    /// Allocates an IRQ for an MSI of the PCI device with the given requester
+    /// ID, returns the IRQ number and the message that the device should
+    /// write.
+    pub fn alloc_msi_irq(device_id: u32) -> Option<(usize, crate::irq::MsiMessage)> {
+        None
+    }
+
+    /// Frees an IRQ allocated by [`alloc_msi_irq`], whose handler must not be
+    /// registered.
+    pub fn free_msi_irq(irq_num: usize) {}
+
     /// Dispatches the IRQ.
     ///
     /// This function is called by the common interrupt handler. It looks
     /// up in the IRQ handler table and calls the corresponding handler. If
     /// necessary, it also acknowledges the interrupt controller after handling.
     pub fn dispatch_irq(irq_num: usize) {}
+
+    /// Enables the performance counter overflow interrupt on the current CPU.
+    pub fn enable_pmu_irq() {}
+
+    /// Sends an inter-processor interrupt to the given CPU.
+    pub fn send_ipi(cpu_id: usize) {}
+
+    /// Sends an inter-processor interrupt to all CPUs except the current one.
+    pub fn send_ipi_all_others() {}
 }
 
 /// Initializes the platform devices for the primary CPU.
@@ -0,0 +1,199 @@
+//! RISC-V Advanced Platform-Level Interrupt Controller (APLIC).
+//!
+//! Only the supervisor-level interrupt domain is used, to which the firmware
+//! delegates the wired interrupt sources. All sources are level-triggered
+//! (active high) on QEMU virt.
+//!
+//! In direct delivery mode, the interrupts are signaled to the harts by the
+//! interrupt delivery control (IDC) structure of each hart, where they are
+//! claimed. In MSI delivery mode, they are forwarded as MSIs to the IMSIC of
+//! the target hart, with the source number as the interrupt identity.
+//!
+//! See The RISC-V Advanced Interrupt Architecture, chapter 4.
+
+const APLIC_DOMAINCFG: usize = 0x0000;
+const APLIC_SOURCECFG: usize = 0x0004;
+const APLIC_SETIENUM: usize = 0x1edc;
+const APLIC_CLRIE: usize = 0x1f00;
+const APLIC_CLRIENUM: usize = 0x1fdc;
+const APLIC_TARGET: usize = 0x3004;
+const APLIC_IDC: usize = 0x4000;
+const APLIC_IDC_STRIDE: usize = 0x20;
+
+const IDC_IDELIVERY: usize = 0x00;
+const IDC_ITHRESHOLD: usize = 0x08;
+const IDC_CLAIMI: usize = 0x1c;
+
+/// Interrupt enable.
+const DOMAINCFG_IE: u32 = 1 << 8;
+/// Delivery mode: MSI.
+const DOMAINCFG_DM: u32 = 1 << 2;
+
+/// The source mode of `sourcecfg`: level-sensitive, active high.
+const SOURCECFG_SM_LEVEL1: u32 = 6;
+
+const TARGET_HART_SHIFT: u32 = 18;
+/// The priority of all sources in direct delivery mode (0 is invalid).
+const TARGET_IPRIO: u32 = 1;
+
+unsafe fn read32(addr: usize) -> u32 {
+    (addr as *const u32).read_volatile()
+}
+
+unsafe fn write32(addr: usize, value: u32) {
+    (addr as *mut u32).write_volatile(value)
+}
+
+pub struct Aplic {
+    base: usize,
+    num_sources: usize,
+    msi_mode: bool,
+}
+
+impl Aplic {
+    pub const fn new(base: usize, num_sources: usize, msi_mode: bool) -> Self {
+        Self {
+            base,
+            num_sources,
+            msi_mode,
+        }
+    }
+
+    fn idc_base(&self, hart_id: usize) -> usize {
+        self.base + APLIC_IDC + hart_id * APLIC_IDC_STRIDE
+    }
+
+    /// Disables all sources, and enables the domain in the delivery mode.
+    pub fn init(&self) {
+        unsafe {
+            write32(self.base + APLIC_DOMAINCFG, 0);
+            for i in 0..self.num_sources.div_ceil(32) {
+                write32(self.base + APLIC_CLRIE + i * 4, u32::MAX);
+            }
+            let dm = if self.msi_mode { DOMAINCFG_DM } else { 0 };
+            write32(self.base + APLIC_DOMAINCFG, DOMAINCFG_IE | dm);
+        }
+    }
+
+    /// Initializes the IDC of the hart in direct delivery mode.
+    pub fn init_hart(&self, hart_id: usize) {
+        if !self.msi_mode {
+            let idc = self.idc_base(hart_id);
+            unsafe {
+                write32(idc + IDC_ITHRESHOLD, 0);
+                write32(idc + IDC_IDELIVERY, 1);
+            }
+        }
+    }
+
+    /// Enables or disables the interrupt source. It is delivered to the hart
+    /// `hart_id` when enabled.
+    pub fn set_enable(&self, hart_id: usize, source: usize, enabled: bool) {
+        if source == 0 || source >= self.num_sources {
+            return;
+        }
+        let target = if self.msi_mode {
+            // The EIID of the MSI is the source number.
+            source as u32
+        } else {
+            TARGET_IPRIO
+        };
+        unsafe {
+            if enabled {
+                write32(
+                    self.base + APLIC_SOURCECFG + (source - 1) * 4,
+                    SOURCECFG_SM_LEVEL1,
+                );
+                write32(
+                    self.base + APLIC_TARGET + (source - 1) * 4,
+                    ((hart_id as u32) << TARGET_HART_SHIFT) | target,
+                );
+                write32(self.base + APLIC_SETIENUM, source as u32);
+            } else {
+                write32(self.base + APLIC_CLRIENUM, source as u32);
+            }
+        }
+    }
+
+    /// Claims the highest priority pending interrupt source of the hart in
+    /// direct delivery mode, or returns [`None`] if there is none.
+    pub fn claim(&self, hart_id: usize) -> Option<usize> {
+        let claimi = unsafe { read32(self.idc_base(hart_id) + IDC_CLAIMI) };
+        let source = (claimi >> 16) as usize;
+        (source != 0).then_some(source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fake registers of an APLIC with IDCs for 4 harts.
+    fn regs() -> Vec<u32> {
+        vec![0; (APLIC_IDC + 4 * APLIC_IDC_STRIDE) / 4]
+    }
+
+    fn reg(regs: &[u32], offset: usize) -> u32 {
+        regs[offset / 4]
+    }
+
+    #[test]
+    fn test_init() {
+        let mut regs = regs();
+        let base = regs.as_mut_ptr() as usize;
+        Aplic::new(base, 64, false).init();
+        assert_eq!(reg(&regs, APLIC_DOMAINCFG), DOMAINCFG_IE);
+        assert_eq!(reg(&regs, APLIC_CLRIE), u32::MAX);
+        assert_eq!(reg(&regs, APLIC_CLRIE + 4), u32::MAX);
+        assert_eq!(reg(&regs, APLIC_CLRIE + 8), 0);
+
+        Aplic::new(base, 64, true).init();
+        assert_eq!(reg(&regs, APLIC_DOMAINCFG), DOMAINCFG_IE | DOMAINCFG_DM);
+    }
+
+    #[test]
+    fn test_direct_delivery() {
+        let mut regs = regs();
+        let aplic = Aplic::new(regs.as_mut_ptr() as usize, 64, false);
+        aplic.init_hart(2);
+        let idc = APLIC_IDC + 2 * APLIC_IDC_STRIDE;
+        assert_eq!(reg(&regs, idc + IDC_IDELIVERY), 1);
+
+        aplic.set_enable(2, 10, true);
+        assert_eq!(reg(&regs, APLIC_SOURCECFG + 9 * 4), SOURCECFG_SM_LEVEL1);
+        assert_eq!(reg(&regs, APLIC_TARGET + 9 * 4), 2 << 18 | TARGET_IPRIO);
+        assert_eq!(reg(&regs, APLIC_SETIENUM), 10);
+
+        aplic.set_enable(2, 10, false);
+        assert_eq!(reg(&regs, APLIC_CLRIENUM), 10);
+
+        // The source identity is in bits 25:16 of `claimi`.
+        regs[(idc + IDC_CLAIMI) / 4] = 10 << 16 | TARGET_IPRIO;
+        assert_eq!(aplic.claim(2), Some(10));
+        regs[(idc + IDC_CLAIMI) / 4] = 0;
+        assert_eq!(aplic.claim(2), None);
+    }
+
+    #[test]
+    fn test_msi_delivery() {
+        let mut regs = regs();
+        let aplic = Aplic::new(regs.as_mut_ptr() as usize, 64, true);
+        // No IDCs in MSI delivery mode.
+        aplic.init_hart(1);
+        assert_eq!(reg(&regs, APLIC_IDC + APLIC_IDC_STRIDE + IDC_IDELIVERY), 0);
+
+        // The EIID is the source number.
+        aplic.set_enable(3, 33, true);
+        assert_eq!(reg(&regs, APLIC_TARGET + 32 * 4), 3 << 18 | 33);
+        assert_eq!(reg(&regs, APLIC_SETIENUM), 33);
+    }
+
+    #[test]
+    fn test_invalid_source() {
+        let mut regs = regs();
+        let aplic = Aplic::new(regs.as_mut_ptr() as usize, 64, false);
+        aplic.set_enable(0, 0, true);
+        aplic.set_enable(0, 64, true);
+        assert!(regs.iter().all(|&r| r == 0));
+    }
+}
@@ -15,8 +15,12 @@ unsafe fn init_boot_page_table() {
     BOOT_PT_SV39[2] = (0x80000 << 10) | 0xef;
     // 0xffff_ffc0_0000_0000..0xffff_ffc0_4000_0000, VRWX_GAD, 1G block
     BOOT_PT_SV39[0x100] = (0x80000 << 10) | 0xef;
-    // 0xffff_ffc0_8000_0000..0xffff_ffc0_c000_0000, VRWX_GAD, 1G block
-    BOOT_PT_SV39[0x102] = (0x80000 << 10) | 0xef;
+    // 0xffff_ffc0_8000_0000..0xffff_ffff_ffff_ffff, VRWX_GAD, 1G blocks, so
+    // that all the memory in the device tree is accessible before paging
+    // is initialized.
+    for i in 2..0x100 {
+        BOOT_PT_SV39[0x100 + i] = ((i as u64) << 28) | 0xef;
+    }
 }
 
 unsafe fn init_mmu() {
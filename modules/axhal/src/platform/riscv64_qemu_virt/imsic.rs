@@ -0,0 +1,169 @@
+//! RISC-V Incoming MSI Controller (IMSIC).
+//!
+//! Each hart has a supervisor-level interrupt file, in which an MSI (a write
+//! of the interrupt identity to the file) sets a pending bit. The hart claims
+//! the pending interrupts with the `stopei` CSR, and accesses the other
+//! registers of its file indirectly with the `siselect` and `sireg` CSRs.
+//!
+//! All identities are enabled in the interrupt files, so the interrupts are
+//! masked at their sources: the APLIC for wired interrupts, and the devices
+//! for the MSIs of PCI devices.
+//!
+//! See The RISC-V Advanced Interrupt Architecture, chapter 3.
+
+use core::arch::asm;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// The number of interrupt identities of each file on QEMU virt.
+const NUM_IDS: usize = 255;
+/// The size of the interrupt file of a hart.
+const FILE_SIZE: usize = 0x1000;
+
+const EIDELIVERY: usize = 0x70;
+const EITHRESHOLD: usize = 0x72;
+/// The interrupt enable bits of identities 0..64. There are only
+/// even-numbered registers on RV64.
+const EIE0: usize = 0xc0;
+
+fn write_ireg(reg: usize, value: usize) {
+    unsafe {
+        asm!("csrw 0x150, {}", in(reg) reg); // siselect
+        asm!("csrw 0x151, {}", in(reg) value); // sireg
+    }
+}
+
+/// The number of words of the bitmap of the allocated identities.
+const ID_WORDS: usize = (NUM_IDS + 1).div_ceil(64);
+
+pub struct Imsic {
+    paddr: usize,
+    base: usize,
+    /// The first identity for MSIs.
+    first_msi_id: usize,
+    /// The allocated identities for MSIs, one bit for each identity.
+    msi_ids: [AtomicU64; ID_WORDS],
+}
+
+impl Imsic {
+    /// Creates the IMSIC with the interrupt files at `paddr` (mapped at
+    /// `base`), and the identities from `first_msi_id` allocated for MSIs.
+    pub const fn new(paddr: usize, base: usize, first_msi_id: usize) -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const FREE: AtomicU64 = AtomicU64::new(0);
+        Self {
+            paddr,
+            base,
+            first_msi_id,
+            msi_ids: [FREE; ID_WORDS],
+        }
+    }
+
+    /// Enables the interrupt file of the current hart, and all identities in
+    /// it.
+    pub fn init_hart(&self) {
+        for i in 0..=NUM_IDS / 64 {
+            write_ireg(EIE0 + i * 2, usize::MAX);
+        }
+        write_ireg(EITHRESHOLD, 0);
+        write_ireg(EIDELIVERY, 1);
+    }
+
+    /// Returns the physical address to write MSIs to the hart.
+    pub fn msi_address(&self, hart_id: usize) -> u64 {
+        (self.paddr + hart_id * FILE_SIZE) as u64
+    }
+
+    /// Sends the interrupt identity `id` to the hart.
+    pub fn send(&self, hart_id: usize, id: usize) {
+        // `seteipnum_le` is at offset 0 of the file.
+        let reg = (self.base + hart_id * FILE_SIZE) as *mut u32;
+        unsafe { reg.write_volatile(id as u32) };
+    }
+
+    /// Allocates an interrupt identity for MSIs, or returns [`None`] if all are
+    /// allocated.
+    pub fn alloc_msi_id(&self) -> Option<usize> {
+        for (i, word) in self.msi_ids.iter().enumerate() {
+            let mask = self.msi_id_mask(i);
+            let res = word.fetch_update(Ordering::AcqRel, Ordering::Acquire, |bits| {
+                let free = !bits & mask;
+                // Set the lowest free bit.
+                (free != 0).then(|| bits | (free & free.wrapping_neg()))
+            });
+            if let Ok(old) = res {
+                return Some(i * 64 + (!old & mask).trailing_zeros() as usize);
+            }
+        }
+        None
+    }
+
+    /// Returns the bits of the identities for MSIs in the word `i` of the
+    /// bitmap.
+    fn msi_id_mask(&self, i: usize) -> u64 {
+        (0..64)
+            .filter(|&bit| (self.first_msi_id..=NUM_IDS).contains(&(i * 64 + bit)))
+            .fold(0, |mask, bit| mask | 1 << bit)
+    }
+
+    /// Frees an interrupt identity allocated by [`Imsic::alloc_msi_id`].
+    pub fn free_msi_id(&self, id: usize) {
+        if (self.first_msi_id..=NUM_IDS).contains(&id) {
+            self.msi_ids[id / 64].fetch_and(!(1 << (id % 64)), Ordering::AcqRel);
+        }
+    }
+
+    /// Claims the highest priority pending interrupt of the current hart, or
+    /// returns [`None`] if there is none.
+    pub fn claim(&self) -> Option<usize> {
+        let topei: usize;
+        unsafe { asm!("csrrw {}, 0x15c, zero", out(reg) topei) }; // stopei
+        let id = topei >> 16;
+        (id != 0).then_some(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_msi_address() {
+        let imsic = Imsic::new(0x2800_0000, 0, 1);
+        assert_eq!(imsic.msi_address(0), 0x2800_0000);
+        assert_eq!(imsic.msi_address(3), 0x2800_3000);
+    }
+
+    #[test]
+    fn test_send() {
+        let mut files = vec![0u32; 2 * FILE_SIZE / 4];
+        let imsic = Imsic::new(0, files.as_mut_ptr() as usize, 1);
+        imsic.send(1, 42);
+        assert_eq!(files[FILE_SIZE / 4], 42);
+        assert_eq!(files[0], 0);
+    }
+
+    #[test]
+    fn test_alloc_msi_id() {
+        let imsic = Imsic::new(0, 0, NUM_IDS - 1);
+        assert_eq!(imsic.alloc_msi_id(), Some(NUM_IDS - 1));
+        assert_eq!(imsic.alloc_msi_id(), Some(NUM_IDS));
+        assert_eq!(imsic.alloc_msi_id(), None);
+        assert_eq!(imsic.alloc_msi_id(), None);
+    }
+
+    #[test]
+    fn test_free_msi_id() {
+        let imsic = Imsic::new(0, 0, 2);
+        let ids: Vec<_> = (0..NUM_IDS - 1).map(|_| imsic.alloc_msi_id()).collect();
+        assert_eq!(ids, (2..=NUM_IDS).map(Some).collect::<Vec<_>>());
+        assert_eq!(imsic.alloc_msi_id(), None);
+        imsic.free_msi_id(64);
+        imsic.free_msi_id(3);
+        assert_eq!(imsic.alloc_msi_id(), Some(3));
+        assert_eq!(imsic.alloc_msi_id(), Some(64));
+        assert_eq!(imsic.alloc_msi_id(), None);
+        // Identities not for MSIs are ignored.
+        imsic.free_msi_id(1);
+        assert_eq!(imsic.alloc_msi_id(), None);
+    }
+}
@@ -1,14 +1,32 @@
-//! TODO: PLIC
+//! Interrupt handling of the RISC-V QEMU virt machine.
+//!
+//! The local interrupts (software, timer and counter overflow) are identified
+//! by their `scause` values. The external interrupts are delivered by the
+//! controller selected by `irq-controller` in the platform configuration:
+//!
+//! - `plic`: the Platform-Level Interrupt Controller (PLIC).
+//! - `aplic`: the Advanced Platform-Level Interrupt Controller (APLIC) of the
+//!   Advanced Interrupt Architecture (AIA), in direct delivery mode.
+//! - `aplic-imsic`: the APLIC in MSI delivery mode, which forwards the wired
+//!   interrupts to the Incoming MSI Controller (IMSIC) of the harts. PCI
+//!   devices can send MSIs to the IMSIC too, and IPIs are sent through it.
+//!
+//! The external IRQ numbers are the wired interrupt source numbers, and the
+//! IMSIC interrupt identities allocated for MSIs. All of them are delivered
+//! to the primary CPU.
 
-use crate::irq::IrqHandler;
+use crate::irq::{IrqHandler, MsiMessage};
+use crate::mem::phys_to_virt;
 use lazyinit::LazyInit;
-use riscv::register::sie;
+use riscv::register::{sie, sip};
+use sbi_rt::HartMask;
+
+use super::{aplic::Aplic, imsic::Imsic, plic::Plic};
 
 /// `Interrupt` bit in `scause`
 pub(super) const INTC_IRQ_BASE: usize = 1 << (usize::BITS - 1);
 
 /// Supervisor software interrupt in `scause`
-#[allow(unused)]
 pub(super) const S_SOFT: usize = INTC_IRQ_BASE + 1;
 
 /// Supervisor timer interrupt in `scause`
@@ -17,48 +35,184 @@ pub(super) const S_TIMER: usize = INTC_IRQ_BASE + 5;
 /// Supervisor external interrupt in `scause`
 pub(super) const S_EXT: usize = INTC_IRQ_BASE + 9;
 
+/// Local counter overflow interrupt in `scause` (the Sscofpmf extension)
+pub(super) const S_LCOF: usize = INTC_IRQ_BASE + 13;
+
+/// The bit of the local counter overflow interrupt in `sie` and `sip`.
+const LCOF_BIT: usize = 1 << 13;
+
 static TIMER_HANDLER: LazyInit<IrqHandler> = LazyInit::new();
 
+static IPI_HANDLER: LazyInit<IrqHandler> = LazyInit::new();
+
+static PMU_HANDLER: LazyInit<IrqHandler> = LazyInit::new();
+
+enum IrqController {
+    Plic(Plic),
+    Aplic(Aplic),
+    AplicImsic(Aplic, Imsic),
+}
+
+struct IrqState {
+    controller: IrqController,
+    /// The hart that the external interrupts are delivered to.
+    primary_hart: usize,
+}
+
+static IRQ_STATE: LazyInit<IrqState> = LazyInit::new();
+
+/// The number of wired interrupt sources of the PLIC or the APLIC, including
+/// the invalid source 0.
+const NUM_SOURCES: usize = 96;
+
+/// The IMSIC interrupt identity of IPIs. The identities below are the wired
+/// interrupt sources forwarded by the APLIC, and the ones above are allocated
+/// for MSIs.
+const IPI_ID: usize = NUM_SOURCES;
+
 /// The maximum number of IRQs.
 pub const MAX_IRQ_COUNT: usize = 1024;
 
 /// The timer IRQ number (supervisor timer interrupt in `scause`).
 pub const TIMER_IRQ_NUM: usize = S_TIMER;
 
+/// The inter-processor interrupt (IPI) number (supervisor software interrupt
+/// in `scause`).
+pub const IPI_IRQ_NUM: usize = S_SOFT;
+
+/// The performance counter overflow IRQ number (local counter overflow
+/// interrupt in `scause`).
+pub const PMU_IRQ_NUM: usize = S_LCOF;
+
 macro_rules! with_cause {
-    ($cause: expr, @TIMER => $timer_op: expr, @EXT => $ext_op: expr $(,)?) => {
+    (
+        $cause: expr,
+        @SOFT => $soft_op: expr,
+        @TIMER => $timer_op: expr,
+        @EXT => $ext_op: expr,
+        @LCOF => $lcof_op: expr $(,)?
+    ) => {
         match $cause {
+            S_SOFT => $soft_op,
             S_TIMER => $timer_op,
             S_EXT => $ext_op,
+            S_LCOF => $lcof_op,
             _ => panic!("invalid trap cause: {:#x}", $cause),
         }
     };
 }
 
 /// Enables or disables the given IRQ.
-pub fn set_enable(scause: usize, _enabled: bool) {
-    if scause == S_EXT {
-        // TODO: set enable in PLIC
+///
+/// Only the wired interrupt sources can be disabled. The local interrupts are
+/// always enabled, and the MSIs are masked by the devices.
+pub fn set_enable(irq_num: usize, enabled: bool) {
+    if irq_num >= NUM_SOURCES {
+        return;
+    }
+    let hart_id = IRQ_STATE.primary_hart;
+    match &IRQ_STATE.controller {
+        IrqController::Plic(plic) => plic.set_enable(hart_id, irq_num, enabled),
+        IrqController::Aplic(aplic) | IrqController::AplicImsic(aplic, _) => {
+            aplic.set_enable(hart_id, irq_num, enabled)
+        }
     }
 }
 
 /// Registers an IRQ handler for the given IRQ.
 ///
-/// It also enables the IRQ if the registration succeeds. It returns `false` if
-/// the registration failed.
-pub fn register_handler(scause: usize, handler: IrqHandler) -> bool {
+/// The IRQ is either an external IRQ number, or the `scause` value of a local
+/// interrupt. It also enables the IRQ if the registration succeeds. It returns
+/// `false` if the registration failed.
+pub fn register_handler(irq_num: usize, handler: IrqHandler) -> bool {
+    if irq_num < INTC_IRQ_BASE {
+        return crate::irq::register_handler_common(irq_num, handler);
+    }
     with_cause!(
-        scause,
+        irq_num,
+        @SOFT => if !IPI_HANDLER.is_inited() {
+            IPI_HANDLER.init_once(handler);
+            true
+        } else {
+            false
+        },
         @TIMER => if !TIMER_HANDLER.is_inited() {
             TIMER_HANDLER.init_once(handler);
             true
         } else {
             false
         },
-        @EXT => crate::irq::register_handler_common(scause & !INTC_IRQ_BASE, handler),
+        // External interrupts are registered by their IRQ numbers.
+        @EXT => false,
+        @LCOF => if !PMU_HANDLER.is_inited() {
+            PMU_HANDLER.init_once(handler);
+            true
+        } else {
+            false
+        },
     )
 }
 
+/// Allocates an IRQ for an MSI of the PCI device with the given requester ID
+/// (bus, device and function numbers), returns the IRQ number and the message
+/// that the device should write.
+///
+/// It allocates an interrupt identity of the IMSIC, and returns [`None`] if
+/// the IMSIC is not used or there is no free identity.
+pub fn alloc_msi_irq(_device_id: u32) -> Option<(usize, MsiMessage)> {
+    match &IRQ_STATE.controller {
+        IrqController::AplicImsic(_, imsic) => {
+            let id = imsic.alloc_msi_id()?;
+            let msg = MsiMessage {
+                address: imsic.msi_address(IRQ_STATE.primary_hart),
+                data: id as u32,
+            };
+            Some((id, msg))
+        }
+        _ => None,
+    }
+}
+
+/// Frees an IRQ allocated by [`alloc_msi_irq`], whose handler must not be
+/// registered.
+pub fn free_msi_irq(irq_num: usize) {
+    if let IrqController::AplicImsic(_, imsic) = &IRQ_STATE.controller {
+        imsic.free_msi_id(irq_num);
+    }
+}
+
+fn handle_ipi() {
+    if let Some(handler) = IPI_HANDLER.get() {
+        handler();
+    }
+}
+
+/// Claims and dispatches an external interrupt.
+fn dispatch_external() {
+    let hart_id = crate::cpu::this_cpu_id();
+    match &IRQ_STATE.controller {
+        IrqController::Plic(plic) => {
+            if let Some(source) = plic.claim(hart_id) {
+                crate::irq::dispatch_irq_common(source);
+                plic.complete(hart_id, source);
+            }
+        }
+        IrqController::Aplic(aplic) => {
+            if let Some(source) = aplic.claim(hart_id) {
+                crate::irq::dispatch_irq_common(source);
+            }
+        }
+        IrqController::AplicImsic(_, imsic) => match imsic.claim() {
+            Some(IPI_ID) => {
+                trace!("IRQ: IPI");
+                handle_ipi();
+            }
+            Some(id) => crate::irq::dispatch_irq_common(id),
+            None => {}
+        },
+    }
+}
+
 /// Dispatches the IRQ.
 ///
 /// This function is called by the common interrupt handler. It looks
@@ -67,15 +221,99 @@ pub fn register_handler(scause: usize, handler: IrqHandler) -> bool {
 pub fn dispatch_irq(scause: usize) {
     with_cause!(
         scause,
+        @SOFT => {
+            trace!("IRQ: IPI");
+            unsafe { sip::clear_ssoft() };
+            handle_ipi();
+        },
         @TIMER => {
             trace!("IRQ: timer");
             TIMER_HANDLER();
         },
-        @EXT => crate::irq::dispatch_irq_common(0), // TODO: get IRQ number from PLIC
+        @EXT => dispatch_external(),
+        @LCOF => {
+            trace!("IRQ: counter overflow");
+            unsafe { core::arch::asm!("csrc sip, {}", in(reg) LCOF_BIT) };
+            if let Some(handler) = PMU_HANDLER.get() {
+                handler();
+            }
+        },
     );
 }
 
+/// Enables the performance counter overflow interrupt on the current CPU.
+pub fn enable_pmu_irq() {
+    unsafe { core::arch::asm!("csrs sie, {}", in(reg) LCOF_BIT) };
+}
+
+/// Sends an inter-processor interrupt to the given CPU.
+///
+/// It is sent through the IMSIC if it is used, otherwise through the SBI.
+pub fn send_ipi(cpu_id: usize) {
+    match &IRQ_STATE.controller {
+        IrqController::AplicImsic(_, imsic) => imsic.send(cpu_id, IPI_ID),
+        _ => {
+            sbi_rt::send_ipi(HartMask::from_mask_base(1, cpu_id));
+        }
+    }
+}
+
+/// Sends an inter-processor interrupt to all CPUs except the current one.
+pub fn send_ipi_all_others() {
+    let this_cpu = crate::cpu::this_cpu_id();
+    if let IrqController::AplicImsic(_, imsic) = &IRQ_STATE.controller {
+        for cpu_id in (0..axconfig::SMP).filter(|&id| id != this_cpu) {
+            imsic.send(cpu_id, IPI_ID);
+        }
+        return;
+    }
+    let all = usize::MAX >> (usize::BITS as usize - axconfig::SMP);
+    let others = all & !(1 << this_cpu);
+    if others != 0 {
+        sbi_rt::send_ipi(HartMask::from_mask_base(others, 0));
+    }
+}
+
+/// Initializes the interrupt controller on the primary CPU.
+pub(super) fn init_primary() {
+    let controller = match axconfig::IRQ_CONTROLLER {
+        "plic" => {
+            info!("Initialize PLIC...");
+            let base = phys_to_virt(pa!(axconfig::PLIC_PADDR)).as_usize();
+            IrqController::Plic(Plic::new(base))
+        }
+        "aplic" | "aplic-imsic" => {
+            let msi_mode = axconfig::IRQ_CONTROLLER == "aplic-imsic";
+            info!("Initialize APLIC (MSI delivery mode: {})...", msi_mode);
+            let base = phys_to_virt(pa!(axconfig::APLIC_PADDR)).as_usize();
+            let aplic = Aplic::new(base, NUM_SOURCES, msi_mode);
+            aplic.init();
+            if msi_mode {
+                let paddr = axconfig::IMSIC_PADDR;
+                let base = phys_to_virt(pa!(paddr)).as_usize();
+                IrqController::AplicImsic(aplic, Imsic::new(paddr, base, IPI_ID + 1))
+            } else {
+                IrqController::Aplic(aplic)
+            }
+        }
+        name => panic!("Unsupported interrupt controller: {}", name),
+    };
+    IRQ_STATE.init_once(IrqState {
+        controller,
+        primary_hart: crate::cpu::this_cpu_id(),
+    });
+    init_percpu();
+}
+
+/// Initializes the interrupt controller on the current CPU, and enables the
+/// interrupts.
 pub(super) fn init_percpu() {
+    let hart_id = crate::cpu::this_cpu_id();
+    match &IRQ_STATE.controller {
+        IrqController::Plic(plic) => plic.init_hart(hart_id),
+        IrqController::Aplic(aplic) => aplic.init_hart(hart_id),
+        IrqController::AplicImsic(_, imsic) => imsic.init_hart(),
+    }
     // enable soft interrupts, timer interrupts, and external interrupts
     unsafe {
         sie::set_ssoft();
@@ -1,6 +1,8 @@
 use crate::mem::MemRegion;
 
-/// Returns platform-specific memory regions.
+/// Returns platform-specific memory regions, from the device tree if any.
 pub(crate) fn platform_regions() -> impl Iterator<Item = MemRegion> {
-    crate::mem::default_free_regions().chain(crate::mem::default_mmio_regions())
+    crate::mem::dtb_free_regions()
+        .chain(crate::mem::boot_module_regions())
+        .chain(crate::mem::dtb_mmio_regions())
 }
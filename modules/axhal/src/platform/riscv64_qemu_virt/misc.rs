@@ -1,5 +1,6 @@
-/// Shutdown the whole system, including all CPUs.
-pub fn terminate() -> ! {
+/// Powers off the whole system, including all CPUs, with the SBI System
+/// Reset (SRST) extension.
+pub fn poweroff() -> ! {
     info!("Shutting down...");
     sbi_rt::system_reset(sbi_rt::Shutdown, sbi_rt::NoReason);
     warn!("It should shutdown!");
@@ -7,3 +8,18 @@ pub fn terminate() -> ! {
         crate::arch::halt();
     }
 }
+
+/// Reboots the whole system, with the SBI System Reset (SRST) extension.
+pub fn reboot() -> ! {
+    info!("Rebooting...");
+    sbi_rt::system_reset(sbi_rt::ColdReboot, sbi_rt::NoReason);
+    warn!("It should reboot!");
+    loop {
+        crate::arch::halt();
+    }
+}
+
+/// Shutdown the whole system, including all CPUs.
+pub fn terminate() -> ! {
+    poweroff()
+}
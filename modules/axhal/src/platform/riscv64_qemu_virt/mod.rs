@@ -5,8 +5,14 @@ pub mod mem;
 pub mod misc;
 pub mod time;
 
+#[cfg(feature = "irq")]
+mod aplic;
+#[cfg(feature = "irq")]
+mod imsic;
 #[cfg(feature = "irq")]
 pub mod irq;
+#[cfg(feature = "irq")]
+mod plic;
 
 #[cfg(feature = "smp")]
 pub mod mp;
@@ -20,6 +26,7 @@ extern "C" {
 unsafe extern "C" fn rust_entry(cpu_id: usize, dtb: usize) {
     crate::mem::clear_bss();
     crate::cpu::init_primary(cpu_id);
+    crate::dtb::init(dtb);
     self::time::init_early();
     rust_main(cpu_id, dtb);
 }
@@ -35,7 +42,7 @@ unsafe extern "C" fn rust_entry_secondary(cpu_id: usize) {
 /// For example, the interrupt controller and the timer.
 pub fn platform_init() {
     #[cfg(feature = "irq")]
-    self::irq::init_percpu();
+    self::irq::init_primary();
     self::time::init_percpu();
 }
 
@@ -0,0 +1,90 @@
+//! RISC-V Platform-Level Interrupt Controller (PLIC).
+//!
+//! Each hart has a machine-mode and a supervisor-mode context on QEMU virt,
+//! and only the supervisor-mode contexts are used. All interrupt sources have
+//! the same priority, and the threshold of the contexts is 0.
+//!
+//! See the RISC-V Platform-Level Interrupt Controller Specification.
+
+use kspin::SpinNoIrq;
+
+const PLIC_PRIORITY: usize = 0x00_0000;
+const PLIC_ENABLE: usize = 0x00_2000;
+const PLIC_ENABLE_STRIDE: usize = 0x80;
+const PLIC_CONTEXT: usize = 0x20_0000;
+const PLIC_CONTEXT_STRIDE: usize = 0x1000;
+const PLIC_CONTEXT_THRESHOLD: usize = 0x0;
+const PLIC_CONTEXT_CLAIM: usize = 0x4;
+
+/// The priority of all interrupt sources (0 means never interrupt).
+const DEFAULT_PRIORITY: u32 = 1;
+
+unsafe fn read32(addr: usize) -> u32 {
+    (addr as *const u32).read_volatile()
+}
+
+unsafe fn write32(addr: usize, value: u32) {
+    (addr as *mut u32).write_volatile(value)
+}
+
+/// Returns the supervisor-mode context of the hart.
+const fn context(hart_id: usize) -> usize {
+    2 * hart_id + 1
+}
+
+pub struct Plic {
+    base: usize,
+    /// Serializes the read-modify-write of the enable bits.
+    enable_lock: SpinNoIrq<()>,
+}
+
+impl Plic {
+    pub const fn new(base: usize) -> Self {
+        Self {
+            base,
+            enable_lock: SpinNoIrq::new(()),
+        }
+    }
+
+    fn context_base(&self, hart_id: usize) -> usize {
+        self.base + PLIC_CONTEXT + context(hart_id) * PLIC_CONTEXT_STRIDE
+    }
+
+    /// Enables or disables the interrupt source in the context of the hart.
+    pub fn set_enable(&self, hart_id: usize, source: usize, enabled: bool) {
+        let reg =
+            self.base + PLIC_ENABLE + context(hart_id) * PLIC_ENABLE_STRIDE + (source / 32) * 4;
+        let bit = 1 << (source % 32);
+        let _guard = self.enable_lock.lock();
+        unsafe {
+            if enabled {
+                write32(self.base + PLIC_PRIORITY + source * 4, DEFAULT_PRIORITY);
+                write32(reg, read32(reg) | bit);
+            } else {
+                write32(reg, read32(reg) & !bit);
+            }
+        }
+    }
+
+    /// Initializes the context of the hart.
+    pub fn init_hart(&self, hart_id: usize) {
+        unsafe { write32(self.context_base(hart_id) + PLIC_CONTEXT_THRESHOLD, 0) };
+    }
+
+    /// Claims the highest priority pending interrupt source of the hart, or
+    /// returns [`None`] if there is none.
+    pub fn claim(&self, hart_id: usize) -> Option<usize> {
+        let source = unsafe { read32(self.context_base(hart_id) + PLIC_CONTEXT_CLAIM) };
+        (source != 0).then_some(source as usize)
+    }
+
+    /// Signals the completion of the handling of a claimed interrupt source.
+    pub fn complete(&self, hart_id: usize, source: usize) {
+        unsafe {
+            write32(
+                self.context_base(hart_id) + PLIC_CONTEXT_CLAIM,
+                source as u32,
+            )
+        };
+    }
+}
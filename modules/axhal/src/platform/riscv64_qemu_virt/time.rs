@@ -1,6 +1,8 @@
+use int_ratio::Ratio;
 use riscv::register::time;
 
-const NANOS_PER_TICK: u64 = crate::time::NANOS_PER_SEC / axconfig::TIMER_FREQUENCY as u64;
+static mut TICKS_TO_NANOS_RATIO: Ratio = Ratio::zero();
+static mut NANOS_TO_TICKS_RATIO: Ratio = Ratio::zero();
 /// RTC wall time offset in nanoseconds at monotonic time base.
 static mut RTC_EPOCHOFFSET_NANOS: u64 = 0;
 
@@ -12,14 +14,14 @@ pub fn current_ticks() -> u64 {
 
 /// Converts hardware ticks to nanoseconds.
 #[inline]
-pub const fn ticks_to_nanos(ticks: u64) -> u64 {
-    ticks * NANOS_PER_TICK
+pub fn ticks_to_nanos(ticks: u64) -> u64 {
+    unsafe { TICKS_TO_NANOS_RATIO.mul_trunc(ticks) }
 }
 
 /// Converts nanoseconds to hardware ticks.
 #[inline]
-pub const fn nanos_to_ticks(nanos: u64) -> u64 {
-    nanos / NANOS_PER_TICK
+pub fn nanos_to_ticks(nanos: u64) -> u64 {
+    unsafe { NANOS_TO_TICKS_RATIO.mul_trunc(nanos) }
 }
 
 /// Return epoch offset in nanoseconds (wall time offset to monotonic clock start).
@@ -35,7 +37,15 @@ pub fn set_oneshot_timer(deadline_ns: u64) {
     sbi_rt::set_timer(nanos_to_ticks(deadline_ns));
 }
 
+/// Early stage initialization: stores the timer frequency (the
+/// `timebase-frequency` in the device tree, or [`axconfig::TIMER_FREQUENCY`]).
 pub(super) fn init_early() {
+    let freq = crate::dtb::timer_frequency().unwrap_or(axconfig::TIMER_FREQUENCY as u64);
+    unsafe {
+        TICKS_TO_NANOS_RATIO = Ratio::new(crate::time::NANOS_PER_SEC as u32, freq as u32);
+        NANOS_TO_TICKS_RATIO = TICKS_TO_NANOS_RATIO.inverse();
+    }
+
     #[cfg(feature = "rtc")]
     if axconfig::RTC_PADDR != 0 {
         use crate::mem::phys_to_virt;
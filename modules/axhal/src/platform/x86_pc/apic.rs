@@ -3,17 +3,21 @@
 use kspin::SpinNoIrq;
 use lazyinit::LazyInit;
 use memory_addr::PhysAddr;
-use x2apic::ioapic::IoApic;
-use x2apic::lapic::{xapic_base, LocalApic, LocalApicBuilder};
+use x2apic::ioapic::{IoApic, IrqFlags, IrqMode, RedirectionTableEntry};
+use x2apic::lapic::{xapic_base, IpiAllShorthand, LocalApic, LocalApicBuilder};
 use x86_64::instructions::port::Port;
 
 use self::vectors::*;
 use crate::mem::phys_to_virt;
 
 pub(super) mod vectors {
+    pub const GSI_VECTOR_START: u8 = 0x20;
+    pub const MSI_VECTOR_START: u8 = 0x40;
     pub const APIC_TIMER_VECTOR: u8 = 0xf0;
     pub const APIC_SPURIOUS_VECTOR: u8 = 0xf1;
     pub const APIC_ERROR_VECTOR: u8 = 0xf2;
+    pub const APIC_IPI_VECTOR: u8 = 0xf3;
+    pub const APIC_PMU_VECTOR: u8 = 0xf4;
 }
 
 /// The maximum number of IRQs.
@@ -22,22 +26,74 @@ pub const MAX_IRQ_COUNT: usize = 256;
 /// The timer IRQ number.
 pub const TIMER_IRQ_NUM: usize = APIC_TIMER_VECTOR as usize;
 
+/// The inter-processor interrupt (IPI) number.
+pub const IPI_IRQ_NUM: usize = APIC_IPI_VECTOR as usize;
+
+/// The performance counter overflow IRQ number.
+pub const PMU_IRQ_NUM: usize = APIC_PMU_VECTOR as usize;
+
+/// The IRQ number of the COM1 UART (ISA IRQ 4, identity-mapped to GSI 4).
+pub const UART_IRQ_NUM: usize = GSI_VECTOR_START as usize + 4;
+
+/// The number of GSIs with IRQ numbers (vectors).
+const NUM_GSI_VECTORS: u32 = (MSI_VECTOR_START - GSI_VECTOR_START) as u32;
+
+/// The default IO APIC base address, used if there is no ACPI MADT.
 const IO_APIC_BASE: PhysAddr = pa!(0xFEC0_0000);
 
+/// The base address of MSIs, which are delivered to the local APIC whose ID
+/// is in bits 19:12.
+const MSI_ADDRESS_BASE: u64 = 0xFEE0_0000;
+
+/// An IO APIC, which handles the global system interrupts (GSIs) from
+/// `gsi_base` to `gsi_base + num_entries - 1`.
+struct IoApicChip {
+    gsi_base: u32,
+    num_entries: u32,
+    io_apic: SpinNoIrq<IoApic>,
+}
+
 static mut LOCAL_APIC: Option<LocalApic> = None;
 static mut IS_X2APIC: bool = false;
-static IO_APIC: LazyInit<SpinNoIrq<IoApic>> = LazyInit::new();
+static IO_APICS: LazyInit<[Option<IoApicChip>; crate::acpi::MAX_IO_APICS]> = LazyInit::new();
+
+/// The number of vectors for MSIs, from [`MSI_VECTOR_START`] to
+/// [`APIC_TIMER_VECTOR`].
+const NUM_MSI_VECTORS: usize = (APIC_TIMER_VECTOR - MSI_VECTOR_START) as usize;
+
+/// The allocated MSI vectors, one bit for each vector from
+/// [`MSI_VECTOR_START`].
+static MSI_VECTORS: SpinNoIrq<[u64; NUM_MSI_VECTORS.div_ceil(64)]> =
+    SpinNoIrq::new([0; NUM_MSI_VECTORS.div_ceil(64)]);
+
+/// Returns the IO APIC which handles the given GSI, and the pin number of the
+/// GSI on it.
+fn io_apic_of(gsi: u32) -> Option<(&'static SpinNoIrq<IoApic>, u8)> {
+    IO_APICS.iter().flatten().find_map(|chip| {
+        (gsi >= chip.gsi_base && gsi - chip.gsi_base < chip.num_entries)
+            .then(|| (&chip.io_apic, (gsi - chip.gsi_base) as u8))
+    })
+}
 
 /// Enables or disables the given IRQ.
+///
+/// The IRQs from [`GSI_VECTOR_START`] to [`MSI_VECTOR_START`] are the GSIs
+/// from 0, which are routed to the IO APIC handling them. MSIs are masked in
+/// the devices instead.
 #[cfg(feature = "irq")]
 pub fn set_enable(vector: usize, enabled: bool) {
-    // should not affect LAPIC interrupts
-    if vector < APIC_TIMER_VECTOR as _ {
+    // should not affect LAPIC interrupts and MSIs
+    if (GSI_VECTOR_START as usize..MSI_VECTOR_START as usize).contains(&vector) {
+        let gsi = (vector - GSI_VECTOR_START as usize) as u32;
+        let Some((io_apic, pin)) = io_apic_of(gsi) else {
+            warn!("No IO APIC for GSI {}", gsi);
+            return;
+        };
         unsafe {
             if enabled {
-                IO_APIC.lock().enable_irq(vector as u8);
+                io_apic.lock().enable_irq(pin);
             } else {
-                IO_APIC.lock().disable_irq(vector as u8);
+                io_apic.lock().disable_irq(pin);
             }
         }
     }
@@ -52,6 +108,43 @@ pub fn register_handler(vector: usize, handler: crate::irq::IrqHandler) -> bool
     crate::irq::register_handler_common(vector, handler)
 }
 
+/// Allocates an IRQ for an MSI of the PCI device with the given requester ID
+/// (bus, device and function numbers), returns the IRQ number and the message
+/// that the device should write.
+///
+/// It allocates a vector from [`MSI_VECTOR_START`] to [`APIC_TIMER_VECTOR`],
+/// delivered to the local APIC of the primary CPU. Returns [`None`] if all the
+/// vectors are allocated.
+#[cfg(feature = "irq")]
+pub fn alloc_msi_irq(_device_id: u32) -> Option<(usize, crate::irq::MsiMessage)> {
+    let idx = {
+        let mut vectors = MSI_VECTORS.lock();
+        let idx = (0..NUM_MSI_VECTORS).find(|&i| vectors[i / 64] & (1 << (i % 64)) == 0)?;
+        vectors[idx / 64] |= 1 << (idx % 64);
+        idx
+    };
+    let vector = MSI_VECTOR_START + idx as u8;
+    let msg = crate::irq::MsiMessage {
+        address: MSI_ADDRESS_BASE | ((apic_id_of(0) as u64 & 0xff) << 12),
+        // Fixed delivery mode, edge-triggered.
+        data: vector as u32,
+    };
+    Some((vector as usize, msg))
+}
+
+/// Frees an IRQ allocated by [`alloc_msi_irq`], whose handler must not be
+/// registered.
+#[cfg(feature = "irq")]
+pub fn free_msi_irq(vector: usize) {
+    let Some(idx) = vector
+        .checked_sub(MSI_VECTOR_START as usize)
+        .filter(|&idx| idx < NUM_MSI_VECTORS)
+    else {
+        return;
+    };
+    MSI_VECTORS.lock()[idx / 64] &= !(1 << (idx % 64));
+}
+
 /// Dispatches the IRQ.
 ///
 /// This function is called by the common interrupt handler. It looks
@@ -63,16 +156,53 @@ pub fn dispatch_irq(vector: usize) {
     unsafe { local_apic().end_of_interrupt() };
 }
 
+/// Sends an inter-processor interrupt to the given CPU.
+#[cfg(feature = "irq")]
+pub fn send_ipi(cpu_id: usize) {
+    unsafe { local_apic().send_ipi(APIC_IPI_VECTOR, raw_apic_id(apic_id_of(cpu_id))) };
+}
+
+/// Sends an inter-processor interrupt to all CPUs except the current one.
+#[cfg(feature = "irq")]
+pub fn send_ipi_all_others() {
+    unsafe { local_apic().send_ipi_all(APIC_IPI_VECTOR, IpiAllShorthand::AllExcludingSelf) };
+}
+
+/// Enables the performance counter overflow interrupt on the current CPU.
+///
+/// The CPU masks the interrupt (in the LVT) on each overflow, so it must be
+/// enabled again after handling it.
+#[cfg(feature = "irq")]
+pub fn enable_pmu_irq() {
+    const XAPIC_LVT_PERF_OFFSET: usize = 0x340;
+    const X2APIC_LVT_PERF_MSR: u32 = 0x834;
+    unsafe {
+        if IS_X2APIC {
+            x86::msr::wrmsr(X2APIC_LVT_PERF_MSR, APIC_PMU_VECTOR as u64);
+        } else {
+            let lvt = phys_to_virt(pa!(xapic_base() as usize + XAPIC_LVT_PERF_OFFSET));
+            (lvt.as_usize() as *mut u32).write_volatile(APIC_PMU_VECTOR as u32);
+        }
+    }
+}
+
 pub(super) fn local_apic<'a>() -> &'a mut LocalApic {
     // It's safe as LAPIC is per-cpu.
     unsafe { LOCAL_APIC.as_mut().unwrap() }
 }
 
-pub(super) fn raw_apic_id(id_u8: u8) -> u32 {
+/// Returns the local APIC ID of the given CPU, from the ACPI MADT.
+///
+/// Without the MADT, CPU IDs are the local APIC IDs.
+pub(super) fn apic_id_of(cpu_id: usize) -> u32 {
+    crate::acpi::cpu_apic_id(cpu_id).unwrap_or(cpu_id as u32)
+}
+
+pub(super) fn raw_apic_id(apic_id: u32) -> u32 {
     if unsafe { IS_X2APIC } {
-        id_u8 as u32
+        apic_id
     } else {
-        (id_u8 as u32) << 24
+        apic_id << 24
     }
 }
 
@@ -114,8 +244,36 @@ pub(super) fn init_primary() {
     }
 
     info!("Initialize IO APIC...");
-    let io_apic = unsafe { IoApic::new(phys_to_virt(IO_APIC_BASE).as_usize() as u64) };
-    IO_APIC.init_once(SpinNoIrq::new(io_apic));
+    let mut io_apics = crate::acpi::io_apics()
+        .map(|info| (info.paddr, info.gsi_base))
+        .peekable();
+    let default = io_apics.peek().is_none().then_some((IO_APIC_BASE, 0));
+    let mut chips: [Option<IoApicChip>; crate::acpi::MAX_IO_APICS] = Default::default();
+    for ((paddr, gsi_base), chip) in io_apics.chain(default).zip(&mut chips) {
+        let mut io_apic = unsafe { IoApic::new(phys_to_virt(paddr).as_usize() as u64) };
+        let num_entries = unsafe { io_apic.max_table_entry() } as u32 + 1;
+        info!(
+            "IO APIC at {:#x}: GSI {}..{}",
+            paddr,
+            gsi_base,
+            gsi_base + num_entries
+        );
+        // Route the GSIs with vectors to the primary CPU, masked.
+        for pin in 0..num_entries.min(NUM_GSI_VECTORS.saturating_sub(gsi_base)) {
+            let mut entry = RedirectionTableEntry::default();
+            entry.set_mode(IrqMode::Fixed);
+            entry.set_flags(IrqFlags::MASKED);
+            entry.set_vector(GSI_VECTOR_START + (gsi_base + pin) as u8);
+            entry.set_dest(apic_id_of(0) as u8);
+            unsafe { io_apic.set_table_entry(pin as u8, entry) };
+        }
+        *chip = Some(IoApicChip {
+            gsi_base,
+            num_entries,
+            io_apic: SpinNoIrq::new(io_apic),
+        });
+    }
+    IO_APICS.init_once(chips);
 }
 
 #[cfg(feature = "smp")]
@@ -7,8 +7,9 @@ use axconfig::{PHYS_VIRT_OFFSET, TASK_STACK_SIZE};
 
 /// Flags set in the ’flags’ member of the multiboot header.
 ///
-/// (bits 1, 16: memory information, address fields in header)
-const MULTIBOOT_HEADER_FLAGS: usize = 0x0001_0002;
+/// (bits 0, 1, 16: page-aligned modules, memory information, address fields
+/// in header)
+const MULTIBOOT_HEADER_FLAGS: usize = 0x0001_0003;
 
 /// The magic field should contain this.
 const MULTIBOOT_HEADER_MAGIC: usize = 0x1BADB002;
@@ -16,6 +17,16 @@ const MULTIBOOT_HEADER_MAGIC: usize = 0x1BADB002;
 /// This should be in EAX.
 pub(super) const MULTIBOOT_BOOTLOADER_MAGIC: usize = 0x2BADB002;
 
+/// The magic field of the multiboot2 header.
+const MULTIBOOT2_HEADER_MAGIC: usize = 0xE85250D6;
+
+/// This should be in EAX when booted by a multiboot2 bootloader.
+pub(super) const MULTIBOOT2_BOOTLOADER_MAGIC: usize = 0x36D76289;
+
+/// Passed to `rust_entry` as the magic when booted with the Linux/x86 boot
+/// protocol (the "HdrS" signature of the setup header).
+pub(super) const LINUX_BOOT_MAGIC: usize = 0x53726448;
+
 const CR0: u64 = Cr0Flags::PROTECTED_MODE_ENABLE.bits()
     | Cr0Flags::MONITOR_COPROCESSOR.bits()
     | Cr0Flags::NUMERIC_ERROR.bits()
@@ -38,6 +49,8 @@ global_asm!(
     mb_magic = const MULTIBOOT_BOOTLOADER_MAGIC,
     mb_hdr_magic = const MULTIBOOT_HEADER_MAGIC,
     mb_hdr_flags = const MULTIBOOT_HEADER_FLAGS,
+    mb2_hdr_magic = const MULTIBOOT2_HEADER_MAGIC,
+    linux_magic = const LINUX_BOOT_MAGIC,
     entry = sym super::rust_entry,
     entry_secondary = sym super::rust_entry_secondary,
 
@@ -50,3 +63,9 @@ global_asm!(
     efer_msr = const x86::msr::IA32_EFER,
     efer = const EFER,
 );
+
+global_asm!(
+    include_str!("linux_setup.S"),
+    offset = const PHYS_VIRT_OFFSET,
+    cmdline_size = const crate::bootinfo::MAX_CMDLINE_LEN,
+);
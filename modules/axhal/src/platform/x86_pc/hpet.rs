@@ -0,0 +1,80 @@
+//! High Precision Event Timer (HPET), which is only used to calibrate the TSC
+//! and the local APIC timer.
+//!
+//! See the IA-PC HPET specification.
+
+use memory_addr::VirtAddr;
+
+use crate::mem::phys_to_virt;
+
+const HPET_CAP: usize = 0x00;
+const HPET_CONFIG: usize = 0x10;
+const HPET_COUNTER: usize = 0xf0;
+
+/// The `COUNT_SIZE_CAP` bit of the capabilities register: the main counter
+/// is 64-bit wide (otherwise 32-bit).
+const HPET_CAP_COUNT_SIZE_64: u64 = 1 << 13;
+
+/// The `ENABLE_CNF` bit of the general configuration register.
+const HPET_CONFIG_ENABLE: u64 = 1 << 0;
+
+const FEMTOS_PER_NANO: u64 = 1_000_000;
+
+pub(super) struct Hpet {
+    base: VirtAddr,
+    /// The period of the main counter in femtoseconds.
+    period_fs: u64,
+    /// The mask of the valid bits of the main counter.
+    counter_mask: u64,
+}
+
+impl Hpet {
+    /// Enables the main counter of the HPET in the ACPI HPET table, returns
+    /// [`None`] if there is no HPET.
+    pub fn new() -> Option<Self> {
+        let base = phys_to_virt(crate::acpi::hpet_paddr()?);
+        let mut hpet = Self {
+            base,
+            period_fs: 0,
+            counter_mask: u64::MAX,
+        };
+        unsafe {
+            let cap = hpet.read(HPET_CAP);
+            hpet.period_fs = cap >> 32;
+            if hpet.period_fs == 0 {
+                return None;
+            }
+            if cap & HPET_CAP_COUNT_SIZE_64 == 0 {
+                hpet.counter_mask = u32::MAX as u64;
+            }
+            let config = hpet.read(HPET_CONFIG);
+            hpet.write(HPET_CONFIG, config | HPET_CONFIG_ENABLE);
+        }
+        Some(hpet)
+    }
+
+    unsafe fn read(&self, reg: usize) -> u64 {
+        ((self.base.as_usize() + reg) as *const u64).read_volatile()
+    }
+
+    unsafe fn write(&self, reg: usize, value: u64) {
+        ((self.base.as_usize() + reg) as *mut u64).write_volatile(value)
+    }
+
+    /// Returns the value of the main counter.
+    pub fn counter(&self) -> u64 {
+        unsafe { self.read(HPET_COUNTER) & self.counter_mask }
+    }
+
+    /// Busy waits for the given nanoseconds, with the main counter.
+    ///
+    /// The wait must be shorter than a wrap-around of the main counter
+    /// (about 5 minutes for a 32-bit counter at 14.318 MHz).
+    pub fn busy_wait_nanos(&self, nanos: u64) {
+        let ticks = nanos * FEMTOS_PER_NANO / self.period_fs;
+        let start = self.counter();
+        while self.counter().wrapping_sub(start) & self.counter_mask < ticks {
+            core::hint::spin_loop();
+        }
+    }
+}
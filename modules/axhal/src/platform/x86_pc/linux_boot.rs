@@ -0,0 +1,99 @@
+//! Parsing of the boot parameters (the "zero page") of the Linux/x86 boot
+//! protocol.
+//!
+//! See <https://www.kernel.org/doc/html/latest/arch/x86/zero-page.html>.
+
+use super::multiboot::{add_memory, bytes, cstr, read};
+use crate::bootinfo::{BootInfo, Framebuffer, ACPI_RSDP_LEN, MAX_CMDLINE_LEN};
+
+const BP_SCREEN_INFO: usize = 0x000;
+const BP_ACPI_RSDP_ADDR: usize = 0x070;
+const BP_EXT_RAMDISK_IMAGE: usize = 0x0c0;
+const BP_EXT_RAMDISK_SIZE: usize = 0x0c4;
+const BP_EXT_CMD_LINE_PTR: usize = 0x0c8;
+const BP_E820_ENTRIES: usize = 0x1e8;
+const BP_RAMDISK_IMAGE: usize = 0x218;
+const BP_RAMDISK_SIZE: usize = 0x21c;
+const BP_CMD_LINE_PTR: usize = 0x228;
+const BP_E820_TABLE: usize = 0x2d0;
+
+/// Offsets in `struct screen_info`.
+const SI_VIDEO_TYPE: usize = 0x0f;
+const SI_LFB_WIDTH: usize = 0x12;
+const SI_LFB_HEIGHT: usize = 0x14;
+const SI_LFB_DEPTH: usize = 0x16;
+const SI_LFB_BASE: usize = 0x18;
+const SI_LFB_LINELENGTH: usize = 0x24;
+const SI_EXT_LFB_BASE: usize = 0x3a;
+
+/// VESA VGA in graphic mode.
+const VIDEO_TYPE_VLFB: u8 = 0x23;
+/// EFI graphic mode.
+const VIDEO_TYPE_EFI: u8 = 0x70;
+
+const E820_MAX_ENTRIES: usize = 128;
+const E820_ENTRY_SIZE: usize = 20;
+const E820_TYPE_RAM: u32 = 1;
+
+/// Length of the RSDP of ACPI 1.0.
+const ACPI_RSDP_V1_LEN: usize = 20;
+
+/// Reads a 64-bit value split into the low 32 bits at `lo` and the high 32
+/// bits at `hi`.
+unsafe fn read_split(lo: usize, hi: usize) -> usize {
+    read::<u32>(lo) as usize | (read::<u32>(hi) as usize) << 32
+}
+
+/// Collects the memory map, the command line, the initial ramdisk, the
+/// framebuffer and the ACPI RSDP from the boot parameters at `bp`.
+///
+/// The structure must be accessible in the linear mapping of the boot page
+/// table (the bootloader places it below 4G).
+pub(super) unsafe fn parse(bp: usize) -> BootInfo {
+    let mut info = BootInfo::new();
+
+    let cmdline = read_split(bp + BP_CMD_LINE_PTR, bp + BP_EXT_CMD_LINE_PTR);
+    if cmdline != 0 {
+        info.set_cmdline(cstr(cmdline, MAX_CMDLINE_LEN));
+    }
+
+    let ramdisk = read_split(bp + BP_RAMDISK_IMAGE, bp + BP_EXT_RAMDISK_IMAGE);
+    let ramdisk_size = read_split(bp + BP_RAMDISK_SIZE, bp + BP_EXT_RAMDISK_SIZE);
+    if ramdisk != 0 && ramdisk_size != 0 {
+        info.add_module(ramdisk, ramdisk_size, b"initrd");
+    }
+
+    let entries = (read::<u8>(bp + BP_E820_ENTRIES) as usize).min(E820_MAX_ENTRIES);
+    for i in 0..entries {
+        let entry = bp + BP_E820_TABLE + i * E820_ENTRY_SIZE;
+        let base: u64 = read(entry);
+        let len: u64 = read(entry + 8);
+        let ty: u32 = read(entry + 16);
+        add_memory(&mut info, base, len, ty == E820_TYPE_RAM);
+    }
+
+    let si = bp + BP_SCREEN_INFO;
+    let video_type: u8 = read(si + SI_VIDEO_TYPE);
+    if video_type == VIDEO_TYPE_VLFB || video_type == VIDEO_TYPE_EFI {
+        info.framebuffer = Some(Framebuffer {
+            paddr: read_split(si + SI_LFB_BASE, si + SI_EXT_LFB_BASE).into(),
+            width: read::<u16>(si + SI_LFB_WIDTH) as u32,
+            height: read::<u16>(si + SI_LFB_HEIGHT) as u32,
+            pitch: read::<u16>(si + SI_LFB_LINELENGTH) as u32,
+            bpp: read::<u16>(si + SI_LFB_DEPTH) as u8,
+        });
+    }
+
+    let rsdp: u64 = read(bp + BP_ACPI_RSDP_ADDR);
+    if rsdp != 0 {
+        // The `revision` field is 0 for ACPI 1.0.
+        let revision: u8 = read(rsdp as usize + 15);
+        let len = if revision == 0 {
+            ACPI_RSDP_V1_LEN
+        } else {
+            ACPI_RSDP_LEN
+        };
+        info.set_acpi_rsdp(bytes(rsdp as usize, len));
+    }
+    info
+}
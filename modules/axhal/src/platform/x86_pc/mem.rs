@@ -1,8 +1,7 @@
-// TODO: get memory regions from multiboot info.
-
 use crate::mem::{MemRegion, MemRegionFlags};
 
-/// Returns platform-specific memory regions.
+/// Returns platform-specific memory regions, from the memory map passed by the
+/// bootloader and the ACPI tables if any.
 pub(crate) fn platform_regions() -> impl Iterator<Item = MemRegion> {
     core::iter::once(MemRegion {
         paddr: pa!(0x1000),
@@ -10,6 +9,8 @@ pub(crate) fn platform_regions() -> impl Iterator<Item = MemRegion> {
         flags: MemRegionFlags::RESERVED | MemRegionFlags::READ | MemRegionFlags::WRITE,
         name: "low memory",
     })
-    .chain(crate::mem::default_free_regions())
-    .chain(crate::mem::default_mmio_regions())
+    .chain(crate::mem::bootinfo_free_regions())
+    .chain(crate::mem::boot_module_regions())
+    .chain(crate::mem::acpi_mmio_regions())
+    .chain(crate::mem::boot_framebuffer_regions())
 }
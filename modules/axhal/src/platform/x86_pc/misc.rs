@@ -1,18 +1,61 @@
-use x86_64::instructions::port::PortWriteOnly;
+use x86_64::instructions::port::{Port, PortWriteOnly};
 
-/// Shutdown the whole system (in QEMU), including all CPUs.
+use crate::time::{busy_wait, Duration};
+
+/// The `SCI_EN` bit of the PM1 control register: ACPI is enabled.
+const PM1_SCI_EN: u16 = 1 << 0;
+/// The `SLP_TYP` field of the PM1 control register.
+const PM1_SLP_TYP_SHIFT: u16 = 10;
+const PM1_SLP_TYP_MASK: u16 = 0b111 << PM1_SLP_TYP_SHIFT;
+/// The `SLP_EN` bit of the PM1 control register.
+const PM1_SLP_EN: u16 = 1 << 13;
+
+/// The keyboard controller command to pulse the CPU reset line.
+const KBC_CMD_RESET: u8 = 0xfe;
+const KBC_CMD_PORT: u16 = 0x64;
+
+/// Enters the ACPI S5 (soft-off) state, with the PM1 control registers in the
+/// FADT. Returns if there is no ACPI or no S5 state.
+fn acpi_poweroff() {
+    let Some(power) = crate::acpi::power() else {
+        return;
+    };
+    let Some((slp_typ_a, slp_typ_b)) = power.s5_slp_typ else {
+        return;
+    };
+    unsafe {
+        let mut pm1a = Port::<u16>::new(power.pm1a_cnt);
+        if pm1a.read() & PM1_SCI_EN == 0 && power.smi_cmd != 0 && power.acpi_enable != 0 {
+            // Switch from the legacy mode to the ACPI mode.
+            PortWriteOnly::<u8>::new(power.smi_cmd).write(power.acpi_enable);
+            for _ in 0..300 {
+                if pm1a.read() & PM1_SCI_EN != 0 {
+                    break;
+                }
+                busy_wait(Duration::from_millis(10));
+            }
+        }
+        let mut sleep = |port: u16, slp_typ: u16| {
+            let mut pm1 = Port::<u16>::new(port);
+            let value = pm1.read() & !(PM1_SLP_TYP_MASK | PM1_SLP_EN);
+            pm1.write(value | (slp_typ << PM1_SLP_TYP_SHIFT) | PM1_SLP_EN);
+        };
+        if power.pm1b_cnt != 0 {
+            sleep(power.pm1b_cnt, slp_typ_b);
+        }
+        sleep(power.pm1a_cnt, slp_typ_a);
+    }
+}
+
+/// Powers off the whole system, including all CPUs.
+///
+/// It enters the ACPI S5 (soft-off) state. Without ACPI, it only works in
+/// QEMU (`x86_64-qemu-q35`).
 ///
 /// See <https://wiki.osdev.org/Shutdown> for more information.
-pub fn terminate() -> ! {
+pub fn poweroff() -> ! {
     info!("Shutting down...");
-
-    #[cfg(platform = "x86_64-pc-oslab")]
-    {
-        axlog::ax_println!("System will reboot, press any key to continue ...");
-        while super::console::getchar().is_none() {}
-        axlog::ax_println!("Rebooting ...");
-        unsafe { PortWriteOnly::new(0x64).write(0xfeu8) };
-    }
+    acpi_poweroff();
 
     #[cfg(platform = "x86_64-qemu-q35")]
     unsafe {
@@ -25,3 +68,37 @@ pub fn terminate() -> ! {
         crate::arch::halt();
     }
 }
+
+/// Reboots the whole system.
+///
+/// It writes the reset register in the ACPI FADT if supported, otherwise
+/// resets the CPU with the keyboard controller.
+pub fn reboot() -> ! {
+    info!("Rebooting...");
+    if let Some((port, value)) = crate::acpi::power().and_then(|power| power.reset_reg) {
+        unsafe { PortWriteOnly::<u8>::new(port).write(value) };
+        busy_wait(Duration::from_millis(100));
+    }
+    unsafe { PortWriteOnly::<u8>::new(KBC_CMD_PORT).write(KBC_CMD_RESET) };
+    busy_wait(Duration::from_millis(100));
+
+    warn!("It should reboot!");
+    loop {
+        crate::arch::halt();
+    }
+}
+
+/// Shutdown the whole system, including all CPUs.
+///
+/// On `x86_64-pc-oslab`, it reboots the system after a key press instead.
+pub fn terminate() -> ! {
+    #[cfg(platform = "x86_64-pc-oslab")]
+    {
+        axlog::ax_println!("System will reboot, press any key to continue ...");
+        while super::console::getchar().is_none() {}
+        reboot()
+    }
+
+    #[cfg(not(platform = "x86_64-pc-oslab"))]
+    poweroff()
+}
@@ -1,5 +1,8 @@
 mod apic;
 mod boot;
+mod hpet;
+mod linux_boot;
+mod multiboot;
 mod uart16550;
 
 pub mod mem;
@@ -24,22 +27,30 @@ extern "C" {
     fn rust_main_secondary(cpu_id: usize) -> !;
 }
 
+/// Returns the ID of the current CPU, which is its index in the ACPI MADT, or
+/// its initial local APIC ID if there is no MADT.
 fn current_cpu_id() -> usize {
-    match raw_cpuid::CpuId::new().get_feature_info() {
-        Some(finfo) => finfo.initial_local_apic_id() as usize,
+    let apic_id = match raw_cpuid::CpuId::new().get_feature_info() {
+        Some(finfo) => finfo.initial_local_apic_id() as u32,
         None => 0,
-    }
+    };
+    crate::acpi::cpu_id_of_apic(apic_id).unwrap_or(apic_id as usize)
 }
 
-unsafe extern "C" fn rust_entry(magic: usize, _mbi: usize) {
-    // TODO: handle multiboot info
-    if magic == self::boot::MULTIBOOT_BOOTLOADER_MAGIC {
-        crate::mem::clear_bss();
-        crate::cpu::init_primary(current_cpu_id());
-        self::uart16550::init();
-        self::time::init_early();
-        rust_main(current_cpu_id(), 0);
-    }
+unsafe extern "C" fn rust_entry(magic: usize, info: usize) {
+    let parse = match magic {
+        self::boot::MULTIBOOT_BOOTLOADER_MAGIC => self::multiboot::parse,
+        self::boot::MULTIBOOT2_BOOTLOADER_MAGIC => self::multiboot::parse_v2,
+        self::boot::LINUX_BOOT_MAGIC => self::linux_boot::parse,
+        _ => return,
+    };
+    crate::mem::clear_bss();
+    crate::bootinfo::init(parse(info));
+    crate::acpi::init(crate::bootinfo::acpi_rsdp());
+    crate::cpu::init_primary(current_cpu_id());
+    self::uart16550::init();
+    self::time::init_early();
+    rust_main(current_cpu_id(), 0);
 }
 
 #[allow(unused_variables)]
@@ -55,6 +66,8 @@ unsafe extern "C" fn rust_entry_secondary(magic: usize) {
 pub fn platform_init() {
     self::apic::init_primary();
     self::time::init_primary();
+    #[cfg(feature = "irq")]
+    self::uart16550::init_irq();
 }
 
 /// Initializes the platform devices for secondary CPUs.
@@ -29,10 +29,13 @@ unsafe fn setup_startup_page(stack_top: PhysAddr) {
 }
 
 /// Starts the given secondary CPU with its boot stack.
-pub fn start_secondary_cpu(apic_id: usize, stack_top: PhysAddr) {
+///
+/// The CPU is identified by its ID, which is mapped to its local APIC ID with
+/// the ACPI MADT.
+pub fn start_secondary_cpu(cpu_id: usize, stack_top: PhysAddr) {
     unsafe { setup_startup_page(stack_top) };
 
-    let apic_id = super::apic::raw_apic_id(apic_id as u8);
+    let apic_id = super::apic::raw_apic_id(super::apic::apic_id_of(cpu_id));
     let lapic = super::apic::local_apic();
 
     // INIT-SIPI-SIPI Sequence
@@ -0,0 +1,222 @@
+//! Parsing of the multiboot and multiboot2 information structures.
+//!
+//! See <https://www.gnu.org/software/grub/manual/multiboot/multiboot.html> and
+//! <https://www.gnu.org/software/grub/manual/multiboot2/multiboot.html>.
+
+use crate::bootinfo::{BootInfo, Framebuffer, MAX_CMDLINE_LEN};
+use crate::mem::phys_to_virt;
+
+/// The `mem_lower` and `mem_upper` fields are valid.
+const MB_INFO_MEMORY: u32 = 1 << 0;
+/// The `cmdline` field is valid.
+const MB_INFO_CMDLINE: u32 = 1 << 2;
+/// The `mods_count` and `mods_addr` fields are valid.
+const MB_INFO_MODS: u32 = 1 << 3;
+/// The `mmap_length` and `mmap_addr` fields are valid.
+const MB_INFO_MEM_MAP: u32 = 1 << 6;
+
+/// Memory map entry type of the available RAM.
+const MB_MEMORY_AVAILABLE: u32 = 1;
+
+/// Size of a module entry (`mod_start`, `mod_end`, `string`, `reserved`).
+const MB_MOD_ENTRY_SIZE: usize = 16;
+
+const MB2_TAG_END: u32 = 0;
+const MB2_TAG_CMDLINE: u32 = 1;
+const MB2_TAG_MODULE: u32 = 3;
+const MB2_TAG_BASIC_MEMINFO: u32 = 4;
+const MB2_TAG_MMAP: u32 = 6;
+const MB2_TAG_FRAMEBUFFER: u32 = 8;
+const MB2_TAG_ACPI_OLD: u32 = 14;
+const MB2_TAG_ACPI_NEW: u32 = 15;
+const MB2_TAG_EFI_MMAP: u32 = 17;
+
+/// Framebuffer type of direct RGB color.
+const MB2_FRAMEBUFFER_TYPE_RGB: u8 = 1;
+
+/// Memory types of the EFI memory map that are usable after the boot
+/// services exit: loader code and data, boot services code and data, and
+/// conventional memory.
+const EFI_USABLE_MEMORY_TYPES: [u32; 5] = [1, 2, 3, 4, 7];
+
+const EFI_PAGE_SIZE: usize = 0x1000;
+
+pub(super) unsafe fn read<T: Copy>(paddr: usize) -> T {
+    phys_to_virt(paddr.into())
+        .as_ptr()
+        .cast::<T>()
+        .read_unaligned()
+}
+
+/// Returns the `len` bytes at `paddr`.
+pub(super) unsafe fn bytes(paddr: usize, len: usize) -> &'static [u8] {
+    core::slice::from_raw_parts(phys_to_virt(paddr.into()).as_ptr(), len)
+}
+
+/// Returns the bytes of the NUL-terminated string at `paddr`, at most
+/// `max_len` bytes.
+pub(super) unsafe fn cstr(paddr: usize, max_len: usize) -> &'static [u8] {
+    let ptr = phys_to_virt(paddr.into()).as_ptr();
+    let mut len = 0;
+    while len < max_len && *ptr.add(len) != 0 {
+        len += 1;
+    }
+    core::slice::from_raw_parts(ptr, len)
+}
+
+/// Records a memory map entry, which is usable RAM if `available`.
+pub(super) fn add_memory(info: &mut BootInfo, base: u64, len: u64, available: bool) {
+    if len == 0 {
+        return;
+    }
+    if available {
+        info.memory.push(base as usize, len as usize);
+    } else {
+        info.reserved.reserve(base as usize, len as usize);
+    }
+}
+
+/// Collects the memory map, the command line and the modules from the
+/// multiboot information structure at `mbi`.
+///
+/// The structure must be accessible in the linear mapping of the boot page
+/// table (the bootloader places it below 4G).
+pub(super) unsafe fn parse(mbi: usize) -> BootInfo {
+    let mut info = BootInfo::new();
+    let flags: u32 = read(mbi);
+
+    if flags & MB_INFO_CMDLINE != 0 {
+        let cmdline: u32 = read(mbi + 16);
+        if cmdline != 0 {
+            info.set_cmdline(cstr(cmdline as usize, MAX_CMDLINE_LEN));
+        }
+    }
+
+    if flags & MB_INFO_MODS != 0 {
+        let mods_count: u32 = read(mbi + 20);
+        let mods_addr: u32 = read(mbi + 24);
+        for i in 0..mods_count as usize {
+            let entry = mods_addr as usize + i * MB_MOD_ENTRY_SIZE;
+            let start: u32 = read(entry);
+            let end: u32 = read(entry + 4);
+            let name: u32 = read(entry + 8);
+            let name = if name != 0 {
+                cstr(name as usize, MAX_CMDLINE_LEN)
+            } else {
+                &[]
+            };
+            info.add_module(start as usize, end.saturating_sub(start) as usize, name);
+        }
+    }
+
+    if flags & MB_INFO_MEM_MAP != 0 {
+        let mmap_length: u32 = read(mbi + 44);
+        let mmap_addr: u32 = read(mbi + 48);
+        let end = mmap_addr as usize + mmap_length as usize;
+        let mut entry = mmap_addr as usize;
+        while entry < end {
+            // The `size` field does not include itself.
+            let size: u32 = read(entry);
+            let base: u64 = read(entry + 4);
+            let len: u64 = read(entry + 12);
+            let ty: u32 = read(entry + 20);
+            add_memory(&mut info, base, len, ty == MB_MEMORY_AVAILABLE);
+            entry += size as usize + 4;
+        }
+    } else if flags & MB_INFO_MEMORY != 0 {
+        // Only the amount of upper memory (starting at 1M) is known, in KB.
+        let mem_upper: u32 = read(mbi + 8);
+        info.memory.push(0x10_0000, mem_upper as usize * 1024);
+    }
+    info
+}
+
+/// Collects the memory map, the command line, the modules, the framebuffer
+/// and the ACPI RSDP from the multiboot2 information structure at `mbi`.
+///
+/// The memory map is from the EFI memory map if there is no multiboot2
+/// memory map. The structure must be accessible in the linear mapping of the
+/// boot page table (the bootloader places it below 4G).
+pub(super) unsafe fn parse_v2(mbi: usize) -> BootInfo {
+    let mut info = BootInfo::new();
+    let total_size: u32 = read(mbi);
+    let end = mbi + total_size as usize;
+    let mut has_mmap = false;
+    let mut efi_mmap = None;
+    let mut mem_upper = None;
+    let mut has_new_rsdp = false;
+
+    // Tags start after `total_size` and `reserved`, and are 8-byte aligned.
+    let mut tag = mbi + 8;
+    while tag + 8 <= end {
+        let ty: u32 = read(tag);
+        let size: u32 = read(tag + 4);
+        if size < 8 {
+            break;
+        }
+        match ty {
+            MB2_TAG_END => break,
+            MB2_TAG_CMDLINE => info.set_cmdline(cstr(tag + 8, MAX_CMDLINE_LEN)),
+            MB2_TAG_MODULE => {
+                let start: u32 = read(tag + 8);
+                let end: u32 = read(tag + 12);
+                let name = cstr(tag + 16, MAX_CMDLINE_LEN);
+                info.add_module(start as usize, end.saturating_sub(start) as usize, name);
+            }
+            MB2_TAG_BASIC_MEMINFO => mem_upper = Some(read::<u32>(tag + 12)),
+            MB2_TAG_MMAP => {
+                has_mmap = true;
+                let entry_size: u32 = read(tag + 8);
+                let mut entry = tag + 16;
+                while entry_size != 0 && entry + entry_size as usize <= tag + size as usize {
+                    let base: u64 = read(entry);
+                    let len: u64 = read(entry + 8);
+                    let ty: u32 = read(entry + 16);
+                    add_memory(&mut info, base, len, ty == MB_MEMORY_AVAILABLE);
+                    entry += entry_size as usize;
+                }
+            }
+            MB2_TAG_FRAMEBUFFER => {
+                let fb_type: u8 = read(tag + 29);
+                if fb_type == MB2_FRAMEBUFFER_TYPE_RGB {
+                    info.framebuffer = Some(Framebuffer {
+                        paddr: (read::<u64>(tag + 8) as usize).into(),
+                        pitch: read(tag + 16),
+                        width: read(tag + 20),
+                        height: read(tag + 24),
+                        bpp: read(tag + 28),
+                    });
+                }
+            }
+            // Prefer the RSDP of ACPI 2.0 or later.
+            MB2_TAG_ACPI_OLD if !has_new_rsdp => {
+                info.set_acpi_rsdp(bytes(tag + 8, size as usize - 8));
+            }
+            MB2_TAG_ACPI_NEW => {
+                has_new_rsdp = true;
+                info.set_acpi_rsdp(bytes(tag + 8, size as usize - 8));
+            }
+            MB2_TAG_EFI_MMAP => efi_mmap = Some((tag, size as usize)),
+            _ => {}
+        }
+        tag += (size as usize + 7) & !7;
+    }
+
+    if !has_mmap {
+        if let Some((tag, size)) = efi_mmap {
+            let desc_size: u32 = read(tag + 8);
+            let mut desc = tag + 16;
+            while desc_size != 0 && desc + desc_size as usize <= tag + size {
+                let ty: u32 = read(desc);
+                let base: u64 = read(desc + 8);
+                let pages: u64 = read(desc + 24);
+                let usable = EFI_USABLE_MEMORY_TYPES.contains(&ty);
+                add_memory(&mut info, base, pages * EFI_PAGE_SIZE as u64, usable);
+                desc += desc_size as usize;
+            }
+        } else if let Some(mem_upper) = mem_upper {
+            info.memory.push(0x10_0000, mem_upper as usize * 1024);
+        }
+    }
+    info
+}
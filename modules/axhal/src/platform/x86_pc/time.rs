@@ -1,10 +1,17 @@
 use raw_cpuid::CpuId;
 
+use super::hpet::Hpet;
+
 #[cfg(feature = "irq")]
 use int_ratio::Ratio;
 
+/// The LAPIC timer frequency, used if there is no HPET to calibrate it.
 #[cfg(feature = "irq")]
-const LAPIC_TICKS_PER_SEC: u64 = 1_000_000_000; // TODO: need to calibrate
+const LAPIC_TICKS_PER_SEC: u64 = 1_000_000_000;
+
+/// The duration to measure the TSC and the LAPIC timer frequencies with the
+/// HPET.
+const CALIBRATE_NANOS: u64 = 10 * crate::time::NANOS_PER_MILLIS;
 
 #[cfg(feature = "irq")]
 static mut NANOS_TO_LAPIC_TICKS_RATIO: Ratio = Ratio::zero();
@@ -57,11 +64,17 @@ pub(super) fn init_early() {
     if let Some(freq) = CpuId::new()
         .get_processor_frequency_info()
         .map(|info| info.processor_base_frequency())
+        .filter(|&freq| freq > 0)
     {
-        if freq > 0 {
-            axlog::ax_println!("Got TSC frequency by CPUID: {} MHz", freq);
-            unsafe { CPU_FREQ_MHZ = freq as u64 }
-        }
+        axlog::ax_println!("Got TSC frequency by CPUID: {} MHz", freq);
+        unsafe { CPU_FREQ_MHZ = freq as u64 }
+    } else if let Some(hpet) = Hpet::new() {
+        let start = unsafe { core::arch::x86_64::_rdtsc() };
+        hpet.busy_wait_nanos(CALIBRATE_NANOS);
+        let ticks = unsafe { core::arch::x86_64::_rdtsc() } - start;
+        let freq = ticks * 1_000 / CALIBRATE_NANOS;
+        axlog::ax_println!("Calibrated TSC frequency by HPET: {} MHz", freq);
+        unsafe { CPU_FREQ_MHZ = freq }
     }
 
     unsafe {
@@ -90,11 +103,23 @@ pub(super) fn init_primary() {
         lapic.set_timer_divide(TimerDivide::Div256); // indeed it is Div1, the name is confusing.
         lapic.enable_timer();
 
-        // TODO: calibrate with HPET
-        NANOS_TO_LAPIC_TICKS_RATIO = Ratio::new(
-            LAPIC_TICKS_PER_SEC as u32,
-            crate::time::NANOS_PER_SEC as u32,
-        );
+        let ticks_per_sec = match Hpet::new() {
+            Some(hpet) => {
+                lapic.set_timer_initial(u32::MAX);
+                hpet.busy_wait_nanos(CALIBRATE_NANOS);
+                let ticks = (u32::MAX - lapic.timer_current()) as u64;
+                lapic.set_timer_initial(0);
+                let ticks_per_sec = ticks * crate::time::NANOS_PER_SEC / CALIBRATE_NANOS;
+                info!(
+                    "Calibrated LAPIC timer frequency by HPET: {} Hz",
+                    ticks_per_sec
+                );
+                ticks_per_sec
+            }
+            None => LAPIC_TICKS_PER_SEC,
+        };
+        NANOS_TO_LAPIC_TICKS_RATIO =
+            Ratio::new(ticks_per_sec as u32, crate::time::NANOS_PER_SEC as u32);
     }
 }
 
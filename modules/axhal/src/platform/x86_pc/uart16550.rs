@@ -27,6 +27,9 @@ struct Uart16550 {
     line_sts: PortReadOnly<u8>,
 }
 
+/// The "received data available" interrupt in the interrupt enable register.
+const INT_EN_RX_AVAILABLE: u8 = 1 << 0;
+
 impl Uart16550 {
     const fn new(port: u16) -> Self {
         Self {
@@ -65,6 +68,10 @@ impl Uart16550 {
         }
     }
 
+    fn enable_rx_interrupt(&mut self) {
+        unsafe { self.int_en.write(INT_EN_RX_AVAILABLE) };
+    }
+
     fn line_sts(&mut self) -> LineStsFlags {
         unsafe { LineStsFlags::from_bits_truncate(self.line_sts.read()) }
     }
@@ -103,3 +110,13 @@ pub fn getchar() -> Option<u8> {
 pub(super) fn init() {
     COM1.lock().init(115200);
 }
+
+/// Enables the receive interrupt of the UART.
+#[cfg(feature = "irq")]
+pub(super) fn init_irq() {
+    COM1.lock().enable_rx_interrupt();
+    crate::console::register_input_irq(
+        crate::platform::irq::UART_IRQ_NUM,
+        crate::console::handle_input_irq,
+    );
+}
@@ -0,0 +1,130 @@
+//! Kernel entropy pool.
+//!
+//! The pool is seeded from the hardware counter (and the `RDRAND` instruction
+//! on x86_64 if available) on first use, and keeps collecting entropy from the
+//! timing of interrupts. The entropy is accumulated without locking, as it is
+//! added on every IRQ, and mixed into the pool when a number is generated.
+//! Random numbers are generated by a `xoshiro256**` generator over the pool
+//! state.
+//!
+//! It is **not** cryptographically secure, but good enough for things like
+//! address space layout randomization.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use kspin::SpinNoIrq;
+
+struct EntropyPool {
+    state: [u64; 4],
+    seeded: bool,
+}
+
+static POOL: SpinNoIrq<EntropyPool> = SpinNoIrq::new(EntropyPool {
+    state: [0; 4],
+    seeded: false,
+});
+
+/// The entropy added since the pool was last used.
+static ACCUMULATOR: AtomicU64 = AtomicU64::new(0);
+
+/// The `splitmix64` finalizer, used to spread input bits over the state.
+const fn mix64(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(target_arch = "x86_64")]
+fn hw_random() -> Option<u64> {
+    if !raw_cpuid::CpuId::new().get_feature_info()?.has_rdrand() {
+        return None;
+    }
+    let value: u64;
+    let ok: u8;
+    unsafe {
+        core::arch::asm!(
+            "rdrand {0}",
+            "setc {1}",
+            out(reg) value,
+            out(reg_byte) ok,
+        )
+    };
+    (ok != 0).then_some(value)
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn hw_random() -> Option<u64> {
+    None
+}
+
+impl EntropyPool {
+    fn mix_in(&mut self, data: u64) {
+        for (i, s) in self.state.iter_mut().enumerate() {
+            *s ^= mix64(data.rotate_left(i as u32 * 16) ^ *s);
+        }
+    }
+
+    fn seed(&mut self) {
+        self.mix_in(crate::time::current_ticks());
+        if let Some(r) = hw_random() {
+            self.mix_in(r);
+        }
+        // xoshiro must not have an all-zero state.
+        if self.state.iter().all(|&s| s == 0) {
+            self.state[0] = 1;
+        }
+        self.seeded = true;
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        if !self.seeded {
+            self.seed();
+        }
+        let s = &mut self.state;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        result
+    }
+}
+
+/// Mixes some data (e.g., a timestamp of an unpredictable event) into the
+/// entropy pool.
+///
+/// It does not lock, so it can be called in IRQ handlers on any CPU.
+pub fn add_entropy(data: u64) {
+    ACCUMULATOR
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |acc| {
+            Some(mix64(acc.rotate_left(17) ^ data))
+        })
+        .ok();
+}
+
+/// Returns a random 64-bit number.
+pub fn random_u64() -> u64 {
+    let mut pool = POOL.lock();
+    pool.mix_in(ACCUMULATOR.swap(0, Ordering::Relaxed));
+    // Also mix in the current time, so that the output depends on when it is
+    // requested, not only on the number of requests.
+    pool.mix_in(crate::time::current_ticks());
+    pool.next_u64()
+}
+
+/// Returns a random `usize` number.
+pub fn random_usize() -> usize {
+    random_u64() as usize
+}
+
+/// Fills the buffer with random bytes.
+pub fn fill_bytes(buf: &mut [u8]) {
+    for chunk in buf.chunks_mut(8) {
+        let bytes = random_u64().to_ne_bytes();
+        chunk.copy_from_slice(&bytes[..chunk.len()]);
+    }
+}
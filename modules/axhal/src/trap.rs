@@ -1,45 +1,304 @@
 //! Trap handling.
+//!
+//! Handlers of each trap are registered in a distributed slice, and are
+//! called in descending order of priority until one of them handles the trap.
+//! A handler declines the trap by returning `false` (or [`None`] for
+//! syscalls, or `-ENOSYS` for the plain syscall handlers), so that, for
+//! example, a debugger, a user-space fault handler and demand paging can all
+//! hook page faults at once.
+//!
+//! Handlers registered with [`register_trap_handler`] in the plain slices
+//! ([`IRQ`], [`PAGE_FAULT`], `SYSCALL`) have the priority
+//! [`DEFAULT_PRIORITY`]. To specify a priority, register a [`TrapHandler`]
+//! in the corresponding `*_HANDLERS` slice instead:
+//!
+//! ```ignore
+//! use axhal::trap::{register_trap_handler, PageFaultFn, TrapHandler, PAGE_FAULT_HANDLERS};
+//!
+//! #[register_trap_handler(PAGE_FAULT_HANDLERS)]
+//! static DEBUGGER_PF: TrapHandler<PageFaultFn> = TrapHandler::new(100, debugger_page_fault);
+//! ```
 
 use linkme::distributed_slice as def_trap_handler;
 use memory_addr::VirtAddr;
 use page_table_entry::MappingFlags;
 
-#[cfg(feature = "uspace")]
 use crate::arch::TrapFrame;
 
 pub use linkme::distributed_slice as register_trap_handler;
 
+/// The priority of handlers without an explicit priority.
+pub const DEFAULT_PRIORITY: i32 = 0;
+
+/// An IRQ handler. Returns `true` if the IRQ is handled.
+pub type IrqFn = fn(usize) -> bool;
+
+/// A page fault handler, called with the fault address, the access type and
+/// whether the fault is from user mode. Returns `true` if the fault is
+/// handled.
+pub type PageFaultFn = fn(VirtAddr, MappingFlags, bool) -> bool;
+
+/// A syscall handler with priority, called with the trap frame and the
+/// syscall number. Returns [`None`] to decline the syscall.
+#[cfg(feature = "uspace")]
+pub type SyscallFn = fn(&TrapFrame, usize) -> Option<isize>;
+
+/// A trap handler with a priority.
+///
+/// Handlers with higher priorities are called first.
+pub struct TrapHandler<F> {
+    /// The priority of the handler.
+    pub priority: i32,
+    /// The handler function.
+    pub handler: F,
+}
+
+impl<F> TrapHandler<F> {
+    /// Creates a trap handler with the given priority.
+    pub const fn new(priority: i32, handler: F) -> Self {
+        Self { priority, handler }
+    }
+}
+
 /// A slice of IRQ handler functions.
 #[def_trap_handler]
 pub static IRQ: [fn(usize) -> bool];
 
+/// A slice of IRQ handlers with priorities.
+#[def_trap_handler]
+pub static IRQ_HANDLERS: [TrapHandler<IrqFn>];
+
 /// A slice of page fault handler functions.
 #[def_trap_handler]
 pub static PAGE_FAULT: [fn(VirtAddr, MappingFlags, bool) -> bool];
 
+/// A slice of page fault handlers with priorities.
+#[def_trap_handler]
+pub static PAGE_FAULT_HANDLERS: [TrapHandler<PageFaultFn>];
+
 /// A slice of syscall handler functions.
+///
+/// A handler declines a syscall by returning `-ENOSYS`, so that the handlers
+/// with lower priorities are tried.
 #[cfg(feature = "uspace")]
 #[def_trap_handler]
 pub static SYSCALL: [fn(&TrapFrame, usize) -> isize];
 
-#[allow(unused_macros)]
-macro_rules! handle_trap {
-    ($trap:ident, $($args:tt)*) => {{
-        let mut iter = $crate::trap::$trap.iter();
-        if let Some(func) = iter.next() {
-            if iter.next().is_some() {
-                warn!("Multiple handlers for trap {} are not currently supported", stringify!($trap));
+/// A slice of syscall handlers with priorities.
+#[cfg(feature = "uspace")]
+#[def_trap_handler]
+pub static SYSCALL_HANDLERS: [TrapHandler<SyscallFn>];
+
+/// A slice of functions called before returning to user space from a syscall
+/// or an exception, e.g., to terminate a killed task.
+///
+/// They are not called on the return from IRQs, as the interrupted context
+/// is not a safe point to exit the task at. A task killed while running in
+/// user space is terminated at its next syscall or exception.
+#[cfg(feature = "uspace")]
+#[def_trap_handler]
+pub static RETURN_TO_USER: [fn()];
+
+/// Calls the handlers in `plain` (with [`DEFAULT_PRIORITY`]) and `prio` in
+/// descending order of priority, until one of them returns `Some`.
+///
+/// Handlers with the same priority are called in the order of registration
+/// (which is unspecified across crates).
+fn dispatch<A: Copy, B: Copy, R>(
+    name: &str,
+    plain: &[A],
+    prio: &[TrapHandler<B>],
+    mut call_plain: impl FnMut(A) -> Option<R>,
+    mut call_prio: impl FnMut(B) -> Option<R>,
+) -> Option<R> {
+    let total = plain.len() + prio.len();
+    if total == 0 {
+        warn!("No registered handler for trap {}", name);
+        return None;
+    }
+    let priority = |i: usize| {
+        if i < plain.len() {
+            DEFAULT_PRIORITY
+        } else {
+            prio[i - plain.len()].priority
+        }
+    };
+    // Select the handlers one by one, ordered by (priority descending, index
+    // ascending), as there is no memory allocation in trap handling.
+    let mut last: Option<(i32, usize)> = None;
+    for _ in 0..total {
+        let mut next: Option<(i32, usize)> = None;
+        for i in 0..total {
+            let p = priority(i);
+            let after_last = last.map_or(true, |(lp, li)| p < lp || (p == lp && i > li));
+            let before_next = next.map_or(true, |(np, ni)| p > np || (p == np && i < ni));
+            if after_last && before_next {
+                next = Some((p, i));
             }
-            func($($args)*)
+        }
+        let (_, i) = next?;
+        let res = if i < plain.len() {
+            call_plain(plain[i])
         } else {
-            warn!("No registered handler for trap {}", stringify!($trap));
-            false
+            call_prio(prio[i - plain.len()].handler)
+        };
+        if res.is_some() {
+            return res;
         }
-    }}
+        last = next;
+    }
+    None
 }
 
-/// Call the external syscall handler.
+/// The trap frame of the IRQ being handled on each CPU, or 0.
+#[percpu::def_percpu]
+static IRQ_TRAP_FRAME: usize = 0;
+
+/// Calls `f` with the trap frame of the code interrupted by the IRQ being
+/// handled on the current CPU, e.g., to sample the interrupted PC.
+///
+/// Returns [`None`] if it is not called in an IRQ handler.
+pub fn with_irq_trap_frame<R>(f: impl FnOnce(&TrapFrame) -> R) -> Option<R> {
+    let _guard = kernel_guard::IrqSave::new();
+    let tf = unsafe { IRQ_TRAP_FRAME.read_current_raw() };
+    // Safety: the trap frame is alive until the IRQ handling returns.
+    (tf != 0).then(|| f(unsafe { &*(tf as *const TrapFrame) }))
+}
+
+/// Calls the IRQ handlers.
+#[allow(dead_code)]
+pub(crate) fn handle_irq(irq_num: usize, tf: &TrapFrame) -> bool {
+    let prev_tf = unsafe { IRQ_TRAP_FRAME.read_current_raw() };
+    unsafe { IRQ_TRAP_FRAME.write_current_raw(tf as *const _ as usize) };
+    let call = |f: IrqFn| f(irq_num).then_some(());
+    let res = dispatch("IRQ", &IRQ, &IRQ_HANDLERS, call, call).is_some();
+    unsafe { IRQ_TRAP_FRAME.write_current_raw(prev_tf) };
+    res
+}
+
+/// Calls the page fault handlers.
+#[allow(dead_code)]
+pub(crate) fn handle_page_fault(
+    vaddr: VirtAddr,
+    access_flags: MappingFlags,
+    is_user: bool,
+) -> bool {
+    let call = |f: PageFaultFn| f(vaddr, access_flags, is_user).then_some(());
+    dispatch("PAGE_FAULT", &PAGE_FAULT, &PAGE_FAULT_HANDLERS, call, call).is_some()
+}
+
+#[allow(unused_macros)]
+macro_rules! handle_trap {
+    (IRQ, $($args:tt)*) => {
+        $crate::trap::handle_irq($($args)*)
+    };
+    (PAGE_FAULT, $($args:tt)*) => {
+        $crate::trap::handle_page_fault($($args)*)
+    };
+}
+
+/// Call the external syscall handlers.
+///
+/// Returns `-ENOSYS` if no handler accepts the syscall.
 #[cfg(feature = "uspace")]
 pub(crate) fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
-    SYSCALL[0](tf, syscall_num)
+    const ENOSYS: isize = 38;
+    dispatch(
+        "SYSCALL",
+        &SYSCALL,
+        &SYSCALL_HANDLERS,
+        |f: fn(&TrapFrame, usize) -> isize| Some(f(tf, syscall_num)).filter(|&r| r != -ENOSYS),
+        |f: SyscallFn| f(tf, syscall_num),
+    )
+    .unwrap_or_else(|| {
+        warn!("Unhandled syscall {}", syscall_num);
+        -ENOSYS
+    })
+}
+
+/// Calls the functions in [`RETURN_TO_USER`].
+#[cfg(feature = "uspace")]
+pub(crate) fn handle_return_to_user() {
+    for f in RETURN_TO_USER {
+        f();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// Dispatches to the handlers named in `plain` and `prio`, the ones in
+    /// `accept` handle the trap. Returns the handler that handled it and the
+    /// ones called.
+    fn run(
+        plain: &[&'static str],
+        prio: &[TrapHandler<&'static str>],
+        accept: &[&str],
+    ) -> (Option<&'static str>, Vec<&'static str>) {
+        let called = RefCell::new(Vec::new());
+        let call = |name: &'static str| {
+            called.borrow_mut().push(name);
+            accept.contains(&name).then_some(name)
+        };
+        let res = dispatch("TEST", plain, prio, call, call);
+        (res, called.into_inner())
+    }
+
+    const PRIO: [TrapHandler<&str>; 4] = [
+        TrapHandler::new(-5, "low"),
+        TrapHandler::new(10, "high"),
+        TrapHandler::new(DEFAULT_PRIORITY, "default"),
+        TrapHandler::new(10, "high2"),
+    ];
+
+    #[test]
+    fn test_order() {
+        // All decline: descending priority, then registration order, with
+        // the plain handlers first among the default priority ones.
+        let (res, called) = run(&["plain0", "plain1"], &PRIO, &[]);
+        assert_eq!(res, None);
+        assert_eq!(
+            called,
+            ["high", "high2", "plain0", "plain1", "default", "low"]
+        );
+    }
+
+    #[test]
+    fn test_highest_wins() {
+        let (res, called) = run(&["plain0"], &PRIO, &["high2", "plain0", "low"]);
+        assert_eq!(res, Some("high2"));
+        assert_eq!(called, ["high", "high2"]);
+    }
+
+    #[test]
+    fn test_fall_through() {
+        // The higher ones decline, so the trap falls through to lower ones.
+        let (res, called) = run(&["plain0"], &PRIO, &["low"]);
+        assert_eq!(res, Some("low"));
+        assert_eq!(called.len(), 5);
+
+        let (res, called) = run(&["plain0", "plain1"], &PRIO, &["plain1", "default"]);
+        assert_eq!(res, Some("plain1"));
+        assert_eq!(called, ["high", "high2", "plain0", "plain1"]);
+    }
+
+    #[test]
+    fn test_plain_only() {
+        let (res, called) = run(&["plain0", "plain1"], &[], &["plain1"]);
+        assert_eq!(res, Some("plain1"));
+        assert_eq!(called, ["plain0", "plain1"]);
+
+        let (res, called) = run(&[], &PRIO[..1], &["low"]);
+        assert_eq!(res, Some("low"));
+        assert_eq!(called, ["low"]);
+    }
+
+    #[test]
+    fn test_no_handler() {
+        let (res, called) = run(&[], &[], &["plain0"]);
+        assert_eq!(res, None);
+        assert!(called.is_empty());
+    }
 }
@@ -18,6 +18,8 @@ pub struct AddrSpace {
     va_range: VirtAddrRange,
     areas: MemorySet<Backend>,
     pt: PageTable,
+    /// Whether it is the kernel address space, which is shared by all CPUs.
+    kernel: bool,
 }
 
 impl AddrSpace {
@@ -36,6 +38,14 @@ impl AddrSpace {
         self.va_range.size()
     }
 
+    /// Returns the total size of all mapped areas.
+    ///
+    /// Areas populated on demand are counted as a whole, even if some of
+    /// their pages have not been accessed yet.
+    pub fn mapped_size(&self) -> usize {
+        self.areas.iter().map(|area| area.size()).sum()
+    }
+
     /// Returns the reference to the inner page table.
     pub const fn page_table(&self) -> &PageTable {
         &self.pt
@@ -53,14 +63,35 @@ impl AddrSpace {
     }
 
     /// Creates a new empty address space.
-    pub(crate) fn new_empty(base: VirtAddr, size: usize) -> AxResult<Self> {
+    pub(crate) fn new_empty(base: VirtAddr, size: usize, kernel: bool) -> AxResult<Self> {
         Ok(Self {
             va_range: VirtAddrRange::from_start_size(base, size),
             areas: MemorySet::new(),
             pt: PageTable::try_new().map_err(|_| AxError::NoMemory)?,
+            kernel,
         })
     }
 
+    /// Returns the set of CPUs (as a bitmap) on which the address space is
+    /// active.
+    pub fn active_cpus(&self) -> usize {
+        if self.kernel {
+            usize::MAX
+        } else {
+            axhal::paging::cpus_using_page_table(self.page_table_root())
+        }
+    }
+
+    /// Flushes the TLB entries of the given range on other CPUs using the
+    /// address space, after the mappings are removed or changed, and waits
+    /// for the completion.
+    ///
+    /// The entries on the current CPU are flushed by the page table
+    /// operations.
+    pub(crate) fn flush_tlb_remote(&self, start: VirtAddr, size: usize) {
+        axhal::paging::flush_tlb_remote(self.active_cpus(), start, size, true);
+    }
+
     pub fn from_exited_space(other: &AddrSpace) -> AxResult<Self> {
         let mut aspace = new_user_aspace(other.base(), other.size())?;
         aspace.copy_from(other);
@@ -113,6 +144,38 @@ impl AddrSpace {
         self.areas.find_free_area(hint, size, limit)
     }
 
+    /// Finds a free area that can accommodate the given size, searching
+    /// downward from the given top address.
+    ///
+    /// The area should be below `top` and within the given limit range.
+    ///
+    /// Returns the start address of the highest such area. Returns None if no
+    /// such area is found.
+    pub fn find_free_area_top_down(
+        &self,
+        top: VirtAddr,
+        size: usize,
+        limit: VirtAddrRange,
+    ) -> Option<VirtAddr> {
+        let top = top.min(limit.end).align_down_4k();
+        let mut found = None;
+        // Check the gaps between the areas (sorted by address) below `top` in
+        // order, the last one that fits is the highest.
+        let mut gap_start = limit.start.align_up_4k();
+        let areas = self.areas.iter().map(|area| (area.start(), area.end()));
+        for (area_start, area_end) in areas.chain(core::iter::once((limit.end, limit.end))) {
+            let gap_end = area_start.min(top);
+            if gap_end > gap_start && gap_end - gap_start >= size {
+                found = Some(gap_end - size);
+            }
+            if area_start >= top {
+                break;
+            }
+            gap_start = gap_start.max(area_end);
+        }
+        found
+    }
+
     /// Add a new linear mapping.
     ///
     /// See [`Backend`] for more details about the mapping backends.
@@ -184,9 +247,12 @@ impl AddrSpace {
             return ax_err!(InvalidInput, "address not aligned");
         }
 
+        // The frames of allocation mappings are freed by the backend after
+        // the remote TLB flush, the flush here is for other mappings.
         self.areas
             .unmap(start, size, &mut self.pt)
             .map_err(mapping_err_to_ax_err)?;
+        self.flush_tlb_remote(start, size);
         Ok(())
     }
 
@@ -269,17 +335,38 @@ impl AddrSpace {
             return ax_err!(InvalidInput, "address not aligned");
         }
 
-        // TODO
+        self.protect_pages(start, size, flags)?;
+        self.flush_tlb_remote(start, size);
+        Ok(())
+    }
+
+    /// Changes the flags of the mapped pages in the range, flushing the TLB
+    /// entries on the current CPU only.
+    ///
+    /// Only the page table entries are updated, the areas are left unchanged,
+    /// so it never allocates memory as long as the range is mapped with 4K
+    /// pages, which is the case for linear mappings (see [`Backend`]).
+    pub(crate) fn protect_pages(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+    ) -> AxResult {
+        // TODO: update the flags of the areas
         self.pt
             .protect_region(start, size, flags, true)
             .map_err(|_| AxError::BadState)?
             .ignore();
+        for vaddr in PageIter4K::new(start, start + size).unwrap() {
+            axhal::arch::flush_tlb(Some(vaddr));
+        }
         Ok(())
     }
 
     /// Removes all mappings in the address space.
     pub fn clear(&mut self) {
         self.areas.clear(&mut self.pt).unwrap();
+        self.flush_tlb_remote(self.base(), self.size());
     }
 
     /// Handles a page fault at the given address.
@@ -4,6 +4,7 @@ use axhal::paging::{MappingFlags, PageSize, PageTable};
 use memory_addr::{PageIter4K, PhysAddr, VirtAddr, PAGE_SIZE_4K};
 
 use super::Backend;
+use crate::cpus_using_page_table;
 
 fn alloc_frame(zeroed: bool) -> Option<PhysAddr> {
     let vaddr = VirtAddr::from(global_allocator().alloc_pages(1, PAGE_SIZE_4K).ok()?);
@@ -19,6 +20,22 @@ fn dealloc_frame(frame: PhysAddr) {
     global_allocator().dealloc_pages(vaddr.as_usize(), 1);
 }
 
+/// Maximum number of frames unmapped before they are freed.
+const FREE_BATCH: usize = 64;
+
+/// Frees the frames unmapped from `[start, end)`, after flushing the TLB
+/// entries on other CPUs using the page table, as the frames may be still
+/// accessed through stale entries until then.
+fn flush_and_free_frames(pt: &PageTable, start: VirtAddr, end: VirtAddr, frames: &[PhysAddr]) {
+    if frames.is_empty() {
+        return;
+    }
+    axhal::paging::flush_tlb_remote(cpus_using_page_table(pt), start, end - start, true);
+    for &frame in frames {
+        dealloc_frame(frame);
+    }
+}
+
 impl Backend {
     /// Creates a new allocation mapping backend.
     pub const fn new_alloc(populate: bool) -> Self {
@@ -69,19 +86,31 @@ impl Backend {
         _populate: bool,
     ) -> bool {
         debug!("unmap_alloc: [{:#x}, {:#x})", start, start + size);
-        for addr in PageIter4K::new(start, start + size).unwrap() {
+        let end = start + size;
+        let mut frames = [PhysAddr::from(0); FREE_BATCH];
+        let mut num_frames = 0;
+        let mut batch_start = start;
+        for addr in PageIter4K::new(start, end).unwrap() {
             if let Ok((frame, page_size, tlb)) = pt.unmap(addr) {
                 // Deallocate the physical frame if there is a mapping in the
                 // page table.
                 if page_size.is_huge() {
+                    flush_and_free_frames(pt, batch_start, end, &frames[..num_frames]);
                     return false;
                 }
                 tlb.flush();
-                dealloc_frame(frame);
+                frames[num_frames] = frame;
+                num_frames += 1;
+                if num_frames == FREE_BATCH {
+                    flush_and_free_frames(pt, batch_start, addr + PAGE_SIZE_4K, &frames);
+                    num_frames = 0;
+                    batch_start = addr + PAGE_SIZE_4K;
+                }
             } else {
                 // Deallocation is needn't if the page is not mapped.
             }
         }
+        flush_and_free_frames(pt, batch_start, end, &frames[..num_frames]);
         true
     }
 
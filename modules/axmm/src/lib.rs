@@ -8,18 +8,21 @@ extern crate alloc;
 
 mod aspace;
 mod backend;
+mod lock;
 
 pub use self::aspace::AddrSpace;
 pub use self::backend::Backend;
+pub use self::lock::{KernelAspaceGuard, KernelAspaceLock};
 
 use axerrno::{AxError, AxResult};
 use axhal::mem::phys_to_virt;
-use kspin::SpinNoIrq;
+use axhal::paging::{MappingFlags, PageTable};
 use lazyinit::LazyInit;
-use memory_addr::{va, PhysAddr, VirtAddr};
+use memory_addr::{va, PhysAddr, VirtAddr, PAGE_SIZE_4K};
 use memory_set::MappingError;
 
-static KERNEL_ASPACE: LazyInit<SpinNoIrq<AddrSpace>> = LazyInit::new();
+/// The kernel address space, see [`KernelAspaceLock`].
+static KERNEL_ASPACE: LazyInit<KernelAspaceLock> = LazyInit::new();
 
 fn mapping_err_to_ax_err(err: MappingError) -> AxError {
     warn!("Mapping error: {:?}", err);
@@ -35,6 +38,7 @@ pub fn new_kernel_aspace() -> AxResult<AddrSpace> {
     let mut aspace = AddrSpace::new_empty(
         va!(axconfig::KERNEL_ASPACE_BASE),
         axconfig::KERNEL_ASPACE_SIZE,
+        true,
     )?;
     for r in axhal::mem::memory_regions() {
         if r.size == 0 {
@@ -48,7 +52,7 @@ pub fn new_kernel_aspace() -> AxResult<AddrSpace> {
 
 /// Creates a new address space for user processes.
 pub fn new_user_aspace(base: VirtAddr, size: usize) -> AxResult<AddrSpace> {
-    let mut aspace = AddrSpace::new_empty(base, size)?;
+    let mut aspace = AddrSpace::new_empty(base, size, false)?;
     if !cfg!(target_arch = "aarch64") {
         // ARMv8 use a separate page table (TTBR0_EL1) for user space, it
         // doesn't need to copy the kernel portion to the user page table.
@@ -58,7 +62,7 @@ pub fn new_user_aspace(base: VirtAddr, size: usize) -> AxResult<AddrSpace> {
 }
 
 /// Returns the globally unique kernel address space.
-pub fn kernel_aspace() -> &'static SpinNoIrq<AddrSpace> {
+pub fn kernel_aspace() -> &'static KernelAspaceLock {
     &KERNEL_ASPACE
 }
 
@@ -67,6 +71,83 @@ pub fn kernel_page_table_root() -> PhysAddr {
     KERNEL_ASPACE.lock().page_table_root()
 }
 
+/// Returns the set of CPUs (as a bitmap) on which the page table may be
+/// active, see [`AddrSpace::active_cpus`].
+pub(crate) fn cpus_using_page_table(pt: &PageTable) -> usize {
+    let root = pt.root_paddr();
+    if KERNEL_ASPACE.is_inited() && root == axhal::paging::kernel_page_table_root() {
+        usize::MAX
+    } else {
+        axhal::paging::cpus_using_page_table(root)
+    }
+}
+
+/// Changes the access permission of pages in the kernel address space.
+///
+/// The pages become readable and writable if `accessible` is true, otherwise
+/// any access to them will trap. It is suitable for the guard pages of the
+/// debug allocator, see [`protect_kernel_pages`] for the details.
+///
+/// If the current CPU holds the lock of the kernel address space (e.g., it
+/// allocates memory while mapping), making the pages inaccessible is deferred
+/// until the lock is released, and making them accessible fails.
+pub fn set_kernel_pages_accessible(vaddr: usize, num_pages: usize, accessible: bool) -> bool {
+    let flags = if accessible {
+        MappingFlags::READ | MappingFlags::WRITE
+    } else {
+        MappingFlags::empty()
+    };
+    if let Some(kernel_aspace) = KERNEL_ASPACE.get() {
+        if !accessible && kernel_aspace.is_held_by_current_cpu() {
+            let start = VirtAddr::from(vaddr);
+            return kernel_aspace.defer_protect(start, num_pages * PAGE_SIZE_4K, flags);
+        }
+    }
+    protect_kernel_pages(vaddr, num_pages, flags)
+}
+
+/// Changes the mapping flags of pages in the kernel address space, e.g., to
+/// make the kernel code writable for the debugger.
+///
+/// The pages must be in the linear mapping of the physical memory, which is
+/// set up with 4K pages by [`init_memory_management`]. Only the page table
+/// entries are updated, so it does not allocate memory and the number of
+/// areas does not grow. The TLB entries are flushed on all CPUs before it
+/// returns.
+///
+/// Returns `false` if the kernel address space is not initialized, the pages
+/// are not mapped, or the current CPU holds the lock of the kernel address
+/// space (which would deadlock).
+pub fn protect_kernel_pages(vaddr: usize, num_pages: usize, flags: MappingFlags) -> bool {
+    let Some(kernel_aspace) = KERNEL_ASPACE.get() else {
+        return false;
+    };
+    if kernel_aspace.is_held_by_current_cpu() {
+        warn!(
+            "protect_kernel_pages: kernel address space is locked by the current CPU, \
+             can not protect [{:#x}, {} pages)",
+            vaddr, num_pages
+        );
+        return false;
+    }
+    let start = VirtAddr::from(vaddr);
+    let size = num_pages * PAGE_SIZE_4K;
+    protect_locked(&mut kernel_aspace.lock(), start, size, flags)
+}
+
+fn protect_locked(
+    aspace: &mut AddrSpace,
+    start: VirtAddr,
+    size: usize,
+    flags: MappingFlags,
+) -> bool {
+    if aspace.protect_pages(start, size, flags).is_err() {
+        return false;
+    }
+    aspace.flush_tlb_remote(start, size);
+    true
+}
+
 /// Initializes virtual memory management.
 ///
 /// It mainly sets up the kernel virtual memory address space and recreate a
@@ -76,7 +157,7 @@ pub fn init_memory_management() {
 
     let kernel_aspace = new_kernel_aspace().expect("failed to initialize kernel address space");
     debug!("kernel address space init OK: {:#x?}", kernel_aspace);
-    KERNEL_ASPACE.init_once(SpinNoIrq::new(kernel_aspace));
+    KERNEL_ASPACE.init_once(KernelAspaceLock::new(kernel_aspace));
     axhal::paging::set_kernel_page_table_root(kernel_page_table_root());
 }
 
@@ -0,0 +1,125 @@
+//! The lock of the kernel address space.
+
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axhal::paging::MappingFlags;
+use kspin::{SpinNoIrq, SpinNoPreempt, SpinNoPreemptGuard};
+use memory_addr::VirtAddr;
+
+use crate::AddrSpace;
+
+/// Maximum number of page protections deferred while the lock is held.
+const MAX_DEFERRED: usize = 16;
+
+type Protection = (VirtAddr, usize, MappingFlags);
+
+struct Deferred {
+    list: [Protection; MAX_DEFERRED],
+    len: usize,
+}
+
+/// The lock of the kernel address space.
+///
+/// IRQs are not disabled while waiting for it, so that the TLB shootdowns of
+/// the holder can be served.
+///
+/// It remembers the CPU holding it. The page protections requested by that
+/// CPU while holding it (e.g., for the guard pages of the memory it allocates
+/// while mapping) can not take it again, so they are deferred until it is
+/// released.
+pub struct KernelAspaceLock {
+    inner: SpinNoPreempt<AddrSpace>,
+    /// ID of the CPU holding the lock plus 1, or 0 if it is not held.
+    owner: AtomicUsize,
+    deferred: SpinNoIrq<Deferred>,
+}
+
+/// A guard that provides mutable access to the kernel address space.
+///
+/// The deferred page protections are applied when it is dropped, before the
+/// lock is released.
+pub struct KernelAspaceGuard<'a> {
+    lock: &'a KernelAspaceLock,
+    guard: SpinNoPreemptGuard<'a, AddrSpace>,
+}
+
+impl KernelAspaceLock {
+    pub(crate) const fn new(aspace: AddrSpace) -> Self {
+        Self {
+            inner: SpinNoPreempt::new(aspace),
+            owner: AtomicUsize::new(0),
+            deferred: SpinNoIrq::new(Deferred {
+                list: [(VirtAddr::from_usize(0), 0, MappingFlags::empty()); MAX_DEFERRED],
+                len: 0,
+            }),
+        }
+    }
+
+    /// Locks the kernel address space, spinning until it is available.
+    pub fn lock(&self) -> KernelAspaceGuard<'_> {
+        let guard = self.inner.lock();
+        self.owner
+            .store(axhal::cpu::this_cpu_id() + 1, Ordering::Relaxed);
+        KernelAspaceGuard { lock: self, guard }
+    }
+
+    /// Whether the lock is held by the current CPU, in which case locking it
+    /// again would deadlock.
+    pub fn is_held_by_current_cpu(&self) -> bool {
+        // Preemption is disabled while the lock is held, so a task can not
+        // migrate to the owner CPU unless it is interrupting the owner.
+        self.owner.load(Ordering::Relaxed) == axhal::cpu::this_cpu_id() + 1
+    }
+
+    /// Defers changing the flags of `size` bytes of pages at `start` until
+    /// the lock is released. It must be called by the CPU holding the lock.
+    ///
+    /// Returns `false` if too many protections are deferred.
+    pub(crate) fn defer_protect(&self, start: VirtAddr, size: usize, flags: MappingFlags) -> bool {
+        let mut deferred = self.deferred.lock();
+        if deferred.len == MAX_DEFERRED {
+            return false;
+        }
+        let len = deferred.len;
+        deferred.list[len] = (start, size, flags);
+        deferred.len += 1;
+        true
+    }
+
+    fn pop_deferred(&self) -> Option<Protection> {
+        let mut deferred = self.deferred.lock();
+        deferred.len = deferred.len.checked_sub(1)?;
+        Some(deferred.list[deferred.len])
+    }
+}
+
+impl Deref for KernelAspaceGuard<'_> {
+    type Target = AddrSpace;
+
+    fn deref(&self) -> &AddrSpace {
+        &self.guard
+    }
+}
+
+impl DerefMut for KernelAspaceGuard<'_> {
+    fn deref_mut(&mut self) -> &mut AddrSpace {
+        &mut self.guard
+    }
+}
+
+impl Drop for KernelAspaceGuard<'_> {
+    fn drop(&mut self) {
+        while let Some((start, size, flags)) = self.lock.pop_deferred() {
+            if !crate::protect_locked(&mut self.guard, start, size, flags) {
+                warn!(
+                    "failed to protect the kernel pages [{:#x}, {:#x}) to {:?}",
+                    start,
+                    start + size,
+                    flags
+                );
+            }
+        }
+        self.lock.owner.store(0, Ordering::Relaxed);
+    }
+}
@@ -9,11 +9,15 @@
 //! - [`TcpSocket`]: A TCP socket that provides POSIX-like APIs.
 //! - [`UdpSocket`]: A UDP socket that provides POSIX-like APIs.
 //! - [`dns_query`]: Function for DNS query.
+//! - [`tcp_sockets`]: Function to list all TCP sockets and their states.
 //!
 //! # Cargo Features
 //!
 //! - `smoltcp`: Use [smoltcp] as the underlying network stack. This is enabled
 //!   by default.
+//! - `irq`, `multitask`: If both are enabled and the NIC raises interrupts,
+//!   blocking socket operations sleep until the next NIC interrupt or network
+//!   timer, instead of polling the NIC repeatedly.
 //!
 //! [smoltcp]: https://github.com/smoltcp-rs/smoltcp
 
@@ -35,6 +39,8 @@ pub use self::net_impl::TcpSocket;
 pub use self::net_impl::UdpSocket;
 pub use self::net_impl::{bench_receive, bench_transmit};
 pub use self::net_impl::{dns_query, poll_interfaces};
+pub use self::net_impl::{set_somaxconn, somaxconn};
+pub use self::net_impl::{tcp_sockets, TcpSocketInfo, TcpState};
 
 use axdriver::{prelude::*, AxDeviceContainer};
 
@@ -7,7 +7,7 @@ use smoltcp::socket::dns::{self, GetQueryResultError, StartQueryError};
 use smoltcp::wire::DnsQueryType;
 
 use super::addr::into_core_ipaddr;
-use super::{SocketSetWrapper, ETH0, SOCKET_SET};
+use super::{net_irq_count, wait_for_events, SocketSetWrapper, ETH0, SOCKET_SET};
 
 /// A DNS socket.
 struct DnsSocket {
@@ -52,6 +52,7 @@ impl DnsSocket {
                 }
             })?;
         loop {
+            let irq_count = net_irq_count();
             SOCKET_SET.poll_interfaces();
             match SOCKET_SET.with_socket_mut::<dns::Socket, _, _>(handle, |socket| {
                 socket.get_query_result(query_handle).map_err(|e| match e {
@@ -68,7 +69,7 @@ impl DnsSocket {
                     }
                     return Ok(res);
                 }
-                Err(AxError::WouldBlock) => axtask::yield_now(),
+                Err(AxError::WouldBlock) => wait_for_events(irq_count),
                 Err(e) => return Err(e),
             }
         }
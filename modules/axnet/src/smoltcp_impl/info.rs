@@ -0,0 +1,96 @@
+use alloc::vec::Vec;
+use core::net::SocketAddr;
+
+use smoltcp::socket::tcp::{self, State};
+use smoltcp::socket::AnySocket;
+use smoltcp::wire::IpEndpoint;
+
+use super::addr::{into_core_sockaddr, UNSPECIFIED_ENDPOINT, UNSPECIFIED_IP};
+use super::{somaxconn, LISTEN_TABLE, SOCKET_SET};
+
+/// The state of a TCP connection, numbered as in Linux.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TcpState {
+    Established = 1,
+    SynSent = 2,
+    SynReceived = 3,
+    FinWait1 = 4,
+    FinWait2 = 5,
+    TimeWait = 6,
+    Closed = 7,
+    CloseWait = 8,
+    LastAck = 9,
+    Listen = 10,
+    Closing = 11,
+}
+
+impl From<State> for TcpState {
+    fn from(state: State) -> Self {
+        match state {
+            State::Closed => Self::Closed,
+            State::Listen => Self::Listen,
+            State::SynSent => Self::SynSent,
+            State::SynReceived => Self::SynReceived,
+            State::Established => Self::Established,
+            State::FinWait1 => Self::FinWait1,
+            State::FinWait2 => Self::FinWait2,
+            State::CloseWait => Self::CloseWait,
+            State::Closing => Self::Closing,
+            State::LastAck => Self::LastAck,
+            State::TimeWait => Self::TimeWait,
+        }
+    }
+}
+
+/// The information of a TCP socket, see [`tcp_sockets`].
+#[derive(Debug, Clone, Copy)]
+pub struct TcpSocketInfo {
+    /// The local address and port.
+    pub local_addr: SocketAddr,
+    /// The remote address and port, unspecified if not connected.
+    pub peer_addr: SocketAddr,
+    /// The state of the connection.
+    pub state: TcpState,
+    /// The number of bytes waiting to be sent, or the maximum number of
+    /// pending connections of a listening socket.
+    pub tx_queue: usize,
+    /// The number of bytes received but not read yet, or the number of
+    /// pending connections of a listening socket.
+    pub rx_queue: usize,
+}
+
+/// Returns the information of all TCP sockets, including the listening ones.
+pub fn tcp_sockets() -> Vec<TcpSocketInfo> {
+    let mut sockets: Vec<_> = LISTEN_TABLE
+        .listening()
+        .into_iter()
+        .map(|(endpoint, pending)| TcpSocketInfo {
+            local_addr: into_core_sockaddr(IpEndpoint::new(
+                endpoint.addr.unwrap_or(UNSPECIFIED_IP),
+                endpoint.port,
+            )),
+            peer_addr: into_core_sockaddr(UNSPECIFIED_ENDPOINT),
+            state: TcpState::Listen,
+            tx_queue: somaxconn(),
+            rx_queue: pending,
+        })
+        .collect();
+
+    let set = SOCKET_SET.0.lock();
+    sockets.extend(set.iter().filter_map(|(_, socket)| {
+        let socket = tcp::Socket::downcast(socket)?;
+        // Sockets in the SYN queues of the listening ones are not connected.
+        if socket.state() == State::Listen {
+            return None;
+        }
+        Some(TcpSocketInfo {
+            local_addr: into_core_sockaddr(socket.local_endpoint()?),
+            peer_addr: into_core_sockaddr(socket.remote_endpoint().unwrap_or(UNSPECIFIED_ENDPOINT)),
+            state: socket.state().into(),
+            tx_queue: socket.send_queue(),
+            rx_queue: socket.recv_queue(),
+        })
+    }));
+    sockets
+}
@@ -1,4 +1,4 @@
-use alloc::{boxed::Box, collections::VecDeque};
+use alloc::{boxed::Box, collections::VecDeque, vec::Vec};
 use core::ops::{Deref, DerefMut};
 
 use axerrno::{ax_err, AxError, AxResult};
@@ -7,7 +7,7 @@ use smoltcp::iface::{SocketHandle, SocketSet};
 use smoltcp::socket::tcp::{self, State};
 use smoltcp::wire::{IpAddress, IpEndpoint, IpListenEndpoint};
 
-use super::{SocketSetWrapper, LISTEN_QUEUE_SIZE, SOCKET_SET};
+use super::{somaxconn, SocketSetWrapper, SOCKET_SET};
 
 const PORT_NUM: usize = 65536;
 
@@ -20,7 +20,7 @@ impl ListenTableEntry {
     pub fn new(listen_endpoint: IpListenEndpoint) -> Self {
         Self {
             listen_endpoint,
-            syn_queue: VecDeque::with_capacity(LISTEN_QUEUE_SIZE),
+            syn_queue: VecDeque::new(),
         }
     }
 
@@ -78,6 +78,19 @@ impl ListenTable {
         *self.tcp[port as usize].lock() = None;
     }
 
+    /// Returns the listening endpoints, and the number of pending connections
+    /// of each.
+    pub fn listening(&self) -> Vec<(IpListenEndpoint, usize)> {
+        self.tcp
+            .iter()
+            .filter_map(|entry| {
+                let entry = entry.lock();
+                let entry = entry.as_ref()?;
+                Some((entry.listen_endpoint, entry.syn_queue.len()))
+            })
+            .collect()
+    }
+
     pub fn can_accept(&self, port: u16) -> AxResult<bool> {
         if let Some(entry) = self.tcp[port as usize].lock().deref() {
             Ok(entry.syn_queue.iter().any(|&handle| is_connected(handle)))
@@ -121,7 +134,7 @@ impl ListenTable {
                 // not listening on this address
                 return;
             }
-            if entry.syn_queue.len() >= LISTEN_QUEUE_SIZE {
+            if entry.syn_queue.len() >= somaxconn() {
                 // SYN queue is full, drop the packet
                 warn!("SYN queue overflow!");
                 return;
@@ -1,6 +1,7 @@
 mod addr;
 mod bench;
 mod dns;
+mod info;
 mod listen_table;
 mod tcp;
 mod udp;
@@ -8,9 +9,11 @@ mod udp;
 use alloc::vec;
 use core::cell::RefCell;
 use core::ops::DerefMut;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use axdriver::prelude::*;
 use axdriver_net::{DevError, NetBufPtr};
+use axerrno::{ax_err, AxResult};
 use axhal::time::{wall_time_nanos, NANOS_PER_MICROS};
 use axsync::Mutex;
 use lazyinit::LazyInit;
@@ -23,6 +26,7 @@ use smoltcp::wire::{EthernetAddress, HardwareAddress, IpAddress, IpCidr};
 use self::listen_table::ListenTable;
 
 pub use self::dns::dns_query;
+pub use self::info::{tcp_sockets, TcpSocketInfo, TcpState};
 pub use self::tcp::TcpSocket;
 pub use self::udp::UdpSocket;
 
@@ -48,12 +52,25 @@ const TCP_RX_BUF_LEN: usize = 64 * 1024;
 const TCP_TX_BUF_LEN: usize = 64 * 1024;
 const UDP_RX_BUF_LEN: usize = 64 * 1024;
 const UDP_TX_BUF_LEN: usize = 64 * 1024;
+/// The default of [`somaxconn`].
 const LISTEN_QUEUE_SIZE: usize = 512;
 
+/// The maximum time to wait for network events before polling again.
+#[cfg(all(feature = "irq", feature = "multitask"))]
+const MAX_WAIT_TIME: core::time::Duration = core::time::Duration::from_millis(100);
+
 static LISTEN_TABLE: LazyInit<ListenTable> = LazyInit::new();
 static SOCKET_SET: LazyInit<SocketSetWrapper> = LazyInit::new();
 static ETH0: LazyInit<InterfaceWrapper> = LazyInit::new();
 
+/// The number of NIC interrupts, to detect the ones after a poll.
+static NET_IRQ_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+static SOMAXCONN: AtomicUsize = AtomicUsize::new(LISTEN_QUEUE_SIZE);
+
+#[cfg(all(feature = "irq", feature = "multitask"))]
+static NET_IRQ_WQ: axtask::WaitQueue = axtask::WaitQueue::new();
+
 struct SocketSetWrapper<'a>(Mutex<SocketSet<'a>>);
 
 struct DeviceWrapper {
@@ -177,6 +194,18 @@ impl InterfaceWrapper {
         let timestamp = Self::current_time();
         iface.poll(timestamp, dev.deref_mut(), &mut sockets);
     }
+
+    /// Returns the time until the next timer of the interface or the
+    /// sockets (e.g., TCP retransmission), or [`None`] if there is none.
+    #[cfg(all(feature = "irq", feature = "multitask"))]
+    pub fn poll_delay(&self, sockets: &Mutex<SocketSet>) -> Option<core::time::Duration> {
+        let mut iface = self.iface.lock();
+        let sockets = sockets.lock();
+        let timestamp = Self::current_time();
+        iface
+            .poll_delay(timestamp, &sockets)
+            .map(|delay| core::time::Duration::from_micros(delay.total_micros()))
+    }
 }
 
 impl DeviceWrapper {
@@ -306,6 +335,58 @@ pub fn poll_interfaces() {
     SOCKET_SET.poll_interfaces();
 }
 
+/// Returns the maximum number of pending connections of each listening TCP
+/// socket. The SYN packets beyond it are dropped.
+pub fn somaxconn() -> usize {
+    SOMAXCONN.load(Ordering::Relaxed)
+}
+
+/// Sets the maximum number of pending connections of each listening TCP
+/// socket, see [`somaxconn`].
+///
+/// Returns [`InvalidInput`](axerrno::AxError::InvalidInput) if it is 0.
+pub fn set_somaxconn(max: usize) -> AxResult {
+    if max == 0 {
+        return ax_err!(InvalidInput, "somaxconn must be positive");
+    }
+    SOMAXCONN.store(max, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Returns the number of NIC interrupts so far.
+///
+/// It is read before polling the interfaces, and passed to
+/// [`wait_for_events`] if the poll makes no progress.
+pub(crate) fn net_irq_count() -> usize {
+    NET_IRQ_COUNT.load(Ordering::Acquire)
+}
+
+/// Waits for network events when a blocking socket operation can not make
+/// progress, where `irq_count` is from [`net_irq_count`] before the last poll.
+///
+/// If the NIC raises interrupts, the current task sleeps until there is a
+/// NIC interrupt after the poll, or a timer of the network stack expires.
+/// Otherwise, it only yields the CPU.
+pub(crate) fn wait_for_events(irq_count: usize) {
+    #[cfg(all(feature = "irq", feature = "multitask"))]
+    if axdriver::irq::has_irq(DeviceType::Net) {
+        let timeout = ETH0
+            .poll_delay(&SOCKET_SET.0)
+            .map_or(MAX_WAIT_TIME, |delay| delay.min(MAX_WAIT_TIME));
+        NET_IRQ_WQ.wait_timeout_until(timeout, || net_irq_count() != irq_count);
+        return;
+    }
+    let _ = irq_count;
+    axtask::yield_now();
+}
+
+#[cfg(feature = "irq")]
+fn net_irq_hook() {
+    NET_IRQ_COUNT.fetch_add(1, Ordering::Release);
+    #[cfg(all(feature = "irq", feature = "multitask"))]
+    NET_IRQ_WQ.notify_all(false);
+}
+
 /// Benchmark raw socket transmit bandwidth.
 pub fn bench_transmit() {
     ETH0.dev.lock().bench_transmit_bandwidth();
@@ -329,6 +410,9 @@ pub(crate) fn init(net_dev: AxNetDevice) {
     SOCKET_SET.init_once(SocketSetWrapper::new());
     LISTEN_TABLE.init_once(ListenTable::new());
 
+    #[cfg(feature = "irq")]
+    axdriver::irq::set_irq_hook(DeviceType::Net, net_irq_hook);
+
     info!("created net interface {:?}:", ETH0.name());
     info!("  ether:    {}", ETH0.ethernet_address());
     info!("  ip:       {}/{}", ip, IP_PREFIX);
@@ -0,0 +1,268 @@
+//! [ArceOS](https://github.com/arceos-org/arceos) sampling profiler.
+//!
+//! The profiler samples the running code with the overflow interrupts of a
+//! hardware performance counter (see [`axhal::perf`]) on every CPU. Each
+//! sample records the current task and the backtrace of the interrupted
+//! code (only the PC if backtraces are not available, e.g., in user mode or
+//! without the `backtrace` feature of `axhal`).
+//!
+//! The samples are dumped to a file in the folded stack format, which can be
+//! rendered by the usual flame graph tools:
+//!
+//! ```text
+//! <task>;<outermost function>;...;<innermost function> <count>
+//! ```
+//!
+//! Samples are stored in a buffer allocated when profiling starts, so no
+//! memory is allocated in the interrupt handler. Samples taken after the
+//! buffer is full are dropped, so are the samples taken while the profile
+//! is being dumped.
+
+#![no_std]
+
+#[macro_use]
+extern crate log;
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axerrno::{ax_err, AxError, AxResult};
+use axhal::arch::TrapFrame;
+use axhal::backtrace::{symbolize, Backtrace};
+use axhal::cpu::this_cpu_id;
+use axhal::perf::{self, CounterConfig, PerfError};
+use kspin::{SpinNoIrq, SpinNoPreempt};
+
+pub use axhal::perf::PerfEvent;
+
+/// Maximum number of samples kept until the profile is dumped.
+pub const MAX_SAMPLES: usize = 8192;
+
+/// Maximum number of frames recorded in a sample.
+pub const MAX_DEPTH: usize = 16;
+
+/// Maximum number of bytes of the task name recorded in a sample.
+const MAX_NAME_LEN: usize = 32;
+
+const NO_COUNTER: usize = usize::MAX;
+
+struct Sample {
+    task_id: u64,
+    name: [u8; MAX_NAME_LEN],
+    name_len: usize,
+    /// PCs of the frames, innermost first.
+    pcs: [usize; MAX_DEPTH],
+    depth: usize,
+}
+
+struct Profiler {
+    samples: Vec<Sample>,
+    dropped: usize,
+}
+
+static PROFILER: SpinNoIrq<Option<Profiler>> = SpinNoIrq::new(None);
+
+/// Whether the profiler is running. The lock serializes [`start`] and
+/// [`stop`]; it keeps IRQs enabled, so that the waiters still serve the
+/// calls of [`axhal::irq::run_on_cpus`].
+static RUNNING: SpinNoPreempt<bool> = SpinNoPreempt::new(false);
+
+/// The counter used for sampling on each CPU, or `NO_COUNTER`.
+#[allow(clippy::declare_interior_mutable_const)]
+static COUNTERS: [AtomicUsize; axconfig::SMP] = {
+    const NONE: AtomicUsize = AtomicUsize::new(NO_COUNTER);
+    [NONE; axconfig::SMP]
+};
+
+/// Number of CPUs on which sampling failed to start.
+static FAILED_CPUS: AtomicUsize = AtomicUsize::new(0);
+
+fn perf_error(err: PerfError) -> AxError {
+    match err {
+        PerfError::Unsupported | PerfError::UnsupportedEvent => AxError::Unsupported,
+        PerfError::InvalidCounter | PerfError::InvalidPeriod => AxError::InvalidInput,
+    }
+}
+
+fn ready_cpus() -> impl Iterator<Item = usize> {
+    let this_cpu = this_cpu_id();
+    let ready = axhal::irq::ipi_ready_cpus();
+    (0..axconfig::SMP).filter(move |&cpu| cpu == this_cpu || ready & (1 << cpu) != 0)
+}
+
+/// Starts sampling `event` every `period` occurrences, on all CPUs.
+///
+/// The samples of the previous run are discarded. Returns an error if the
+/// profiler is already running, or if no counter of the current CPU can
+/// sample the event. Other CPUs that can not sample it are skipped.
+pub fn start(event: PerfEvent, period: u64) -> AxResult {
+    if period == 0 {
+        return ax_err!(InvalidInput, "the sample period must not be 0");
+    }
+    let mut running = RUNNING.lock();
+    if *running {
+        return ax_err!(ResourceBusy, "the profiler is already running");
+    }
+    let cfg = CounterConfig {
+        sample_period: period,
+        ..CounterConfig::new(event)
+    };
+    // Configure the current CPU first, to report the error if it fails.
+    {
+        let _guard = kernel_guard::NoPreempt::new();
+        configure_counter(&cfg).map_err(perf_error)?;
+    }
+
+    *PROFILER.lock() = Some(Profiler {
+        samples: Vec::with_capacity(MAX_SAMPLES),
+        dropped: 0,
+    });
+    perf::set_overflow_handler(record_sample);
+    FAILED_CPUS.store(0, Ordering::Relaxed);
+    axhal::irq::run_on_cpus(ready_cpus(), start_on_cpu, &cfg as *const _ as usize, true);
+    *running = true;
+    let failed = FAILED_CPUS.load(Ordering::Relaxed);
+    if failed > 0 {
+        warn!("perf: sampling not started on {} CPU(s)", failed);
+    }
+    info!("perf: sampling {:?} every {} events", event, period);
+    Ok(())
+}
+
+/// Stops sampling on all CPUs. The samples are kept until the next
+/// [`start`].
+pub fn stop() {
+    let mut running = RUNNING.lock();
+    axhal::irq::run_on_cpus(ready_cpus(), stop_on_cpu, 0, true);
+    *running = false;
+    if let Some(profiler) = PROFILER.lock().as_ref() {
+        info!(
+            "perf: stopped with {} samples ({} dropped)",
+            profiler.samples.len(),
+            profiler.dropped
+        );
+    }
+}
+
+/// Writes the recorded samples to the file at `path`, in the folded stack
+/// format. Returns the number of samples written.
+///
+/// The samples taken while dumping are dropped, so the profiler should be
+/// stopped first.
+pub fn dump(path: &str) -> AxResult<usize> {
+    // Take the samples out, so that the lock (which disables IRQs) is not
+    // held while formatting.
+    let Some(profiler) = PROFILER.lock().take() else {
+        return ax_err!(NotFound, "no profile recorded");
+    };
+    let res = write_folded(path, &profiler.samples);
+    // Put them back, unless a new run has started meanwhile.
+    let count = profiler.samples.len();
+    let mut slot = PROFILER.lock();
+    if slot.is_none() {
+        *slot = Some(profiler);
+    }
+    drop(slot);
+    res?;
+    info!("perf: dumped {} samples to {:?}", count, path);
+    Ok(count)
+}
+
+fn write_folded(path: &str, samples: &[Sample]) -> AxResult {
+    let mut folded = BTreeMap::<String, usize>::new();
+    for sample in samples.iter() {
+        let mut line = String::new();
+        let name = core::str::from_utf8(&sample.name[..sample.name_len]).unwrap_or("?");
+        let _ = write!(line, "{}:{}", name, sample.task_id);
+        for &pc in sample.pcs[..sample.depth].iter().rev() {
+            let _ = match symbolize(pc) {
+                Some((sym, _)) => write!(line, ";{}", sym),
+                None => write!(line, ";{:#x}", pc),
+            };
+        }
+        *folded.entry(line).or_default() += 1;
+    }
+
+    let mut out = String::new();
+    for (stack, count) in folded.iter() {
+        let _ = writeln!(out, "{} {}", stack, count);
+    }
+    axfs::api::write(path, out)?;
+    Ok(())
+}
+
+/// Configures the first counter of the current CPU that can sample the
+/// event, and returns its number.
+fn configure_counter(cfg: &CounterConfig) -> Result<usize, PerfError> {
+    let mut err = PerfError::Unsupported;
+    for counter in 0..perf::num_counters() {
+        match perf::configure(counter, cfg) {
+            Ok(()) => return Ok(counter),
+            Err(e) => err = e,
+        }
+    }
+    Err(err)
+}
+
+fn start_on_cpu(cfg: usize) {
+    // Safety: `start` waits for the calls to complete.
+    let cfg = unsafe { &*(cfg as *const CounterConfig) };
+    match configure_counter(cfg) {
+        Ok(counter) => {
+            COUNTERS[this_cpu_id()].store(counter, Ordering::Release);
+            let _ = perf::start(counter);
+        }
+        Err(_) => {
+            FAILED_CPUS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+fn stop_on_cpu(_: usize) {
+    let counter = COUNTERS[this_cpu_id()].swap(NO_COUNTER, Ordering::AcqRel);
+    if counter != NO_COUNTER {
+        let _ = perf::stop(counter);
+    }
+}
+
+/// The overflow handler, records a sample of the interrupted code.
+fn record_sample(counter: usize, pc: usize, tf: &TrapFrame) {
+    if COUNTERS[this_cpu_id()].load(Ordering::Acquire) != counter {
+        return;
+    }
+    let mut sample = Sample {
+        task_id: 0,
+        name: [0; MAX_NAME_LEN],
+        name_len: 0,
+        pcs: [0; MAX_DEPTH],
+        depth: 0,
+    };
+    if let Some(curr) = axtask::current_may_uninit() {
+        let name = curr.name().as_bytes();
+        let len = name.len().min(MAX_NAME_LEN);
+        sample.task_id = curr.id().as_u64();
+        sample.name[..len].copy_from_slice(&name[..len]);
+        sample.name_len = len;
+    }
+    let bt = Backtrace::from_trap(tf);
+    let frames = if bt.frames().is_empty() {
+        core::slice::from_ref(&pc)
+    } else {
+        bt.frames()
+    };
+    sample.depth = frames.len().min(MAX_DEPTH);
+    sample.pcs[..sample.depth].copy_from_slice(&frames[..sample.depth]);
+
+    let mut profiler = PROFILER.lock();
+    if let Some(profiler) = profiler.as_mut() {
+        if profiler.samples.len() < MAX_SAMPLES {
+            profiler.samples.push(sample);
+        } else {
+            profiler.dropped += 1;
+        }
+    }
+}
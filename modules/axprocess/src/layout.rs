@@ -0,0 +1,63 @@
+use memory_addr::{VirtAddr, PAGE_SIZE_4K};
+
+/// Maximum number of pages the PIE load address is shifted by (256 MiB).
+const PIE_RANDOM_PAGES: usize = 1 << 16;
+/// Maximum number of pages the heap base is shifted by (1 GiB).
+const HEAP_RANDOM_PAGES: usize = 1 << 18;
+/// Maximum number of pages the `mmap` base is shifted down by (16 GiB).
+const MMAP_RANDOM_PAGES: usize = 1 << 22;
+/// Maximum number of pages the stack top is shifted by (1 GiB).
+const STACK_RANDOM_PAGES: usize = 1 << 18;
+
+/// The layout of a user address space.
+///
+/// The addresses are the ones in the platform configuration, shifted by
+/// random page-aligned offsets if ASLR is enabled (`axconfig::ASLR`).
+#[derive(Debug, Clone, Copy)]
+pub struct UserLayout {
+    /// Top address of the user stack (it grows downward).
+    pub stack_top: VirtAddr,
+    /// Base address of the heap, i.e., the initial program break.
+    pub heap_base: VirtAddr,
+    /// Top of the `mmap` area, below which the mappings without a hint are
+    /// placed (top-down). It is also the limit of the heap.
+    pub mmap_base: VirtAddr,
+    /// Load address of position-independent executables.
+    pub pie_base: VirtAddr,
+}
+
+fn random_offset(max_pages: usize) -> usize {
+    axhal::random::random_usize() % max_pages * PAGE_SIZE_4K
+}
+
+impl UserLayout {
+    /// Returns the layout without randomization.
+    pub const fn fixed() -> Self {
+        Self {
+            stack_top: VirtAddr::from_usize(axconfig::USER_STACK_TOP),
+            heap_base: VirtAddr::from_usize(axconfig::USER_HEAP_BASE),
+            mmap_base: VirtAddr::from_usize(axconfig::USER_MMAP_BASE),
+            pie_base: VirtAddr::from_usize(axconfig::USER_PIE_BASE),
+        }
+    }
+
+    /// Generates a new layout, randomized if ASLR is enabled.
+    pub fn new() -> Self {
+        let layout = Self::fixed();
+        if !axconfig::ASLR {
+            return layout;
+        }
+        Self {
+            stack_top: layout.stack_top - random_offset(STACK_RANDOM_PAGES),
+            heap_base: layout.heap_base + random_offset(HEAP_RANDOM_PAGES),
+            mmap_base: layout.mmap_base - random_offset(MMAP_RANDOM_PAGES),
+            pie_base: layout.pie_base + random_offset(PIE_RANDOM_PAGES),
+        }
+    }
+}
+
+impl Default for UserLayout {
+    fn default() -> Self {
+        Self::new()
+    }
+}
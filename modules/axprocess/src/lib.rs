@@ -0,0 +1,66 @@
+//! [ArceOS](https://github.com/arceos-org/arceos) process management module.
+//!
+//! A [`Process`] groups the resources shared by all threads of a user
+//! application: the user [`AddrSpace`], the [`AxNamespace`] that holds
+//! per-process resources (file descriptors, current directory, etc.), the
+//! list of child processes and the exit status.
+//!
+//! This module provides the kernel side of `fork`, `clone`, `execve`, `wait4`
+//! and `exit_group`. The POSIX-compatible syscall wrappers are provided by
+//! `arceos_posix_api` with the `uspace` feature enabled.
+//!
+//! [`AddrSpace`]: axmm::AddrSpace
+//! [`AxNamespace`]: axns::AxNamespace
+
+#![no_std]
+
+#[macro_use]
+extern crate log;
+extern crate alloc;
+
+mod layout;
+mod loader;
+mod process;
+mod task;
+
+pub use self::layout::UserLayout;
+pub use self::process::{
+    current_process, find_process, init_process, CloneFlags, Pid, Process, ProcessRef, WaitOptions,
+    NS_DROP_HOOKS, NS_INIT_HOOKS,
+};
+pub use self::task::{
+    clone_current, exec_current, exit_current, exit_group_current, exit_if_killed, TaskExt,
+};
+
+use axerrno::AxResult;
+use axhal::arch::UspaceContext;
+use memory_addr::VirtAddr;
+
+/// Loads the ELF executable at `path` into a new user address space, and
+/// creates the first user process (the `init` process) to run it.
+///
+/// Returns the main task of the `init` process.
+pub fn run_user_app(path: &str, args: &[&str], envs: &[&str]) -> AxResult<axtask::AxTaskRef> {
+    let mut aspace = axmm::new_user_aspace(
+        VirtAddr::from(axconfig::USER_SPACE_BASE),
+        axconfig::USER_SPACE_SIZE,
+    )?;
+    let layout = UserLayout::new();
+    let (entry, ustack_top) = loader::load_user_app(&mut aspace, path, args, envs, &layout)?;
+
+    axalloc::oom::set_oom_killer(process::oom_kill);
+    axtty::set_interrupt_hook(process::tty_interrupt);
+    // User applications expect the default terminal attributes of Linux, in
+    // canonical mode with echo, and `^C` to kill the foreground processes.
+    axtty::console().set_termios(&axtty::Termios::new());
+
+    let task = task::new_user_task(path);
+    let proc = Process::new_init(task.id().as_u64(), aspace, layout);
+    // The processes stay in the group of `init` unless moved by
+    // `Process::set_pgid`, so `^C` kills them all (but `init`) by default.
+    axtty::console().set_foreground(proc.pgid());
+    let uctx = UspaceContext::new(entry, ustack_top, 0);
+    let task = task::spawn_user_task(&proc, task, uctx);
+    proc.set_main_task(task.clone());
+    Ok(task)
+}
@@ -0,0 +1,271 @@
+//! A minimal loader for statically linked ELF64 executables.
+
+use alloc::vec::Vec;
+
+use axerrno::{ax_err, AxResult};
+use axhal::paging::MappingFlags;
+use axmm::AddrSpace;
+use memory_addr::{MemoryAddr, VirtAddr, PAGE_SIZE_4K};
+
+use crate::UserLayout;
+
+const ELF_MAGIC: &[u8; 4] = b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+
+const ET_EXEC: u16 = 2;
+const ET_DYN: u16 = 3;
+
+#[cfg(target_arch = "x86_64")]
+const EM_CURRENT: u16 = 62; // EM_X86_64
+#[cfg(target_arch = "aarch64")]
+const EM_CURRENT: u16 = 183; // EM_AARCH64
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+const EM_CURRENT: u16 = 243; // EM_RISCV
+
+const PT_LOAD: u32 = 1;
+const PT_INTERP: u32 = 3;
+const PT_PHDR: u32 = 6;
+
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+
+// Auxiliary vector entry types.
+const AT_NULL: usize = 0;
+const AT_PHDR: usize = 3;
+const AT_PHENT: usize = 4;
+const AT_PHNUM: usize = 5;
+const AT_PAGESZ: usize = 6;
+const AT_BASE: usize = 7;
+const AT_ENTRY: usize = 9;
+const AT_RANDOM: usize = 25;
+
+fn read_u16(data: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes(data[off..off + 2].try_into().unwrap())
+}
+
+fn read_u32(data: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes(data[off..off + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], off: usize) -> usize {
+    u64::from_le_bytes(data[off..off + 8].try_into().unwrap()) as usize
+}
+
+/// A program header of the ELF file.
+struct ProgramHeader {
+    p_type: u32,
+    p_flags: u32,
+    p_offset: usize,
+    p_vaddr: usize,
+    p_filesz: usize,
+    p_memsz: usize,
+}
+
+impl ProgramHeader {
+    fn parse(data: &[u8]) -> Self {
+        Self {
+            p_type: read_u32(data, 0),
+            p_flags: read_u32(data, 4),
+            p_offset: read_u64(data, 8),
+            p_vaddr: read_u64(data, 16),
+            p_filesz: read_u64(data, 32),
+            p_memsz: read_u64(data, 40),
+        }
+    }
+
+    fn mapping_flags(&self) -> MappingFlags {
+        let mut flags = MappingFlags::USER;
+        if self.p_flags & PF_R != 0 {
+            flags |= MappingFlags::READ;
+        }
+        if self.p_flags & PF_W != 0 {
+            flags |= MappingFlags::WRITE;
+        }
+        if self.p_flags & PF_X != 0 {
+            flags |= MappingFlags::EXECUTE;
+        }
+        flags
+    }
+}
+
+/// Information about a loaded ELF file, used to build the auxiliary vector.
+struct ElfInfo {
+    entry: usize,
+    phdr: usize,
+    phnum: usize,
+}
+
+fn load_elf(aspace: &mut AddrSpace, data: &[u8], pie_base: VirtAddr) -> AxResult<ElfInfo> {
+    if data.len() < EHDR_SIZE
+        || &data[0..4] != ELF_MAGIC
+        || data[4] != ELFCLASS64
+        || data[5] != ELFDATA2LSB
+    {
+        return ax_err!(InvalidData, "not a valid ELF64 file");
+    }
+    let e_type = read_u16(data, 16);
+    if read_u16(data, 18) != EM_CURRENT {
+        return ax_err!(InvalidData, "ELF machine type mismatch");
+    }
+    let bias = match e_type {
+        ET_EXEC => 0,
+        ET_DYN => pie_base.as_usize(),
+        _ => return ax_err!(InvalidData, "unsupported ELF type"),
+    };
+    let e_entry = read_u64(data, 24);
+    let e_phoff = read_u64(data, 32);
+    let e_phentsize = read_u16(data, 54) as usize;
+    let e_phnum = read_u16(data, 56) as usize;
+    if e_phentsize != PHDR_SIZE || e_phoff + e_phnum * PHDR_SIZE > data.len() {
+        return ax_err!(InvalidData, "invalid ELF program headers");
+    }
+
+    let mut phdr = 0;
+    // The end and flags of the last loaded segment, whose last page may be
+    // shared with the next one.
+    let mut mapped_end = VirtAddr::from(0);
+    let mut mapped_flags = MappingFlags::empty();
+    for i in 0..e_phnum {
+        let off = e_phoff + i * PHDR_SIZE;
+        let ph = ProgramHeader::parse(&data[off..off + PHDR_SIZE]);
+        match ph.p_type {
+            PT_PHDR => phdr = ph.p_vaddr + bias,
+            PT_INTERP => return ax_err!(Unsupported, "dynamically linked ELF is not supported"),
+            PT_LOAD => {
+                if ph.p_offset + ph.p_filesz > data.len() || ph.p_filesz > ph.p_memsz {
+                    return ax_err!(InvalidData, "invalid ELF segment");
+                }
+                let vaddr = VirtAddr::from(ph.p_vaddr + bias);
+                let flags = ph.mapping_flags();
+                let mut start = vaddr.align_down_4k();
+                let end = (vaddr + ph.p_memsz).align_up_4k();
+                debug!("load ELF segment: [{:#x}, {:#x}) {:?}", start, end, flags);
+                if start < mapped_end {
+                    // Segments are sorted by address, and only the first page
+                    // can be shared with the previous segment. The shared page
+                    // is already mapped, and must allow the accesses of both.
+                    if mapped_end - start > PAGE_SIZE_4K {
+                        return ax_err!(InvalidData, "overlapping ELF segments");
+                    }
+                    aspace.protect(start, PAGE_SIZE_4K, mapped_flags | flags)?;
+                    start = mapped_end;
+                }
+                if start < end {
+                    aspace.map_alloc(start, end - start, flags, true)?;
+                    mapped_end = end;
+                    mapped_flags = flags;
+                } else {
+                    mapped_flags |= flags;
+                }
+                aspace.write(vaddr, &data[ph.p_offset..ph.p_offset + ph.p_filesz])?;
+                if phdr == 0 && ph.p_offset == 0 {
+                    // The program headers are in the first loaded segment.
+                    phdr = vaddr.as_usize() + e_phoff;
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(ElfInfo {
+        entry: e_entry + bias,
+        phdr,
+        phnum: e_phnum,
+    })
+}
+
+/// Maps the user stack and pushes the arguments, environment variables and
+/// auxiliary vector onto it, following the System V ABI.
+///
+/// Returns the initial user stack pointer.
+fn init_user_stack(
+    aspace: &mut AddrSpace,
+    args: &[&str],
+    envs: &[&str],
+    info: &ElfInfo,
+    ustack_top: VirtAddr,
+) -> AxResult<VirtAddr> {
+    let ustack_size = axconfig::USER_STACK_SIZE;
+    aspace.map_alloc(
+        ustack_top - ustack_size,
+        ustack_size,
+        MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER,
+        true,
+    )?;
+
+    let mut sp = ustack_top.as_usize();
+    let mut push_bytes = |aspace: &AddrSpace, bytes: &[u8]| -> AxResult<usize> {
+        sp -= bytes.len();
+        aspace.write(sp.into(), bytes)?;
+        Ok(sp)
+    };
+
+    let mut push_str = |aspace: &AddrSpace, s: &str| -> AxResult<usize> {
+        push_bytes(aspace, &[0])?;
+        push_bytes(aspace, s.as_bytes())
+    };
+    let env_ptrs = envs
+        .iter()
+        .map(|s| push_str(aspace, s))
+        .collect::<AxResult<Vec<_>>>()?;
+    let arg_ptrs = args
+        .iter()
+        .map(|s| push_str(aspace, s))
+        .collect::<AxResult<Vec<_>>>()?;
+
+    let mut random_bytes = [0; 16];
+    axhal::random::fill_bytes(&mut random_bytes);
+    let random = push_bytes(aspace, &random_bytes)?;
+
+    let auxv = [
+        (AT_PHDR, info.phdr),
+        (AT_PHENT, PHDR_SIZE),
+        (AT_PHNUM, info.phnum),
+        (AT_PAGESZ, PAGE_SIZE_4K),
+        (AT_BASE, 0),
+        (AT_ENTRY, info.entry),
+        (AT_RANDOM, random),
+        (AT_NULL, 0),
+    ];
+
+    let mut words = Vec::new();
+    words.push(args.len());
+    words.extend(arg_ptrs);
+    words.push(0);
+    words.extend(env_ptrs);
+    words.push(0);
+    for (key, value) in auxv {
+        words.push(key);
+        words.push(value);
+    }
+
+    let size = words.len() * core::mem::size_of::<usize>();
+    let sp = memory_addr::align_down(sp - size, 16);
+    let bytes = words
+        .iter()
+        .flat_map(|w| w.to_ne_bytes())
+        .collect::<Vec<_>>();
+    aspace.write(sp.into(), &bytes)?;
+    Ok(sp.into())
+}
+
+/// Loads the ELF executable at `path` into the given address space with the
+/// given layout, and prepares the user stack with `args` and `envs`.
+///
+/// Returns the entry point and the initial user stack pointer.
+pub(crate) fn load_user_app(
+    aspace: &mut AddrSpace,
+    path: &str,
+    args: &[&str],
+    envs: &[&str],
+    layout: &UserLayout,
+) -> AxResult<(usize, VirtAddr)> {
+    let data = axfs::api::read(path)?;
+    let info = load_elf(aspace, &data, layout.pie_base)?;
+    let ustack_top = init_user_stack(aspace, args, envs, &info, layout.stack_top)?;
+    Ok((info.entry, ustack_top))
+}
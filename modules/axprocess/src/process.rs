@@ -0,0 +1,635 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering};
+
+use axerrno::{ax_err, AxResult};
+use axhal::paging::MappingFlags;
+use axmm::AddrSpace;
+use axns::AxNamespace;
+use axtask::{AxTaskRef, TaskExtRef, WaitQueue};
+use kspin::{SpinNoIrq, SpinNoPreempt};
+use lazyinit::LazyInit;
+use linkme::distributed_slice;
+use memory_addr::{MemoryAddr, VirtAddr, VirtAddrRange};
+
+use crate::UserLayout;
+
+/// The process identifier.
+///
+/// It is the same as the task ID of the main thread of the process.
+pub type Pid = u64;
+
+/// The reference type of a process.
+pub type ProcessRef = Arc<Process>;
+
+/// Hooks to initialize the resources of a newly created process namespace.
+///
+/// Each hook is called with the new namespace and the flags of `clone`, while
+/// the current namespace is still the one of the parent process (or the
+/// global namespace for the `init` process). Modules that define per-process
+/// resources with [`axns::def_resource!`] should register a hook to copy
+/// them, or to share them if the flags say so (e.g.,
+/// [`CloneFlags::CLONE_FILES`]).
+#[distributed_slice]
+pub static NS_INIT_HOOKS: [fn(&AxNamespace, CloneFlags)];
+
+/// Hooks to release the resources of a process namespace when the process is
+/// dropped.
+#[distributed_slice]
+pub static NS_DROP_HOOKS: [fn(&AxNamespace)];
+
+bitflags::bitflags! {
+    /// Flags for the `clone` syscall.
+    #[derive(Debug, Clone, Copy)]
+    pub struct CloneFlags: u32 {
+        /// The calling process and the child process share the address space.
+        const CLONE_VM = 0x0000_0100;
+        /// The caller and the child share the filesystem information.
+        const CLONE_FS = 0x0000_0200;
+        /// The caller and the child share the file descriptor table.
+        const CLONE_FILES = 0x0000_0400;
+        /// The caller and the child share the signal handlers.
+        const CLONE_SIGHAND = 0x0000_0800;
+        /// The caller is suspended until the child exits or calls `execve`.
+        const CLONE_VFORK = 0x0000_4000;
+        /// The parent of the child will be the same as that of the caller.
+        const CLONE_PARENT = 0x0000_8000;
+        /// The child is placed in the same thread group as the caller.
+        const CLONE_THREAD = 0x0001_0000;
+        /// Set the TLS (thread local storage) of the child to `tls`.
+        const CLONE_SETTLS = 0x0008_0000;
+        /// Store the child thread ID at `parent_tid` in the parent's memory.
+        const CLONE_PARENT_SETTID = 0x0010_0000;
+        /// Clear the child thread ID at `child_tid` in the child's memory
+        /// when the child exits.
+        const CLONE_CHILD_CLEARTID = 0x0020_0000;
+        /// Store the child thread ID at `child_tid` in the child's memory.
+        const CLONE_CHILD_SETTID = 0x0100_0000;
+    }
+}
+
+bitflags::bitflags! {
+    /// Options for the `wait4` syscall.
+    #[derive(Debug, Clone, Copy)]
+    pub struct WaitOptions: u32 {
+        /// Return immediately if no child has exited.
+        const WNOHANG = 0x0000_0001;
+        /// Also return if a child has stopped.
+        const WUNTRACED = 0x0000_0002;
+    }
+}
+
+static PROCESS_TABLE: SpinNoIrq<BTreeMap<Pid, Weak<Process>>> = SpinNoIrq::new(BTreeMap::new());
+
+static INIT_PROCESS: LazyInit<ProcessRef> = LazyInit::new();
+
+/// The foreground process group of the terminal which received the interrupt
+/// character (`^C`), if the processes in it are not killed yet, or 0.
+static INTERRUPT_PGID: AtomicU64 = AtomicU64::new(0);
+
+/// A user process.
+///
+/// It holds the resources shared by all threads in the process.
+pub struct Process {
+    pid: Pid,
+    pgid: AtomicU64,
+    parent: SpinNoIrq<Weak<Process>>,
+    children: SpinNoIrq<Vec<ProcessRef>>,
+    threads: SpinNoIrq<Vec<u64>>,
+    main_task: SpinNoIrq<Option<AxTaskRef>>,
+    /// The inner lock keeps IRQs enabled, as its holder may wait for TLB
+    /// shootdowns on the other CPUs, which may be spinning on it.
+    aspace: SpinNoIrq<Arc<SpinNoPreempt<AddrSpace>>>,
+    layout: SpinNoIrq<UserLayout>,
+    /// Held while changing the heap mappings, so it keeps IRQs enabled too.
+    brk: SpinNoPreempt<usize>,
+    ns: AxNamespace,
+    exit_code: AtomicI32,
+    zombie: AtomicBool,
+    killed: AtomicBool,
+    /// The exit code of `exit_group`, once a thread has called it.
+    group_exit: SpinNoIrq<Option<i32>>,
+    /// The thread calling `execve`, for which the other threads exit, or 0.
+    exec_tid: AtomicU64,
+    thread_exit_wq: WaitQueue,
+    /// Whether the parent blocked by `vfork` can continue.
+    vfork_done: AtomicBool,
+    vfork_wq: WaitQueue,
+    child_exit_wq: WaitQueue,
+}
+
+unsafe impl Send for Process {}
+unsafe impl Sync for Process {}
+
+impl Process {
+    fn new(
+        pid: Pid,
+        pgid: Pid,
+        parent: Weak<Process>,
+        aspace: Arc<SpinNoPreempt<AddrSpace>>,
+        layout: UserLayout,
+        brk: usize,
+        flags: CloneFlags,
+    ) -> ProcessRef {
+        let ns = AxNamespace::new_thread_local();
+        for hook in NS_INIT_HOOKS {
+            hook(&ns, flags);
+        }
+        let proc = Arc::new(Self {
+            pid,
+            pgid: AtomicU64::new(pgid),
+            parent: SpinNoIrq::new(parent),
+            children: SpinNoIrq::new(Vec::new()),
+            threads: SpinNoIrq::new(Vec::new()),
+            main_task: SpinNoIrq::new(None),
+            aspace: SpinNoIrq::new(aspace),
+            layout: SpinNoIrq::new(layout),
+            brk: SpinNoPreempt::new(brk),
+            ns,
+            exit_code: AtomicI32::new(0),
+            zombie: AtomicBool::new(false),
+            killed: AtomicBool::new(false),
+            group_exit: SpinNoIrq::new(None),
+            exec_tid: AtomicU64::new(0),
+            thread_exit_wq: WaitQueue::new(),
+            vfork_done: AtomicBool::new(false),
+            vfork_wq: WaitQueue::new(),
+            child_exit_wq: WaitQueue::new(),
+        });
+        PROCESS_TABLE.lock().insert(pid, Arc::downgrade(&proc));
+        proc
+    }
+
+    /// Creates the `init` process with the given address space and layout.
+    ///
+    /// Its namespace is initialized from the global namespace. Orphaned
+    /// processes will be re-parented to it. It leads a new process group.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `init` process is already created.
+    pub(crate) fn new_init(pid: Pid, aspace: AddrSpace, layout: UserLayout) -> ProcessRef {
+        let aspace = Arc::new(SpinNoPreempt::new(aspace));
+        let proc = Self::new(
+            pid,
+            pid,
+            Weak::new(),
+            aspace,
+            layout,
+            layout.heap_base.as_usize(),
+            CloneFlags::empty(),
+        );
+        INIT_PROCESS.init_once(proc.clone());
+        proc
+    }
+
+    /// Creates a child process of `self` with the given PID.
+    ///
+    /// If `flags` contains [`CloneFlags::CLONE_VM`], the child shares the
+    /// address space with `self`. Otherwise, the address space is copied. The
+    /// child inherits the layout, program break and process group of `self`
+    /// in both cases.
+    /// The other flags are passed to the [`NS_INIT_HOOKS`].
+    pub(crate) fn fork(self: &Arc<Self>, pid: Pid, flags: CloneFlags) -> AxResult<ProcessRef> {
+        let aspace = if flags.contains(CloneFlags::CLONE_VM) {
+            self.aspace()
+        } else {
+            let aspace = AddrSpace::from_exited_space(&self.aspace().lock())?;
+            Arc::new(SpinNoPreempt::new(aspace))
+        };
+        let parent = if flags.contains(CloneFlags::CLONE_PARENT) {
+            self.parent.lock().clone()
+        } else {
+            Arc::downgrade(self)
+        };
+        let layout = *self.layout.lock();
+        let brk = *self.brk.lock();
+        let child = Self::new(pid, self.pgid(), parent.clone(), aspace, layout, brk, flags);
+        if let Some(parent) = parent.upgrade() {
+            parent.children.lock().push(child.clone());
+        }
+        Ok(child)
+    }
+
+    /// Returns the process ID.
+    pub const fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    /// Returns the process group ID.
+    pub fn pgid(&self) -> Pid {
+        self.pgid.load(Ordering::Acquire)
+    }
+
+    /// Moves the process to the process group `pgid` (as `setpgid`), or to a
+    /// new group led by itself if `pgid` is 0.
+    pub fn set_pgid(&self, pgid: Pid) {
+        let pgid = if pgid == 0 { self.pid } else { pgid };
+        self.pgid.store(pgid, Ordering::Release);
+    }
+
+    /// Returns the parent process, or [`None`] for the `init` process.
+    pub fn parent(&self) -> Option<ProcessRef> {
+        self.parent.lock().upgrade()
+    }
+
+    /// Returns the PID of the parent process, or 0 if it has no parent.
+    pub fn ppid(&self) -> Pid {
+        self.parent().map_or(0, |p| p.pid())
+    }
+
+    /// Returns the child processes (including exited but not yet waited ones).
+    pub fn children(&self) -> Vec<ProcessRef> {
+        self.children.lock().clone()
+    }
+
+    /// Returns the IDs of all alive threads in the process.
+    pub fn threads(&self) -> Vec<u64> {
+        self.threads.lock().clone()
+    }
+
+    /// Returns the user address space of the process.
+    pub fn aspace(&self) -> Arc<SpinNoPreempt<AddrSpace>> {
+        self.aspace.lock().clone()
+    }
+
+    /// Replaces the user address space and its layout (on `execve`), and
+    /// resets the program break.
+    ///
+    /// The old address space is released if it is not shared with other
+    /// processes.
+    pub(crate) fn set_aspace(&self, aspace: AddrSpace, layout: UserLayout) {
+        let old = core::mem::replace(
+            &mut *self.aspace.lock(),
+            Arc::new(SpinNoPreempt::new(aspace)),
+        );
+        *self.layout.lock() = layout;
+        *self.brk.lock() = layout.heap_base.as_usize();
+        drop(old);
+    }
+
+    /// Returns the layout of the user address space.
+    pub fn layout(&self) -> UserLayout {
+        *self.layout.lock()
+    }
+
+    /// Sets the program break (the end of the heap) to `new_brk`.
+    ///
+    /// The heap pages are allocated lazily. The break can not be lower than
+    /// the heap base, higher than the `mmap` base, or overlap other mappings;
+    /// in which case (or if `new_brk` is 0) it is not changed.
+    ///
+    /// Returns the new program break, or the current one on failure.
+    pub fn brk(&self, new_brk: usize) -> usize {
+        let layout = self.layout();
+        let mut brk = self.brk.lock();
+        if new_brk < layout.heap_base.as_usize() || new_brk > layout.mmap_base.as_usize() {
+            return *brk;
+        }
+        let old_end = VirtAddr::from(*brk).align_up_4k();
+        let new_end = VirtAddr::from(new_brk).align_up_4k();
+        let aspace = self.aspace();
+        let mut aspace = aspace.lock();
+        let res = if new_end > old_end {
+            let flags = MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER;
+            aspace.map_alloc(old_end, new_end - old_end, flags, false)
+        } else if new_end < old_end {
+            aspace.unmap(new_end, old_end - new_end)
+        } else {
+            Ok(())
+        };
+        if res.is_ok() {
+            *brk = new_brk;
+        }
+        *brk
+    }
+
+    /// Finds a free area of `size` bytes in the user address space for
+    /// `mmap`.
+    ///
+    /// The area at `hint` is used if it is free. Otherwise, the search goes
+    /// downward from the `mmap` base of the layout (randomized with ASLR),
+    /// then upward from it if nothing is free below.
+    pub fn find_mmap_area(&self, hint: Option<VirtAddr>, size: usize) -> Option<VirtAddr> {
+        let mmap_base = self.layout().mmap_base;
+        let aspace = self.aspace();
+        let aspace = aspace.lock();
+        let limit = VirtAddrRange::new(aspace.base(), aspace.end());
+        hint.filter(|&hint| aspace.find_free_area(hint, size, limit) == Some(hint))
+            .or_else(|| aspace.find_free_area_top_down(mmap_base, size, limit))
+            .or_else(|| aspace.find_free_area(mmap_base, size, limit))
+    }
+
+    /// Returns the namespace of the process.
+    pub fn namespace(&self) -> &AxNamespace {
+        &self.ns
+    }
+
+    /// Returns the exit code of the process.
+    pub fn exit_code(&self) -> i32 {
+        self.exit_code.load(Ordering::Acquire)
+    }
+
+    /// Whether the process has exited but not yet been waited by its parent.
+    pub fn is_zombie(&self) -> bool {
+        self.zombie.load(Ordering::Acquire)
+    }
+
+    /// Marks the process to be killed.
+    ///
+    /// Its threads exit when they enter the kernel next time (see
+    /// [`exit_if_killed`]).
+    ///
+    /// [`exit_if_killed`]: crate::exit_if_killed
+    pub fn kill(&self) {
+        self.killed.store(true, Ordering::Release);
+    }
+
+    /// Whether the process has been marked to be killed.
+    pub fn is_killed(&self) -> bool {
+        self.killed.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn set_main_task(&self, task: AxTaskRef) {
+        *self.main_task.lock() = Some(task);
+    }
+
+    pub(crate) fn add_thread(&self, tid: u64) {
+        self.threads.lock().push(tid);
+    }
+
+    /// Removes the thread from the process.
+    ///
+    /// Returns `true` if it is the last thread of the process.
+    pub(crate) fn remove_thread(&self, tid: u64) -> bool {
+        let mut threads = self.threads.lock();
+        threads.retain(|&t| t != tid);
+        let last = threads.is_empty();
+        drop(threads);
+        self.thread_exit_wq.notify_all(false);
+        last
+    }
+
+    /// Starts to exit all threads of the process (on `exit_group`).
+    ///
+    /// Returns the exit code of the process, which is the one of the first
+    /// call.
+    pub(crate) fn set_group_exit(&self, exit_code: i32) -> i32 {
+        *self.group_exit.lock().get_or_insert(exit_code)
+    }
+
+    /// Returns the exit code of `exit_group`, if a thread has called it.
+    pub(crate) fn group_exit_code(&self) -> Option<i32> {
+        *self.group_exit.lock()
+    }
+
+    /// Whether the thread `tid` should exit, as the process is exiting, or
+    /// another thread is executing a new program.
+    pub(crate) fn should_thread_exit(&self, tid: u64) -> bool {
+        let exec_tid = self.exec_tid.load(Ordering::Acquire);
+        self.group_exit_code().is_some() || (exec_tid != 0 && exec_tid != tid)
+    }
+
+    /// Makes all threads except `tid` exit (on `execve`), and waits for them
+    /// to exit, as they may still run on the old address space.
+    ///
+    /// The threads exit when they enter or return from the kernel next time
+    /// (see [`exit_if_killed`]), so threads blocked in the kernel are waited
+    /// until they are woken up.
+    ///
+    /// Returns `false` if the process is exiting or another thread is calling
+    /// `execve`, in which case the thread `tid` should exit itself.
+    ///
+    /// [`exit_if_killed`]: crate::exit_if_killed
+    pub(crate) fn exit_other_threads(&self, tid: u64) -> bool {
+        if self.group_exit_code().is_some()
+            || self
+                .exec_tid
+                .compare_exchange(0, tid, Ordering::AcqRel, Ordering::Acquire)
+                .is_err()
+        {
+            return false;
+        }
+        self.thread_exit_wq
+            .wait_until(|| self.threads.lock().as_slice() == [tid]);
+        self.exec_tid.store(0, Ordering::Release);
+        true
+    }
+
+    /// Lets the parent blocked by `vfork` continue, as the process has called
+    /// `execve` or exited and does not use the memory of the parent any more.
+    pub(crate) fn set_vfork_done(&self) {
+        self.vfork_done.store(true, Ordering::Release);
+        self.vfork_wq.notify_all(false);
+    }
+
+    /// Blocks the current task until the process calls `execve` or exits, for
+    /// the parent of `vfork`.
+    pub(crate) fn wait_vfork_done(&self) {
+        self.vfork_wq
+            .wait_until(|| self.vfork_done.load(Ordering::Acquire));
+    }
+
+    /// Marks the process as exited with the given exit code.
+    ///
+    /// The user memory is released immediately (if it is not shared), the
+    /// children are re-parented to the `init` process, and the parent process
+    /// is notified.
+    pub(crate) fn exit(&self, exit_code: i32) {
+        if self.zombie.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        debug!("process {} exited with code {}", self.pid, exit_code);
+        self.exit_code.store(exit_code, Ordering::Release);
+        self.set_vfork_done();
+
+        // Release it early unless shared (with the reference here), and not
+        // under the `SpinNoIrq` locks, as unmapping waits for TLB shootdowns.
+        let aspace = self.aspace();
+        if Arc::strong_count(&aspace) == 2 {
+            aspace.lock().clear();
+        }
+        drop(aspace);
+
+        let children = core::mem::take(&mut *self.children.lock());
+        if let Some(init) = INIT_PROCESS.get().filter(|p| p.pid != self.pid) {
+            for child in children {
+                *child.parent.lock() = Arc::downgrade(init);
+                init.children.lock().push(child);
+            }
+            init.child_exit_wq.notify_all(false);
+        }
+
+        if let Some(parent) = self.parent() {
+            parent.child_exit_wq.notify_all(false);
+        }
+    }
+
+    /// Waits for a child process to exit, and reaps it.
+    ///
+    /// If `pid` is `-1`, waits for any child process. Otherwise, waits for the
+    /// child process with the given PID.
+    ///
+    /// The times of the reaped child are accumulated to the current task (see
+    /// [`TaskInner::add_child_time`]).
+    ///
+    /// Returns the PID and exit code of the reaped child, or [`None`] if
+    /// [`WaitOptions::WNOHANG`] is specified and no child has exited yet.
+    /// Returns [`AxError::NotFound`] if there is no such child.
+    ///
+    /// [`TaskInner::add_child_time`]: axtask::TaskInner::add_child_time
+    /// [`AxError::NotFound`]: axerrno::AxError::NotFound
+    pub fn wait_child(&self, pid: isize, options: WaitOptions) -> AxResult<Option<(Pid, i32)>> {
+        let matches = |p: &ProcessRef| pid == -1 || p.pid == pid as Pid;
+        loop {
+            let mut children = self.children.lock();
+            if !children.iter().any(matches) {
+                return ax_err!(NotFound, "no such child process");
+            }
+            if let Some(idx) = children.iter().position(|c| matches(c) && c.is_zombie()) {
+                let child = children.remove(idx);
+                drop(children);
+                if let Some(task) = child.main_task.lock().take() {
+                    axtask::current().add_child_time(&task);
+                }
+                PROCESS_TABLE.lock().remove(&child.pid);
+                return Ok(Some((child.pid, child.exit_code())));
+            }
+            drop(children);
+
+            if options.contains(WaitOptions::WNOHANG) {
+                return Ok(None);
+            }
+            self.child_exit_wq.wait_until(|| {
+                self.children
+                    .lock()
+                    .iter()
+                    .any(|c| matches(c) && c.is_zombie())
+            });
+        }
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        debug!("process drop: {}", self.pid);
+        for hook in NS_DROP_HOOKS {
+            hook(&self.ns);
+        }
+    }
+}
+
+/// Returns the process of the current task.
+///
+/// # Panics
+///
+/// Panics if the current task is not a user task.
+pub fn current_process() -> ProcessRef {
+    axtask::current().task_ext().process().clone()
+}
+
+/// Returns the `init` process, or [`None`] if it is not created yet.
+pub fn init_process() -> Option<ProcessRef> {
+    INIT_PROCESS.get().cloned()
+}
+
+/// Finds an alive process by its PID.
+pub fn find_process(pid: Pid) -> Option<ProcessRef> {
+    PROCESS_TABLE.lock().get(&pid).and_then(Weak::upgrade)
+}
+
+/// The OOM killer: kills the process with the largest mapped user memory,
+/// except the `init` process.
+///
+/// It is called by the allocator when memory runs out, so locks that may be
+/// held by the allocating task are only tried.
+pub(crate) fn oom_kill() -> bool {
+    let init_pid = INIT_PROCESS.get().map(|p| p.pid);
+    // The processes are upgraded one at a time, and dropped without the table
+    // lock held, as dropping the last reference runs the namespace drop
+    // hooks. Nothing is allocated, as memory has run out.
+    let mut victim: Option<(Pid, usize)> = None;
+    let mut next_pid = 0;
+    loop {
+        let Some(table) = PROCESS_TABLE.try_lock() else {
+            return false;
+        };
+        let Some((&pid, proc)) = table.range(next_pid..).next() else {
+            break;
+        };
+        let proc = proc.upgrade();
+        drop(table);
+        next_pid = pid + 1;
+
+        let Some(proc) = proc else {
+            continue;
+        };
+        if Some(pid) == init_pid || proc.is_zombie() || proc.is_killed() {
+            continue;
+        }
+        let Some(aspace) = proc.aspace.try_lock().map(|a| a.clone()) else {
+            continue;
+        };
+        let Some(size) = aspace.try_lock().map(|a| a.mapped_size()) else {
+            continue;
+        };
+        if victim.map_or(true, |(_, s)| size > s) {
+            victim = Some((pid, size));
+        }
+    }
+
+    let Some((pid, size)) = victim else {
+        return false;
+    };
+    let Some(proc) = PROCESS_TABLE
+        .try_lock()
+        .and_then(|table| table.get(&pid).and_then(Weak::upgrade))
+    else {
+        return false;
+    };
+    warn!(
+        "out of memory: killing process {} ({} KiB mapped)",
+        pid,
+        size / 1024
+    );
+    proc.kill();
+    true
+}
+
+/// The interrupt hook of the terminals (see [`axtty::set_interrupt_hook`]),
+/// called with the foreground process group of the terminal.
+///
+/// It is called in the interrupt context, so the foreground processes are
+/// killed later by [`kill_interrupted`], when a thread returns to user space.
+pub(crate) fn tty_interrupt(pgid: u64) {
+    if pgid != 0 {
+        INTERRUPT_PGID.store(pgid, Ordering::Release);
+    }
+}
+
+/// Kills the processes in the foreground process group of the terminal that
+/// has been interrupted, except the `init` process.
+pub(crate) fn kill_interrupted() {
+    if INTERRUPT_PGID.load(Ordering::Acquire) == 0 {
+        return;
+    }
+    let pgid = INTERRUPT_PGID.swap(0, Ordering::AcqRel);
+    if pgid == 0 {
+        return;
+    }
+    let init_pid = INIT_PROCESS.get().map(|p| p.pid);
+    // The processes are not upgraded under the lock, as dropping the last
+    // reference runs the namespace drop hooks.
+    let pids: Vec<Pid> = PROCESS_TABLE.lock().keys().copied().collect();
+    for proc in pids
+        .into_iter()
+        .filter(|&pid| Some(pid) != init_pid)
+        .filter_map(find_process)
+    {
+        if proc.pgid() == pgid && !proc.is_zombie() && !proc.is_killed() {
+            info!("interrupted: killing process {}", proc.pid);
+            proc.kill();
+        }
+    }
+}
@@ -0,0 +1,316 @@
+use alloc::string::String;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axerrno::AxResult;
+use axhal::arch::{TrapFrame, UspaceContext};
+use axhal::paging::MappingFlags;
+use axhal::trap::{register_trap_handler, PAGE_FAULT, RETURN_TO_USER};
+use axns::{AxNamespace, AxNamespaceIf};
+use axtask::{AxTaskRef, TaskExtRef, TaskInner};
+use memory_addr::VirtAddr;
+
+use crate::process::{CloneFlags, ProcessRef};
+use crate::UserLayout;
+
+/// The exit code of processes killed by the kernel, as if killed by
+/// `SIGKILL` in a shell.
+const KILLED_EXIT_CODE: i32 = 128 + 9;
+
+/// Task extended data for user tasks.
+pub struct TaskExt {
+    proc: ProcessRef,
+    uctx: UspaceContext,
+    clear_child_tid: AtomicUsize,
+}
+
+impl TaskExt {
+    const fn new(proc: ProcessRef, uctx: UspaceContext) -> Self {
+        Self {
+            proc,
+            uctx,
+            clear_child_tid: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the process that the task belongs to.
+    pub fn process(&self) -> &ProcessRef {
+        &self.proc
+    }
+
+    /// Returns the user address to be cleared (and woken up) when the task
+    /// exits, i.e., the `clear_child_tid` set by `clone` or `set_tid_address`.
+    pub fn clear_child_tid(&self) -> usize {
+        self.clear_child_tid.load(Ordering::Relaxed)
+    }
+
+    /// Sets the `clear_child_tid` address.
+    pub fn set_clear_child_tid(&self, addr: usize) {
+        self.clear_child_tid.store(addr, Ordering::Relaxed);
+    }
+}
+
+axtask::def_task_ext!(TaskExt);
+
+struct AxNamespaceImpl;
+
+#[crate_interface::impl_interface]
+impl AxNamespaceIf for AxNamespaceImpl {
+    fn current_namespace_base() -> *mut u8 {
+        match axtask::current_may_uninit() {
+            // Kernel tasks have no task extended data, use the global namespace.
+            Some(curr) if !unsafe { curr.task_ext_ptr() }.is_null() => {
+                curr.task_ext().proc.namespace().base()
+            }
+            _ => AxNamespace::global().base(),
+        }
+    }
+}
+
+#[register_trap_handler(PAGE_FAULT)]
+fn handle_page_fault(vaddr: VirtAddr, access_flags: MappingFlags, is_user: bool) -> bool {
+    if is_user {
+        exit_if_killed();
+    }
+    let Some(curr) = axtask::current_may_uninit() else {
+        return false;
+    };
+    if unsafe { curr.task_ext_ptr() }.is_null() {
+        return false;
+    }
+    // The kernel may also access user memory in syscalls, so faults from the
+    // kernel mode are handled too, as long as the address is in user space.
+    trace!(
+        "user page fault @ {:#x} ({:?}, is_user={})",
+        vaddr,
+        access_flags,
+        is_user
+    );
+    curr.task_ext()
+        .proc
+        .aspace()
+        .lock()
+        .handle_page_fault(vaddr, access_flags)
+}
+
+/// Creates a new task that enters user space with the context in its task
+/// extended data.
+pub(crate) fn new_user_task(name: &str) -> TaskInner {
+    TaskInner::new(
+        || {
+            let curr = axtask::current();
+            let kstack_top = curr.kernel_stack_top().unwrap();
+            info!(
+                "Enter user space: entry={:#x}, ustack={:#x}, kstack={:#x}",
+                curr.task_ext().uctx.get_ip(),
+                curr.task_ext().uctx.get_sp(),
+                kstack_top,
+            );
+            unsafe { curr.task_ext().uctx.enter_uspace(kstack_top) };
+        },
+        String::from(name),
+        axconfig::TASK_STACK_SIZE,
+    )
+}
+
+/// Attaches the task to the process and adds it to the run queue.
+pub(crate) fn spawn_user_task(
+    proc: &ProcessRef,
+    mut task: TaskInner,
+    uctx: UspaceContext,
+) -> AxTaskRef {
+    task.ctx_mut()
+        .set_page_table_root(proc.aspace().lock().page_table_root());
+    proc.add_thread(task.id().as_u64());
+    task.init_task_ext(TaskExt::new(proc.clone(), uctx));
+    axtask::spawn_task(task)
+}
+
+/// Sets the user thread pointer (TLS) of a new task.
+#[allow(unused_variables)]
+fn set_user_tls(task: &mut TaskInner, tf: &mut TrapFrame, tls: usize) {
+    #[cfg(target_arch = "x86_64")]
+    {
+        task.ctx_mut().fs_base = tls;
+    }
+    #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+    {
+        tf.regs.tp = tls;
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        task.ctx_mut().tpidr_el0 = tls as u64;
+    }
+}
+
+/// Creates a new thread or process as a copy of the current one.
+///
+/// It is the kernel side of the `clone` syscall. `tf` is the trap frame of
+/// the current task, from which the child starts with a return value of 0.
+/// If `stack` is not zero, it is used as the user stack pointer of the child.
+///
+/// If `flags` contains [`CloneFlags::CLONE_THREAD`], the child is a new
+/// thread in the current process. Otherwise, a new process is created. If
+/// `flags` contains [`CloneFlags::CLONE_VFORK`], the current task is blocked
+/// until the new process calls `execve` or exits.
+///
+/// Returns the thread ID of the child (which is also the PID if it is a new
+/// process).
+pub fn clone_current(
+    tf: &TrapFrame,
+    flags: CloneFlags,
+    stack: usize,
+    parent_tid: usize,
+    tls: usize,
+    child_tid: usize,
+) -> AxResult<u64> {
+    let curr = axtask::current();
+    let curr_proc = curr.task_ext().proc.clone();
+
+    let mut task = new_user_task(curr.name());
+    let tid = task.id().as_u64();
+
+    let mut tf = *tf;
+    if flags.contains(CloneFlags::CLONE_SETTLS) {
+        set_user_tls(&mut task, &mut tf, tls);
+    }
+    let mut uctx = UspaceContext::from(&tf);
+    uctx.set_retval(0);
+    if stack != 0 {
+        uctx.set_sp(stack);
+    }
+
+    let proc = if flags.contains(CloneFlags::CLONE_THREAD) {
+        curr_proc
+    } else {
+        curr_proc.fork(tid, flags)?
+    };
+
+    if flags.contains(CloneFlags::CLONE_PARENT_SETTID) && parent_tid != 0 {
+        unsafe { (parent_tid as *mut u32).write(tid as u32) };
+    }
+    let new_task = spawn_user_task(&proc, task, uctx);
+    if flags.contains(CloneFlags::CLONE_CHILD_SETTID) && child_tid != 0 {
+        // The child may have a different address space, write through it.
+        let bytes = (tid as u32).to_ne_bytes();
+        proc.aspace().lock().write(child_tid.into(), &bytes)?;
+    }
+    if flags.contains(CloneFlags::CLONE_CHILD_CLEARTID) {
+        new_task.task_ext().set_clear_child_tid(child_tid);
+    }
+    if !flags.contains(CloneFlags::CLONE_THREAD) {
+        proc.set_main_task(new_task);
+        if flags.contains(CloneFlags::CLONE_VFORK) {
+            proc.wait_vfork_done();
+        }
+    }
+    Ok(tid)
+}
+
+/// Replaces the program of the current process with the ELF executable at
+/// `path`, and enters user space at its entry point.
+///
+/// The other threads of the process exit before the program is replaced, as
+/// `execve` on Linux does. If the process is exiting, or another thread is
+/// also calling `execve`, the current thread exits instead.
+///
+/// It only returns if the executable cannot be loaded, in which case the
+/// current program is not affected.
+pub fn exec_current(path: &str, args: &[&str], envs: &[&str]) -> AxResult {
+    let curr = axtask::current();
+    let proc = curr.task_ext().proc.clone();
+
+    let mut aspace = axmm::new_user_aspace(
+        VirtAddr::from(axconfig::USER_SPACE_BASE),
+        axconfig::USER_SPACE_SIZE,
+    )?;
+    let layout = UserLayout::new();
+    let (entry, ustack_top) = crate::loader::load_user_app(&mut aspace, path, args, envs, &layout)?;
+    let root = aspace.page_table_root();
+
+    if !proc.exit_other_threads(curr.id().as_u64()) {
+        drop(aspace);
+        drop(proc);
+        drop(curr);
+        exit_current(0);
+    }
+
+    // Switch to the new page table before the old mappings are released.
+    unsafe {
+        curr.get_ctx_mut().set_page_table_root(root);
+        #[cfg(target_arch = "aarch64")]
+        axhal::arch::write_page_table_root0(root);
+        #[cfg(not(target_arch = "aarch64"))]
+        axhal::arch::write_page_table_root(root);
+    }
+    proc.set_aspace(aspace, layout);
+    proc.set_vfork_done();
+
+    let uctx = UspaceContext::new(entry, ustack_top, 0);
+    let kstack_top = curr.kernel_stack_top().unwrap();
+    info!(
+        "exec {:?}: entry={:#x}, ustack={:#x}",
+        path, entry, ustack_top
+    );
+    unsafe { uctx.enter_uspace(kstack_top) }
+}
+
+fn clear_child_tid(curr: &TaskInner) {
+    let addr = curr.task_ext().clear_child_tid();
+    if addr != 0 {
+        unsafe { (addr as *mut u32).write(0) };
+    }
+}
+
+/// Exits the current thread.
+///
+/// If it is the last thread of the process, the process also exits with the
+/// same exit code, or the one of [`exit_group_current`] if it has been called.
+pub fn exit_current(exit_code: i32) -> ! {
+    let curr = axtask::current();
+    clear_child_tid(&curr);
+    let proc = curr.task_ext().proc.clone();
+    if proc.remove_thread(curr.id().as_u64()) {
+        proc.exit(proc.group_exit_code().unwrap_or(exit_code));
+    }
+    drop(proc);
+    drop(curr);
+    axtask::exit(exit_code)
+}
+
+/// Exits the current thread if its process has been killed (e.g., by the OOM
+/// killer or `^C` on the console), is exiting by [`exit_group_current`], or
+/// if another thread is calling `execve`.
+///
+/// It is called before returning to user space from a syscall or an
+/// exception, once the trap has been handled and no IRQ handler is running,
+/// and on user page faults.
+#[register_trap_handler(RETURN_TO_USER)]
+pub fn exit_if_killed() {
+    let Some(curr) = axtask::current_may_uninit() else {
+        return;
+    };
+    if unsafe { curr.task_ext_ptr() }.is_null() {
+        return;
+    }
+    crate::process::kill_interrupted();
+    let proc = curr.task_ext().proc.clone();
+    let killed = proc.is_killed();
+    let should_exit = proc.should_thread_exit(curr.id().as_u64());
+    drop(proc);
+    drop(curr);
+    if killed {
+        exit_group_current(KILLED_EXIT_CODE);
+    } else if should_exit {
+        exit_current(0);
+    }
+}
+
+/// Exits all threads of the current process.
+///
+/// The current thread exits immediately, and the others exit when they enter
+/// or return from the kernel next time (see [`exit_if_killed`]). The process
+/// exits with `exit_code` when its last thread exits.
+pub fn exit_group_current(exit_code: i32) -> ! {
+    let exit_code = axtask::current().task_ext().proc.set_group_exit(exit_code);
+    exit_current(exit_code)
+}
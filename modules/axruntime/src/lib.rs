@@ -10,9 +10,10 @@
 //! - `irq`: Enable interrupt handling support.
 //! - `multitask`: Enable multi-threading support.
 //! - `smp`: Enable SMP (symmetric multiprocessing) support.
-//! - `fs`: Enable filesystem support.
+//! - `fs`: Enable filesystem support, with the kernel state in `/proc`.
 //! - `net`: Enable networking support.
 //! - `display`: Enable graphics support.
+//! - `gdbstub`: Enable the GDB stub, and wait for GDB before entering `main`.
 //!
 //! All the features are optional and disabled by default.
 
@@ -22,12 +23,18 @@
 #[macro_use]
 extern crate axlog;
 
+#[cfg(feature = "fs")]
+extern crate alloc;
+
 #[cfg(all(target_os = "none", not(test)))]
 mod lang_items;
 
 #[cfg(feature = "smp")]
 mod mp;
 
+#[cfg(feature = "fs")]
+mod procfs;
+
 #[cfg(feature = "smp")]
 pub use self::mp::rust_main_secondary;
 
@@ -88,7 +95,7 @@ use core::sync::atomic::{AtomicUsize, Ordering};
 static INITED_CPUS: AtomicUsize = AtomicUsize::new(0);
 
 fn is_init_ok() -> bool {
-    INITED_CPUS.load(Ordering::Acquire) == axconfig::SMP
+    INITED_CPUS.load(Ordering::Acquire) == axhal::cpu::cpu_count()
 }
 
 /// The main entry point of the ArceOS runtime.
@@ -147,8 +154,16 @@ pub extern "C" fn rust_main(cpu_id: usize, dtb: usize) -> ! {
     #[cfg(feature = "paging")]
     axmm::init_memory_management();
 
+    #[cfg(feature = "alloc-debug-guard")]
+    axalloc::debug::set_guard_hook(axmm::set_kernel_pages_accessible);
+
+    #[cfg(all(feature = "gdbstub", feature = "paging"))]
+    axhal::gdbstub::set_protect_hook(axmm::protect_kernel_pages);
+
     info!("Initialize platform devices...");
     axhal::platform_init();
+    #[cfg(feature = "tty")]
+    axtty::init();
 
     #[cfg(feature = "multitask")]
     axtask::init_scheduler();
@@ -166,6 +181,9 @@ pub extern "C" fn rust_main(cpu_id: usize, dtb: usize) -> ! {
 
         #[cfg(feature = "display")]
         axdisplay::init_display(all_devices.display);
+
+        #[cfg(feature = "fs")]
+        self::procfs::init();
     }
 
     #[cfg(feature = "smp")]
@@ -192,6 +210,12 @@ pub extern "C" fn rust_main(cpu_id: usize, dtb: usize) -> ! {
         core::hint::spin_loop();
     }
 
+    #[cfg(feature = "gdbstub")]
+    {
+        ax_println!("Waiting for GDB on the console...");
+        axhal::gdbstub::breakpoint();
+    }
+
     unsafe { main() };
 
     #[cfg(feature = "multitask")]
@@ -203,32 +227,107 @@ pub extern "C" fn rust_main(cpu_id: usize, dtb: usize) -> ! {
     }
 }
 
+/// Physical memory below this address is in the DMA32 zone.
+#[cfg(feature = "alloc")]
+const DMA32_LIMIT: u64 = 1 << 32;
+
+/// Free memory pieces smaller than this are added to the byte allocator
+/// instead of becoming separate page pools.
+#[cfg(feature = "alloc")]
+const MIN_PAGE_POOL_SIZE: usize = 0x10_0000; // 1M
+
+/// Splits a physical memory region into pieces that are each in a single
+/// memory zone and NUMA node, and calls `f(paddr, size, zone, node)` for
+/// each of them.
+///
+/// Regions are only split at the end of the DMA32 zone with the `zones`
+/// feature of `axalloc`, as the zones are not distinguished otherwise.
+#[cfg(feature = "alloc")]
+fn for_each_zone_piece(
+    paddr: usize,
+    size: usize,
+    mut f: impl FnMut(usize, usize, axalloc::MemZone, usize),
+) {
+    use axalloc::MemZone;
+    use axhal::mem::{numa_node_of, numa_ranges};
+
+    let end = paddr + size;
+    let mut cur = paddr;
+    while cur < end {
+        let mut next = end;
+        if cfg!(feature = "alloc-zones") && (cur as u64) < DMA32_LIMIT && (end as u64) > DMA32_LIMIT
+        {
+            next = DMA32_LIMIT as usize;
+        }
+        for r in numa_ranges() {
+            let range_start = r.paddr.as_usize();
+            for bound in [range_start, range_start + r.size] {
+                if bound > cur && bound < next {
+                    next = bound;
+                }
+            }
+        }
+        let zone = if (cur as u64) < DMA32_LIMIT {
+            MemZone::Dma32
+        } else {
+            MemZone::Normal
+        };
+        f(cur, next - cur, zone, numa_node_of(cur.into()));
+        cur = next;
+    }
+}
+
 #[cfg(feature = "alloc")]
 fn init_allocator() {
-    use axhal::mem::{memory_regions, phys_to_virt, MemRegionFlags};
+    use axalloc::MemZone;
+    use axhal::mem::{memory_regions, num_numa_nodes, phys_to_virt, MemRegionFlags, PAGE_SIZE_4K};
 
     info!("Initialize global memory allocator...");
     info!("  use {} allocator.", axalloc::global_allocator().name());
 
-    let mut max_region_size = 0;
-    let mut max_region_paddr = 0.into();
-    for r in memory_regions() {
-        if r.flags.contains(MemRegionFlags::FREE) && r.size > max_region_size {
-            max_region_size = r.size;
-            max_region_paddr = r.paddr;
-        }
+    let free_regions = || memory_regions().filter(|r| r.flags.contains(MemRegionFlags::FREE));
+
+    // The largest piece is used to initialize the allocator.
+    let mut max_piece = (0, 0, MemZone::Normal, 0);
+    for r in free_regions() {
+        for_each_zone_piece(r.paddr.as_usize(), r.size, |paddr, size, zone, node| {
+            if size > max_piece.1 {
+                max_piece = (paddr, size, zone, node);
+            }
+        });
     }
-    for r in memory_regions() {
-        if r.flags.contains(MemRegionFlags::FREE) && r.paddr == max_region_paddr {
-            axalloc::global_init(phys_to_virt(r.paddr).as_usize(), r.size);
-            break;
-        }
+    let (max_paddr, max_size, max_zone, max_node) = max_piece;
+    let start = phys_to_virt(max_paddr.into()).as_usize();
+    // With ASLR enabled, the heap starts at a random page in the first 1/16 of
+    // the region, and the skipped part is added back later.
+    let max_pages = max_size / 16 / PAGE_SIZE_4K;
+    let offset = if axconfig::ASLR && max_pages > 0 {
+        axhal::random::random_usize() % max_pages * PAGE_SIZE_4K
+    } else {
+        0
+    };
+    axalloc::global_init_zone(start + offset, max_size - offset, max_zone, max_node);
+    if offset > 0 {
+        axalloc::global_add_memory(start, offset).expect("add heap memory region failed");
     }
-    for r in memory_regions() {
-        if r.flags.contains(MemRegionFlags::FREE) && r.paddr != max_region_paddr {
-            axalloc::global_add_memory(phys_to_virt(r.paddr).as_usize(), r.size)
-                .expect("add heap memory region failed");
-        }
+
+    // Other pieces become separate page pools if possible (with the `zones`
+    // feature of `axalloc`), or are added to the byte allocator otherwise.
+    for r in free_regions() {
+        for_each_zone_piece(r.paddr.as_usize(), r.size, |paddr, size, zone, node| {
+            if paddr == max_paddr {
+                return;
+            }
+            let start = phys_to_virt(paddr.into()).as_usize();
+            if size < MIN_PAGE_POOL_SIZE
+                || axalloc::global_add_pages(start, size, zone, node).is_err()
+            {
+                axalloc::global_add_memory(start, size).expect("add heap memory region failed");
+            }
+        });
+    }
+    if num_numa_nodes() > 1 {
+        info!("  {} NUMA nodes.", num_numa_nodes());
     }
 }
 
@@ -256,10 +355,15 @@ fn init_interrupt() {
 
     axhal::irq::register_handler(TIMER_IRQ_NUM, || {
         update_timer();
+        #[cfg(feature = "tty")]
+        axtty::on_timer_tick();
         #[cfg(feature = "multitask")]
         axtask::on_timer_tick();
     });
 
+    // Setup IPI handler for cross-CPU function calls
+    axhal::irq::init_ipi();
+
     // Enable IRQs before starting app
     axhal::arch::enable_irqs();
 }
@@ -10,7 +10,7 @@ static ENTERED_CPUS: AtomicUsize = AtomicUsize::new(1);
 
 pub fn start_secondary_cpus(primary_cpu_id: usize) {
     let mut logic_cpu_id = 0;
-    for i in 0..SMP {
+    for i in 0..axhal::cpu::cpu_count() {
         if i != primary_cpu_id {
             let stack_top = virt_to_phys(VirtAddr::from(unsafe {
                 SECONDARY_BOOT_STACK[logic_cpu_id].as_ptr_range().end as usize
@@ -51,7 +51,10 @@ pub extern "C" fn rust_main_secondary(cpu_id: usize) -> ! {
     }
 
     #[cfg(feature = "irq")]
-    axhal::arch::enable_irqs();
+    {
+        axhal::irq::init_ipi_secondary();
+        axhal::arch::enable_irqs();
+    }
 
     #[cfg(all(feature = "tls", not(feature = "multitask")))]
     super::init_tls();
@@ -0,0 +1,276 @@
+//! Publishes the kernel state in the procfs (`/proc`).
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::fmt::Write;
+
+use axalloc::oom::{self, OomPolicy, OvercommitMode};
+use axfs::procfs::{self, ProcFile, VfsError, VfsNodeRef, VfsResult};
+
+const KB_PER_PAGE: usize = axhal::mem::PAGE_SIZE_4K / 1024;
+
+fn file(read: impl Fn() -> String + Send + Sync + 'static) -> VfsNodeRef {
+    Arc::new(ProcFile::new(read))
+}
+
+fn tunable(
+    read: impl Fn() -> String + Send + Sync + 'static,
+    write: impl Fn(&str) -> VfsResult + Send + Sync + 'static,
+) -> VfsNodeRef {
+    Arc::new(ProcFile::new_writable(read, write))
+}
+
+fn parse_usize(value: &str) -> VfsResult<usize> {
+    value.parse().map_err(|_| VfsError::InvalidInput)
+}
+
+fn cpuinfo() -> String {
+    let mut s = String::new();
+    for cpu_id in 0..axhal::cpu::cpu_count() {
+        writeln!(s, "processor\t: {}", cpu_id).ok();
+        writeln!(s, "arch\t\t: {}", option_env!("AX_ARCH").unwrap_or("")).ok();
+        writeln!(
+            s,
+            "platform\t: {}",
+            option_env!("AX_PLATFORM").unwrap_or("")
+        )
+        .ok();
+        writeln!(s).ok();
+    }
+    s
+}
+
+fn uptime() -> String {
+    let now = axhal::time::monotonic_time();
+    // The idle time is not accounted.
+    format!("{}.{:02} 0.00\n", now.as_secs(), now.subsec_millis() / 10)
+}
+
+fn meminfo() -> String {
+    let allocator = axalloc::global_allocator();
+    let mut s = String::new();
+    let mut field = |name: &str, kb: usize| {
+        writeln!(s, "{:<16}{:>8} kB", name, kb).ok();
+    };
+    field("MemTotal:", allocator.total_pages() * KB_PER_PAGE);
+    field("MemFree:", allocator.available_pages() * KB_PER_PAGE);
+    field("MemAvailable:", allocator.available_pages() * KB_PER_PAGE);
+    field("KernelHeap:", allocator.used_bytes() / 1024);
+    field("KernelHeapFree:", allocator.available_bytes() / 1024);
+    s
+}
+
+#[cfg(feature = "irq")]
+fn interrupts() -> String {
+    let mut s = String::new();
+    for (irq_num, count) in axhal::irq::irq_counts() {
+        writeln!(s, "{:>4}: {:>10}", irq_num, count).ok();
+    }
+    s
+}
+
+/// Formats the sockets as in `/proc/net/tcp` (`ipv6 = false`) or
+/// `/proc/net/tcp6` (`ipv6 = true`) of Linux.
+#[cfg(feature = "net")]
+fn net_tcp(ipv6: bool) -> String {
+    use alloc::vec::Vec;
+    use core::net::{IpAddr, SocketAddr};
+
+    // The addresses are in the network byte order, printed as 32-bit words.
+    fn fmt_addr(s: &mut String, addr: SocketAddr) {
+        let words: Vec<u32> = match addr.ip() {
+            IpAddr::V4(ip) => alloc::vec![u32::from_le_bytes(ip.octets())],
+            IpAddr::V6(ip) => ip
+                .octets()
+                .chunks(4)
+                .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+                .collect(),
+        };
+        for word in words {
+            write!(s, "{:08X}", word).ok();
+        }
+        write!(s, ":{:04X}", addr.port()).ok();
+    }
+
+    let mut s = String::from(
+        "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  \
+         timeout inode\n",
+    );
+    let sockets = axnet::tcp_sockets()
+        .into_iter()
+        .filter(|socket| socket.local_addr.is_ipv6() == ipv6);
+    for (i, socket) in sockets.enumerate() {
+        write!(s, "{:>4}: ", i).ok();
+        fmt_addr(&mut s, socket.local_addr);
+        s.push(' ');
+        fmt_addr(&mut s, socket.peer_addr);
+        writeln!(
+            s,
+            " {:02X} {:08X}:{:08X} 00:00000000 00000000     0        0 0",
+            socket.state as u8, socket.tx_queue, socket.rx_queue
+        )
+        .ok();
+    }
+    s
+}
+
+#[cfg(feature = "multitask")]
+mod task {
+    use super::*;
+    use alloc::string::ToString;
+    use alloc::vec::Vec;
+
+    use axfs::procfs::ProcDir;
+    use axhal::time::{ticks_to_nanos, NANOS_PER_SEC};
+    use axtask::{AxTaskRef, TaskState};
+
+    /// The unit of the times in `/proc/<tid>/stat`, as `USER_HZ` of Linux.
+    const CLOCK_TICKS_PER_SEC: u64 = 100;
+
+    fn clock_ticks(ticks: isize) -> u64 {
+        ticks_to_nanos(ticks.max(0) as u64) / (NANOS_PER_SEC / CLOCK_TICKS_PER_SEC)
+    }
+
+    fn state(task: &AxTaskRef) -> (char, &'static str) {
+        match task.state() {
+            TaskState::Running | TaskState::Ready => ('R', "running"),
+            TaskState::Blocked => ('S', "sleeping"),
+            TaskState::Exited => ('Z', "zombie"),
+        }
+    }
+
+    fn status(task: &AxTaskRef) -> String {
+        let (state, state_name) = state(task);
+        let tid = task.id().as_u64();
+        format!(
+            "Name:\t{}\nState:\t{} ({})\nTgid:\t{}\nPid:\t{}\nPPid:\t0\nThreads:\t1\n",
+            task.name(),
+            state,
+            state_name,
+            tid,
+            tid,
+        )
+    }
+
+    fn stat(task: &AxTaskRef) -> String {
+        let times = task.sys_times(&[]);
+        format!(
+            "{} ({}) {} 0 0 0 0 -1 0 0 0 0 0 {} {} {} {} 20 0 1 0 0 0 0\n",
+            task.id().as_u64(),
+            task.name(),
+            state(task).0,
+            clock_ticks(times.tms_utime),
+            clock_ticks(times.tms_stime),
+            clock_ticks(times.tms_cutime),
+            clock_ticks(times.tms_cstime),
+        )
+    }
+
+    /// The directory `/proc/<tid>`. It refers to the task by the ID, so that
+    /// it does not keep the task alive, and is empty after the task is gone.
+    fn task_dir(tid: u64) -> VfsNodeRef {
+        let task_file = |f: fn(&AxTaskRef) -> String| {
+            file(move || axtask::get_task(tid).map_or_else(String::new, |task| f(&task)))
+        };
+        ProcDir::with_entries([
+            ("status".to_string(), task_file(status)),
+            ("stat".to_string(), task_file(stat)),
+        ])
+    }
+
+    pub(super) fn task_dirs() -> Vec<(String, VfsNodeRef)> {
+        let mut dirs: Vec<_> = axtask::all_tasks()
+            .into_iter()
+            .map(|task| {
+                let tid = task.id().as_u64();
+                (tid.to_string(), task_dir(tid))
+            })
+            .collect();
+        dirs.push((
+            "self".to_string(),
+            task_dir(axtask::current().id().as_u64()),
+        ));
+        dirs
+    }
+}
+
+/// Registers the files of the kernel state in the procfs.
+///
+/// It must be called after the filesystems and the network are initialized.
+pub(crate) fn init() {
+    info!("Initialize procfs entries...");
+    let register = |path: &str, node: VfsNodeRef| {
+        if let Err(e) = procfs::register(path, node) {
+            warn!("failed to register /proc/{}: {:?}", path, e);
+        }
+    };
+
+    register("cpuinfo", file(cpuinfo));
+    register("meminfo", file(meminfo));
+    register("uptime", file(uptime));
+    register(
+        "sys/vm/panic_on_oom",
+        tunable(
+            || format!("{}\n", (oom::policy() == OomPolicy::Panic) as u8),
+            |value| {
+                match value {
+                    "0" if oom::policy() == OomPolicy::Panic => {
+                        oom::set_policy(OomPolicy::ReturnError)
+                    }
+                    "0" => {}
+                    "1" => oom::set_policy(OomPolicy::Panic),
+                    _ => return Err(VfsError::InvalidInput),
+                }
+                Ok(())
+            },
+        ),
+    );
+    register(
+        "sys/vm/overcommit_memory",
+        tunable(
+            || format!("{}\n", oom::overcommit_mode() as u8),
+            |value| {
+                let mode = match value {
+                    "0" => OvercommitMode::Heuristic,
+                    "1" => OvercommitMode::Always,
+                    "2" => OvercommitMode::Never,
+                    _ => return Err(VfsError::InvalidInput),
+                };
+                oom::set_overcommit_mode(mode);
+                Ok(())
+            },
+        ),
+    );
+    register(
+        "sys/vm/min_free_kbytes",
+        tunable(
+            || format!("{}\n", oom::low_watermark() * KB_PER_PAGE),
+            |value| {
+                oom::set_low_watermark(parse_usize(value)? / KB_PER_PAGE);
+                Ok(())
+            },
+        ),
+    );
+
+    #[cfg(feature = "irq")]
+    register("interrupts", file(interrupts));
+
+    #[cfg(feature = "net")]
+    {
+        register("net/tcp", file(|| net_tcp(false)));
+        register("net/tcp6", file(|| net_tcp(true)));
+        register(
+            "sys/net/core/somaxconn",
+            tunable(
+                || format!("{}\n", axnet::somaxconn()),
+                |value| axnet::set_somaxconn(parse_usize(value)?),
+            ),
+        );
+    }
+
+    #[cfg(feature = "multitask")]
+    if let Err(e) = procfs::register_generator("", task::task_dirs) {
+        warn!("failed to register the task directories in /proc: {:?}", e);
+    }
+}
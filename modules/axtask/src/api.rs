@@ -5,7 +5,7 @@ use alloc::{string::String, sync::Arc};
 pub(crate) use crate::run_queue::{AxRunQueue, RUN_QUEUE};
 
 #[doc(cfg(feature = "multitask"))]
-pub use crate::task::{CurrentTask, TaskId, TaskInner};
+pub use crate::task::{all_tasks, get_task, CurrentTask, TaskId, TaskInner};
 #[doc(cfg(feature = "multitask"))]
 pub use crate::task_ext::{TaskExtMut, TaskExtRef};
 #[doc(cfg(feature = "multitask"))]
@@ -64,6 +64,12 @@ pub fn current() -> CurrentTask {
     CurrentTask::get()
 }
 
+/// Returns the bounds of the kernel stack of the current task, for unwinding.
+fn current_stack_bounds() -> Option<(usize, usize)> {
+    let (bottom, top) = current_may_uninit()?.kernel_stack_bounds()?;
+    Some((bottom.as_usize(), top.as_usize()))
+}
+
 /// Initializes the task scheduler (for the primary CPU).
 pub fn init_scheduler() {
     info!("Initialize scheduling...");
@@ -71,6 +77,7 @@ pub fn init_scheduler() {
     crate::run_queue::init();
     #[cfg(feature = "irq")]
     crate::timers::init();
+    axhal::backtrace::set_stack_bounds_hook(current_stack_bounds);
 
     info!("  use {} scheduler.", Scheduler::scheduler_name());
 }
@@ -169,6 +176,6 @@ pub fn run_idle() -> ! {
         yield_now();
         debug!("idle task: waiting for IRQs...");
         #[cfg(feature = "irq")]
-        axhal::arch::wait_for_irqs();
+        crate::run_queue::idle_wait_for_irqs();
     }
 }
@@ -1,5 +1,7 @@
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
+#[cfg(feature = "irq")]
+use core::sync::atomic::{AtomicUsize, Ordering};
 use kspin::SpinNoIrq;
 use lazyinit::LazyInit;
 use scheduler::BaseScheduler;
@@ -15,6 +17,10 @@ static EXITED_TASKS: SpinNoIrq<VecDeque<AxTaskRef>> = SpinNoIrq::new(VecDeque::n
 
 static WAIT_FOR_EXIT: WaitQueue = WaitQueue::new();
 
+/// The bitmap of CPUs that are waiting for IRQs in their idle tasks.
+#[cfg(feature = "irq")]
+static IDLE_CPUS: AtomicUsize = AtomicUsize::new(0);
+
 #[percpu::def_percpu]
 static IDLE_TASK: LazyInit<AxTaskRef> = LazyInit::new();
 
@@ -34,6 +40,8 @@ impl AxRunQueue {
         debug!("task spawn: {}", task.id_name());
         assert!(task.is_ready());
         self.scheduler.add_task(task);
+        #[cfg(feature = "irq")]
+        kick_idle_cpu();
     }
 
     #[cfg(feature = "irq")]
@@ -122,6 +130,8 @@ impl AxRunQueue {
         if task.is_blocked() {
             task.set_state(TaskState::Ready);
             self.scheduler.add_task(task); // TODO: priority
+            #[cfg(feature = "irq")]
+            kick_idle_cpu();
             if resched {
                 #[cfg(feature = "preempt")]
                 crate::current().set_preempt_pending(true);
@@ -145,6 +155,44 @@ impl AxRunQueue {
     }
 }
 
+/// Waits for IRQs in the idle task, marking the current CPU as idle so that
+/// other CPUs can kick it when new tasks are ready.
+#[cfg(feature = "irq")]
+pub(crate) fn idle_wait_for_irqs() {
+    let mask = 1 << axhal::cpu::this_cpu_id();
+    axhal::arch::disable_irqs();
+    // Mark the CPU as idle before the last check of the run queue, so that
+    // the tasks added after the check kick it. IRQs stay disabled until it
+    // waits, so that the kick stays pending instead of being handled (and
+    // lost) before.
+    loop {
+        IDLE_CPUS.fetch_or(mask, Ordering::AcqRel);
+        crate::yield_now();
+        // If the bit is cleared, the CPU has been kicked (possibly while
+        // running other tasks), so check again.
+        if IDLE_CPUS.load(Ordering::Acquire) & mask != 0 {
+            break;
+        }
+    }
+    axhal::arch::enable_irqs_and_wait();
+    IDLE_CPUS.fetch_and(!mask, Ordering::AcqRel);
+}
+
+/// Sends an IPI to an idle CPU other than the current one (if any), so that it
+/// picks up the newly ready task instead of waiting for the next timer tick.
+#[cfg(feature = "irq")]
+fn kick_idle_cpu() {
+    let this_mask = 1 << axhal::cpu::this_cpu_id();
+    let idle = IDLE_CPUS.load(Ordering::Acquire) & !this_mask;
+    if idle != 0 {
+        let cpu_id = idle.trailing_zeros() as usize;
+        // Clear the bit, so that the next ready task kicks another CPU (or
+        // none, if this one is running tasks since the last check).
+        IDLE_CPUS.fetch_and(!(1 << cpu_id), Ordering::AcqRel);
+        axhal::irq::send_ipi(cpu_id);
+    }
+}
+
 impl AxRunQueue {
     /// Common reschedule subroutine. If `preempt`, keep current task's time
     /// slice, otherwise reset it.
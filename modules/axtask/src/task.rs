@@ -1,4 +1,6 @@
-use alloc::{boxed::Box, string::String, sync::Arc};
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::{boxed::Box, string::String, vec::Vec};
 use core::ops::Deref;
 use core::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, AtomicU8, Ordering};
 use core::{alloc::Layout, cell::UnsafeCell, fmt, ptr::NonNull};
@@ -10,11 +12,15 @@ use core::sync::atomic::AtomicUsize;
 use axhal::tls::TlsArea;
 
 use axhal::arch::TaskContext;
+use kspin::SpinNoIrq;
 use memory_addr::{align_up_4k, VirtAddr};
 
 use crate::task_ext::AxTaskExt;
 use crate::{AxRunQueue, AxTask, AxTaskRef, WaitQueue};
 
+/// All the tasks that are not dropped yet, indexed by their IDs.
+static TASK_REGISTRY: SpinNoIrq<BTreeMap<u64, Weak<AxTask>>> = SpinNoIrq::new(BTreeMap::new());
+
 /// A unique identifier for a thread.
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub struct TaskId(u64);
@@ -252,7 +258,10 @@ impl TaskInner {
     }
 
     pub(crate) fn into_arc(self) -> AxTaskRef {
-        Arc::new(AxTask::new(self))
+        let id = self.id.as_u64();
+        let task = Arc::new(AxTask::new(self));
+        TASK_REGISTRY.lock().insert(id, Arc::downgrade(&task));
+        task
     }
 
     #[inline]
@@ -370,6 +379,14 @@ impl TaskInner {
         }
     }
 
+    /// Returns the bounds `[bottom, top)` of the kernel stack.
+    ///
+    /// It is [`None`] for the tasks running on the boot stacks (the main and
+    /// the idle tasks of secondary CPUs).
+    pub fn kernel_stack_bounds(&self) -> Option<(VirtAddr, VirtAddr)> {
+        self.kstack.as_ref().map(|s| (s.bottom(), s.top()))
+    }
+
     pub fn get_times_mut(&self) -> &mut Times {
         unsafe { &mut *self.times.get() }
     }
@@ -392,9 +409,27 @@ impl fmt::Debug for TaskInner {
 impl Drop for TaskInner {
     fn drop(&mut self) {
         debug!("task drop: {}", self.id_name());
+        TASK_REGISTRY.lock().remove(&self.id.as_u64());
     }
 }
 
+/// Returns the task with the given ID, or [`None`] if there is no such task
+/// or it has been dropped.
+pub fn get_task(id: u64) -> Option<AxTaskRef> {
+    TASK_REGISTRY.lock().get(&id).and_then(Weak::upgrade)
+}
+
+/// Returns all the tasks that are not dropped yet, in the order of their IDs.
+///
+/// The exited tasks are included until they are dropped.
+pub fn all_tasks() -> Vec<AxTaskRef> {
+    TASK_REGISTRY
+        .lock()
+        .values()
+        .filter_map(Weak::upgrade)
+        .collect()
+}
+
 struct TaskStack {
     ptr: NonNull<u8>,
     layout: Layout,
@@ -412,6 +447,10 @@ impl TaskStack {
     pub const fn top(&self) -> VirtAddr {
         unsafe { core::mem::transmute(self.ptr.as_ptr().add(self.layout.size())) }
     }
+
+    pub fn bottom(&self) -> VirtAddr {
+        VirtAddr::from(self.ptr.as_ptr() as usize)
+    }
 }
 
 impl Drop for TaskStack {
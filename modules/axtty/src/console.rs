@@ -0,0 +1,45 @@
+use crate::termios::{InputFlags, LocalFlags, Termios};
+use crate::tty::{Tty, TtyDriver};
+
+/// The driver of the system console, on the console UART of [`axhal`].
+pub struct ConsoleDriver;
+
+impl TtyDriver for ConsoleDriver {
+    fn write(&self, buf: &[u8]) -> usize {
+        axhal::console::write_bytes(buf);
+        buf.len()
+    }
+
+    fn poll_input(&self) -> Option<u8> {
+        axhal::console::getchar()
+    }
+
+    #[cfg(feature = "irq")]
+    fn has_input_irq(&self) -> bool {
+        axhal::console::has_input_irq()
+    }
+
+    #[cfg(not(feature = "irq"))]
+    fn has_input_irq(&self) -> bool {
+        false
+    }
+}
+
+/// The initial attributes of the console, with which it is read as before
+/// there were terminals: byte by byte without echo, with CR translated to NL.
+const fn console_termios() -> Termios {
+    let mut termios = Termios::new();
+    termios.iflag = InputFlags::ICRNL;
+    termios.lflag = LocalFlags::empty();
+    termios
+}
+
+static CONSOLE: Tty<ConsoleDriver> = Tty::with_termios(ConsoleDriver, console_termios());
+
+/// Returns the terminal of the system console.
+///
+/// It is initially in raw mode (except that CR is translated to NL), and can
+/// be switched to canonical mode by [`Tty::set_termios`].
+pub fn console() -> &'static Tty<ConsoleDriver> {
+    &CONSOLE
+}
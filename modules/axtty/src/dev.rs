@@ -0,0 +1,150 @@
+//! Terminal device files, to be added to the device filesystem.
+//!
+//! - `/dev/console` and `/dev/ttyS0`: the system console ([`TtyNode`]).
+//! - `/dev/ptmx`: the pseudo-terminal multiplexer ([`PtmxNode`]). Opening it
+//!   allocates a new pseudo-terminal, which is done by the upper layers with
+//!   [`PtyMaster::open`](crate::PtyMaster::open), as a device file does not
+//!   have per-open state.
+//! - `/dev/pts/N`: the slave of the pseudo-terminal numbered `N`
+//!   ([`PtsDir`]).
+
+use alloc::format;
+use alloc::sync::Arc;
+
+use axfs_vfs::{VfsDirEntry, VfsError, VfsNodeAttr, VfsNodeOps, VfsNodePerm, VfsNodeRef};
+use axfs_vfs::{VfsNodeType, VfsResult};
+
+use crate::pty::{pty_indices, pty_slave};
+use crate::tty::TtyRef;
+
+fn char_device_attr(perm: u16) -> VfsNodeAttr {
+    VfsNodeAttr::new(
+        VfsNodePerm::from_bits_truncate(perm),
+        VfsNodeType::CharDevice,
+        0,
+        0,
+    )
+}
+
+/// The device file of a terminal.
+pub struct TtyNode(TtyRef);
+
+impl TtyNode {
+    /// Creates the device file of the terminal.
+    pub const fn new(tty: TtyRef) -> Self {
+        Self(tty)
+    }
+
+    /// Creates the device file of the system console.
+    pub fn console() -> Self {
+        Self(TtyRef::Static(crate::console()))
+    }
+
+    /// Returns the terminal of the device file.
+    pub fn tty(&self) -> &TtyRef {
+        &self.0
+    }
+}
+
+impl VfsNodeOps for TtyNode {
+    axfs_vfs::impl_vfs_non_dir_default! {}
+
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        Ok(char_device_attr(0o620))
+    }
+
+    fn read_at(&self, _offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        self.0.read(buf, false)
+    }
+
+    fn write_at(&self, _offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        self.0.write(buf)
+    }
+
+    fn truncate(&self, _size: u64) -> VfsResult {
+        Ok(())
+    }
+
+    fn as_any(&self) -> &dyn core::any::Any {
+        self
+    }
+}
+
+/// The device file of the pseudo-terminal multiplexer, `/dev/ptmx`.
+pub struct PtmxNode;
+
+impl VfsNodeOps for PtmxNode {
+    axfs_vfs::impl_vfs_non_dir_default! {}
+
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        Ok(char_device_attr(0o666))
+    }
+
+    fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> VfsResult<usize> {
+        Err(VfsError::Unsupported)
+    }
+
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> VfsResult<usize> {
+        Err(VfsError::Unsupported)
+    }
+
+    fn as_any(&self) -> &dyn core::any::Any {
+        self
+    }
+}
+
+/// The directory of the pseudo-terminal slaves, `/dev/pts`.
+pub struct PtsDir;
+
+impl VfsNodeOps for PtsDir {
+    axfs_vfs::impl_vfs_dir_default! {}
+
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        Ok(VfsNodeAttr::new(
+            VfsNodePerm::from_bits_truncate(0o755),
+            VfsNodeType::Dir,
+            0,
+            0,
+        ))
+    }
+
+    fn lookup(self: Arc<Self>, path: &str) -> VfsResult<VfsNodeRef> {
+        let path = path.trim_matches('/');
+        if path.is_empty() || path == "." {
+            return Ok(self);
+        }
+        if let Some(rest) = path.strip_prefix("./") {
+            return self.lookup(rest);
+        }
+        let slave = path
+            .parse()
+            .ok()
+            .and_then(pty_slave)
+            .ok_or(VfsError::NotFound)?;
+        Ok(Arc::new(TtyNode::new(TtyRef::Shared(slave))))
+    }
+
+    fn read_dir(&self, start_idx: usize, dirents: &mut [VfsDirEntry]) -> VfsResult<usize> {
+        let indices = pty_indices();
+        let mut entries = [".", ".."]
+            .into_iter()
+            .map(|name| VfsDirEntry::new(name, VfsNodeType::Dir))
+            .chain(
+                indices
+                    .iter()
+                    .map(|i| VfsDirEntry::new(&format!("{}", i), VfsNodeType::CharDevice)),
+            )
+            .skip(start_idx);
+        for (i, out_entry) in dirents.iter_mut().enumerate() {
+            match entries.next() {
+                Some(entry) => *out_entry = entry,
+                None => return Ok(i),
+            }
+        }
+        Ok(dirents.len())
+    }
+
+    fn as_any(&self) -> &dyn core::any::Any {
+        self
+    }
+}
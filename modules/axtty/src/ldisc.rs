@@ -0,0 +1,387 @@
+//! The line discipline, which processes the input bytes of a terminal
+//! according to its [`Termios`].
+
+use crate::termios::*;
+
+/// The size of the queue of the input ready to be read.
+const READ_QUEUE_SIZE: usize = 4096;
+/// The maximum length of a line being edited in canonical mode.
+const MAX_CANON: usize = 255;
+
+const NL: u8 = b'\n';
+const CR: u8 = b'\r';
+
+/// Returns whether the byte is a control character, which is echoed as `^X`
+/// with `ECHOCTL`.
+const fn is_ctrl(c: u8) -> bool {
+    (c < 0x20 && c != b'\t' && c != NL) || c == 0x7f
+}
+
+/// The kinds of the bytes in the read queue.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mark {
+    /// An ordinary byte.
+    Data,
+    /// The last byte of a line (e.g., NL or `VEOL`), where a canonical read
+    /// stops.
+    LineEnd,
+    /// `VEOF`, which terminates a line and is not read. On an empty line, it
+    /// makes a canonical read return 0 (an end-of-file).
+    Eof,
+}
+
+pub struct LineDiscipline {
+    termios: Termios,
+    /// The ring buffer of the bytes ready to be read.
+    read_buf: [u8; READ_QUEUE_SIZE],
+    read_marks: [Mark; READ_QUEUE_SIZE],
+    read_head: usize,
+    read_len: usize,
+    /// The line being edited in canonical mode.
+    line: [u8; MAX_CANON],
+    line_len: usize,
+}
+
+impl LineDiscipline {
+    pub const fn new(termios: Termios) -> Self {
+        Self {
+            termios,
+            read_buf: [0; READ_QUEUE_SIZE],
+            read_marks: [Mark::Data; READ_QUEUE_SIZE],
+            read_head: 0,
+            read_len: 0,
+            line: [0; MAX_CANON],
+            line_len: 0,
+        }
+    }
+
+    pub const fn termios(&self) -> &Termios {
+        &self.termios
+    }
+
+    /// Sets the attributes. The line being edited becomes readable if the
+    /// canonical mode is turned off.
+    pub fn set_termios(&mut self, termios: &Termios) {
+        if self.termios.is_canonical() && !termios.is_canonical() {
+            self.commit_line(Mark::Data);
+        }
+        self.termios = *termios;
+    }
+
+    /// Returns whether there is input to read, or an end-of-file.
+    pub const fn readable(&self) -> bool {
+        self.read_len > 0
+    }
+
+    /// Discards all input not read yet.
+    pub fn flush_input(&mut self) {
+        self.read_head = 0;
+        self.read_len = 0;
+        self.line_len = 0;
+    }
+
+    fn push_read(&mut self, c: u8, mark: Mark) {
+        if self.read_len < READ_QUEUE_SIZE {
+            let idx = (self.read_head + self.read_len) % READ_QUEUE_SIZE;
+            self.read_buf[idx] = c;
+            self.read_marks[idx] = mark;
+            self.read_len += 1;
+        }
+    }
+
+    fn pop_read(&mut self) -> (u8, Mark) {
+        let idx = self.read_head;
+        self.read_head = (self.read_head + 1) % READ_QUEUE_SIZE;
+        self.read_len -= 1;
+        (self.read_buf[idx], self.read_marks[idx])
+    }
+
+    /// Makes the line being edited readable. Its last byte is marked with
+    /// `end`, which is [`Mark::Data`] if the line is not terminated.
+    fn commit_line(&mut self, end: Mark) {
+        let line = self.line;
+        let len = self.line_len;
+        for (i, &c) in line[..len].iter().enumerate() {
+            self.push_read(c, if i + 1 == len { end } else { Mark::Data });
+        }
+        self.line_len = 0;
+    }
+
+    fn echo_char(&self, c: u8, echo: &mut dyn FnMut(&[u8])) {
+        if !self.termios.lflag.contains(LocalFlags::ECHO) {
+            return;
+        }
+        if is_ctrl(c) && self.termios.lflag.contains(LocalFlags::ECHOCTL) {
+            echo(&[b'^', c ^ 0x40]);
+        } else {
+            echo(&[c]);
+        }
+    }
+
+    /// Erases the last character of the line being edited, and returns
+    /// whether there was one.
+    fn erase_char(&mut self, echo: &mut dyn FnMut(&[u8])) -> bool {
+        if self.line_len == 0 {
+            return false;
+        }
+        self.line_len -= 1;
+        let lflag = self.termios.lflag;
+        if lflag.contains(LocalFlags::ECHO | LocalFlags::ECHOE) {
+            let c = self.line[self.line_len];
+            let width = if is_ctrl(c) && lflag.contains(LocalFlags::ECHOCTL) {
+                2
+            } else {
+                1
+            };
+            for _ in 0..width {
+                echo(b"\x08 \x08");
+            }
+        }
+        true
+    }
+
+    /// Processes a received byte, and echoes it by `echo` if necessary.
+    ///
+    /// Returns `true` if it is the interrupt character (`VINTR`) with `ISIG`
+    /// set, so that the caller can interrupt the foreground task.
+    pub fn receive(&mut self, mut c: u8, echo: &mut dyn FnMut(&[u8])) -> bool {
+        let Termios {
+            iflag, lflag, cc, ..
+        } = self.termios;
+
+        if iflag.contains(InputFlags::ISTRIP) {
+            c &= 0x7f;
+        }
+        if c == CR {
+            if iflag.contains(InputFlags::IGNCR) {
+                return false;
+            }
+            if iflag.contains(InputFlags::ICRNL) {
+                c = NL;
+            }
+        } else if c == NL && iflag.contains(InputFlags::INLCR) {
+            c = CR;
+        }
+
+        if lflag.contains(LocalFlags::ISIG) && c == cc[VINTR] && c != 0 {
+            if !lflag.contains(LocalFlags::NOFLSH) {
+                self.flush_input();
+            }
+            self.echo_char(c, echo);
+            return true;
+        }
+
+        if !lflag.contains(LocalFlags::ICANON) {
+            self.push_read(c, Mark::Data);
+            self.echo_char(c, echo);
+            return false;
+        }
+
+        if c == NL || (c == cc[VEOL] && c != 0) || (c == cc[VEOL2] && c != 0) {
+            // There is always room for the line terminator.
+            self.line[self.line_len] = c;
+            self.line_len += 1;
+            self.commit_line(Mark::LineEnd);
+            if lflag.contains(LocalFlags::ECHO) || (c == NL && lflag.contains(LocalFlags::ECHONL)) {
+                echo(&[c]);
+            }
+        } else if c == cc[VEOF] && c != 0 {
+            self.commit_line(Mark::Data);
+            self.push_read(c, Mark::Eof);
+        } else if c == cc[VERASE] && c != 0 {
+            self.erase_char(echo);
+        } else if c == cc[VKILL] && c != 0 {
+            if lflag.contains(LocalFlags::ECHOKE) {
+                while self.erase_char(echo) {}
+            } else {
+                self.line_len = 0;
+                if lflag.contains(LocalFlags::ECHOK) {
+                    self.echo_char(c, echo);
+                    echo(b"\n");
+                }
+            }
+        } else if c == cc[VWERASE] && c != 0 && lflag.contains(LocalFlags::IEXTEN) {
+            while self.line_len > 0 && self.line[self.line_len - 1] == b' ' {
+                self.erase_char(echo);
+            }
+            while self.line_len > 0 && self.line[self.line_len - 1] != b' ' {
+                self.erase_char(echo);
+            }
+        } else if self.line_len < MAX_CANON - 1 {
+            self.line[self.line_len] = c;
+            self.line_len += 1;
+            self.echo_char(c, echo);
+        }
+        false
+    }
+
+    /// Reads the input into `buf`, and returns the number of bytes read.
+    ///
+    /// In canonical mode, at most one line is read, and 0 is returned at an
+    /// end-of-file. It also returns 0 if there is no input.
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let canonical = self.termios.is_canonical();
+        let mut n = 0;
+        while n < buf.len() && self.read_len > 0 {
+            let (c, mark) = self.pop_read();
+            match mark {
+                Mark::Eof if canonical => return n,
+                // Left over from canonical mode.
+                Mark::Eof => continue,
+                Mark::LineEnd if canonical => {
+                    buf[n] = c;
+                    return n + 1;
+                }
+                _ => {
+                    buf[n] = c;
+                    n += 1;
+                }
+            }
+        }
+        // `VEOF` terminating the line just read is consumed with the line, so
+        // the next read does not return an end-of-file.
+        if canonical && n > 0 && self.read_len > 0 && self.read_marks[self.read_head] == Mark::Eof {
+            self.pop_read();
+        }
+        n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn canonical() -> LineDiscipline {
+        LineDiscipline::new(Termios::new())
+    }
+
+    fn raw() -> LineDiscipline {
+        let mut termios = Termios::new();
+        termios.make_raw();
+        LineDiscipline::new(termios)
+    }
+
+    /// Passes `input` to the line discipline, and returns the echo.
+    fn feed(ldisc: &mut LineDiscipline, input: &[u8]) -> Vec<u8> {
+        let mut echoed = Vec::new();
+        for &c in input {
+            ldisc.receive(c, &mut |s| echoed.extend_from_slice(s));
+        }
+        echoed
+    }
+
+    fn read(ldisc: &mut LineDiscipline, len: usize) -> Vec<u8> {
+        let mut buf = vec![0; len];
+        let n = ldisc.read(&mut buf);
+        buf.truncate(n);
+        buf
+    }
+
+    #[test]
+    fn test_canonical_lines() {
+        let mut ldisc = canonical();
+        assert_eq!(feed(&mut ldisc, b"ab"), b"ab");
+        assert!(!ldisc.readable());
+        feed(&mut ldisc, b"\rcd\n");
+        assert_eq!(read(&mut ldisc, 64), b"ab\n");
+        assert_eq!(read(&mut ldisc, 1), b"c");
+        assert_eq!(read(&mut ldisc, 64), b"d\n");
+        assert!(!ldisc.readable());
+    }
+
+    #[test]
+    fn test_canonical_eol() {
+        let mut termios = Termios::new();
+        termios.cc[VEOL] = b';';
+        termios.cc[VEOL2] = b'|';
+        let mut ldisc = LineDiscipline::new(termios);
+        feed(&mut ldisc, b"a;b|c\n");
+        assert_eq!(read(&mut ldisc, 64), b"a;");
+        assert_eq!(read(&mut ldisc, 64), b"b|");
+        assert_eq!(read(&mut ldisc, 64), b"c\n");
+    }
+
+    #[test]
+    fn test_canonical_eof() {
+        let mut ldisc = canonical();
+        // `^D` terminates a non-empty line, without being read.
+        feed(&mut ldisc, b"ab\x04cd\n");
+        assert_eq!(read(&mut ldisc, 64), b"ab");
+        assert_eq!(read(&mut ldisc, 64), b"cd\n");
+
+        // `^D` on an empty line is an end-of-file.
+        feed(&mut ldisc, b"\x04ef\x04");
+        assert!(ldisc.readable());
+        assert_eq!(read(&mut ldisc, 64), b"");
+        assert_eq!(read(&mut ldisc, 2), b"ef");
+        assert!(!ldisc.readable());
+    }
+
+    #[test]
+    fn test_canonical_erase() {
+        let mut ldisc = canonical();
+        assert_eq!(feed(&mut ldisc, b"ab\x7f"), b"ab\x08 \x08");
+        // BS is not the erase character by default.
+        assert_eq!(feed(&mut ldisc, b"\x08"), b"^H");
+        feed(&mut ldisc, b"\n");
+        assert_eq!(read(&mut ldisc, 64), b"a\x08\n");
+
+        let mut termios = Termios::new();
+        termios.cc[VERASE] = 0x08;
+        ldisc.set_termios(&termios);
+        feed(&mut ldisc, b"ab\x08\n");
+        assert_eq!(read(&mut ldisc, 64), b"a\n");
+    }
+
+    #[test]
+    fn test_canonical_kill_and_werase() {
+        let mut ldisc = canonical();
+        feed(&mut ldisc, b"abc\x15de fg \x17\n");
+        assert_eq!(read(&mut ldisc, 64), b"de \n");
+    }
+
+    #[test]
+    fn test_canonical_line_full() {
+        let mut ldisc = canonical();
+        feed(&mut ldisc, &[b'a'; MAX_CANON + 10]);
+        feed(&mut ldisc, b"\nb\n");
+        let line = read(&mut ldisc, 2 * MAX_CANON);
+        assert_eq!(line.len(), MAX_CANON);
+        assert_eq!(line.last(), Some(&b'\n'));
+        assert_eq!(read(&mut ldisc, 64), b"b\n");
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let mut ldisc = canonical();
+        feed(&mut ldisc, b"ab\ncd");
+        let mut echoed = Vec::new();
+        assert!(ldisc.receive(0x03, &mut |s| echoed.extend_from_slice(s)));
+        assert_eq!(echoed, b"^C");
+        assert!(!ldisc.readable());
+
+        let mut termios = Termios::new();
+        termios.lflag.remove(LocalFlags::ISIG);
+        ldisc.set_termios(&termios);
+        assert!(!ldisc.receive(0x03, &mut |_| {}));
+    }
+
+    #[test]
+    fn test_raw() {
+        let mut ldisc = raw();
+        assert_eq!(feed(&mut ldisc, b"a\r\x7f\x04\x03"), b"");
+        assert!(ldisc.readable());
+        assert_eq!(read(&mut ldisc, 64), b"a\r\x7f\x04\x03");
+    }
+
+    #[test]
+    fn test_switch_mode() {
+        let mut ldisc = canonical();
+        feed(&mut ldisc, b"ab\x04cd");
+        let mut termios = Termios::new();
+        termios.make_raw();
+        ldisc.set_termios(&termios);
+        // The pending line becomes readable, and `^D` is dropped.
+        assert_eq!(read(&mut ldisc, 64), b"abcd");
+    }
+}
@@ -0,0 +1,83 @@
+//! [ArceOS](https://github.com/arceos-org/arceos) terminal (TTY) devices.
+//!
+//! A terminal ([`Tty`]) consists of a low-level driver ([`TtyDriver`]) and a
+//! line discipline, which processes the input according to the terminal
+//! attributes ([`Termios`]):
+//!
+//! - In canonical mode, the input is edited by lines (erase with backspace,
+//!   `^U`, `^W`) and becomes readable after a newline or `^D`.
+//! - In raw mode, the input is readable byte by byte, without processing.
+//! - The input is echoed if `ECHO` is set, and `^C` (with `ISIG`) discards
+//!   the pending input and calls the hook set by [`set_interrupt_hook`].
+//!
+//! The system console ([`console()`]) is a terminal on the console UART. It
+//! starts in raw mode, so it is read byte by byte as the UART itself.
+//! Remote sessions can be attached to it by [`ConsoleSession`].
+//!
+//! Pseudo-terminals ([`PtyMaster`]) are pairs of a master and a slave
+//! terminal, which are connected to each other.
+//!
+//! # Cargo Features
+//!
+//! - `irq`: Receive the console input by the UART interrupts, on the
+//!   platforms that support it. Otherwise, it is polled when read.
+//! - `multitask`: Block the readers in a wait queue until the input arrives
+//!   (requires `irq` for the console), instead of polling or yielding.
+//! - `alloc`: Enable pseudo-terminals and console sessions.
+//! - `devfs`: Provide the terminal device files (mod [`dev`]) for the device
+//!   filesystem.
+
+#![cfg_attr(not(test), no_std)]
+
+#[macro_use]
+extern crate log;
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+mod console;
+mod ldisc;
+pub mod termios;
+mod tty;
+
+#[cfg(feature = "devfs")]
+pub mod dev;
+#[cfg(feature = "alloc")]
+mod mux;
+#[cfg(feature = "alloc")]
+mod output;
+#[cfg(feature = "alloc")]
+mod pty;
+
+pub use self::console::{console, ConsoleDriver};
+pub use self::termios::Termios;
+pub use self::tty::{set_interrupt_hook, Tty, TtyDriver};
+
+#[cfg(feature = "alloc")]
+pub use self::mux::{ConsoleSession, MAX_SESSIONS};
+#[cfg(feature = "alloc")]
+pub use self::pty::{pty_indices, pty_slave, PtyMaster, PtySlave, PtySlaveDriver, MAX_PTYS};
+#[cfg(feature = "alloc")]
+pub use self::tty::TtyRef;
+
+/// Initializes the terminals.
+///
+/// With the `irq` feature, the console input received by interrupts is passed
+/// to the console terminal as it arrives. With the `alloc` feature, the
+/// console output is mirrored to the attached sessions.
+pub fn init() {
+    info!("Initialize terminals...");
+    #[cfg(feature = "irq")]
+    axhal::console::set_input_hook(|| console().poll_input());
+    #[cfg(feature = "alloc")]
+    axhal::console::set_output_hook(mux::mirror_output);
+}
+
+/// Wakes up the readers of the console sessions with new output.
+///
+/// It should be called on each timer tick, as the console output can be
+/// written in any context, where the readers are not woken up.
+pub fn on_timer_tick() {
+    #[cfg(feature = "alloc")]
+    mux::wake_sessions();
+}
@@ -0,0 +1,114 @@
+//! The console multiplexer, which attaches remote sessions to the system
+//! console.
+//!
+//! The console output (everything written by [`axhal::console::write_bytes`],
+//! including the kernel logs) is mirrored to all attached sessions, and the
+//! input of each session is passed to the console terminal as if it is
+//! received from the UART.
+
+use alloc::sync::Arc;
+
+use axerrno::{ax_err, AxError, AxResult};
+use kspin::SpinNoIrq;
+
+use crate::output::OutputBuffer;
+
+/// The maximum number of attached sessions.
+pub const MAX_SESSIONS: usize = 4;
+
+/// The size of the output buffer of each session. The output is dropped if
+/// the session does not read it in time, as the console output cannot block.
+const SESSION_BUF_SIZE: usize = 16384;
+
+static SESSIONS: SpinNoIrq<[Option<Arc<OutputBuffer>>; MAX_SESSIONS]> =
+    SpinNoIrq::new([const { None }; MAX_SESSIONS]);
+
+/// Mirrors the console output to all attached sessions. It is the output hook
+/// of [`axhal::console`], so it does not wake up the readers of the sessions
+/// (see [`OutputBuffer`]), which is done by [`wake_sessions`].
+pub(crate) fn mirror_output(bytes: &[u8]) {
+    for session in SESSIONS.lock().iter().flatten() {
+        // Translate NL to CR-NL, as the console UART does.
+        session.push(bytes, true);
+    }
+}
+
+/// Wakes up the readers of the sessions with the output mirrored since they
+/// were last woken up.
+///
+/// Called on each timer tick, and after passing the input of a session to the
+/// console (for the echo).
+pub(crate) fn wake_sessions() {
+    // Do not notify with the lock held, which is also taken by the output
+    // hook, maybe with the run queue locked.
+    let sessions = SESSIONS.lock().clone();
+    for session in sessions.iter().flatten() {
+        session.wake_readers();
+    }
+}
+
+/// A remote session attached to the system console.
+///
+/// It is detached when dropped.
+pub struct ConsoleSession {
+    slot: usize,
+    output: Arc<OutputBuffer>,
+}
+
+impl ConsoleSession {
+    /// Attaches a new session to the console.
+    ///
+    /// Returns [`ResourceBusy`](AxError::ResourceBusy) if there are already
+    /// [`MAX_SESSIONS`] sessions.
+    pub fn attach() -> AxResult<Self> {
+        let output = Arc::new(OutputBuffer::new(SESSION_BUF_SIZE));
+        let mut sessions = SESSIONS.lock();
+        let Some(slot) = sessions.iter().position(Option::is_none) else {
+            drop(sessions);
+            return ax_err!(ResourceBusy, "too many console sessions");
+        };
+        sessions[slot] = Some(output.clone());
+        drop(sessions);
+        info!("console session {} attached", slot);
+        Ok(Self { slot, output })
+    }
+
+    /// Reads the console output into `buf`.
+    ///
+    /// It blocks until some output is available, unless `nonblocking` is set
+    /// (returns [`WouldBlock`](AxError::WouldBlock) then). 0 is returned after
+    /// the session is closed and all the output is read.
+    pub fn read(&self, buf: &mut [u8], nonblocking: bool) -> AxResult<usize> {
+        self.output.read(buf, nonblocking)
+    }
+
+    /// Passes the bytes to the console as its input.
+    ///
+    /// Returns [`NotConnected`](AxError::NotConnected) after the session is
+    /// closed.
+    pub fn write(&self, buf: &[u8]) -> AxResult<usize> {
+        if self.output.is_closed() {
+            return Err(AxError::NotConnected);
+        }
+        crate::console().receive(buf);
+        wake_sessions();
+        Ok(buf.len())
+    }
+
+    /// Closes the session, e.g., when the remote side is disconnected. The
+    /// readers get an end-of-file after reading the remaining output.
+    ///
+    /// The session is still attached until it is dropped.
+    pub fn close(&self) {
+        self.output.close(false);
+    }
+}
+
+impl Drop for ConsoleSession {
+    fn drop(&mut self) {
+        // Do not drop the `Arc` with the lock held, which may deallocate.
+        let output = SESSIONS.lock()[self.slot].take();
+        drop(output);
+        info!("console session {} detached", self.slot);
+    }
+}
@@ -0,0 +1,198 @@
+//! The output buffers of pseudo-terminals and console sessions.
+//!
+//! They are written by the terminal drivers and the console output hook,
+//! which may be called in any context (e.g., in interrupt handlers, or while
+//! logging with the run queue locked). So writing neither allocates memory
+//! nor wakes up the readers: it stores what fits in the buffer, and marks a
+//! wakeup as pending, which is done later by [`OutputBuffer::wake_readers`]
+//! in a context that can notify a wait queue.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use axerrno::{AxError, AxResult};
+use kspin::SpinNoIrq;
+
+struct RingBuffer {
+    data: Box<[u8]>,
+    head: usize,
+    len: usize,
+}
+
+impl RingBuffer {
+    fn free(&self) -> usize {
+        self.data.len() - self.len
+    }
+
+    fn push(&mut self, c: u8) {
+        let idx = (self.head + self.len) % self.data.len();
+        self.data[idx] = c;
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let c = self.data[self.head];
+        self.head = (self.head + 1) % self.data.len();
+        self.len -= 1;
+        Some(c)
+    }
+}
+
+/// A fixed-size buffer of output bytes.
+pub(crate) struct OutputBuffer {
+    ring: SpinNoIrq<RingBuffer>,
+    closed: AtomicBool,
+    /// Whether there is output pushed since the readers were last woken up.
+    wake_pending: AtomicBool,
+    #[cfg(all(feature = "multitask", feature = "irq"))]
+    read_wq: axtask::WaitQueue,
+    #[cfg(all(feature = "multitask", feature = "irq"))]
+    write_wq: axtask::WaitQueue,
+}
+
+impl OutputBuffer {
+    /// Creates an empty buffer of `capacity` bytes.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            ring: SpinNoIrq::new(RingBuffer {
+                // Allocated on the heap directly, not on the stack first.
+                data: vec![0; capacity].into_boxed_slice(),
+                head: 0,
+                len: 0,
+            }),
+            closed: AtomicBool::new(false),
+            wake_pending: AtomicBool::new(false),
+            #[cfg(all(feature = "multitask", feature = "irq"))]
+            read_wq: axtask::WaitQueue::new(),
+            #[cfg(all(feature = "multitask", feature = "irq"))]
+            write_wq: axtask::WaitQueue::new(),
+        }
+    }
+
+    /// Appends the bytes, with NL translated to CR-NL if `onlcr` is set.
+    ///
+    /// It can be called in any context. Returns the number of bytes of
+    /// `bytes` appended, which stops at the first one that does not fit, or
+    /// 0 after the buffer is closed. The readers are not woken up, see
+    /// [`OutputBuffer::wake_readers`].
+    pub fn push(&self, bytes: &[u8], onlcr: bool) -> usize {
+        if self.is_closed() {
+            return 0;
+        }
+        let mut ring = self.ring.lock();
+        let mut n = 0;
+        for &c in bytes {
+            let crlf = onlcr && c == b'\n';
+            if ring.free() < 1 + crlf as usize {
+                break;
+            }
+            if crlf {
+                ring.push(b'\r');
+            }
+            ring.push(c);
+            n += 1;
+        }
+        if n > 0 {
+            self.wake_pending.store(true, Ordering::Release);
+        }
+        n
+    }
+
+    /// Wakes up the blocked readers if there is output pushed since they were
+    /// last woken up.
+    ///
+    /// It must be called in a context that can notify a wait queue, i.e., in
+    /// a task or an interrupt handler, but not with the run queue locked.
+    pub fn wake_readers(&self) {
+        if self.wake_pending.swap(false, Ordering::AcqRel) {
+            #[cfg(all(feature = "multitask", feature = "irq"))]
+            self.read_wq.notify_all(false);
+        }
+    }
+
+    /// Whether there is output to read without blocking, or the buffer is
+    /// closed.
+    pub fn readable(&self) -> bool {
+        self.ring.lock().len > 0 || self.is_closed()
+    }
+
+    /// Whether some bytes can be pushed, or the buffer is closed.
+    fn writable(&self) -> bool {
+        self.ring.lock().free() > 0 || self.is_closed()
+    }
+
+    /// Reads the output into `buf`.
+    ///
+    /// It blocks until some output is available, unless `nonblocking` is set
+    /// (returns [`WouldBlock`](AxError::WouldBlock) then). 0 is returned
+    /// after the buffer is closed and all the output is read.
+    pub fn read(&self, buf: &mut [u8], nonblocking: bool) -> AxResult<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            {
+                let mut ring = self.ring.lock();
+                let mut n = 0;
+                while n < buf.len() {
+                    match ring.pop() {
+                        Some(c) => buf[n] = c,
+                        None => break,
+                    }
+                    n += 1;
+                }
+                if n > 0 || self.is_closed() {
+                    drop(ring);
+                    #[cfg(all(feature = "multitask", feature = "irq"))]
+                    self.write_wq.notify_all(false);
+                    return Ok(n);
+                }
+            }
+            if nonblocking {
+                return Err(AxError::WouldBlock);
+            }
+            // The writers only mark the wakeup as pending, which is done on
+            // the next timer tick at the latest (see `crate::on_timer_tick`).
+            #[cfg(all(feature = "multitask", feature = "irq"))]
+            self.read_wq.wait_until(|| self.readable());
+            #[cfg(not(all(feature = "multitask", feature = "irq")))]
+            axtask::yield_now();
+        }
+    }
+
+    /// Blocks until some bytes can be pushed, or the buffer is closed.
+    pub fn wait_writable(&self) {
+        #[cfg(all(feature = "multitask", feature = "irq"))]
+        self.write_wq.wait_until(|| self.writable());
+        #[cfg(not(all(feature = "multitask", feature = "irq")))]
+        while !self.writable() {
+            axtask::yield_now();
+        }
+    }
+
+    /// Closes the buffer. The readers get an end-of-file after reading the
+    /// remaining output, or immediately if `discard` is set.
+    ///
+    /// It must be called in a context that can notify a wait queue, as it
+    /// wakes up the blocked readers and writers.
+    pub fn close(&self, discard: bool) {
+        self.closed.store(true, Ordering::Release);
+        if discard {
+            self.ring.lock().len = 0;
+        }
+        #[cfg(all(feature = "multitask", feature = "irq"))]
+        {
+            self.read_wq.notify_all(false);
+            self.write_wq.notify_all(false);
+        }
+    }
+
+    /// Whether the buffer is closed.
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+}
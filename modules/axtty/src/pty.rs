@@ -0,0 +1,135 @@
+//! Pseudo-terminals.
+//!
+//! A pseudo-terminal is a pair of a master ([`PtyMaster`]) and a slave
+//! terminal. The bytes written to the master are the input of the slave, and
+//! the output of the slave (including the echo) is read from the master.
+//!
+//! The slaves are numbered, and can be found by [`pty_slave`] while their
+//! masters are alive. Dropping the master hangs up the slave.
+
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+
+use axerrno::{ax_err, AxResult};
+use kspin::SpinNoIrq;
+
+use crate::output::OutputBuffer;
+use crate::tty::{Tty, TtyDriver};
+
+/// The maximum number of pseudo-terminals.
+pub const MAX_PTYS: usize = 64;
+
+/// The size of the output buffer of each slave. Writing to the slave blocks
+/// while it is full, and the echo is dropped then.
+const PTY_BUF_SIZE: usize = 16384;
+
+/// The slave terminal of a pseudo-terminal.
+pub type PtySlave = Tty<PtySlaveDriver>;
+
+static PTYS: SpinNoIrq<BTreeMap<usize, Weak<PtySlave>>> = SpinNoIrq::new(BTreeMap::new());
+
+/// The driver of the slave terminal, which passes its output to the master.
+pub struct PtySlaveDriver {
+    index: usize,
+    output: OutputBuffer,
+}
+
+impl PtySlaveDriver {
+    /// Returns the number of the pseudo-terminal.
+    pub const fn index(&self) -> usize {
+        self.index
+    }
+}
+
+impl TtyDriver for PtySlaveDriver {
+    fn write(&self, buf: &[u8]) -> usize {
+        let n = self.output.push(buf, false);
+        // Both the writes and the echo of the slave come from the tasks (the
+        // echo is of the bytes written to the master), so the master can be
+        // woken up here.
+        self.output.wake_readers();
+        n
+    }
+
+    fn wait_writable(&self) {
+        self.output.wait_writable();
+    }
+
+    fn output_processing(&self) -> bool {
+        true
+    }
+}
+
+/// The master side of a pseudo-terminal.
+pub struct PtyMaster {
+    slave: Arc<PtySlave>,
+}
+
+impl PtyMaster {
+    /// Allocates a new pseudo-terminal with the lowest free number, and
+    /// returns its master.
+    pub fn open() -> AxResult<Self> {
+        let mut ptys = PTYS.lock();
+        let Some(index) = (0..MAX_PTYS).find(|i| !ptys.contains_key(i)) else {
+            return ax_err!(StorageFull, "too many pseudo-terminals");
+        };
+        let slave = Arc::new(Tty::new(PtySlaveDriver {
+            index,
+            output: OutputBuffer::new(PTY_BUF_SIZE),
+        }));
+        ptys.insert(index, Arc::downgrade(&slave));
+        Ok(Self { slave })
+    }
+
+    /// Returns the number of the pseudo-terminal.
+    pub fn index(&self) -> usize {
+        self.slave.driver().index
+    }
+
+    /// Returns the slave terminal.
+    pub fn slave(&self) -> &Arc<PtySlave> {
+        &self.slave
+    }
+
+    /// Reads the output of the slave into `buf`.
+    ///
+    /// It blocks until some output is available, unless `nonblocking` is set
+    /// (returns [`WouldBlock`](AxError::WouldBlock) then).
+    pub fn read(&self, buf: &mut [u8], nonblocking: bool) -> AxResult<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.slave.driver().output.read(buf, nonblocking)
+    }
+
+    /// Writes the bytes as the input of the slave.
+    pub fn write(&self, buf: &[u8]) -> AxResult<usize> {
+        self.slave.receive(buf);
+        Ok(buf.len())
+    }
+
+    /// Whether there is output of the slave to read without blocking.
+    pub fn readable(&self) -> bool {
+        self.slave.driver().output.readable()
+    }
+}
+
+impl Drop for PtyMaster {
+    fn drop(&mut self) {
+        PTYS.lock().remove(&self.index());
+        self.slave.driver().output.close(true);
+        self.slave.hangup();
+    }
+}
+
+/// Returns the slave terminal of the pseudo-terminal numbered `index`, or
+/// [`None`] if there is no such pseudo-terminal.
+pub fn pty_slave(index: usize) -> Option<Arc<PtySlave>> {
+    PTYS.lock().get(&index).and_then(Weak::upgrade)
+}
+
+/// Returns the numbers of all pseudo-terminals, in ascending order.
+pub fn pty_indices() -> Vec<usize> {
+    PTYS.lock().keys().copied().collect()
+}
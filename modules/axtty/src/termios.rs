@@ -0,0 +1,172 @@
+//! Terminal attributes, with the same flags and control characters as the
+//! `termios` structure of Linux.
+
+use bitflags::bitflags;
+
+bitflags! {
+    /// Input modes (`c_iflag`).
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct InputFlags: u32 {
+        const IGNBRK = 0o000001;
+        const BRKINT = 0o000002;
+        const IGNPAR = 0o000004;
+        const PARMRK = 0o000010;
+        const INPCK = 0o000020;
+        /// Strip the eighth bit.
+        const ISTRIP = 0o000040;
+        /// Translate NL to CR.
+        const INLCR = 0o000100;
+        /// Ignore CR.
+        const IGNCR = 0o000200;
+        /// Translate CR to NL.
+        const ICRNL = 0o000400;
+        const IUCLC = 0o001000;
+        const IXON = 0o002000;
+        const IXANY = 0o004000;
+        const IXOFF = 0o010000;
+        const IMAXBEL = 0o020000;
+        const IUTF8 = 0o040000;
+    }
+
+    /// Output modes (`c_oflag`).
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct OutputFlags: u32 {
+        const OPOST = 0o000001;
+        const OLCUC = 0o000002;
+        const ONLCR = 0o000004;
+        const OCRNL = 0o000010;
+        const ONOCR = 0o000020;
+        const ONLRET = 0o000040;
+    }
+
+    /// Local modes (`c_lflag`).
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct LocalFlags: u32 {
+        /// Generate signals on the interrupt characters.
+        const ISIG = 0o000001;
+        /// Canonical mode: the input is edited and read by lines.
+        const ICANON = 0o000002;
+        /// Echo the input characters.
+        const ECHO = 0o000010;
+        /// Erase the last character on the screen on `VERASE`.
+        const ECHOE = 0o000020;
+        const ECHOK = 0o000040;
+        /// Echo NL even if `ECHO` is not set.
+        const ECHONL = 0o000100;
+        /// Do not flush the input on the interrupt characters.
+        const NOFLSH = 0o000200;
+        const TOSTOP = 0o000400;
+        /// Echo control characters as `^X`.
+        const ECHOCTL = 0o001000;
+        const ECHOPRT = 0o002000;
+        /// Erase the line on the screen on `VKILL`.
+        const ECHOKE = 0o004000;
+        /// Enable the extended input processing (`VWERASE`).
+        const IEXTEN = 0o100000;
+    }
+}
+
+/// The number of control characters.
+pub const NCCS: usize = 32;
+
+/// The indices of the control characters (`c_cc`).
+pub const VINTR: usize = 0;
+pub const VQUIT: usize = 1;
+pub const VERASE: usize = 2;
+pub const VKILL: usize = 3;
+pub const VEOF: usize = 4;
+pub const VTIME: usize = 5;
+pub const VMIN: usize = 6;
+pub const VSTART: usize = 8;
+pub const VSTOP: usize = 9;
+pub const VSUSP: usize = 10;
+pub const VEOL: usize = 11;
+pub const VREPRINT: usize = 12;
+pub const VDISCARD: usize = 13;
+pub const VWERASE: usize = 14;
+pub const VLNEXT: usize = 15;
+pub const VEOL2: usize = 16;
+
+/// The control modes (`c_cflag`) of the terminals: 38400 baud, 8 data bits,
+/// the receiver enabled, and hang up on last close.
+pub const DEFAULT_CFLAG: u32 = 0o000017 | 0o000060 | 0o000200 | 0o002000;
+
+/// The attributes of a terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Termios {
+    pub iflag: InputFlags,
+    pub oflag: OutputFlags,
+    pub cflag: u32,
+    pub lflag: LocalFlags,
+    pub cc: [u8; NCCS],
+}
+
+impl Termios {
+    /// Creates the default attributes of Linux, in canonical mode with echo.
+    pub const fn new() -> Self {
+        let mut cc = [0; NCCS];
+        cc[VINTR] = 0x03; // ^C
+        cc[VQUIT] = 0x1c; // ^\
+        cc[VERASE] = 0x7f; // DEL
+        cc[VKILL] = 0x15; // ^U
+        cc[VEOF] = 0x04; // ^D
+        cc[VMIN] = 1;
+        cc[VSTART] = 0x11; // ^Q
+        cc[VSTOP] = 0x13; // ^S
+        cc[VSUSP] = 0x1a; // ^Z
+        cc[VREPRINT] = 0x12; // ^R
+        cc[VDISCARD] = 0x0f; // ^O
+        cc[VWERASE] = 0x17; // ^W
+        cc[VLNEXT] = 0x16; // ^V
+        Self {
+            iflag: InputFlags::ICRNL.union(InputFlags::IXON),
+            oflag: OutputFlags::OPOST.union(OutputFlags::ONLCR),
+            cflag: DEFAULT_CFLAG,
+            lflag: LocalFlags::ISIG
+                .union(LocalFlags::ICANON)
+                .union(LocalFlags::ECHO)
+                .union(LocalFlags::ECHOE)
+                .union(LocalFlags::ECHOK)
+                .union(LocalFlags::ECHOCTL)
+                .union(LocalFlags::ECHOKE)
+                .union(LocalFlags::IEXTEN),
+            cc,
+        }
+    }
+
+    /// Sets the attributes to raw mode, as `cfmakeraw` does: the input is
+    /// available byte by byte, without echo and any processing.
+    pub fn make_raw(&mut self) {
+        self.iflag.remove(
+            InputFlags::IGNBRK
+                | InputFlags::BRKINT
+                | InputFlags::PARMRK
+                | InputFlags::ISTRIP
+                | InputFlags::INLCR
+                | InputFlags::IGNCR
+                | InputFlags::ICRNL
+                | InputFlags::IXON,
+        );
+        self.oflag.remove(OutputFlags::OPOST);
+        self.lflag.remove(
+            LocalFlags::ECHO
+                | LocalFlags::ECHONL
+                | LocalFlags::ICANON
+                | LocalFlags::ISIG
+                | LocalFlags::IEXTEN,
+        );
+        self.cc[VMIN] = 1;
+        self.cc[VTIME] = 0;
+    }
+
+    /// Returns whether the terminal is in canonical mode.
+    pub const fn is_canonical(&self) -> bool {
+        self.lflag.contains(LocalFlags::ICANON)
+    }
+}
+
+impl Default for Termios {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,368 @@
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use axerrno::{AxError, AxResult};
+use kspin::SpinNoIrq;
+
+use crate::ldisc::LineDiscipline;
+use axhal::time::{monotonic_time, TimeValue};
+
+use crate::termios::{OutputFlags, Termios, VMIN, VTIME};
+
+static INTERRUPT_HOOK: AtomicUsize = AtomicUsize::new(0);
+
+/// Sets the function called when the interrupt character (`VINTR`, `^C` by
+/// default) is received by a terminal with `ISIG` set, e.g., to kill the
+/// foreground processes.
+///
+/// It is called with the foreground process group of the terminal (see
+/// [`Tty::set_foreground`]), which is 0 if there is none. The hook may be
+/// called in the interrupt context, so it must not block. The blocked readers
+/// of the terminal return after it is called.
+pub fn set_interrupt_hook(hook: fn(u64)) {
+    INTERRUPT_HOOK.store(hook as usize, Ordering::Release);
+}
+
+/// Calls the interrupt hook, and returns `false` if it is not set.
+fn call_interrupt_hook(pgid: u64) -> bool {
+    let hook = INTERRUPT_HOOK.load(Ordering::Acquire);
+    if hook == 0 {
+        return false;
+    }
+    let hook: fn(u64) = unsafe { core::mem::transmute(hook) };
+    hook(pgid);
+    true
+}
+
+/// The low-level driver of a terminal, which transmits its output and
+/// receives its input.
+pub trait TtyDriver: Send + Sync {
+    /// Transmits the bytes written to the terminal, and the echoed input.
+    /// Returns the number of bytes taken, which is less than `buf.len()` if
+    /// the driver cannot take more for now.
+    ///
+    /// It may be called in the interrupt context, so it must not block.
+    fn write(&self, buf: &[u8]) -> usize;
+
+    /// Blocks until the driver can take some bytes again, after
+    /// [`TtyDriver::write`] took less than given.
+    fn wait_writable(&self) {}
+
+    /// Polls a received byte, or returns [`None`] if there is none.
+    ///
+    /// Only used if the driver does not pass the input to [`Tty::receive`]
+    /// itself.
+    fn poll_input(&self) -> Option<u8> {
+        None
+    }
+
+    /// Whether the input is passed to [`Tty::receive`] (e.g., by an interrupt
+    /// handler) as it arrives, so the readers can sleep until then. Otherwise,
+    /// the readers poll the input by [`TtyDriver::poll_input`].
+    fn has_input_irq(&self) -> bool {
+        true
+    }
+
+    /// Whether the terminal processes the output according to `c_oflag`
+    /// (e.g., translates NL to CR-NL) before passing it to the driver.
+    ///
+    /// Drivers which do the translation themselves, such as the console UART,
+    /// return `false`.
+    fn output_processing(&self) -> bool {
+        false
+    }
+}
+
+/// Writes `buf` to the driver, with the output processing of `oflag`.
+///
+/// Returns the number of bytes of `buf` taken by the driver.
+fn write_output<D: TtyDriver + ?Sized>(driver: &D, oflag: OutputFlags, buf: &[u8]) -> usize {
+    if !driver.output_processing() || !oflag.contains(OutputFlags::OPOST | OutputFlags::ONLCR) {
+        return driver.write(buf);
+    }
+    let mut written = 0;
+    for (i, line) in buf.split(|&c| c == b'\n').enumerate() {
+        if i > 0 {
+            // CR-NL is taken as a whole, or not at all.
+            if driver.write(b"\r\n") < 2 {
+                return written;
+            }
+            written += 1;
+        }
+        if !line.is_empty() {
+            let n = driver.write(line);
+            written += n;
+            if n < line.len() {
+                return written;
+            }
+        }
+    }
+    written
+}
+
+/// A terminal device, with a driver and a line discipline.
+///
+/// The driver can be a trait object, e.g., `Arc<Tty<dyn TtyDriver>>`.
+pub struct Tty<D: ?Sized> {
+    ldisc: SpinNoIrq<LineDiscipline>,
+    #[cfg(feature = "multitask")]
+    read_wq: axtask::WaitQueue,
+    hung_up: AtomicBool,
+    /// The number of the interrupt characters handled by the hook, so that
+    /// the blocked readers can return.
+    interrupts: AtomicUsize,
+    /// The foreground process group, or 0.
+    foreground: AtomicU64,
+    driver: D,
+}
+
+impl<D> Tty<D> {
+    /// Creates a terminal with the driver, in canonical mode with echo.
+    pub const fn new(driver: D) -> Self {
+        Self::with_termios(driver, Termios::new())
+    }
+
+    /// Creates a terminal with the driver and the initial attributes.
+    pub const fn with_termios(driver: D, termios: Termios) -> Self {
+        Self {
+            ldisc: SpinNoIrq::new(LineDiscipline::new(termios)),
+            #[cfg(feature = "multitask")]
+            read_wq: axtask::WaitQueue::new(),
+            hung_up: AtomicBool::new(false),
+            interrupts: AtomicUsize::new(0),
+            foreground: AtomicU64::new(0),
+            driver,
+        }
+    }
+}
+
+impl<D: TtyDriver + ?Sized> Tty<D> {
+    /// Returns the driver of the terminal.
+    pub const fn driver(&self) -> &D {
+        &self.driver
+    }
+
+    /// Passes the received bytes to the line discipline, and wakes up the
+    /// readers. It can be called in the interrupt context.
+    pub fn receive(&self, buf: &[u8]) {
+        let mut ldisc = self.ldisc.lock();
+        let mut interrupted = false;
+        for &c in buf {
+            interrupted |= self.receive_locked(&mut ldisc, c);
+        }
+        drop(ldisc);
+        self.input_received(interrupted);
+    }
+
+    /// Polls all the input of the driver, and passes it to the line
+    /// discipline.
+    ///
+    /// The lock of the line discipline is held while polling, so the input
+    /// is not reordered by concurrent pollers (e.g., the interrupt handler
+    /// and a reader on another CPU).
+    pub fn poll_input(&self) {
+        let mut ldisc = self.ldisc.lock();
+        let mut received = false;
+        let mut interrupted = false;
+        while let Some(c) = self.driver.poll_input() {
+            interrupted |= self.receive_locked(&mut ldisc, c);
+            received = true;
+        }
+        drop(ldisc);
+        if received {
+            self.input_received(interrupted);
+        }
+    }
+
+    fn receive_locked(&self, ldisc: &mut LineDiscipline, c: u8) -> bool {
+        let oflag = ldisc.termios().oflag;
+        ldisc.receive(c, &mut |echo| write_output(&self.driver, oflag, echo))
+    }
+
+    fn input_received(&self, interrupted: bool) {
+        if interrupted && call_interrupt_hook(self.foreground()) {
+            self.interrupts.fetch_add(1, Ordering::AcqRel);
+        }
+        #[cfg(feature = "multitask")]
+        self.read_wq.notify_all(false);
+    }
+
+    /// Reads the input of the terminal into `buf`.
+    ///
+    /// It blocks until some input is available, unless `nonblocking` is set
+    /// (returns [`WouldBlock`](AxError::WouldBlock) then). In canonical mode,
+    /// at most one line is read. In non-canonical mode, it waits for the
+    /// input according to `VMIN` and `VTIME`, as on Linux.
+    ///
+    /// 0 is returned at an end-of-file, after the terminal is hung up, or if
+    /// the interrupt hook is called while waiting (so the interrupted task can
+    /// handle it, e.g., exit when returning to the user space).
+    pub fn read(&self, buf: &mut [u8], nonblocking: bool) -> AxResult<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let interrupts = self.interrupts.load(Ordering::Acquire);
+        let mut read_len = 0;
+        let mut deadline = None;
+        loop {
+            self.poll_input();
+            let mut ldisc = self.ldisc.lock();
+            let termios = *ldisc.termios();
+            if termios.is_canonical() {
+                if ldisc.readable() {
+                    return Ok(ldisc.read(buf));
+                }
+            } else {
+                let n = ldisc.read(&mut buf[read_len..]);
+                read_len += n;
+                let min = (termios.cc[VMIN] as usize).min(buf.len());
+                let time = termios.cc[VTIME] as u64;
+                if read_len >= min && (read_len > 0 || time == 0) {
+                    return Ok(read_len);
+                }
+                // `VTIME` is the timeout of the read if `VMIN` is 0, and the
+                // timeout between the bytes (after the first one) otherwise.
+                let restart_timer = if min == 0 { deadline.is_none() } else { n > 0 };
+                if time > 0 && restart_timer {
+                    deadline = Some(monotonic_time() + TimeValue::from_millis(time * 100));
+                }
+            }
+            drop(ldisc);
+            let timed_out = deadline.is_some_and(|deadline| monotonic_time() >= deadline);
+            if timed_out
+                || self.is_hung_up()
+                || self.interrupts.load(Ordering::Acquire) != interrupts
+            {
+                return Ok(read_len);
+            }
+            if nonblocking {
+                return if read_len > 0 {
+                    Ok(read_len)
+                } else {
+                    Err(AxError::WouldBlock)
+                };
+            }
+            self.wait_input(deadline, interrupts);
+        }
+    }
+
+    fn wait_input(&self, deadline: Option<TimeValue>, interrupts: usize) {
+        #[cfg(feature = "multitask")]
+        if self.driver.has_input_irq() {
+            let condition = || {
+                self.readable()
+                    || self.is_hung_up()
+                    || self.interrupts.load(Ordering::Acquire) != interrupts
+            };
+            match deadline {
+                None => {
+                    self.read_wq.wait_until(condition);
+                    return;
+                }
+                #[cfg(feature = "irq")]
+                Some(deadline) => {
+                    let now = monotonic_time();
+                    if deadline > now {
+                        self.read_wq.wait_timeout_until(deadline - now, condition);
+                    }
+                    return;
+                }
+                // Without timers, poll until the deadline.
+                #[cfg(not(feature = "irq"))]
+                Some(_) => {}
+            }
+        }
+        let _ = (deadline, interrupts);
+        axtask::yield_now();
+    }
+
+    /// Writes the bytes to the terminal.
+    ///
+    /// It blocks until the driver takes some bytes, and returns the number of
+    /// them, which may be less than `buf.len()`. Returns
+    /// [`NotConnected`](AxError::NotConnected) after the terminal is hung up.
+    pub fn write(&self, buf: &[u8]) -> AxResult<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if self.is_hung_up() {
+                return Err(AxError::NotConnected);
+            }
+            let oflag = self.ldisc.lock().termios().oflag;
+            let n = write_output(&self.driver, oflag, buf);
+            if n > 0 {
+                return Ok(n);
+            }
+            self.driver.wait_writable();
+        }
+    }
+
+    /// Whether there is input to read without blocking.
+    pub fn readable(&self) -> bool {
+        self.ldisc.lock().readable()
+    }
+
+    /// Returns the attributes of the terminal.
+    pub fn termios(&self) -> Termios {
+        *self.ldisc.lock().termios()
+    }
+
+    /// Sets the attributes of the terminal.
+    pub fn set_termios(&self, termios: &Termios) {
+        self.ldisc.lock().set_termios(termios);
+        #[cfg(feature = "multitask")]
+        self.read_wq.notify_all(false);
+    }
+
+    /// Returns the foreground process group of the terminal, or 0 if there is
+    /// none.
+    pub fn foreground(&self) -> u64 {
+        self.foreground.load(Ordering::Acquire)
+    }
+
+    /// Sets the foreground process group of the terminal (as `tcsetpgrp`),
+    /// which the interrupt character is for (see [`set_interrupt_hook`]).
+    pub fn set_foreground(&self, pgid: u64) {
+        self.foreground.store(pgid, Ordering::Release);
+    }
+
+    /// Discards the input not read yet.
+    pub fn flush_input(&self) {
+        self.ldisc.lock().flush_input();
+    }
+
+    /// Hangs up the terminal, e.g., when the other side of a pseudo-terminal
+    /// is closed. The readers get an end-of-file, and the writers get an
+    /// error.
+    pub fn hangup(&self) {
+        self.hung_up.store(true, Ordering::Release);
+        #[cfg(feature = "multitask")]
+        self.read_wq.notify_all(false);
+    }
+
+    /// Whether the terminal has been hung up.
+    pub fn is_hung_up(&self) -> bool {
+        self.hung_up.load(Ordering::Acquire)
+    }
+}
+
+/// A reference to a terminal, either static (e.g., the console) or shared
+/// (e.g., the slave of a pseudo-terminal).
+#[cfg(feature = "alloc")]
+#[derive(Clone)]
+pub enum TtyRef {
+    Static(&'static Tty<dyn TtyDriver>),
+    Shared(alloc::sync::Arc<Tty<dyn TtyDriver>>),
+}
+
+#[cfg(feature = "alloc")]
+impl core::ops::Deref for TtyRef {
+    type Target = Tty<dyn TtyDriver>;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Self::Static(tty) => tty,
+            Self::Shared(tty) => tty,
+        }
+    }
+}
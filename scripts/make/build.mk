@@ -9,6 +9,15 @@ else
   rust_elf := $(TARGET_DIR)/$(TARGET)/$(MODE)/$(rust_package)
 endif
 
+ifneq ($(filter backtrace,$(FEATURES)),)
+  # Symbol table for backtraces (Rust apps only), see `modules/axhal/build.rs`
+  OUT_KSYMS := $(abspath $(OUT_DIR)/$(APP_NAME)_$(PLATFORM_NAME).ksyms)
+endif
+
+define gen_ksyms
+  $(call run_cmd,$(NM),-n -C --defined-only $(1) | grep -i " [tw] " > $(2))
+endef
+
 ifneq ($(filter $(MAKECMDGOALS),doc doc_check_missing),)  # run `cargo doc`
   $(if $(V), $(info RUSTDOCFLAGS: "$(RUSTDOCFLAGS)"))
   export RUSTDOCFLAGS
@@ -33,6 +42,11 @@ _cargo_build:
 	@printf "    $(GREEN_C)Building$(END_C) App: $(APP_NAME), Arch: $(ARCH), Platform: $(PLATFORM_NAME), App type: $(APP_TYPE)\n"
 ifeq ($(APP_TYPE), rust)
 	$(call cargo_build,$(APP),$(AX_FEAT) $(LIB_FEAT) $(APP_FEAT))
+  ifneq ($(OUT_KSYMS),)
+	@# link again with the symbol table of the first image embedded
+	$(call gen_ksyms,$(rust_elf),$(OUT_KSYMS))
+	$(call cargo_build,$(APP),$(AX_FEAT) $(LIB_FEAT) $(APP_FEAT),AX_KSYMS=$(OUT_KSYMS))
+  endif
 	@cp $(rust_elf) $(OUT_ELF)
 else ifeq ($(APP_TYPE), c)
 	$(call cargo_build,ulib/axlibc,$(AX_FEAT) $(LIB_FEAT))
@@ -44,4 +58,10 @@ $(OUT_DIR):
 $(OUT_BIN): _cargo_build $(OUT_ELF)
 	$(call run_cmd,$(OBJCOPY),$(OUT_ELF) --strip-all -O binary $@)
 
+# The Linux/x86 bzImage: the setup code and header, followed by the binary
+$(OUT_BZIMAGE): $(OUT_BIN)
+	$(call run_cmd,$(OBJCOPY),$(OUT_ELF) --dump-section .linux_setup=$@.setup $@.tmp)
+	@cat $@.setup $(OUT_BIN) > $@
+	@rm -f $@.setup $@.tmp
+
 .PHONY: _cargo_build
@@ -18,6 +18,11 @@ build_args := \
   $(verbose)
 
 RUSTFLAGS := -C link-arg=-T$(LD_SCRIPT) -C link-arg=-no-pie -C link-arg=-znostart-stop-gc
+
+ifneq ($(filter backtrace,$(FEATURES)),)
+  RUSTFLAGS += -C force-frame-pointers=yes
+endif
+
 RUSTDOCFLAGS := -Z unstable-options --enable-index-page -D rustdoc::broken_intra_doc_links
 
 ifeq ($(MAKECMDGOALS), doc_check_missing)
@@ -25,7 +30,7 @@ ifeq ($(MAKECMDGOALS), doc_check_missing)
 endif
 
 define cargo_build
-  $(call run_cmd,cargo -C $(1) build,$(build_args) --features "$(strip $(2))")
+  $(call run_cmd,$(strip $(3) cargo) -C $(1) build,$(build_args) --features "$(strip $(2))")
 endef
 
 clippy_args := -A clippy::new_without_default
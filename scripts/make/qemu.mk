@@ -10,18 +10,36 @@ else
   $(error "BUS" must be one of "mmio" or "pci")
 endif
 
+ifeq ($(X86_BOOT), linux)
+  x86_kernel := $(OUT_BZIMAGE)
+else
+  x86_kernel := $(OUT_ELF)
+endif
+
+ifneq ($(GIC_VERSION),)
+  aarch64_machine := virt,gic-version=$(GIC_VERSION)
+else
+  aarch64_machine := virt
+endif
+
+ifneq ($(filter aplic aplic-imsic,$(IRQ_CONTROLLER)),)
+  riscv64_machine := virt,aia=$(IRQ_CONTROLLER)
+else
+  riscv64_machine := virt
+endif
+
 qemu_args-x86_64 := \
   -machine q35 \
-  -kernel $(OUT_ELF)
+  -kernel $(x86_kernel)
 
 qemu_args-riscv64 := \
-  -machine virt \
+  -machine $(riscv64_machine) \
   -bios default \
   -kernel $(OUT_BIN)
 
 qemu_args-aarch64 := \
   -cpu cortex-a72 \
-  -machine virt \
+  -machine $(aarch64_machine) \
   -kernel $(OUT_BIN)
 
 qemu_args-y := -m 128M -smp $(SMP) $(qemu_args-$(ARCH))
@@ -62,6 +80,11 @@ ifeq ($(GRAPHIC), n)
   qemu_args-y += -nographic
 endif
 
+# The GDB stub talks on the serial port, connect it to a TCP socket
+ifneq ($(filter gdbstub,$(FEATURES)),)
+  qemu_args-y += -serial tcp::$(GDBSTUB_PORT),server=on,wait=off
+endif
+
 ifeq ($(QEMU_LOG), y)
   qemu_args-y += -D qemu.log -d in_asm,int,mmu,pcall,cpu_reset,guest_errors
 endif
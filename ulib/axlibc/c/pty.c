@@ -0,0 +1,29 @@
+#if defined(AX_CONFIG_FD) && defined(AX_CONFIG_TTY)
+
+#include <errno.h>
+#include <stdlib.h>
+
+// The slaves of pseudo-terminals are always accessible and unlocked.
+
+int grantpt(int fd)
+{
+    return 0;
+}
+
+int unlockpt(int fd)
+{
+    return 0;
+}
+
+char *ptsname(int fd)
+{
+    static char buf[32];
+    int err = ptsname_r(fd, buf, sizeof(buf));
+    if (err) {
+        errno = err;
+        return NULL;
+    }
+    return buf;
+}
+
+#endif // AX_CONFIG_FD && AX_CONFIG_TTY
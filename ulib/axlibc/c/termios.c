@@ -0,0 +1,44 @@
+#include <errno.h>
+#include <termios.h>
+
+speed_t cfgetospeed(const struct termios *tio)
+{
+    return tio->c_cflag & CBAUD;
+}
+
+speed_t cfgetispeed(const struct termios *tio)
+{
+    return cfgetospeed(tio);
+}
+
+int cfsetospeed(struct termios *tio, speed_t speed)
+{
+    if (speed & ~CBAUD) {
+        errno = EINVAL;
+        return -1;
+    }
+    tio->c_cflag &= ~CBAUD;
+    tio->c_cflag |= speed;
+    return 0;
+}
+
+int cfsetispeed(struct termios *tio, speed_t speed)
+{
+    return speed ? cfsetospeed(tio, speed) : 0;
+}
+
+int cfsetspeed(struct termios *tio, speed_t speed)
+{
+    return cfsetospeed(tio, speed);
+}
+
+void cfmakeraw(struct termios *t)
+{
+    t->c_iflag &= ~(IGNBRK | BRKINT | PARMRK | ISTRIP | INLCR | IGNCR | ICRNL | IXON);
+    t->c_oflag &= ~OPOST;
+    t->c_lflag &= ~(ECHO | ECHONL | ICANON | ISIG | IEXTEN);
+    t->c_cflag &= ~(CSIZE | PARENB);
+    t->c_cflag |= CS8;
+    t->c_cc[VMIN] = 1;
+    t->c_cc[VTIME] = 0;
+}
@@ -3,6 +3,7 @@
 #include <stdio.h>
 #include <stdlib.h>
 #include <sys/types.h>
+#include <termios.h>
 #include <time.h>
 #include <unistd.h>
 
@@ -27,11 +28,15 @@ pid_t setsid(void)
     return 0;
 }
 
-// TODO
 int isatty(int fd)
 {
-    unimplemented();
-    return 0;
+#ifdef AX_CONFIG_TTY
+    struct termios t;
+    return tcgetattr(fd, &t) == 0;
+#else
+    // The standard streams are on the console.
+    return fd >= 0 && fd <= 2;
+#endif
 }
 
 unsigned int sleep(unsigned int seconds)
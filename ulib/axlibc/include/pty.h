@@ -0,0 +1,8 @@
+#ifndef _PTY_H
+#define _PTY_H
+
+#include <termios.h>
+
+int openpty(int *, int *, char *, const struct termios *, const struct winsize *);
+
+#endif // _PTY_H
@@ -15,6 +15,7 @@
 //! - Upperlayer stacks
 //!     - `fs`: Enable file system support.
 //!     - `net`: Enable networking support.
+//!     - `tty`: Enable the terminals (`termios` and pseudo-terminals).
 //! - Lib C functions
 //!     - `fd`: Enable file descriptor table.
 //!     - `pipe`: Enable pipe support.
@@ -63,6 +64,8 @@ mod net;
 mod pipe;
 #[cfg(feature = "multitask")]
 mod pthread;
+#[cfg(all(feature = "fd", feature = "tty"))]
+mod pty;
 #[cfg(feature = "alloc")]
 mod strftime;
 #[cfg(feature = "fp_simd")]
@@ -75,6 +78,8 @@ mod rand;
 mod resource;
 mod setjmp;
 mod sys;
+#[cfg(feature = "tty")]
+mod termios;
 mod time;
 mod unistd;
 
@@ -87,7 +92,7 @@ pub use self::mktime::mktime;
 pub use self::rand::{rand, random, srand};
 pub use self::resource::{getrlimit, setrlimit};
 pub use self::setjmp::{longjmp, setjmp};
-pub use self::sys::sysconf;
+pub use self::sys::{reboot, sysconf};
 pub use self::time::{clock_gettime, nanosleep};
 pub use self::unistd::{abort, exit, getpid};
 
@@ -116,6 +121,11 @@ pub use self::pthread::{pthread_mutex_init, pthread_mutex_lock, pthread_mutex_un
 #[cfg(feature = "pipe")]
 pub use self::pipe::pipe;
 
+#[cfg(all(feature = "fd", feature = "tty"))]
+pub use self::pty::{openpty, posix_openpt, ptsname_r};
+#[cfg(feature = "tty")]
+pub use self::termios::{tcflush, tcgetattr, tcsetattr};
+
 #[cfg(feature = "select")]
 pub use self::io_mpx::select;
 #[cfg(feature = "epoll")]
@@ -0,0 +1,56 @@
+use core::ffi::{c_char, c_int};
+
+use arceos_posix_api::{sys_close, sys_openpty, sys_posix_openpt, sys_ptsname_r, sys_tcsetattr};
+
+use crate::{ctypes, utils::e};
+
+/// Open the master of a new pseudo-terminal.
+///
+/// Return the file descriptor of the master.
+#[no_mangle]
+pub unsafe extern "C" fn posix_openpt(flags: c_int) -> c_int {
+    e(sys_posix_openpt(flags))
+}
+
+/// Write the path of the slave of the pseudo-terminal, whose master is `fd`,
+/// to `buf`.
+///
+/// Return 0 if succeed, or the error number otherwise.
+#[no_mangle]
+pub unsafe extern "C" fn ptsname_r(fd: c_int, buf: *mut c_char, buflen: usize) -> c_int {
+    -sys_ptsname_r(fd, buf, buflen)
+}
+
+/// Open a new pseudo-terminal, and apply the attributes `termp` to it if it
+/// is not null. The window size `winp` is ignored.
+///
+/// Return 0 if succeed
+#[no_mangle]
+pub unsafe extern "C" fn openpty(
+    master: *mut c_int,
+    slave: *mut c_int,
+    name: *mut c_char,
+    termp: *const ctypes::termios,
+    _winp: *const ctypes::winsize,
+) -> c_int {
+    let mut fds = [0; 2];
+    if e(sys_openpty(&mut fds)) < 0 {
+        return -1;
+    }
+    // The size of `name` is not given, assume it is large enough as glibc.
+    let mut ret = 0;
+    if !name.is_null() {
+        ret = e(sys_ptsname_r(fds[0], name, 32));
+    }
+    if ret == 0 && !termp.is_null() {
+        ret = e(sys_tcsetattr(fds[1], ctypes::TCSANOW as _, termp));
+    }
+    if ret < 0 {
+        sys_close(fds[1]);
+        sys_close(fds[0]);
+        return -1;
+    }
+    *master = fds[0];
+    *slave = fds[1];
+    0
+}
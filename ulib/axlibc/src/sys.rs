@@ -1,4 +1,4 @@
-use arceos_posix_api::sys_sysconf;
+use arceos_posix_api::{sys_reboot, sys_sysconf};
 use core::ffi::{c_int, c_long};
 
 /// Return system configuration infomation
@@ -8,3 +8,19 @@ use core::ffi::{c_int, c_long};
 pub unsafe extern "C" fn sysconf(name: c_int) -> c_long {
     sys_sysconf(name)
 }
+
+/// Reboot or power off the system.
+///
+/// `howto` is one of the `RB_*` commands, e.g., `RB_AUTOBOOT` to reboot and
+/// `RB_POWER_OFF` to power off.
+#[no_mangle]
+pub unsafe extern "C" fn reboot(howto: c_int) -> c_int {
+    const LINUX_REBOOT_MAGIC1: c_int = 0xfee1_dead_u32 as c_int;
+    const LINUX_REBOOT_MAGIC2: c_int = 672274793;
+    sys_reboot(
+        LINUX_REBOOT_MAGIC1,
+        LINUX_REBOOT_MAGIC2,
+        howto,
+        core::ptr::null_mut(),
+    )
+}
@@ -0,0 +1,28 @@
+use core::ffi::c_int;
+
+use arceos_posix_api::{sys_tcflush, sys_tcgetattr, sys_tcsetattr};
+
+use crate::{ctypes, utils::e};
+
+/// Get the attributes of the terminal referred to by `fd`.
+#[no_mangle]
+pub unsafe extern "C" fn tcgetattr(fd: c_int, termios: *mut ctypes::termios) -> c_int {
+    e(sys_tcgetattr(fd, termios))
+}
+
+/// Set the attributes of the terminal referred to by `fd`.
+#[no_mangle]
+pub unsafe extern "C" fn tcsetattr(
+    fd: c_int,
+    optional_actions: c_int,
+    termios: *const ctypes::termios,
+) -> c_int {
+    e(sys_tcsetattr(fd, optional_actions, termios))
+}
+
+/// Discard the data not read or not transmitted of the terminal referred to
+/// by `fd`.
+#[no_mangle]
+pub unsafe extern "C" fn tcflush(fd: c_int, queue_selector: c_int) -> c_int {
+    e(sys_tcflush(fd, queue_selector))
+}